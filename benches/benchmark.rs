@@ -1,8 +1,8 @@
 use criterion::*;
 use image::RgbImage;
-use spectro_cam_rs::config::{Linearize, ReferenceConfig, SpectrometerConfig};
+use spectro_cam_rs::config::{ColumnAggregation, Linearize, ReferenceConfig, SpectrometerConfig};
 use spectro_cam_rs::spectrum::{SpectrumCalculator, SpectrumContainer, SpectrumRgb};
-use spectro_cam_rs::tungsten_halogen::reference_from_filament_temp;
+use spectro_cam_rs::tungsten_halogen::{reference_from_filament_temp, TungstenConfig};
 
 fn spectrum_calculator_bench(c: &mut Criterion) {
     let window = RgbImage::new(1000, 20);
@@ -10,7 +10,13 @@ fn spectrum_calculator_bench(c: &mut Criterion) {
     c.bench_with_input(
         BenchmarkId::new("process_window", "window_1000_20"),
         &window,
-        |b, w| b.iter(|| SpectrumCalculator::process_window(w)),
+        |b, w| b.iter(|| SpectrumCalculator::process_window(w, ColumnAggregation::Mean)),
+    );
+
+    c.bench_with_input(
+        BenchmarkId::new("process_window_median", "window_1000_20"),
+        &window,
+        |b, w| b.iter(|| SpectrumCalculator::process_window(w, ColumnAggregation::Median)),
     );
 }
 
@@ -67,7 +73,13 @@ fn spectrum_buffer_bench(c: &mut Criterion) {
 
 fn config_bench(c: &mut Criterion) {
     let rc = ReferenceConfig {
-        reference: Some(reference_from_filament_temp(2500)),
+        reference: Some(
+            reference_from_filament_temp(&TungstenConfig {
+                filament_temp: 2500,
+                ..Default::default()
+            })
+            .unwrap(),
+        ),
         scale: 1.,
     };
 
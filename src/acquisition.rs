@@ -0,0 +1,129 @@
+use std::fmt::{Display, Formatter};
+
+/// State of the frame acquisition from the main frame source
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub enum AcquisitionState {
+    #[default]
+    Idle,
+    /// The stream was requested, waiting for the frame source to report back
+    Starting,
+    Running,
+    /// The stream stays open, but its frames are not processed
+    Paused,
+    /// The stream failed and the frame source is reopened, with the latest attempt
+    Reconnecting(String),
+    /// The stream failed, with the reason
+    Error(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum AcquisitionEvent {
+    /// Requested by the user or a remote client
+    Start,
+    /// Reported by the frame source once the stream is open, also after a reconnect
+    Started,
+    /// Reported by the frame source for every reconnect attempt
+    Reconnecting(String),
+    Pause,
+    Resume,
+    Stop,
+    /// Reported by the frame source or raised while starting
+    Failed(String),
+}
+
+impl AcquisitionState {
+    /// State after the event, events that are not allowed in the current state are rejected
+    pub fn transition(&self, event: AcquisitionEvent) -> Result<AcquisitionState, String> {
+        use AcquisitionEvent as E;
+        use AcquisitionState as S;
+        match (self, event) {
+            (S::Idle | S::Error(_), E::Start) => Ok(S::Starting),
+            (S::Starting | S::Reconnecting(_), E::Started) => Ok(S::Running),
+            (S::Running | S::Paused | S::Reconnecting(_), E::Reconnecting(attempt)) => {
+                Ok(S::Reconnecting(attempt))
+            }
+            (S::Running, E::Pause) => Ok(S::Paused),
+            (S::Paused, E::Resume) => Ok(S::Running),
+            // Stopping is always possible and clears errors
+            (_, E::Stop) => Ok(S::Idle),
+            (S::Starting | S::Running | S::Paused | S::Reconnecting(_), E::Failed(e)) => {
+                Ok(S::Error(e))
+            }
+            (state, event) => Err(format!("Cannot handle {event:?} while {state}")),
+        }
+    }
+
+    /// The stream is open or being opened, the frame source settings are locked
+    pub fn is_active(&self) -> bool {
+        matches!(
+            self,
+            AcquisitionState::Starting
+                | AcquisitionState::Running
+                | AcquisitionState::Paused
+                | AcquisitionState::Reconnecting(_)
+        )
+    }
+
+    /// New spectra are expected
+    pub fn is_running(&self) -> bool {
+        *self == AcquisitionState::Running
+    }
+}
+
+impl Display for AcquisitionState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AcquisitionState::Idle => write!(f, "Idle"),
+            AcquisitionState::Starting => write!(f, "Starting"),
+            AcquisitionState::Running => write!(f, "Running"),
+            AcquisitionState::Paused => write!(f, "Paused"),
+            AcquisitionState::Reconnecting(attempt) => write!(f, "Reconnecting: {attempt}"),
+            AcquisitionState::Error(e) => write!(f, "Error: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transitions() {
+        let state = AcquisitionState::default();
+        assert!(state.transition(AcquisitionEvent::Pause).is_err());
+        assert!(state.transition(AcquisitionEvent::Started).is_err());
+
+        let state = state.transition(AcquisitionEvent::Start).unwrap();
+        assert!(state.is_active() && !state.is_running());
+        let state = state.transition(AcquisitionEvent::Started).unwrap();
+        assert!(state.is_running());
+        assert!(state.transition(AcquisitionEvent::Start).is_err());
+        let state = state.transition(AcquisitionEvent::Pause).unwrap();
+        assert_eq!(state, AcquisitionState::Paused);
+        // No need to resume before stopping
+        assert_eq!(
+            state.transition(AcquisitionEvent::Stop),
+            Ok(AcquisitionState::Idle)
+        );
+
+        let state = state
+            .transition(AcquisitionEvent::Reconnecting("Attempt 1".to_string()))
+            .unwrap();
+        assert!(state.is_active() && !state.is_running());
+        assert!(state.transition(AcquisitionEvent::Pause).is_err());
+        assert_eq!(
+            state.transition(AcquisitionEvent::Started),
+            Ok(AcquisitionState::Running)
+        );
+
+        let state = state
+            .transition(AcquisitionEvent::Failed("Camera lost".to_string()))
+            .unwrap();
+        assert!(!state.is_active());
+        assert_eq!(state.to_string(), "Error: Camera lost");
+        assert_eq!(
+            state.transition(AcquisitionEvent::Start),
+            Ok(AcquisitionState::Starting)
+        );
+    }
+}
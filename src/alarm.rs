@@ -0,0 +1,154 @@
+use crate::config::{SpectrumPoint, WavelengthRange};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PeakAlarm {
+    pub name: String,
+    pub range: WavelengthRange,
+    /// Triggered if the maximum within the range exceeds this value
+    pub threshold: f32,
+    /// Name of the ROI preset the alarm belongs to, all presets if empty
+    pub profile: String,
+}
+
+impl PeakAlarm {
+    pub fn new(name: String, profile: String) -> Self {
+        Self {
+            name,
+            range: WavelengthRange {
+                low: 250.,
+                high: 258.,
+            },
+            threshold: 0.5,
+            profile,
+        }
+    }
+
+    pub fn applies_to(&self, profile: Option<&str>) -> bool {
+        self.profile.is_empty() || Some(self.profile.as_str()) == profile
+    }
+
+    /// Maximum within the range of points sorted by wavelength
+    ///
+    /// Ranges narrower than the spectrum resolution use the value at their center.
+    pub fn band_value(&self, points: &[SpectrumPoint]) -> Option<f32> {
+        points
+            .iter()
+            .filter(|p| p.wavelength >= self.range.low && p.wavelength <= self.range.high)
+            .map(|p| p.value)
+            .reduce(f32::max)
+            .or_else(|| SpectrumPoint::interpolate(points, (self.range.low + self.range.high) / 2.))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct AlarmConfig {
+    pub active: bool,
+    pub alarms: Vec<PeakAlarm>,
+    /// Notified whenever an alarm is triggered or cleared
+    pub webhook_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AlarmEvent {
+    pub name: String,
+    /// False if the alarm was cleared
+    pub triggered: bool,
+    pub value: f32,
+    pub threshold: f32,
+}
+
+/// Tracks which alarms are triggered to report only changes
+#[derive(Debug, Default)]
+pub struct AlarmMonitor {
+    triggered: Vec<String>,
+}
+
+impl AlarmMonitor {
+    /// Check all alarms of the profile and return the ones that were triggered or cleared
+    pub fn update(
+        &mut self,
+        config: &AlarmConfig,
+        points: &[SpectrumPoint],
+        profile: Option<&str>,
+    ) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+        let mut triggered = Vec::new();
+        for alarm in config.alarms.iter().filter(|a| a.applies_to(profile)) {
+            let Some(value) = alarm.band_value(points) else {
+                continue;
+            };
+            let is_triggered = value > alarm.threshold;
+            if is_triggered != self.triggered.contains(&alarm.name) {
+                events.push(AlarmEvent {
+                    name: alarm.name.clone(),
+                    triggered: is_triggered,
+                    value,
+                    threshold: alarm.threshold,
+                });
+            }
+            if is_triggered {
+                triggered.push(alarm.name.clone());
+            }
+        }
+        self.triggered = triggered;
+        events
+    }
+
+    /// Names of the currently triggered alarms
+    pub fn triggered(&self) -> &[String] {
+        &self.triggered
+    }
+
+    pub fn clear(&mut self) {
+        self.triggered.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn alarm_monitor() {
+        let mut config = AlarmConfig {
+            active: true,
+            alarms: vec![
+                PeakAlarm::new("UV-C".to_string(), String::new()),
+                PeakAlarm::new("Other profile".to_string(), "Cuvette".to_string()),
+            ],
+            webhook_url: String::new(),
+        };
+        config.alarms[1].threshold = 0.;
+        let spectrum = |value| {
+            (240..270)
+                .map(|w| SpectrumPoint {
+                    wavelength: w as f32,
+                    value: if w == 254 { value } else { 0.1 },
+                })
+                .collect::<Vec<_>>()
+        };
+        let mut monitor = AlarmMonitor::default();
+
+        assert!(monitor.update(&config, &spectrum(0.4), None).is_empty());
+        let events = monitor.update(&config, &spectrum(0.8), Some("Default"));
+        assert_eq!(events.len(), 1);
+        assert!(events[0].triggered);
+        assert_eq!(events[0].value, 0.8);
+        assert!(monitor.update(&config, &spectrum(0.9), None).is_empty());
+        assert_eq!(monitor.triggered(), ["UV-C"]);
+        let events = monitor.update(&config, &spectrum(0.2), None);
+        assert!(!events[0].triggered);
+        assert!(monitor.triggered().is_empty());
+
+        config.alarms[0].range = WavelengthRange {
+            low: 254.2,
+            high: 254.4,
+        };
+        approx::assert_relative_eq!(
+            config.alarms[0].band_value(&spectrum(1.1)).unwrap(),
+            0.8,
+            epsilon = 1e-4
+        );
+    }
+}
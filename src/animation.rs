@@ -0,0 +1,158 @@
+use crate::recorder::Recording;
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, Rgba, RgbaImage};
+use std::fs::File;
+use std::time::Duration;
+
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const LINE: Rgba<u8> = Rgba([211, 211, 211, 255]);
+
+/// Render a recording as animated GIF with the current spectrum on top and a waterfall of the
+/// preceding spectra below.
+pub fn export_gif(
+    recording: &Recording,
+    path: &str,
+    fps: f32,
+    duration: f32,
+    width: u32,
+    height: u32,
+) -> Result<(), String> {
+    if recording.spectra.is_empty() {
+        return Err("Recording is empty".to_string());
+    }
+    let frame_count = ((fps * duration).ceil() as usize).max(1);
+    let max_value = recording
+        .spectra
+        .iter()
+        .flat_map(|s| s.sum.iter().cloned())
+        .reduce(f32::max)
+        .unwrap_or(1.)
+        .max(f32::EPSILON);
+    let total_time = recording.relative_time(recording.spectra.len() - 1);
+
+    let file = File::create(path).map_err(|e| e.to_string())?;
+    let mut encoder = GifEncoder::new_with_speed(file, 10);
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .map_err(|e| e.to_string())?;
+
+    let mut index = 0;
+    for i in 0..frame_count {
+        let time = if frame_count > 1 {
+            total_time * i as f32 / (frame_count - 1) as f32
+        } else {
+            total_time
+        };
+        while index + 1 < recording.spectra.len() && recording.relative_time(index + 1) <= time {
+            index += 1;
+        }
+        let image = render_frame(recording, index, max_value, width, height);
+        encoder
+            .encode_frame(Frame::from_parts(
+                image,
+                0,
+                0,
+                Delay::from_saturating_duration(Duration::from_secs_f32(1. / fps.max(1.))),
+            ))
+            .map_err(|e| e.to_string())?;
+    }
+    Ok(())
+}
+
+/// Render one animation frame for the spectrum at `index` of the recording.
+pub fn render_frame(
+    recording: &Recording,
+    index: usize,
+    max_value: f32,
+    width: u32,
+    height: u32,
+) -> RgbaImage {
+    let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+    let plot_height = height / 2;
+    let waterfall_height = height - plot_height;
+
+    let column_value = |spectrum: &[f32], x: u32| -> f32 {
+        let column = x as usize * spectrum.len() / width as usize;
+        spectrum.get(column).cloned().unwrap_or_default() / max_value
+    };
+
+    // Spectrum line
+    let current = &recording.spectra[index].sum;
+    let mut last_y = None;
+    for x in 0..width {
+        let value = column_value(current, x).clamp(0., 1.);
+        let y = plot_height - 1 - (value * (plot_height - 1) as f32) as u32;
+        let (from, to) = match last_y {
+            Some(last_y) if last_y < y => (last_y, y),
+            Some(last_y) => (y, last_y),
+            None => (y, y),
+        };
+        for y in from..=to {
+            image.put_pixel(x, y, LINE);
+        }
+        last_y = Some(y);
+    }
+
+    // Waterfall with the newest spectrum at the top
+    for row in 0..waterfall_height.min(index as u32 + 1) {
+        let spectrum = &recording.spectra[index - row as usize].sum;
+        for x in 0..width {
+            image.put_pixel(
+                x,
+                plot_height + row,
+                intensity_color(column_value(spectrum, x)),
+            );
+        }
+    }
+    image
+}
+
+/// Map a normalized intensity to a black-blue-red-yellow-white color scale.
+pub fn intensity_color(value: f32) -> Rgba<u8> {
+    const STOPS: [[f32; 3]; 5] = [
+        [0., 0., 0.],
+        [0., 0., 255.],
+        [255., 0., 0.],
+        [255., 255., 0.],
+        [255., 255., 255.],
+    ];
+    let position = value.clamp(0., 1.) * (STOPS.len() - 1) as f32;
+    let i = (position.floor() as usize).min(STOPS.len() - 2);
+    let t = position - i as f32;
+    let c = |channel: usize| (STOPS[i][channel] * (1. - t) + STOPS[i + 1][channel] * t) as u8;
+    Rgba([c(0), c(1), c(2), 255])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recorder::RecordedSpectrum;
+    use crate::spectrum::Spectrum;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn gif() {
+        let recording = Recording {
+            spectra: (0..5)
+                .map(|i| {
+                    RecordedSpectrum::from_spectrum(
+                        &Spectrum::from_element(50, i as f32),
+                        SystemTime::UNIX_EPOCH + Duration::from_secs(i),
+                    )
+                })
+                .collect(),
+            ..Default::default()
+        };
+
+        let frame = render_frame(&recording, 4, 4., 100, 40);
+        assert_eq!(frame.dimensions(), (100, 40));
+        assert_eq!(*frame.get_pixel(0, 20), intensity_color(1.));
+
+        let path = std::env::temp_dir().join("spectro_cam_rs_animation_test.gif");
+        let path = path.to_str().unwrap();
+        export_gif(&recording, path, 2., 2., 100, 40).unwrap();
+        let content = std::fs::read(path).unwrap();
+        std::fs::remove_file(path).ok();
+        assert!(content.starts_with(b"GIF89a"));
+    }
+}
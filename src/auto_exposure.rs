@@ -0,0 +1,244 @@
+use crate::Exposure;
+use nokhwa::utils::{
+    CameraControl, ControlValueDescription, ControlValueSetter, KnownCameraControl,
+};
+use serde::{Deserialize, Serialize};
+
+/// Peaks from this fraction of full scale on are treated as clipped
+const SATURATED_PEAK: f32 = 0.98;
+/// Largest factor the brightness is changed by in one step
+const MAX_STEP: f32 = 4.;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct AutoExposureConfig {
+    pub active: bool,
+    /// Peak value to keep the spectrum at, as fraction of full scale
+    pub target: f32,
+    /// Relative deviation from the target that is tolerated
+    pub tolerance: f32,
+    /// Adjust the gain once the exposure time is at its limit
+    pub use_gain: bool,
+    /// Spectra to wait after an adjustment until the new values take effect
+    pub settle_spectra: usize,
+}
+
+impl Default for AutoExposureConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            target: 0.8,
+            tolerance: 0.1,
+            use_gain: false,
+            settle_spectra: 5,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ControlRange {
+    pub min: i64,
+    pub max: i64,
+}
+
+impl ControlRange {
+    /// Range of an integer control as reported by the driver
+    pub fn of(controls: &[CameraControl], control: KnownCameraControl) -> Option<Self> {
+        controls
+            .iter()
+            .find(|c| c.control() == control)
+            .and_then(|c| match c.description() {
+                ControlValueDescription::IntegerRange { min, max, .. } => Some(Self {
+                    min: *min,
+                    max: *max,
+                }),
+                _ => None,
+            })
+    }
+
+    /// Scale a value, returns the new value and the part of the factor that could not be applied
+    fn scale(&self, value: i64, factor: f32) -> (i64, f32) {
+        let value = value.max(1);
+        let scaled = ((value as f32 * factor).round() as i64).clamp(self.min.max(1), self.max);
+        (scaled, factor * value as f32 / scaled as f32)
+    }
+}
+
+/// Closed loop control of exposure time and gain keeping the spectrum peak at the target
+#[derive(Debug, Default)]
+pub struct AutoExposure {
+    /// Last values sent to the camera, the read back values lag behind
+    commanded: Option<Exposure>,
+    settle: usize,
+}
+
+impl AutoExposure {
+    /// Forget the sent values, e.g. after the stream was restarted or controls changed by hand
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+
+    /// Returns the controls to change if the peak is off target
+    ///
+    /// `peak` is the highest channel value of the newest spectrum as fraction of full scale.
+    pub fn update(
+        &mut self,
+        peak: f32,
+        exposure: Exposure,
+        exposure_range: ControlRange,
+        gain_range: Option<ControlRange>,
+        config: &AutoExposureConfig,
+    ) -> Vec<(KnownCameraControl, ControlValueSetter)> {
+        if self.settle > 0 {
+            self.settle -= 1;
+            return vec![];
+        }
+        let factor = if peak >= SATURATED_PEAK {
+            // The actual peak is unknown
+            1. / MAX_STEP
+        } else if (peak / config.target - 1.).abs() <= config.tolerance {
+            return vec![];
+        } else {
+            (config.target / peak.max(f32::EPSILON)).clamp(1. / MAX_STEP, MAX_STEP)
+        };
+        let current = self.commanded.unwrap_or(exposure);
+        let Some(exposure_time) = current.exposure_time else {
+            return vec![];
+        };
+        let gain = current.gain.zip(gain_range).filter(|_| config.use_gain);
+
+        // Prefer short exposure times over high gains for less noise
+        let (new_exposure_time, new_gain) = match gain {
+            Some((gain, gain_range)) if factor < 1. => {
+                let (new_gain, remaining) = gain_range.scale(gain, factor);
+                let new_gain = if gain <= gain_range.min {
+                    gain
+                } else {
+                    new_gain
+                };
+                let remaining = if new_gain == gain { factor } else { remaining };
+                (
+                    exposure_range.scale(exposure_time, remaining).0,
+                    Some(new_gain),
+                )
+            }
+            Some((gain, gain_range)) => {
+                let (new_exposure_time, remaining) = exposure_range.scale(exposure_time, factor);
+                let new_gain = if remaining > 1. + config.tolerance {
+                    gain_range.scale(gain, remaining).0
+                } else {
+                    gain
+                };
+                (new_exposure_time, Some(new_gain))
+            }
+            None => (exposure_range.scale(exposure_time, factor).0, None),
+        };
+
+        let mut controls = vec![];
+        if new_exposure_time != exposure_time {
+            controls.push((
+                KnownCameraControl::Exposure,
+                ControlValueSetter::Integer(new_exposure_time),
+            ));
+        }
+        if let Some(new_gain) = new_gain.filter(|&g| Some(g) != current.gain) {
+            controls.push((
+                KnownCameraControl::Gain,
+                ControlValueSetter::Integer(new_gain),
+            ));
+        }
+        if !controls.is_empty() {
+            self.commanded = Some(Exposure {
+                exposure_time: Some(new_exposure_time),
+                gain: new_gain.or(current.gain),
+            });
+            self.settle = config.settle_spectra;
+        }
+        controls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXPOSURE_RANGE: ControlRange = ControlRange { min: 1, max: 1000 };
+    const GAIN_RANGE: ControlRange = ControlRange { min: 0, max: 100 };
+
+    fn exposure(exposure_time: i64, gain: i64) -> Exposure {
+        Exposure {
+            exposure_time: Some(exposure_time),
+            gain: Some(gain),
+        }
+    }
+
+    #[test]
+    fn exposure_time_follows_peak() {
+        let config = AutoExposureConfig {
+            settle_spectra: 1,
+            ..Default::default()
+        };
+        let mut auto_exposure = AutoExposure::default();
+
+        assert_eq!(
+            auto_exposure.update(0.4, exposure(100, 10), EXPOSURE_RANGE, None, &config),
+            vec![(
+                KnownCameraControl::Exposure,
+                ControlValueSetter::Integer(200)
+            )]
+        );
+        // Waiting for the new exposure time to take effect
+        assert!(auto_exposure
+            .update(0.4, exposure(100, 10), EXPOSURE_RANGE, None, &config)
+            .is_empty());
+        // The commanded value is used instead of the lagging read back value
+        assert_eq!(
+            auto_exposure.update(1., exposure(100, 10), EXPOSURE_RANGE, None, &config),
+            vec![(
+                KnownCameraControl::Exposure,
+                ControlValueSetter::Integer(50)
+            )]
+        );
+        auto_exposure.update(0.75, exposure(50, 10), EXPOSURE_RANGE, None, &config);
+        assert!(auto_exposure
+            .update(0.75, exposure(50, 10), EXPOSURE_RANGE, None, &config)
+            .is_empty());
+    }
+
+    #[test]
+    fn gain_after_exposure_limit() {
+        let config = AutoExposureConfig {
+            use_gain: true,
+            settle_spectra: 0,
+            ..Default::default()
+        };
+        let mut auto_exposure = AutoExposure::default();
+
+        assert_eq!(
+            auto_exposure.update(
+                0.2,
+                exposure(500, 10),
+                EXPOSURE_RANGE,
+                Some(GAIN_RANGE),
+                &config
+            ),
+            vec![
+                (
+                    KnownCameraControl::Exposure,
+                    ControlValueSetter::Integer(1000)
+                ),
+                (KnownCameraControl::Gain, ControlValueSetter::Integer(20)),
+            ]
+        );
+        // The gain is reduced first
+        assert_eq!(
+            auto_exposure.update(
+                1.,
+                exposure(1000, 20),
+                EXPOSURE_RANGE,
+                Some(GAIN_RANGE),
+                &config
+            ),
+            vec![(KnownCameraControl::Gain, ControlValueSetter::Integer(5))]
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use crate::config::{SpectrometerConfig, SpectrumWindow};
+use crate::spectrum::{SpectrumCalculator, SpectrumContainer};
+use egui::Vec2;
+use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Mean time per frame of each pipeline stage
+#[derive(Debug, Clone, Copy)]
+pub struct PipelineBenchReport {
+    pub width: u32,
+    pub height: u32,
+    pub window: SpectrumWindow,
+    pub frames: usize,
+    /// Flipping and extracting the spectrum window in the camera thread
+    pub extract: Duration,
+    /// Reducing the window to a spectrum in the calculator thread
+    pub process: Duration,
+    /// Averaging and postprocessing in the GUI thread
+    pub postprocess: Duration,
+}
+
+impl PipelineBenchReport {
+    /// Spectra per second if all stages run one after another
+    pub fn sequential_rate(&self) -> f64 {
+        1. / (self.extract + self.process + self.postprocess).as_secs_f64()
+    }
+
+    /// Spectra per second limited by the slowest stage, since every stage has its own thread
+    pub fn pipelined_rate(&self) -> f64 {
+        1. / self
+            .extract
+            .max(self.process)
+            .max(self.postprocess)
+            .as_secs_f64()
+    }
+}
+
+impl Display for PipelineBenchReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{} frames of {}x{} px, spectrum window {}x{} px",
+            self.frames, self.width, self.height, self.window.size.x, self.window.size.y
+        )?;
+        writeln!(f, "  extract window: {:>10.3} ms", ms(self.extract))?;
+        writeln!(f, "  process window: {:>10.3} ms", ms(self.process))?;
+        writeln!(f, "  postprocessing: {:>10.3} ms", ms(self.postprocess))?;
+        writeln!(
+            f,
+            "Achievable spectra/s: {:.1} (pipelined), {:.1} (sequential)",
+            self.pipelined_rate(),
+            self.sequential_rate()
+        )
+    }
+}
+
+fn ms(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.
+}
+
+/// Parse a resolution like `1920x1080`
+pub fn parse_resolution(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("Invalid resolution {s}, expected WIDTHxHEIGHT"))?;
+    let parse = |v: &str| {
+        v.trim()
+            .parse::<u32>()
+            .ok()
+            .filter(|&v| v > 0)
+            .ok_or_else(|| format!("Invalid resolution {s}, expected WIDTHxHEIGHT"))
+    };
+    Ok((parse(width)?, parse(height)?))
+}
+
+/// Run synthetic frames through all pipeline stages with the given config
+///
+/// The configured spectrum window is used if it fits into the frame, otherwise a band of 20
+/// rows across the full width.
+pub fn bench_pipeline(
+    width: u32,
+    height: u32,
+    frames: usize,
+    config: &SpectrometerConfig,
+) -> PipelineBenchReport {
+    let configured = config.image_config.window;
+    let window = if configured.size.x >= 1.
+        && configured.size.y >= 1.
+        && configured.offset.x + configured.size.x <= width as f32
+        && configured.offset.y + configured.size.y <= height as f32
+    {
+        configured
+    } else {
+        let rows = 20.min(height);
+        SpectrumWindow {
+            offset: Vec2::new(0., ((height - rows) / 2) as f32),
+            size: Vec2::new(width as f32, rows as f32),
+            angle: 0.,
+        }
+    };
+
+    // Noisy background with a bright band and a few lines
+    let frame = ImageBuffer::from_fn(width, height, |x, y| {
+        let noise = ((x * 7 + y * 13) % 17) as u8;
+        let in_band = (y as f32) >= window.offset.y && (y as f32) < window.offset.y + window.size.y;
+        if in_band {
+            let line = if x % 97 < 3 { 100 } else { 0 };
+            Rgb([80 + noise + line, 120 + noise, 60 + noise + line])
+        } else {
+            Rgb([noise, noise, noise])
+        }
+    });
+
+    let (_spectrum_tx, spectrum_rx) = flume::unbounded();
+    let mut container = SpectrumContainer::new(spectrum_rx);
+
+    let mut extract = Duration::ZERO;
+    let mut process = Duration::ZERO;
+    let mut postprocess = Duration::ZERO;
+    for _ in 0..frames.max(1) {
+        let start = Instant::now();
+        let mut image: ImageBuffer<Rgb<u8>, Vec<u8>> = frame.clone();
+        if config.image_config.flip {
+            image = DynamicImage::ImageRgb8(image).fliph().into_rgb8();
+        }
+        let window_image = image
+            .view(
+                window.offset.x as u32,
+                window.offset.y as u32,
+                window.size.x as u32,
+                window.size.y as u32,
+            )
+            .to_image();
+        let extracted = Instant::now();
+        let spectrum = SpectrumCalculator::process_window(
+            &window_image,
+            config.image_config.column_aggregation,
+        );
+        let processed = Instant::now();
+        container.update_spectrum(spectrum, config);
+        let end = Instant::now();
+
+        extract += extracted - start;
+        process += processed - extracted;
+        postprocess += end - processed;
+    }
+
+    let frames = frames.max(1);
+    PipelineBenchReport {
+        width,
+        height,
+        window,
+        frames,
+        extract: extract / frames as u32,
+        process: process / frames as u32,
+        postprocess: postprocess / frames as u32,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resolution() {
+        assert_eq!(parse_resolution("1920x1080"), Ok((1920, 1080)));
+        assert!(parse_resolution("1920").is_err());
+        assert!(parse_resolution("0x10").is_err());
+    }
+
+    #[test]
+    fn bench_small_frames() {
+        let report = bench_pipeline(64, 48, 3, &SpectrometerConfig::default());
+
+        assert_eq!(report.window.size, Vec2::new(64., 20.));
+        assert_eq!(report.frames, 3);
+        assert!(report.pipelined_rate() >= report.sequential_rate());
+    }
+}
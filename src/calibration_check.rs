@@ -0,0 +1,149 @@
+use crate::config::SpectrumPoint;
+use crate::spectrum::sub_pixel_peak;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct CalibrationCheckConfig {
+    pub active: bool,
+    /// Calibrated wavelength of the reference line in nm
+    pub reference_wavelength: f32,
+    /// The line is searched this many nm around the reference wavelength
+    pub search_width: f32,
+    /// Drift in nm above which a recalibration is prompted
+    pub threshold: f32,
+    /// Seconds between two checks
+    pub interval: f32,
+}
+
+impl Default for CalibrationCheckConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            // Mercury line of fluorescent lamps
+            reference_wavelength: 546.07,
+            search_width: 10.,
+            threshold: 1.,
+            interval: 60.,
+        }
+    }
+}
+
+impl CalibrationCheckConfig {
+    /// Measured minus calibrated wavelength of the reference line in points sorted by wavelength
+    ///
+    /// Returns `None` if the maximum of the search range lies at its edge, so the line was not
+    /// found.
+    pub fn line_drift(&self, points: &[SpectrumPoint]) -> Option<f32> {
+        let in_range = points
+            .iter()
+            .filter(|p| (p.wavelength - self.reference_wavelength).abs() <= self.search_width)
+            .copied()
+            .collect::<Vec<_>>();
+        Some(sub_pixel_peak(&in_range)? - self.reference_wavelength)
+    }
+}
+
+/// Outcome of a drift check
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DriftCheck {
+    Drift(f32),
+    LineNotFound,
+}
+
+/// Periodic check of the calibration against a reference line
+#[derive(Debug, Default)]
+pub struct CalibrationCheck {
+    last_check: Option<Instant>,
+    last_result: Option<DriftCheck>,
+}
+
+impl CalibrationCheck {
+    pub fn is_due(&self, config: &CalibrationCheckConfig, now: Instant) -> bool {
+        self.last_check.is_none_or(|last| {
+            now.duration_since(last) >= Duration::from_secs_f32(config.interval.max(1.))
+        })
+    }
+
+    /// Check the reference line, returns the result if it turned into a warning
+    pub fn update(
+        &mut self,
+        config: &CalibrationCheckConfig,
+        points: &[SpectrumPoint],
+        now: Instant,
+    ) -> Option<DriftCheck> {
+        // Kind of warning, drifts within the threshold are fine
+        let warning = |result: Option<DriftCheck>| match result {
+            Some(DriftCheck::Drift(drift)) if drift.abs() <= config.threshold => None,
+            result => result.map(|r| std::mem::discriminant(&r)),
+        };
+        let result = match config.line_drift(points) {
+            Some(drift) => DriftCheck::Drift(drift),
+            None => DriftCheck::LineNotFound,
+        };
+        let previous = self.last_result.replace(result);
+        self.last_check = Some(now);
+        (warning(Some(result)).is_some() && warning(Some(result)) != warning(previous))
+            .then_some(result)
+    }
+
+    pub fn last_result(&self) -> Option<DriftCheck> {
+        self.last_result
+    }
+
+    /// The last check found the reference line beyond the threshold
+    pub fn needs_recalibration(&self, config: &CalibrationCheckConfig) -> bool {
+        matches!(self.last_result, Some(DriftCheck::Drift(drift)) if drift.abs() > config.threshold)
+    }
+
+    /// Check again with the next spectrum
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn line(center: f32) -> Vec<SpectrumPoint> {
+        (500..600)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: (-(w as f32 - center).powi(2) / 8.).exp(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn drift_check() {
+        let config = CalibrationCheckConfig::default();
+        let mut check = CalibrationCheck::default();
+        let start = Instant::now();
+        assert!(check.is_due(&config, start));
+
+        assert_eq!(check.update(&config, &line(546.5), start), None);
+        assert!(!check.is_due(&config, start + Duration::from_secs(30)));
+        assert!(check.is_due(&config, start + Duration::from_secs(60)));
+        approx::assert_abs_diff_eq!(
+            config.line_drift(&line(548.)).unwrap(),
+            548. - 546.07,
+            epsilon = 1e-3
+        );
+
+        let Some(DriftCheck::Drift(drift)) = check.update(&config, &line(548.), start) else {
+            panic!("Drift not reported");
+        };
+        approx::assert_abs_diff_eq!(drift, 1.93, epsilon = 1e-3);
+        assert!(check.needs_recalibration(&config));
+        // Reported once
+        assert_eq!(check.update(&config, &line(548.), start), None);
+
+        // Outside of the search range
+        assert_eq!(
+            check.update(&config, &line(530.), start),
+            Some(DriftCheck::LineNotFound)
+        );
+        assert!(!check.needs_recalibration(&config));
+    }
+}
@@ -1,14 +1,67 @@
-use crate::config::ImageConfig;
-use crate::{ThreadId, ThreadResult};
+use crate::config::{
+    HdrConfig, ImageConfig, ImageFileConfig, NetworkStreamConfig, ReconnectConfig,
+    ScreenCaptureConfig, SpectrumWindow, StreamBackend, LOW_POWER_INTERVAL,
+};
+use crate::dark_frame::DarkFrame;
+use crate::driver_capture::DriverCapture;
+use crate::flat_field::FlatField;
+use crate::frame_recorder::{FrameRecorder, FrameRecordingConfig};
+use crate::spectrum::{
+    extract_window, orient_bayer_layout, orient_window, to_window_depth, Bracket,
+    WindowAccumulator, WindowImage,
+};
+use crate::{Exposure, FrameMetadata, ThreadId, ThreadResult, Timestamped};
 use flume::{Receiver, Sender};
 use image::{DynamicImage, GenericImageView, ImageBuffer, Rgb};
-use nokhwa::pixel_format::RgbFormat;
+use indexmap::IndexMap;
+use nokhwa::pixel_format::{LumaFormat, RgbFormat};
 use nokhwa::utils::{
-    CameraFormat, CameraIndex, ControlValueSetter, FrameFormat, KnownCameraControl,
-    RequestedFormat, RequestedFormatType, Resolution,
+    frame_formats, ApiBackend, CameraControl, CameraFormat, CameraIndex, ControlValueDescription,
+    ControlValueSetter, FrameFormat, KnownCameraControl, RequestedFormat, RequestedFormatType,
+    Resolution,
 };
-use nokhwa::CallbackCamera;
+use nokhwa::{query, CallbackCamera, Camera};
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::io::{BufRead, BufReader};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use zune_jpeg::zune_core::colorspace::ColorSpace;
+use zune_jpeg::zune_core::options::DecoderOptions;
+use zune_jpeg::JpegDecoder;
+
+/// V4L2_EXPOSURE_MANUAL value of the "Auto Exposure" menu control.
+const EXPOSURE_MANUAL: i64 = 1;
+const WATCH_CONTROLS_INTERVAL: Duration = Duration::from_secs(1);
+const EXPOSURE_READBACK_INTERVAL: Duration = Duration::from_secs(1);
+/// Interval in which the list of connected cameras is re-queried
+const CAMERA_WATCH_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Get the control values that disable all automatic camera controls, e.g. auto white balance
+/// and auto exposure, so that the spectrum shape does not change during a measurement.
+pub fn measurement_mode_controls(
+    controls: &[CameraControl],
+) -> Vec<(KnownCameraControl, ControlValueSetter)> {
+    controls
+        .iter()
+        .filter(|c| c.name().to_lowercase().contains("auto"))
+        .filter_map(|c| match c.description() {
+            ControlValueDescription::Boolean { .. } => {
+                Some((c.control(), ControlValueSetter::Boolean(false)))
+            }
+            ControlValueDescription::IntegerRange { min, max, .. }
+                if c.name().to_lowercase().contains("exposure")
+                    && (*min..=*max).contains(&EXPOSURE_MANUAL) =>
+            {
+                Some((c.control(), ControlValueSetter::Integer(EXPOSURE_MANUAL)))
+            }
+            _ => None,
+        })
+        .collect()
+}
 
 #[derive(Debug, Clone)]
 pub struct CameraInfo {
@@ -31,22 +84,664 @@ impl CameraInfo {
     }
 }
 
+/// Cameras that could be opened, in the order reported by the backend
+pub type CameraList = IndexMap<CameraIndex, CameraInfo>;
+
+/// Open the camera once to read its supported formats
+fn probe_camera(info: &nokhwa::utils::CameraInfo) -> Option<CameraInfo> {
+    for format_type in CameraInfo::get_default_camera_format_types() {
+        match Camera::new(info.index().clone(), requested_format(format_type)) {
+            Ok(mut cam) => {
+                let mut formats = cam.compatible_camera_formats().unwrap_or_default();
+                sort_camera_formats(&mut formats);
+                return Some(CameraInfo {
+                    info: info.clone(),
+                    formats,
+                });
+            }
+            Err(e) => log::warn!("Could not open camera {info} with format {format_type}: {e}"),
+        }
+    }
+    log::warn!("Could not query camera {}", info);
+    None
+}
+
+/// Re-queries the connected cameras and sends the list whenever one is added or removed
+///
+/// Only new devices are opened, so a streaming camera is not disturbed. Devices that could
+/// not be opened are skipped until a refresh is requested.
+pub struct CameraWatcher {
+    list_tx: Sender<CameraList>,
+    refresh_rx: Receiver<()>,
+}
+
+impl CameraWatcher {
+    pub fn new(list_tx: Sender<CameraList>, refresh_rx: Receiver<()>) -> Self {
+        Self {
+            list_tx,
+            refresh_rx,
+        }
+    }
+
+    /// Runs until the receiving side is dropped, the first list is always sent
+    pub fn run(&mut self) {
+        let mut cameras = CameraList::new();
+        let mut failed = HashSet::new();
+        let mut first = true;
+        loop {
+            let devices = query(ApiBackend::Auto).unwrap_or_default();
+            let mut changed = first;
+            let present_before = cameras.len();
+            cameras.retain(|index, _| devices.iter().any(|d| d.index() == index));
+            failed.retain(|index| devices.iter().any(|d| d.index() == index));
+            changed |= cameras.len() != present_before;
+            for device in &devices {
+                if cameras.contains_key(device.index()) || failed.contains(device.index()) {
+                    continue;
+                }
+                match probe_camera(device) {
+                    Some(info) => {
+                        cameras.insert(device.index().clone(), info);
+                        changed = true;
+                    }
+                    None => {
+                        failed.insert(device.index().clone());
+                    }
+                }
+            }
+            if changed {
+                // Keep the order of the backend
+                cameras
+                    .sort_by_cached_key(|index, _| devices.iter().position(|d| d.index() == index));
+                if self.list_tx.send(cameras.clone()).is_err() {
+                    return;
+                }
+            }
+            first = false;
+
+            match self.refresh_rx.recv_timeout(CAMERA_WATCH_INTERVAL) {
+                Ok(()) => failed.clear(),
+                Err(flume::RecvTimeoutError::Timeout) => {}
+                Err(flume::RecvTimeoutError::Disconnected) => return,
+            }
+        }
+    }
+}
+
+/// Camera formats that only differ by frame rate
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraFormatGroup {
+    pub resolution: Resolution,
+    pub format: FrameFormat,
+    /// Highest first
+    pub frame_rates: Vec<u32>,
+}
+
+impl CameraFormatGroup {
+    pub fn camera_format(&self, frame_rate: u32) -> CameraFormat {
+        CameraFormat::new(self.resolution, self.format, frame_rate)
+    }
+
+    pub fn contains(&self, camera_format: &CameraFormat) -> bool {
+        self.resolution == camera_format.resolution() && self.format == camera_format.format()
+    }
+}
+
+impl Display for CameraFormatGroup {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", self.resolution, self.format)
+    }
+}
+
+/// Camera format entered by hand for cameras whose formats are not enumerated correctly
+#[derive(Debug, Clone, PartialEq)]
+pub struct CustomCameraFormat {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: u32,
+    pub fourcc: String,
+}
+
+impl From<CameraFormat> for CustomCameraFormat {
+    fn from(format: CameraFormat) -> Self {
+        Self {
+            width: format.width(),
+            height: format.height(),
+            frame_rate: format.frame_rate(),
+            fourcc: fourcc(format.format()).to_string(),
+        }
+    }
+}
+
+impl CustomCameraFormat {
+    /// Y16 has no nokhwa frame format, it is stored as GREY and read from V4L2
+    pub fn is_y16(&self) -> bool {
+        self.fourcc.trim().eq_ignore_ascii_case("Y16")
+    }
+
+    pub fn to_camera_format(&self) -> Result<CameraFormat, String> {
+        if self.width == 0 || self.height == 0 || self.frame_rate == 0 {
+            return Err("Width, height and frame rate must not be zero".to_string());
+        }
+        let format = if self.is_y16() {
+            FrameFormat::GRAY
+        } else {
+            parse_fourcc(&self.fourcc)?
+        };
+        Ok(CameraFormat::new(
+            Resolution::new(self.width, self.height),
+            format,
+            self.frame_rate,
+        ))
+    }
+}
+
+/// Request a format in any frame format that can be decoded, including the mono GRAY format
+///
+/// Requesting through the RGB decoder would rule out cameras that only provide GRAY.
+pub fn requested_format(format_type: RequestedFormatType) -> RequestedFormat<'static> {
+    RequestedFormat::with_formats(format_type, frame_formats())
+}
+
+/// Format to open the camera with through nokhwa
+///
+/// Y16 streams only use nokhwa for the controls, their format is set on the V4L2 device.
+pub fn stream_request(format: CameraFormat, y16: bool) -> RequestedFormat<'static> {
+    requested_format(if y16 {
+        RequestedFormatType::None
+    } else {
+        RequestedFormatType::Exact(format)
+    })
+}
+
+/// Decode a camera frame, mono frames are kept at a single channel
+///
+/// With `fast_jpeg` MJPEG frames are decoded by zune-jpeg, which needs considerably less CPU
+/// time than the decoder of nokhwa at high resolutions.
+fn decode_frame(buffer: &nokhwa::Buffer, fast_jpeg: bool) -> Result<DynamicImage, String> {
+    match buffer.source_frame_format() {
+        FrameFormat::MJPEG if fast_jpeg => decode_jpeg(buffer.buffer()),
+        FrameFormat::GRAY => buffer
+            .decode_image::<LumaFormat>()
+            .map(DynamicImage::ImageLuma8)
+            .map_err(|e| e.to_string()),
+        _ => buffer
+            .decode_image::<RgbFormat>()
+            .map(DynamicImage::ImageRgb8)
+            .map_err(|e| e.to_string()),
+    }
+}
+
+/// Decode a little endian 16 bit mono frame, rows may be padded
+fn decode_y16(buffer: &nokhwa::Buffer) -> Result<DynamicImage, String> {
+    let (width, height) = (buffer.resolution().width(), buffer.resolution().height());
+    let data = buffer.buffer();
+    let stride = data.len() / height.max(1) as usize;
+    if stride < width as usize * 2 {
+        return Err(format!(
+            "Y16 frame of {} bytes is too small for {width}x{height}",
+            data.len()
+        ));
+    }
+    let pixels = data
+        .chunks_exact(stride)
+        .take(height as usize)
+        .flat_map(|row| {
+            row[..width as usize * 2]
+                .chunks_exact(2)
+                .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        })
+        .collect();
+    ImageBuffer::from_raw(width, height, pixels)
+        .map(DynamicImage::ImageLuma16)
+        .ok_or_else(|| "Y16 frame size does not match".to_string())
+}
+
+/// Decode a JPEG or an MJPEG frame without Huffman tables to RGB
+fn decode_jpeg(data: &[u8]) -> Result<DynamicImage, String> {
+    let options = DecoderOptions::default().jpeg_set_out_colorspace(ColorSpace::RGB);
+    let mut decoder = JpegDecoder::new_with_options(data, options);
+    let pixels = decoder.decode().map_err(|e| format!("{:?}", e))?;
+    let (width, height) = decoder
+        .dimensions()
+        .ok_or_else(|| "JPEG without dimensions".to_string())?;
+    ImageBuffer::from_raw(width as u32, height as u32, pixels)
+        .map(DynamicImage::ImageRgb8)
+        .ok_or_else(|| "JPEG size does not match its dimensions".to_string())
+}
+
+/// V4L2 FourCC of a frame format
+pub fn fourcc(format: FrameFormat) -> &'static str {
+    match format {
+        FrameFormat::MJPEG => "MJPG",
+        FrameFormat::YUYV => "YUYV",
+        FrameFormat::NV12 => "NV12",
+        FrameFormat::GRAY => "GREY",
+        FrameFormat::RAWRGB => "RGB3",
+    }
+}
+
+/// Frame format of a FourCC or nokhwa format name, ignoring case
+pub fn parse_fourcc(fourcc: &str) -> Result<FrameFormat, String> {
+    match fourcc.trim().to_uppercase().as_str() {
+        "MJPG" | "MJPEG" => Ok(FrameFormat::MJPEG),
+        "YUYV" | "YUY2" => Ok(FrameFormat::YUYV),
+        "NV12" => Ok(FrameFormat::NV12),
+        "GREY" | "GRAY" | "Y800" | "Y8" => Ok(FrameFormat::GRAY),
+        "RGB3" | "RAWRGB" => Ok(FrameFormat::RAWRGB),
+        other => Err(format!(
+            "Unsupported FourCC {other}, use MJPG, YUYV, NV12, GREY, RGB3 or Y16"
+        )),
+    }
+}
+
+/// Remove duplicates and sort by resolution, frame format and descending frame rate
+///
+/// Drivers may report the same formats in a different order on every query.
+pub fn sort_camera_formats(formats: &mut Vec<CameraFormat>) {
+    formats.sort_by_key(|f| {
+        (
+            f.width(),
+            f.height(),
+            f.format(),
+            std::cmp::Reverse(f.frame_rate()),
+        )
+    });
+    formats.dedup();
+}
+
+pub fn group_camera_formats(mut formats: Vec<CameraFormat>) -> Vec<CameraFormatGroup> {
+    sort_camera_formats(&mut formats);
+    let mut groups: Vec<CameraFormatGroup> = Vec::new();
+    for f in formats {
+        match groups.last_mut() {
+            Some(group) if group.contains(&f) => group.frame_rates.push(f.frame_rate()),
+            _ => groups.push(CameraFormatGroup {
+                resolution: f.resolution(),
+                format: f.format(),
+                frame_rates: vec![f.frame_rate()],
+            }),
+        }
+    }
+    groups
+}
+
 #[derive(Debug, Clone)]
 pub enum CameraEvent {
     StartStream {
         id: CameraIndex,
         format: CameraFormat,
+        /// Read the frames from V4L2 directly to get the capture times of the driver
+        driver_timestamps: bool,
+        /// Read 16 bit mono Y16 frames from V4L2, `format` only sets resolution and frame rate
+        y16: bool,
     },
     StopStream,
     Config(ImageConfig),
     Controls(Vec<(KnownCameraControl, ControlValueSetter)>),
+    /// Periodically read back these controls and warn if the driver changes them.
+    WatchControls(Vec<(KnownCameraControl, ControlValueSetter)>),
+    /// Cycle through exposure times for HDR spectra
+    Bracketing(HdrConfig),
+    /// Policy for reopening the camera after the stream failed
+    Reconnect(ReconnectConfig),
+    /// Average this number of frames into a dark frame that is subtracted from following frames
+    CaptureDarkFrame(usize),
+    ClearDarkFrame,
+    /// Send the hot pixels of the dark frame with the threshold relative to full scale
+    DetectHotPixels(f32),
+    CaptureFlatField(usize),
+    ClearFlatField,
+    LoadFlatField(String),
+    SaveFlatField(String),
+    /// Save the raw frames of this and following streams alongside processing
+    StartFrameRecording(FrameRecordingConfig),
+    StopFrameRecording,
+    /// Keep the stream open but only send the preview, new streams start unpaused
+    Pause(bool),
+    /// Process this number of frames in single shot mode
+    Capture(usize),
+    StartScreenCapture(ScreenCaptureConfig),
+    /// Feed a still image or a directory of images through the pipeline
+    StartImageFile(ImageFileConfig),
+    /// Decode an RTSP or other network stream with ffmpeg
+    StartNetworkStream(NetworkStreamConfig),
+    /// Decode a video file with ffmpeg at its native frame rate
+    StartFile {
+        path: String,
+        looping: bool,
+    },
 }
 
 struct Exit {}
 
+/// Decides which frames are passed on to stay below a maximum frame rate
+#[derive(Debug, Default)]
+struct FrameRateLimiter {
+    last_frame: Option<Instant>,
+    /// Frames dropped since the last passed frame
+    dropped: usize,
+}
+
+impl FrameRateLimiter {
+    /// Only every `decimation`-th frame is passed on, in addition to the frame rate limit
+    fn frame_due(&mut self, now: Instant, max_frame_rate: Option<f32>, decimation: usize) -> bool {
+        let decimated = self.last_frame.is_none() || self.dropped + 1 >= decimation.max(1);
+        let due = decimated
+            && match (max_frame_rate, self.last_frame) {
+                (Some(rate), Some(last)) => {
+                    now.duration_since(last) >= Duration::from_secs_f32(1. / rate.max(0.01))
+                }
+                _ => true,
+            };
+        if due {
+            self.last_frame = Some(now);
+            self.dropped = 0;
+        } else {
+            self.dropped += 1;
+        }
+        due
+    }
+}
+
+/// Cycles through the exposure times of an HDR bracket
+#[derive(Debug)]
+struct ExposureBracketing {
+    config: HdrConfig,
+    index: usize,
+    /// Frames to drop until the current exposure time takes effect
+    settle: usize,
+}
+
+impl ExposureBracketing {
+    fn new(config: HdrConfig) -> Option<Self> {
+        (config.active && !config.exposures.is_empty()).then_some(Self {
+            settle: config.settle_frames,
+            config,
+            index: 0,
+        })
+    }
+
+    fn exposure_time(&self) -> i64 {
+        self.config.exposures[self.index]
+    }
+
+    /// Bracket of the next frame or None if it is still exposed with the previous exposure time
+    fn next_frame(&mut self) -> Option<Bracket> {
+        if self.settle > 0 {
+            self.settle -= 1;
+            return None;
+        }
+        Some(Bracket {
+            index: self.index,
+            count: self.config.exposures.len(),
+            exposure_time: self.exposure_time(),
+        })
+    }
+
+    /// Move on to the next exposure time and return it
+    fn advance(&mut self) -> i64 {
+        self.index = (self.index + 1) % self.config.exposures.len();
+        self.settle = self.config.settle_frames;
+        self.exposure_time()
+    }
+}
+
+/// Channels and configuration shared by all frame sources of a running stream
+struct StreamContext {
+    config: Arc<Mutex<Option<ImageConfig>>>,
+    inner_config: Option<ImageConfig>,
+    frame_tx: Sender<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    window_tx: Sender<Timestamped<WindowImage>>,
+    second_order_tx: Option<Sender<Timestamped<WindowImage>>>,
+    reference_beam_tx: Option<Sender<Timestamped<WindowImage>>>,
+    result_tx: Sender<ThreadResult>,
+    exit_rx: Receiver<Exit>,
+    frame_rate_limiter: FrameRateLimiter,
+    /// Accumulated windows of the first and second order and the reference beam
+    accumulators: [WindowAccumulator; 3],
+    /// Kept across stream restarts
+    dark_frame: Arc<Mutex<DarkFrame>>,
+    /// Kept across stream restarts
+    flat_field: Arc<Mutex<FlatField>>,
+    /// Kept across stream restarts
+    frame_recorder: Arc<Mutex<Option<FrameRecorder>>>,
+    paused: Arc<AtomicBool>,
+    /// Frames still to be processed in single shot mode
+    captures: Arc<AtomicUsize>,
+    /// Frames passed to `send_frame` since the stream was started
+    sequence: u64,
+    last_preview: Option<Instant>,
+}
+
+impl StreamContext {
+    fn exit_requested(&self) -> bool {
+        self.exit_rx.try_recv().is_ok()
+    }
+
+    fn update_config(&mut self) {
+        if let Some(cfg) = self.config.lock().unwrap().take() {
+            self.inner_config = Some(cfg);
+        }
+    }
+
+    /// Returns false if the next frame exceeds the configured frame rate and should be dropped
+    fn frame_due(&mut self) -> bool {
+        let (max_frame_rate, decimation) = self
+            .inner_config
+            .as_ref()
+            .map_or((None, 1), |cfg| (cfg.max_frame_rate, cfg.frame_decimation));
+        self.frame_rate_limiter
+            .frame_due(Instant::now(), max_frame_rate, decimation)
+    }
+
+    fn send_result(&self, result: Result<(), String>) {
+        self.result_tx
+            .send(ThreadResult {
+                id: ThreadId::Camera,
+                result,
+            })
+            .ok();
+    }
+
+    fn send_flat_field_result(&self, result: Result<(), String>) {
+        self.result_tx
+            .send(ThreadResult {
+                id: ThreadId::FlatField,
+                result,
+            })
+            .ok();
+    }
+
+    /// Flip the frame, extract and orient the spectrum windows and send them with the frame.
+    ///
+    /// While paused or in single shot mode without a pending capture only the frame is sent.
+    ///
+    /// The windows keep the bit depth of 16 bit frames, the preview is always 8 bit.
+    /// With frame accumulation the windows are only sent for every n-th frame.
+    /// Returns false if the receiving side is gone.
+    fn send_frame(
+        &mut self,
+        mut frame: DynamicImage,
+        start: SystemTime,
+        end: SystemTime,
+        frame_metadata: Option<FrameMetadata>,
+        bracket: Option<Bracket>,
+    ) -> bool {
+        self.sequence += 1;
+        let sequence = self.sequence;
+        if let Some(recorder) = self.frame_recorder.lock().unwrap().as_mut() {
+            recorder.push(&frame, self.inner_config.as_ref());
+        }
+        if let Some(cfg) = &self.inner_config {
+            let mut dark_frame = self.dark_frame.lock().unwrap();
+            if dark_frame.is_capturing() || dark_frame.is_available() {
+                frame = to_window_depth(frame);
+                if dark_frame.add(&frame) {
+                    self.result_tx
+                        .send(ThreadResult {
+                            id: ThreadId::DarkFrame,
+                            result: Ok(()),
+                        })
+                        .ok();
+                }
+                frame = dark_frame.subtract(frame);
+            }
+            drop(dark_frame);
+            // Divided out after the dark frame, which does not scale with the light
+            let mut flat_field = self.flat_field.lock().unwrap();
+            if flat_field.is_capturing() || flat_field.is_available() {
+                frame = to_window_depth(frame);
+                if flat_field.add(&frame) {
+                    self.send_flat_field_result(Ok(()));
+                }
+                frame = flat_field.correct(frame);
+            }
+            drop(flat_field);
+            // Repair defects, their coordinates refer to the unflipped frame
+            if !cfg.defect_map.is_empty() {
+                // Neighbors of the same color in undemosaiced frames
+                let distance = if cfg.bayer_pattern.is_some() { 2 } else { 1 };
+                frame = match to_window_depth(frame) {
+                    DynamicImage::ImageRgb16(mut image) => {
+                        cfg.defect_map.repair(&mut image, distance);
+                        DynamicImage::ImageRgb16(image)
+                    }
+                    DynamicImage::ImageRgb8(mut image) => {
+                        cfg.defect_map.repair(&mut image, distance);
+                        DynamicImage::ImageRgb8(image)
+                    }
+                    image => image,
+                };
+            }
+            // Flip
+            if cfg.flip {
+                frame = frame.fliph();
+            }
+            let forward = !self.paused.load(Ordering::Relaxed)
+                && (!cfg.single_shot
+                    || self
+                        .captures
+                        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| n.checked_sub(1))
+                        .is_ok());
+            if !forward {
+                return self.send_preview(frame);
+            }
+            let extract = |window: &SpectrumWindow| {
+                // Channels of the window pixels, depending on the window position and flip
+                let bayer_layout = cfg.bayer_pattern.map(|pattern| {
+                    std::array::from_fn(|wy| {
+                        std::array::from_fn(|wx| {
+                            let x = window.offset.x as u32 + wx as u32;
+                            let x = if cfg.flip {
+                                frame.width().saturating_sub(x + 1)
+                            } else {
+                                x
+                            };
+                            pattern.channel(x, window.offset.y as u32 + wy as u32)
+                        })
+                    })
+                });
+                // Resampling would mix the color planes of undemosaiced frames
+                let window = match bayer_layout {
+                    Some(_) => SpectrumWindow {
+                        angle: 0.,
+                        ..*window
+                    },
+                    None => *window,
+                };
+                let image = extract_window(&frame, &window);
+                let bayer_layout = bayer_layout.map(|layout| {
+                    orient_bayer_layout(
+                        layout,
+                        image.width(),
+                        image.height(),
+                        cfg.orientation,
+                        cfg.rotate_180,
+                    )
+                });
+                let mut window = WindowImage::new(
+                    orient_window(image, cfg.orientation, cfg.rotate_180),
+                    cfg.column_aggregation,
+                    bayer_layout,
+                );
+                window.bracket = bracket;
+                window.long_exposure = cfg.long_exposure.map(Duration::from_secs_f32);
+                Timestamped {
+                    start,
+                    end,
+                    sequence: Some(sequence),
+                    frame_metadata,
+                    saturation: Some(window.saturation()),
+                    channel_peaks: Some(window.channel_peaks()),
+                    value: window,
+                }
+            };
+            // Brackets are merged by the spectrum calculator instead
+            let accumulate = |accumulator: &mut WindowAccumulator, window| {
+                if cfg.accumulate_frames > 1 && bracket.is_none() {
+                    accumulator.add(window, cfg.accumulate_frames, cfg.accumulation_mode)
+                } else {
+                    Some(window)
+                }
+            };
+            let [first_accumulator, second_accumulator, reference_accumulator] =
+                &mut self.accumulators;
+            // The second order and reference beam are optional, their receivers may be gone
+            if let (Some(tx), Some(window)) = (&self.second_order_tx, &cfg.second_order_window) {
+                if let Some(window) = accumulate(second_accumulator, extract(window)) {
+                    tx.send(window).ok();
+                }
+            }
+            if let (Some(tx), Some(window)) = (&self.reference_beam_tx, &cfg.reference_beam_window)
+            {
+                if let Some(window) = accumulate(reference_accumulator, extract(window)) {
+                    tx.send(window).ok();
+                }
+            }
+            if let Some(window) = accumulate(first_accumulator, extract(&cfg.window)) {
+                if self.window_tx.send(window).is_err() {
+                    return false;
+                }
+            }
+        }
+        self.send_preview(frame)
+    }
+
+    /// In low power mode frames within [`LOW_POWER_INTERVAL`] of the last preview are dropped
+    fn send_preview(&mut self, frame: DynamicImage) -> bool {
+        if self.inner_config.as_ref().is_some_and(|cfg| cfg.low_power) {
+            let now = Instant::now();
+            if self
+                .last_preview
+                .is_some_and(|last| now.duration_since(last) < LOW_POWER_INTERVAL)
+            {
+                return true;
+            }
+            self.last_preview = Some(now);
+        }
+        self.frame_tx.send(frame.into_rgb8()).is_ok()
+    }
+}
+
+#[allow(clippy::type_complexity)]
+type SharedControls = Arc<Mutex<Option<Vec<(KnownCameraControl, ControlValueSetter)>>>>;
+type SharedHdrConfig = Arc<Mutex<Option<HdrConfig>>>;
+type SharedReconnectConfig = Arc<Mutex<Option<ReconnectConfig>>>;
+
+/// Outcome of reopening a failed camera stream
+enum Reconnect<T> {
+    Connected(T),
+    Failed,
+    Exit,
+}
+
 pub struct CameraThread {
     frame_tx: Sender<ImageBuffer<Rgb<u8>, Vec<u8>>>,
-    window_tx: Sender<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    window_tx: Sender<Timestamped<WindowImage>>,
+    second_order_tx: Option<Sender<Timestamped<WindowImage>>>,
+    reference_beam_tx: Option<Sender<Timestamped<WindowImage>>>,
+    hot_pixel_tx: Option<Sender<Vec<(u32, u32)>>>,
     config_rx: Receiver<CameraEvent>,
     result_tx: Sender<ThreadResult>,
 }
@@ -54,154 +749,1248 @@ pub struct CameraThread {
 impl CameraThread {
     pub fn new(
         frame_tx: Sender<ImageBuffer<Rgb<u8>, Vec<u8>>>,
-        window_tx: Sender<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+        window_tx: Sender<Timestamped<WindowImage>>,
         config_rx: Receiver<CameraEvent>,
         result_tx: Sender<ThreadResult>,
     ) -> Self {
         Self {
             frame_tx,
             window_tx,
+            second_order_tx: None,
+            reference_beam_tx: None,
+            hot_pixel_tx: None,
             config_rx,
             result_tx,
         }
     }
 
-    pub fn run(&mut self) -> ! {
+    /// Also send the second order window if one is configured
+    pub fn with_second_order(mut self, second_order_tx: Sender<Timestamped<WindowImage>>) -> Self {
+        self.second_order_tx = Some(second_order_tx);
+        self
+    }
+
+    /// Also send the reference beam window if one is configured
+    pub fn with_reference_beam(
+        mut self,
+        reference_beam_tx: Sender<Timestamped<WindowImage>>,
+    ) -> Self {
+        self.reference_beam_tx = Some(reference_beam_tx);
+        self
+    }
+
+    /// Answer hot pixel detection requests
+    pub fn with_hot_pixels(mut self, hot_pixel_tx: Sender<Vec<(u32, u32)>>) -> Self {
+        self.hot_pixel_tx = Some(hot_pixel_tx);
+        self
+    }
+
+    /// Handle events until the sending side is dropped
+    pub fn run(&mut self) {
         let (exit_tx, exit_rx) = flume::bounded(0);
         let config: Arc<Mutex<Option<ImageConfig>>> = Arc::new(Mutex::new(None));
-        #[allow(clippy::type_complexity)]
-        let controls: Arc<Mutex<Option<Vec<(KnownCameraControl, ControlValueSetter)>>>> =
-            Arc::new(Mutex::new(None));
+        let controls: SharedControls = Arc::new(Mutex::new(None));
+        let watched_controls: SharedControls = Arc::new(Mutex::new(None));
+        let hdr_config: SharedHdrConfig = Arc::new(Mutex::new(None));
+        let reconnect_config: SharedReconnectConfig = Arc::new(Mutex::new(None));
+        let dark_frame = Arc::new(Mutex::new(DarkFrame::default()));
+        let flat_field = Arc::new(Mutex::new(FlatField::default()));
+        let frame_recorder: Arc<Mutex<Option<FrameRecorder>>> = Arc::new(Mutex::new(None));
+        let paused = Arc::new(AtomicBool::new(false));
+        let captures = Arc::new(AtomicUsize::new(0));
         let mut join_handle = None;
-        loop {
-            if let Ok(event) = self.config_rx.recv() {
-                match event {
-                    CameraEvent::StartStream { id, format } => {
-                        let config = Arc::clone(&config);
-                        let controls = Arc::clone(&controls);
-
-                        let frame_tx = self.frame_tx.clone();
-                        let window_tx = self.window_tx.clone();
-                        let result_tx = self.result_tx.clone();
-                        let exit_rx = exit_rx.clone();
-                        let hdl = std::thread::spawn(move || {
-                            let mut camera = match CallbackCamera::new(
-                                id,
-                                RequestedFormat::new::<RgbFormat>(
-                                    nokhwa::utils::RequestedFormatType::Exact(format),
-                                ),
-                                |_| {},
-                            ) {
-                                Ok(camera) => camera,
-                                Err(e) => {
-                                    log::error!("{:?}", e);
-                                    result_tx
-                                        .send(ThreadResult {
-                                            id: ThreadId::Camera,
-                                            result: Err("Could not initialize camera".into()),
-                                        })
-                                        .unwrap();
-                                    return;
-                                }
-                            };
-
-                            if let Err(e) = camera.open_stream() {
-                                log::error!("{:?}", e);
-                                result_tx
-                                    .send(ThreadResult {
-                                        id: ThreadId::Camera,
-                                        result: Err("Could not open stream".into()),
-                                    })
-                                    .unwrap();
-                                return;
-                            };
-
-                            result_tx
+        while let Ok(event) = self.config_rx.recv() {
+            if matches!(
+                event,
+                CameraEvent::StartStream { .. }
+                    | CameraEvent::StartScreenCapture(_)
+                    | CameraEvent::StartImageFile(_)
+                    | CameraEvent::StartNetworkStream(_)
+                    | CameraEvent::StartFile { .. }
+            ) {
+                paused.store(false, Ordering::Relaxed);
+                captures.store(0, Ordering::Relaxed);
+            }
+            let context = StreamContext {
+                config: Arc::clone(&config),
+                inner_config: None,
+                frame_tx: self.frame_tx.clone(),
+                window_tx: self.window_tx.clone(),
+                second_order_tx: self.second_order_tx.clone(),
+                reference_beam_tx: self.reference_beam_tx.clone(),
+                result_tx: self.result_tx.clone(),
+                exit_rx: exit_rx.clone(),
+                frame_rate_limiter: FrameRateLimiter::default(),
+                accumulators: Default::default(),
+                dark_frame: Arc::clone(&dark_frame),
+                flat_field: Arc::clone(&flat_field),
+                frame_recorder: Arc::clone(&frame_recorder),
+                paused: Arc::clone(&paused),
+                captures: Arc::clone(&captures),
+                sequence: 0,
+                last_preview: None,
+            };
+            match event {
+                CameraEvent::StartStream {
+                    id,
+                    format,
+                    driver_timestamps,
+                    y16,
+                } => {
+                    let controls = Arc::clone(&controls);
+                    let watched_controls = Arc::clone(&watched_controls);
+                    let hdr_config = Arc::clone(&hdr_config);
+                    let reconnect_config = Arc::clone(&reconnect_config);
+                    join_handle = Some(std::thread::spawn(move || {
+                        Self::run_camera(
+                            context,
+                            id,
+                            format,
+                            driver_timestamps,
+                            y16,
+                            controls,
+                            watched_controls,
+                            hdr_config,
+                            reconnect_config,
+                        )
+                    }));
+                }
+                CameraEvent::StartScreenCapture(screen_config) => {
+                    join_handle = Some(std::thread::spawn(move || {
+                        Self::run_screen_capture(context, screen_config)
+                    }));
+                }
+                CameraEvent::StartImageFile(image_config) => {
+                    join_handle = Some(std::thread::spawn(move || {
+                        Self::run_image_files(context, image_config)
+                    }));
+                }
+                CameraEvent::StartNetworkStream(stream_config) => {
+                    join_handle = Some(std::thread::spawn(move || {
+                        Self::run_network_stream(context, stream_config)
+                    }));
+                }
+                CameraEvent::StartFile { path, looping } => {
+                    join_handle = Some(std::thread::spawn(move || {
+                        Self::run_video_file(context, &path, looping)
+                    }));
+                }
+                CameraEvent::StopStream => {
+                    if let Some(hdl) = join_handle.take() {
+                        exit_tx.send(Exit {}).ok();
+                        hdl.join().ok();
+                    }
+                }
+                CameraEvent::Config(cfg) => {
+                    *config.lock().unwrap() = Some(cfg);
+                }
+                CameraEvent::Controls(ctrls) => {
+                    *controls.lock().unwrap() = Some(ctrls);
+                }
+                CameraEvent::WatchControls(ctrls) => {
+                    *watched_controls.lock().unwrap() = Some(ctrls);
+                }
+                CameraEvent::Bracketing(cfg) => {
+                    *hdr_config.lock().unwrap() = Some(cfg);
+                }
+                CameraEvent::Reconnect(cfg) => {
+                    *reconnect_config.lock().unwrap() = Some(cfg);
+                }
+                CameraEvent::CaptureDarkFrame(frames) => {
+                    dark_frame.lock().unwrap().capture(frames);
+                }
+                CameraEvent::ClearDarkFrame => {
+                    dark_frame.lock().unwrap().clear();
+                }
+                CameraEvent::DetectHotPixels(threshold) => {
+                    let dark_frame = dark_frame.lock().unwrap();
+                    if !dark_frame.is_available() {
+                        self.result_tx
+                            .send(ThreadResult {
+                                id: ThreadId::DarkFrame,
+                                result: Err("Capture a dark frame first".to_string()),
+                            })
+                            .ok();
+                    } else if let Some(hot_pixel_tx) = &self.hot_pixel_tx {
+                        hot_pixel_tx.send(dark_frame.hot_pixels(threshold)).ok();
+                    }
+                }
+                CameraEvent::CaptureFlatField(frames) => {
+                    flat_field.lock().unwrap().capture(frames);
+                }
+                CameraEvent::ClearFlatField => {
+                    flat_field.lock().unwrap().clear();
+                }
+                CameraEvent::LoadFlatField(path) => {
+                    let result = flat_field.lock().unwrap().load(&path);
+                    context.send_flat_field_result(result);
+                }
+                CameraEvent::SaveFlatField(path) => {
+                    let result = flat_field.lock().unwrap().save(&path);
+                    context.send_flat_field_result(result);
+                }
+                CameraEvent::StartFrameRecording(recording_config) => {
+                    let mut frame_recorder = frame_recorder.lock().unwrap();
+                    if let Some(recorder) = frame_recorder.take() {
+                        recorder.finish();
+                    }
+                    match FrameRecorder::start(&recording_config, self.result_tx.clone()) {
+                        Ok(recorder) => *frame_recorder = Some(recorder),
+                        Err(e) => {
+                            self.result_tx
                                 .send(ThreadResult {
-                                    id: ThreadId::Camera,
-                                    result: Ok(()),
+                                    id: ThreadId::FrameRecorder,
+                                    result: Err(e),
                                 })
-                                .unwrap();
+                                .ok();
+                        }
+                    }
+                }
+                CameraEvent::StopFrameRecording => {
+                    let recorder = frame_recorder.lock().unwrap().take();
+                    let dropped = recorder.map_or(0, FrameRecorder::finish);
+                    if dropped > 0 {
+                        self.result_tx
+                            .send(ThreadResult {
+                                id: ThreadId::FrameRecorder,
+                                result: Err(format!(
+                                    "{dropped} frames were dropped while recording"
+                                )),
+                            })
+                            .ok();
+                    }
+                }
+                CameraEvent::Pause(pause) => {
+                    paused.store(pause, Ordering::Relaxed);
+                }
+                CameraEvent::Capture(frames) => {
+                    captures.store(frames, Ordering::Relaxed);
+                }
+            }
+        }
+        if let Some(hdl) = join_handle.take() {
+            exit_tx.send(Exit {}).ok();
+            hdl.join().ok();
+        }
+        // Write the queued frames before exiting
+        let recorder = frame_recorder.lock().unwrap().take();
+        if let Some(recorder) = recorder {
+            recorder.finish();
+        }
+    }
 
-                            let mut inner_config = None;
+    /// Open the camera and its stream
+    ///
+    /// With driver timestamps or Y16 the stream is read from V4L2.
+    fn open_camera(
+        id: &CameraIndex,
+        format: CameraFormat,
+        driver_timestamps: bool,
+        y16: bool,
+    ) -> Result<(CallbackCamera, Option<DriverCapture>), String> {
+        let mut camera = CallbackCamera::new(id.clone(), stream_request(format, y16), |_| {})
+            .map_err(|e| {
+                log::error!("{:?}", e);
+                "Could not initialize camera".to_string()
+            })?;
+        if driver_timestamps || y16 {
+            return Ok((camera, Some(DriverCapture::open(id, format, y16)?)));
+        }
+        camera.open_stream().map_err(|e| {
+            log::error!("{:?}", e);
+            "Could not open stream".to_string()
+        })?;
+        Ok((camera, None))
+    }
 
-                            loop {
-                                // Check exit request
-                                if exit_rx.try_recv().is_ok() {
-                                    return;
-                                }
-                                // Check for new config
-                                if let Some(cfg) = config.lock().unwrap().take() {
-                                    inner_config = Some(cfg);
-                                }
-                                // Check for new controls
-                                if let Some(controls) = controls.lock().unwrap().take() {
-                                    for (control, setter) in &controls {
-                                        let control: &KnownCameraControl = control;
-                                        if let Err(e) =
-                                            camera.set_camera_control(*control, setter.clone())
-                                        {
-                                            log::error!("{:?}", e);
-                                        }
-                                    }
-                                }
-                                // Get frame
-                                let mut frame = match camera
-                                    .poll_frame()
-                                    .and_then(|frame| frame.decode_image::<RgbFormat>())
+    /// Release the failed stream and reopen it with `open` at increasing delays.
+    ///
+    /// The stale handle is dropped before the first attempt, it would keep the device busy
+    /// and a replugged camera could not be opened again.
+    fn reconnect<T>(
+        stale: T,
+        result_tx: &Sender<ThreadResult>,
+        exit_rx: &Receiver<Exit>,
+        config: &ReconnectConfig,
+        mut open: impl FnMut() -> Result<T, String>,
+    ) -> Reconnect<T> {
+        drop(stale);
+        let report = |result| {
+            result_tx
+                .send(ThreadResult {
+                    id: ThreadId::CameraReconnect,
+                    result,
+                })
+                .ok();
+        };
+        let mut attempt = 1;
+        while !config.attempts_exhausted(attempt) {
+            report(Err(format!(
+                "Camera stream failed, reconnect attempt {} in {:.0} s",
+                attempt,
+                config.delay(attempt).as_secs_f32()
+            )));
+            if exit_rx.recv_timeout(config.delay(attempt)).is_ok() {
+                return Reconnect::Exit;
+            }
+            match open() {
+                Ok(opened) => {
+                    log::info!("Camera reconnected after {} attempts", attempt);
+                    report(Ok(()));
+                    return Reconnect::Connected(opened);
+                }
+                Err(e) => log::warn!("Reconnect attempt {} failed: {}", attempt, e),
+            }
+            attempt += 1;
+        }
+        Reconnect::Failed
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn run_camera(
+        mut context: StreamContext,
+        id: CameraIndex,
+        format: CameraFormat,
+        driver_timestamps: bool,
+        y16: bool,
+        controls: SharedControls,
+        watched_controls: SharedControls,
+        hdr_config: SharedHdrConfig,
+        reconnect_config: SharedReconnectConfig,
+    ) {
+        let (mut camera, mut driver_capture) =
+            match Self::open_camera(&id, format, driver_timestamps, y16) {
+                Ok(opened) => opened,
+                Err(e) => {
+                    context.send_result(Err(e));
+                    return;
+                }
+            };
+
+        context.send_result(Ok(()));
+
+        let mut reconnect = None;
+        // Controls to apply again after reconnecting
+        let mut applied_controls: Vec<(KnownCameraControl, ControlValueSetter)> = vec![];
+        let mut inner_watched_controls = vec![];
+        let mut last_watch_check = Instant::now();
+        let mut exposure = None;
+        let mut last_exposure_check = Instant::now();
+        // Frames received since the stream was opened, for the frame metadata
+        let mut frame_number = 0u64;
+        let mut bracketing: Option<ExposureBracketing> = None;
+        // Exposure time to restore once bracketing stops
+        let mut manual_exposure_time = None;
+        let set_exposure_time = |camera: &mut CallbackCamera, exposure_time| {
+            if let Err(e) = camera.set_camera_control(
+                KnownCameraControl::Exposure,
+                ControlValueSetter::Integer(exposure_time),
+            ) {
+                log::error!("{:?}", e);
+            }
+        };
+
+        loop {
+            // Check exit request
+            if context.exit_requested() {
+                return;
+            }
+            // Check for new config
+            context.update_config();
+            // Check for new controls
+            if let Some(controls) = controls.lock().unwrap().take() {
+                for (control, setter) in controls {
+                    if let Err(e) = camera.set_camera_control(control, setter.clone()) {
+                        log::error!("{:?}", e);
+                    }
+                    applied_controls.retain(|(c, _)| *c != control);
+                    applied_controls.push((control, setter));
+                }
+            }
+            if let Some(cfg) = reconnect_config.lock().unwrap().take() {
+                reconnect = Some(cfg);
+            }
+            // Check for new watched controls
+            if let Some(watched) = watched_controls.lock().unwrap().take() {
+                inner_watched_controls = watched
+                    .into_iter()
+                    .map(|(control, setter)| (control, setter, false))
+                    .collect();
+            }
+            // Check for new bracketing config
+            if let Some(cfg) = hdr_config.lock().unwrap().take() {
+                if bracketing.is_none() {
+                    manual_exposure_time = match camera
+                        .camera_control(KnownCameraControl::Exposure)
+                        .map(|c| c.value())
+                    {
+                        Ok(ControlValueSetter::Integer(v)) => Some(v),
+                        _ => None,
+                    };
+                }
+                bracketing = ExposureBracketing::new(cfg);
+                match (&bracketing, manual_exposure_time) {
+                    (Some(b), _) => set_exposure_time(&mut camera, b.exposure_time()),
+                    (None, Some(v)) => set_exposure_time(&mut camera, v),
+                    (None, None) => {}
+                }
+            }
+            // Read back watched controls
+            if !inner_watched_controls.is_empty()
+                && last_watch_check.elapsed() >= WATCH_CONTROLS_INTERVAL
+            {
+                last_watch_check = Instant::now();
+                for (control, expected, reported) in &mut inner_watched_controls {
+                    let Ok(current) = camera.camera_control(*control) else {
+                        continue;
+                    };
+                    if current.value() == *expected {
+                        *reported = false;
+                    } else if !*reported {
+                        *reported = true;
+                        log::warn!(
+                            "Control {} changed to {:?}",
+                            current.name(),
+                            current.value()
+                        );
+                        // The GUI is gone when nobody receives the warning
+                        if context
+                            .result_tx
+                            .send(ThreadResult {
+                                id: ThreadId::CameraControls,
+                                result: Err(format!(
+                                    "Driver re-enabled automatic control \"{}\"",
+                                    current.name()
+                                )),
+                            })
+                            .is_err()
+                        {
+                            return;
+                        }
+                    }
+                }
+            }
+            // Read back exposure settings for the frame metadata
+            if exposure.is_none() || last_exposure_check.elapsed() >= EXPOSURE_READBACK_INTERVAL {
+                last_exposure_check = Instant::now();
+                let read = |control| match camera.camera_control(control).map(|c| c.value()) {
+                    Ok(ControlValueSetter::Integer(v)) => Some(v),
+                    _ => None,
+                };
+                exposure = Some(Exposure {
+                    exposure_time: read(KnownCameraControl::Exposure),
+                    gain: read(KnownCameraControl::Gain),
+                });
+            }
+            // Get frame
+            let start = SystemTime::now();
+            let polled = match driver_capture.as_mut() {
+                Some(capture) => capture.frame(),
+                None => camera
+                    .poll_frame()
+                    .map(|buffer| (buffer, None))
+                    .map_err(|e| format!("{:?}", e)),
+            };
+            let (buffer, capture_time) = match polled {
+                Ok(polled) => polled,
+                Err(e) => {
+                    log::error!("{}", e);
+                    let outcome = match reconnect.as_ref().filter(|r| r.active) {
+                        Some(cfg) => {
+                            if let Err(e) = camera.stop_stream() {
+                                log::warn!("{:?}", e);
+                            }
+                            // The camera and the mapped buffers keep the device busy
+                            Self::reconnect(
+                                (camera, driver_capture.take()),
+                                &context.result_tx,
+                                &context.exit_rx,
+                                cfg,
+                                || Self::open_camera(&id, format, driver_timestamps, y16),
+                            )
+                        }
+                        None => Reconnect::Failed,
+                    };
+                    match outcome {
+                        Reconnect::Connected((new_camera, new_capture)) => {
+                            camera = new_camera;
+                            driver_capture = new_capture;
+                            for (control, setter) in &applied_controls {
+                                if let Err(e) = camera.set_camera_control(*control, setter.clone())
                                 {
-                                    Ok(frame) => frame,
-                                    Err(e) => {
-                                        log::error!("{:?}", e);
-                                        result_tx
-                                            .send(ThreadResult {
-                                                id: ThreadId::Camera,
-                                                result: Err("Could not poll for frame".into()),
-                                            })
-                                            .unwrap();
-                                        return;
-                                    }
-                                };
-
-                                if let Some(cfg) = &inner_config {
-                                    // Flip
-                                    if cfg.flip {
-                                        frame = DynamicImage::ImageRgb8(frame).fliph().into_rgb8();
-                                    }
-                                    // Extract window
-                                    let window = frame
-                                        .view(
-                                            cfg.window.offset.x as u32,
-                                            cfg.window.offset.y as u32,
-                                            cfg.window.size.x as u32,
-                                            cfg.window.size.y as u32,
-                                        )
-                                        .to_image();
-                                    if window_tx.send(window).is_err() {
-                                        return;
-                                    };
+                                    log::error!("{:?}", e);
                                 }
-                                if frame_tx.send(frame).is_err() {
-                                    return;
-                                };
                             }
-                        });
-                        join_handle = Some(hdl);
-                    }
-                    CameraEvent::StopStream => {
-                        if let Some(hdl) = join_handle.take() {
-                            exit_tx.send(Exit {}).ok();
-                            hdl.join().ok();
+                            exposure = None;
+                            if let Some(b) = bracketing.as_ref() {
+                                set_exposure_time(&mut camera, b.exposure_time());
+                            }
+                            continue;
+                        }
+                        Reconnect::Failed => {
+                            context.send_result(Err("Could not poll for frame".into()));
+                            return;
                         }
+                        Reconnect::Exit => return,
                     }
-                    CameraEvent::Config(cfg) => {
-                        *config.lock().unwrap() = Some(cfg);
+                }
+            };
+            frame_number += 1;
+            // Drop frames that are still exposed with the previous bracketing exposure time
+            let bracket = match bracketing.as_mut() {
+                Some(b) => match b.next_frame() {
+                    Some(bracket) => Some(bracket),
+                    None => continue,
+                },
+                None => None,
+            };
+            // Drop surplus frames before the expensive decoding
+            if !context.frame_due() {
+                continue;
+            }
+            let fast_jpeg = context
+                .inner_config
+                .as_ref()
+                .is_some_and(|cfg| cfg.fast_jpeg_decode);
+            let decoded = if y16 {
+                decode_y16(&buffer)
+            } else {
+                decode_frame(&buffer, fast_jpeg)
+            };
+            let frame = match decoded {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::error!("{}", e);
+                    context.send_result(Err("Could not decode frame".into()));
+                    return;
+                }
+            };
+
+            let frame_metadata = FrameMetadata {
+                exposure: match bracket {
+                    Some(bracket) => Exposure {
+                        exposure_time: Some(bracket.exposure_time),
+                        ..exposure.unwrap_or_default()
+                    },
+                    None => exposure.unwrap_or_default(),
+                },
+                frame_number: Some(frame_number),
+                capture_time,
+            };
+            if !context.send_frame(
+                frame,
+                capture_time.unwrap_or(start),
+                SystemTime::now(),
+                Some(frame_metadata),
+                bracket,
+            ) {
+                return;
+            }
+            if let Some(b) = bracketing.as_mut() {
+                let exposure_time = b.advance();
+                set_exposure_time(&mut camera, exposure_time);
+            }
+        }
+    }
+
+    fn run_screen_capture(mut context: StreamContext, screen_config: ScreenCaptureConfig) {
+        let command = screen_config.expand_command();
+        let frame_interval = Duration::from_secs_f32(1. / screen_config.max_fps.max(0.1));
+
+        context.send_result(Ok(()));
+
+        loop {
+            let frame_start = Instant::now();
+            if context.exit_requested() {
+                return;
+            }
+            context.update_config();
+            // Dropped frames are not captured at all, which saves running the command
+            if !context.frame_due() {
+                std::thread::sleep(frame_interval.saturating_sub(frame_start.elapsed()));
+                continue;
+            }
+
+            let start = SystemTime::now();
+            let frame = Command::new("sh")
+                .arg("-c")
+                .arg(&command)
+                .output()
+                .map_err(|e| e.to_string())
+                .and_then(|output| {
+                    if output.status.success() {
+                        image::load_from_memory(&output.stdout).map_err(|e| e.to_string())
+                    } else {
+                        Err(String::from_utf8_lossy(&output.stderr).to_string())
                     }
-                    CameraEvent::Controls(ctrls) => {
-                        *controls.lock().unwrap() = Some(ctrls);
+                });
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    log::error!("{}", e);
+                    context.send_result(Err("Could not capture screen region".into()));
+                    return;
+                }
+            };
+
+            if !context.send_frame(frame, start, SystemTime::now(), None, None) {
+                return;
+            }
+            std::thread::sleep(frame_interval.saturating_sub(frame_start.elapsed()));
+        }
+    }
+
+    fn run_image_files(mut context: StreamContext, image_config: ImageFileConfig) {
+        let paths = match image_file_paths(Path::new(&image_config.path)) {
+            Ok(paths) if !paths.is_empty() => paths,
+            Ok(_) => {
+                context.send_result(Err("No images found".into()));
+                return;
+            }
+            Err(e) => {
+                log::error!("{}", e);
+                context.send_result(Err("Could not read image path".into()));
+                return;
+            }
+        };
+        let frame_interval = Duration::from_secs_f32(1. / image_config.max_fps.max(0.1));
+
+        context.send_result(Ok(()));
+
+        // A single image is only decoded once
+        let mut cached: Option<DynamicImage> = None;
+        let mut size = None;
+        for path in paths.iter().cycle() {
+            let frame_start = Instant::now();
+            if context.exit_requested() {
+                return;
+            }
+            context.update_config();
+
+            let start = SystemTime::now();
+            let frame = match cached.as_ref() {
+                Some(frame) => frame.clone(),
+                None => match image::open(path) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        log::error!("{}", e);
+                        context.send_result(Err(format!("Could not load {}", path.display())));
+                        return;
                     }
+                },
+            };
+            if *size.get_or_insert(frame.dimensions()) != frame.dimensions() {
+                context.send_result(Err(format!(
+                    "{} differs in size from the first image",
+                    path.display()
+                )));
+                return;
+            }
+            if paths.len() == 1 {
+                cached = Some(frame.clone());
+            }
+
+            if context.frame_due()
+                && !context.send_frame(frame, start, SystemTime::now(), None, None)
+            {
+                return;
+            }
+            std::thread::sleep(frame_interval.saturating_sub(frame_start.elapsed()));
+        }
+    }
+
+    fn run_video_file(context: StreamContext, path: &str, looping: bool) {
+        let mut args = vec!["-re"];
+        if looping {
+            args.extend(["-stream_loop", "-1"]);
+        }
+        args.extend(["-i", path]);
+        Self::run_ffmpeg(context, &args, "video file");
+    }
+
+    fn run_network_stream(context: StreamContext, stream_config: NetworkStreamConfig) {
+        match stream_config.backend {
+            StreamBackend::Ffmpeg => {
+                let args = network_stream_args(&stream_config);
+                let args: Vec<&str> = args.iter().map(String::as_str).collect();
+                Self::run_ffmpeg(context, &args, "network stream");
+            }
+            StreamBackend::GStreamer => {
+                let mut command = Command::new("gst-launch-1.0");
+                command.args(gstreamer_args(&stream_config));
+                Self::run_ppm_pipe(context, command, "network stream");
+            }
+        }
+    }
+
+    /// Decode any ffmpeg input given by `input_args` to PPM frames
+    fn run_ffmpeg(context: StreamContext, input_args: &[&str], source: &str) {
+        let mut command = Command::new("ffmpeg");
+        command.args(["-v", "error"]).args(input_args).args([
+            "-f",
+            "image2pipe",
+            "-vcodec",
+            "ppm",
+            "-",
+        ]);
+        Self::run_ppm_pipe(context, command, source);
+    }
+
+    /// Feed the PPM frames a decoder `command` writes to stdout through the pipeline
+    fn run_ppm_pipe(mut context: StreamContext, mut command: Command, source: &str) {
+        let child = command.stdin(Stdio::null()).stdout(Stdio::piped()).spawn();
+        let mut child = match child {
+            Ok(child) => child,
+            Err(e) => {
+                log::error!("{}", e);
+                context.send_result(Err(format!(
+                    "Could not start {}",
+                    command.get_program().to_string_lossy()
+                )));
+                return;
+            }
+        };
+        let mut reader = BufReader::new(child.stdout.take().unwrap());
+
+        context.send_result(Ok(()));
+
+        loop {
+            if context.exit_requested() {
+                break;
+            }
+            context.update_config();
+
+            let start = SystemTime::now();
+            let frame = match read_ppm_frame(&mut reader) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => {
+                    context.send_result(Err(format!("End of {source}")));
+                    break;
+                }
+                Err(e) => {
+                    log::error!("{}", e);
+                    context.send_result(Err(format!("Could not decode {source}")));
+                    break;
                 }
+            };
+            if !context.frame_due() {
+                continue;
+            }
+            if !context.send_frame(frame, start, SystemTime::now(), None, None) {
+                break;
             }
         }
+        child.kill().ok();
+        child.wait().ok();
+    }
+}
+
+/// The image itself or all images of a directory sorted by name
+pub fn image_file_paths(path: &Path) -> Result<Vec<PathBuf>, String> {
+    if !path.is_dir() {
+        return Ok(vec![path.to_path_buf()]);
+    }
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(path)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && image::ImageFormat::from_path(p).is_ok())
+        .collect();
+    paths.sort();
+    Ok(paths)
+}
+
+/// Input arguments of ffmpeg and ffprobe for a network stream
+///
+/// `-rw_timeout` gives up after 5 s without data, so that stopping the stream does not hang. Unlike
+/// `-timeout` it does not put RTSP into listen mode on ffmpeg before 5.0.
+pub fn network_stream_args(stream_config: &NetworkStreamConfig) -> Vec<String> {
+    let mut args = vec![];
+    if stream_config.url.starts_with("rtsp://") && stream_config.rtsp_over_tcp {
+        args.extend(["-rtsp_transport", "tcp"]);
+    }
+    args.extend(["-rw_timeout", "5000000", "-i", &stream_config.url]);
+    args.into_iter().map(str::to_string).collect()
+}
+
+/// Arguments of gst-launch-1.0 for a network stream, which writes PPM frames to stdout
+///
+/// `-q` keeps the state messages of gst-launch-1.0 out of the frame stream.
+pub fn gstreamer_args(stream_config: &NetworkStreamConfig) -> Vec<String> {
+    vec![
+        "-q".to_string(),
+        format!(
+            "{} ! videoconvert ! video/x-raw,format=RGB ! pnmenc ! fdsink fd=1",
+            stream_config.expand_gstreamer_source()
+        ),
+    ]
+}
+
+/// Frame size of a GStreamer network stream from the header of its first frame
+///
+/// There is no ffprobe counterpart for a pipeline, so it is started and killed after the first
+/// frame or the timeout. This blocks, so call it from a worker thread.
+pub fn probe_gstreamer_size(args: &[String], timeout: Duration) -> Result<(u32, u32), String> {
+    let mut child = Command::new("gst-launch-1.0")
+        .args(args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|e| format!("Could not run gst-launch-1.0: {e}"))?;
+    let mut reader = BufReader::new(child.stdout.take().unwrap());
+    let (size_tx, size_rx) = flume::bounded(1);
+    // Killing the pipeline closes stdout and ends a reader that still waits for a frame
+    std::thread::spawn(move || {
+        let size = read_ppm_frame(&mut reader).and_then(|frame| {
+            frame
+                .map(|frame| frame.dimensions())
+                .ok_or_else(|| "Pipeline ended without a frame".to_string())
+        });
+        size_tx.send(size).ok();
+    });
+    let size = size_rx
+        .recv_timeout(timeout)
+        .unwrap_or_else(|_| Err(format!("No frame after {} s", timeout.as_secs())));
+    child.kill().ok();
+    child.wait().ok();
+    size
+}
+
+/// Frame size of the first video stream of an ffmpeg input according to ffprobe
+///
+/// ffprobe is killed if it does not finish within the timeout. This blocks, so call it from a
+/// worker thread.
+pub fn probe_video_size(input_args: &[String], timeout: Duration) -> Result<(u32, u32), String> {
+    let mut child = Command::new("ffprobe")
+        .args([
+            "-v",
+            "error",
+            "-select_streams",
+            "v:0",
+            "-show_entries",
+            "stream=width,height",
+            "-of",
+            "csv=p=0",
+        ])
+        .args(input_args)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Could not run ffprobe: {e}"))?;
+    let deadline = Instant::now() + timeout;
+    while child.try_wait().map_err(|e| e.to_string())?.is_none() {
+        if Instant::now() >= deadline {
+            child.kill().ok();
+            child.wait().ok();
+            return Err(format!("ffprobe timed out after {} s", timeout.as_secs()));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+    }
+    // The output of a frame size is far below the pipe buffer, so it was not blocked
+    let output = child.wait_with_output().map_err(|e| e.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).trim().to_string());
+    }
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let (width, height) = stdout
+        .trim()
+        .split_once(',')
+        .ok_or_else(|| "No video stream found".to_string())?;
+    match (width.parse(), height.parse()) {
+        (Ok(width), Ok(height)) => Ok((width, height)),
+        _ => Err(format!("Invalid frame size {}", stdout.trim())),
+    }
+}
+
+/// Read the next binary PPM image of a stream, `None` at the end of the stream
+///
+/// Maximum values above 255 are read as 16 bit, which ffmpeg writes for high bit depth inputs.
+fn read_ppm_frame(reader: &mut impl BufRead) -> Result<Option<DynamicImage>, String> {
+    if reader.fill_buf().map_err(|e| e.to_string())?.is_empty() {
+        return Ok(None);
+    }
+    // Magic, width, height and maximum value separated by whitespace
+    let mut fields = Vec::with_capacity(4);
+    let mut field = Vec::new();
+    while fields.len() < 4 {
+        let mut byte = [0];
+        reader.read_exact(&mut byte).map_err(|e| e.to_string())?;
+        if byte[0].is_ascii_whitespace() {
+            if !field.is_empty() {
+                fields.push(String::from_utf8_lossy(&field).to_string());
+                field.clear();
+            }
+        } else {
+            field.push(byte[0]);
+        }
+    }
+    let parse = |v: &str| {
+        v.parse::<u32>()
+            .map_err(|_| format!("Invalid PPM header {fields:?}"))
+    };
+    let max_value = parse(&fields[3])?;
+    if fields[0] != "P6" || !(1..=u16::MAX as u32).contains(&max_value) {
+        return Err(format!("Unsupported PPM header {fields:?}"));
+    }
+    let (width, height) = (parse(&fields[1])?, parse(&fields[2])?);
+    let samples = width as usize * height as usize * 3;
+    if max_value <= u8::MAX as u32 {
+        let mut data = vec![0; samples];
+        reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+        Ok(ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb8))
+    } else {
+        let mut data = vec![0; samples * 2];
+        reader.read_exact(&mut data).map_err(|e| e.to_string())?;
+        // Big endian samples scaled to the full 16 bit range
+        let data = data
+            .chunks_exact(2)
+            .map(|b| (u16::from_be_bytes([b[0], b[1]]) as u32 * u16::MAX as u32 / max_value) as u16)
+            .collect();
+        Ok(ImageBuffer::from_raw(width, height, data).map(DynamicImage::ImageRgb16))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use nokhwa::utils::KnownCameraControlFlag;
+
+    fn control(
+        control: KnownCameraControl,
+        name: &str,
+        description: ControlValueDescription,
+    ) -> CameraControl {
+        CameraControl::new(
+            control,
+            name.to_string(),
+            description,
+            vec![KnownCameraControlFlag::Manual],
+            true,
+        )
+    }
+
+    #[test]
+    fn custom_camera_format() {
+        let format = CameraFormat::new(Resolution::new(1280, 720), FrameFormat::GRAY, 30);
+        let mut custom = CustomCameraFormat::from(format);
+        assert_eq!(custom.fourcc, "GREY");
+        assert_eq!(custom.to_camera_format(), Ok(format));
+
+        custom.fourcc = " yuy2".to_string();
+        assert_eq!(
+            custom.to_camera_format().map(|f| f.format()),
+            Ok(FrameFormat::YUYV)
+        );
+        custom.fourcc = "H264".to_string();
+        assert!(custom.to_camera_format().is_err());
+        // Read from V4L2, nokhwa only gets the resolution and frame rate
+        custom.fourcc = "y16 ".to_string();
+        assert!(custom.is_y16());
+        assert_eq!(
+            custom.to_camera_format().map(|f| f.format()),
+            Ok(FrameFormat::GRAY)
+        );
+        custom.fourcc = "MJPG".to_string();
+        custom.frame_rate = 0;
+        assert!(custom.to_camera_format().is_err());
+    }
+
+    #[test]
+    fn y16_frames() {
+        // Rows padded to 8 bytes
+        let data: Vec<u8> = [[1u16, 0x1234, 0xffff], [2, 3, 4]]
+            .iter()
+            .flat_map(|row| {
+                row.iter()
+                    .flat_map(|v| v.to_le_bytes())
+                    .chain([0, 0])
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        let buffer = nokhwa::Buffer::new(Resolution::new(3, 2), &data, FrameFormat::GRAY);
+        let DynamicImage::ImageLuma16(image) = decode_y16(&buffer).unwrap() else {
+            panic!("Y16 frames are 16 bit mono");
+        };
+        assert_eq!(image.as_raw(), &[1, 0x1234, 0xffff, 2, 3, 4]);
+
+        let short = nokhwa::Buffer::new(Resolution::new(3, 2), &data[..10], FrameFormat::GRAY);
+        assert!(decode_y16(&short).is_err());
+    }
+
+    #[test]
+    fn frame_rate_limiter() {
+        let mut limiter = FrameRateLimiter::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        assert!(limiter.frame_due(at(0), None, 1));
+        assert!(!limiter.frame_due(at(100), Some(2.), 1));
+        assert!(!limiter.frame_due(at(499), Some(2.), 1));
+        assert!(limiter.frame_due(at(500), Some(2.), 1));
+        assert!(!limiter.frame_due(at(600), Some(2.), 1));
+        assert!(limiter.frame_due(at(610), None, 1));
+    }
+
+    #[test]
+    fn frame_decimation() {
+        let mut limiter = FrameRateLimiter::default();
+        let start = Instant::now();
+        let at = |ms| start + Duration::from_millis(ms);
+
+        let due: Vec<_> = (0..7)
+            .map(|i| limiter.frame_due(at(i * 100), None, 3))
+            .collect();
+        assert_eq!(due, [true, false, false, true, false, false, true]);
+        // Both limits apply
+        assert!(!limiter.frame_due(at(700), Some(1.), 2));
+        assert!(!limiter.frame_due(at(800), Some(1.), 2));
+        assert!(limiter.frame_due(at(1600), Some(1.), 2));
+    }
+
+    #[test]
+    fn reconnect_releases_stale_handle() {
+        /// Holds the device like a camera handle
+        struct Handle(Arc<AtomicBool>);
+        impl Drop for Handle {
+            fn drop(&mut self) {
+                self.0.store(false, Ordering::SeqCst);
+            }
+        }
+
+        let busy = Arc::new(AtomicBool::new(true));
+        let (result_tx, result_rx) = flume::unbounded();
+        let (_exit_tx, exit_rx) = flume::bounded(0);
+        let config = ReconnectConfig {
+            max_attempts: 2,
+            initial_delay: 0.1,
+            max_delay: 0.1,
+            ..Default::default()
+        };
+        let mut attempts = 0;
+        let outcome = CameraThread::reconnect(
+            Handle(Arc::clone(&busy)),
+            &result_tx,
+            &exit_rx,
+            &config,
+            || {
+                attempts += 1;
+                if busy.swap(true, Ordering::SeqCst) {
+                    Err("Device busy".to_string())
+                } else {
+                    Ok(Handle(Arc::clone(&busy)))
+                }
+            },
+        );
+        assert!(matches!(outcome, Reconnect::Connected(_)));
+        assert_eq!(attempts, 1);
+        let results: Vec<_> = result_rx.drain().map(|r| r.result.is_ok()).collect();
+        assert_eq!(results, vec![false, true]);
+    }
+
+    #[test]
+    fn fast_jpeg_decode() {
+        let image = ImageBuffer::from_fn(32, 16, |x, y| Rgb([x as u8 * 8, y as u8 * 16, 128]));
+        let mut jpeg = vec![];
+        DynamicImage::ImageRgb8(image.clone())
+            .write_to(
+                &mut std::io::Cursor::new(&mut jpeg),
+                image::ImageFormat::Jpeg,
+            )
+            .unwrap();
+
+        let DynamicImage::ImageRgb8(decoded) = decode_jpeg(&jpeg).unwrap() else {
+            panic!("Unexpected format");
+        };
+        assert_eq!(decoded.dimensions(), (32, 16));
+        // Lossy, but close
+        assert!(decoded
+            .iter()
+            .zip(image.iter())
+            .all(|(&a, &b)| a.abs_diff(b) < 16));
+        assert!(decode_jpeg(&jpeg[..20]).is_err());
+    }
+
+    #[test]
+    fn exposure_bracketing() {
+        assert!(ExposureBracketing::new(HdrConfig::default()).is_none());
+        let mut bracketing = ExposureBracketing::new(HdrConfig {
+            active: true,
+            exposures: vec![10, 100],
+            settle_frames: 1,
+        })
+        .unwrap();
+
+        assert_eq!(bracketing.next_frame(), None);
+        assert_eq!(
+            bracketing.next_frame(),
+            Some(Bracket {
+                index: 0,
+                count: 2,
+                exposure_time: 10
+            })
+        );
+        assert_eq!(bracketing.advance(), 100);
+        assert_eq!(bracketing.next_frame(), None);
+        assert_eq!(bracketing.next_frame().unwrap().index, 1);
+        assert_eq!(bracketing.advance(), 10);
+    }
+
+    #[test]
+    fn camera_format_groups() {
+        let format = |width, format, frame_rate| {
+            CameraFormat::new(Resolution::new(width, width * 3 / 4), format, frame_rate)
+        };
+        let groups = group_camera_formats(vec![
+            format(1280, FrameFormat::MJPEG, 15),
+            format(640, FrameFormat::YUYV, 30),
+            format(1280, FrameFormat::MJPEG, 30),
+            format(640, FrameFormat::YUYV, 30),
+            format(1280, FrameFormat::YUYV, 10),
+        ]);
+
+        assert_eq!(
+            groups
+                .iter()
+                .map(|g| (g.to_string(), g.frame_rates.clone()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("640x480 YUYV".to_string(), vec![30]),
+                ("1280x960 MJPEG".to_string(), vec![30, 15]),
+                ("1280x960 YUYV".to_string(), vec![10]),
+            ]
+        );
+    }
+
+    #[test]
+    fn ppm_stream() {
+        let mut stream = Vec::new();
+        for value in [10, 20] {
+            stream.extend_from_slice(b"P6\n2 1\n255\n");
+            stream.extend_from_slice(&[value; 6]);
+        }
+        let mut reader = BufReader::new(stream.as_slice());
+
+        let frame = read_ppm_frame(&mut reader).unwrap().unwrap().into_rgb8();
+        assert_eq!(frame.dimensions(), (2, 1));
+        assert_eq!(frame.get_pixel(1, 0), &Rgb([10, 10, 10]));
+        let frame = read_ppm_frame(&mut reader).unwrap().unwrap().into_rgb8();
+        assert_eq!(frame.get_pixel(0, 0), &Rgb([20, 20, 20]));
+        assert_eq!(read_ppm_frame(&mut reader).unwrap(), None);
+        assert!(read_ppm_frame(&mut BufReader::new(&b"P5 2 1 255 "[..])).is_err());
+
+        // 10 bit samples are scaled to 16 bit
+        let mut stream = b"P6 1 1 1023\n".to_vec();
+        stream.extend_from_slice(&[0x03, 0xff, 0x02, 0x00, 0x00, 0x00]);
+        let frame = read_ppm_frame(&mut BufReader::new(stream.as_slice()))
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            frame.as_rgb16().unwrap().get_pixel(0, 0),
+            &Rgb([u16::MAX, 32799, 0])
+        );
+    }
+
+    #[test]
+    fn gstreamer_arguments() {
+        let mut stream_config = NetworkStreamConfig {
+            backend: StreamBackend::GStreamer,
+            ..Default::default()
+        };
+        let args = gstreamer_args(&stream_config);
+        assert_eq!(args[0], "-q");
+        assert!(args[1].starts_with("rtspsrc location=rtsp://192.168.1.10:554/stream "));
+        assert!(args[1].ends_with("! pnmenc ! fdsink fd=1"));
+
+        stream_config.gstreamer_source = "souphttpsrc location={url} ! jpegdec".to_string();
+        stream_config.url = "http://192.168.1.10/stream.mjpg".to_string();
+        let args = gstreamer_args(&stream_config);
+        assert!(args[1].starts_with("souphttpsrc location=http://192.168.1.10/stream.mjpg ! "));
+    }
+
+    #[test]
+    fn network_stream_arguments() {
+        let mut stream_config = NetworkStreamConfig::default();
+        let args = network_stream_args(&stream_config);
+        assert_eq!(args[..2], ["-rtsp_transport", "tcp"]);
+        assert!(args.contains(&"-rw_timeout".to_string()));
+        assert_eq!(args.last(), Some(&stream_config.url));
+
+        stream_config.rtsp_over_tcp = false;
+        assert!(!network_stream_args(&stream_config).contains(&"tcp".to_string()));
+        stream_config.url = "http://192.168.1.10/stream.mjpg".to_string();
+        stream_config.rtsp_over_tcp = true;
+        assert!(!network_stream_args(&stream_config).contains(&"tcp".to_string()));
+    }
+
+    #[test]
+    fn image_files() {
+        let dir = std::env::temp_dir().join("spectro_cam_rs_image_files_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        for name in ["b.png", "a.tiff", "notes.txt"] {
+            std::fs::write(dir.join(name), []).unwrap();
+        }
+
+        assert_eq!(
+            image_file_paths(&dir).unwrap(),
+            vec![dir.join("a.tiff"), dir.join("b.png")]
+        );
+        assert_eq!(
+            image_file_paths(&dir.join("b.png")).unwrap(),
+            vec![dir.join("b.png")]
+        );
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn measurement_mode() {
+        let controls = [
+            control(
+                KnownCameraControl::Other(1),
+                "White Balance, Automatic",
+                ControlValueDescription::Boolean {
+                    value: true,
+                    default: true,
+                },
+            ),
+            control(
+                KnownCameraControl::Other(2),
+                "Auto Exposure",
+                ControlValueDescription::IntegerRange {
+                    min: 0,
+                    max: 3,
+                    value: 3,
+                    step: 1,
+                    default: 3,
+                },
+            ),
+            control(
+                KnownCameraControl::Brightness,
+                "Brightness",
+                ControlValueDescription::IntegerRange {
+                    min: -64,
+                    max: 64,
+                    value: 0,
+                    step: 1,
+                    default: 0,
+                },
+            ),
+        ];
+
+        assert_eq!(
+            measurement_mode_controls(&controls),
+            vec![
+                (
+                    KnownCameraControl::Other(1),
+                    ControlValueSetter::Boolean(false)
+                ),
+                (
+                    KnownCameraControl::Other(2),
+                    ControlValueSetter::Integer(EXPOSURE_MANUAL)
+                ),
+            ]
+        );
     }
 }
@@ -0,0 +1,149 @@
+use egui::Color32;
+use serde::{Deserialize, Serialize};
+
+/// Rendering of wavelengths beyond the visible range and of the spectrum intensity
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct SpectrumColorConfig {
+    /// Shown below the visible range, faded in towards its violet end
+    pub ultraviolet: Color32,
+    /// Shown above the visible range, faded in towards its red end
+    pub infrared: Color32,
+    /// Scale the brightness with the spectrum value relative to its maximum
+    pub intensity_scaled: bool,
+    /// Lowest brightness as fraction of full scale, keeps dim colors visible on dark backgrounds
+    pub min_brightness: f32,
+}
+
+impl Default for SpectrumColorConfig {
+    fn default() -> Self {
+        Self {
+            ultraviolet: Color32::from_rgb(90, 40, 130),
+            infrared: Color32::from_rgb(110, 20, 20),
+            intensity_scaled: false,
+            min_brightness: 0.25,
+        }
+    }
+}
+
+/// Perceived intensity of monochromatic light, reduced towards the limits of vision
+fn visibility(wavelength: f32) -> f32 {
+    match wavelength {
+        w if (380. ..420.).contains(&w) => 0.3 + 0.7 * (w - 380.) / 40.,
+        w if (420. ..700.).contains(&w) => 1.,
+        w if (700. ..=780.).contains(&w) => 0.3 + 0.7 * (780. - w) / 80.,
+        _ => 0.,
+    }
+}
+
+/// Approximate display color of monochromatic light
+///
+/// Piecewise linear approximation of the visible spectrum with reduced intensity towards the
+/// limits of vision. Wavelengths outside of 380 to 780 nm are black.
+pub fn wavelength_to_color(wavelength: f32) -> Color32 {
+    let w = wavelength;
+    let (r, g, b) = match w {
+        w if (380. ..440.).contains(&w) => ((440. - w) / 60., 0., 1.),
+        w if (440. ..490.).contains(&w) => (0., (w - 440.) / 50., 1.),
+        w if (490. ..510.).contains(&w) => (0., 1., (510. - w) / 20.),
+        w if (510. ..580.).contains(&w) => ((w - 510.) / 70., 1., 0.),
+        w if (580. ..645.).contains(&w) => (1., (645. - w) / 65., 0.),
+        w if (645. ..=780.).contains(&w) => (1., 0., 0.),
+        _ => (0., 0., 0.),
+    };
+    let factor = visibility(w);
+    let c = |v: f32| ((v * factor).powf(0.8) * 255.).round() as u8;
+    Color32::from_rgb(c(r), c(g), c(b))
+}
+
+fn scale_rgb(color: Color32, factor: f32) -> Color32 {
+    let c = |v: u8| (v as f32 * factor).round().clamp(0., 255.) as u8;
+    Color32::from_rgb(c(color.r()), c(color.g()), c(color.b()))
+}
+
+/// Display color of a wavelength including the ultraviolet and infrared fallback colors
+///
+/// The fallback colors fill in where the visible color fades out. On dark backgrounds the
+/// colors are brightened to the minimum brightness.
+pub fn spectrum_color(wavelength: f32, config: &SpectrumColorConfig, dark_mode: bool) -> Color32 {
+    let visible = wavelength_to_color(wavelength);
+    let fallback = if wavelength < 580. {
+        config.ultraviolet
+    } else {
+        config.infrared
+    };
+    let weight = 1. - visibility(wavelength);
+    let c = |v: u8, f: u8| (v as f32 + weight * f as f32).round().min(255.) as u8;
+    let color = Color32::from_rgb(
+        c(visible.r(), fallback.r()),
+        c(visible.g(), fallback.g()),
+        c(visible.b(), fallback.b()),
+    );
+    let brightness = color.r().max(color.g()).max(color.b()) as f32 / 255.;
+    if dark_mode && brightness > 0. && brightness < config.min_brightness {
+        scale_rgb(color, config.min_brightness / brightness)
+    } else {
+        color
+    }
+}
+
+/// Scale the brightness of a color with the relative spectrum value if enabled
+pub fn scale_intensity(
+    color: Color32,
+    relative_value: f32,
+    config: &SpectrumColorConfig,
+) -> Color32 {
+    if !config.intensity_scaled {
+        return color;
+    }
+    let min = config.min_brightness.clamp(0., 1.);
+    scale_rgb(color, min + (1. - min) * relative_value.clamp(0., 1.))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wavelength_to_color_visible_range() {
+        assert_eq!(wavelength_to_color(300.), Color32::BLACK);
+        assert_eq!(wavelength_to_color(900.), Color32::BLACK);
+        assert_eq!(wavelength_to_color(440.), Color32::from_rgb(0, 0, 255));
+        assert_eq!(wavelength_to_color(550.), Color32::from_rgb(163, 255, 0));
+        assert_eq!(wavelength_to_color(650.), Color32::from_rgb(255, 0, 0));
+
+        let dim = wavelength_to_color(770.);
+        assert!(dim.r() < 255 && dim.g() == 0 && dim.b() == 0);
+    }
+
+    #[test]
+    fn spectrum_color_extended_range() {
+        let config = SpectrumColorConfig {
+            intensity_scaled: true,
+            min_brightness: 0.5,
+            ..Default::default()
+        };
+
+        assert_eq!(spectrum_color(300., &config, false), config.ultraviolet);
+        assert_eq!(spectrum_color(900., &config, false), config.infrared);
+        assert_eq!(
+            spectrum_color(550., &config, true),
+            wavelength_to_color(550.)
+        );
+        // Brightened on dark backgrounds
+        assert_eq!(
+            spectrum_color(900., &config, true),
+            Color32::from_rgb(128, 23, 23)
+        );
+        let dim = spectrum_color(775., &config, false);
+        assert!(dim.r() > wavelength_to_color(775.).r());
+
+        assert_eq!(
+            scale_intensity(Color32::from_rgb(200, 100, 0), 0., &config),
+            Color32::from_rgb(100, 50, 0)
+        );
+        assert_eq!(
+            scale_intensity(Color32::from_rgb(200, 100, 0), 1., &config),
+            Color32::from_rgb(200, 100, 0)
+        );
+    }
+}
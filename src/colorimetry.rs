@@ -0,0 +1,777 @@
+use crate::config::SpectrumPoint;
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+
+/// Wavelength of the first table entry in nm
+pub const CMF_START: f32 = 380.;
+/// Wavelength step of the tables in nm
+pub const CMF_STEP: f32 = 10.;
+
+/// CIE 1931 2° standard observer color matching functions x̄, ȳ, z̄ from 380 to 780 nm
+pub const CIE_1931_2DEG: [[f32; 3]; 41] = [
+    [0.001368, 0.000039, 0.00645],
+    [0.004243, 0.00012, 0.02005],
+    [0.01431, 0.000396, 0.06785],
+    [0.04351, 0.00121, 0.2074],
+    [0.13438, 0.004, 0.6456],
+    [0.2839, 0.0116, 1.3856],
+    [0.34828, 0.023, 1.74706],
+    [0.3362, 0.038, 1.77211],
+    [0.2908, 0.06, 1.6692],
+    [0.19536, 0.09098, 1.28764],
+    [0.09564, 0.13902, 0.81295],
+    [0.03201, 0.20802, 0.46518],
+    [0.0049, 0.323, 0.272],
+    [0.0093, 0.503, 0.1582],
+    [0.06327, 0.71, 0.07825],
+    [0.1655, 0.862, 0.04216],
+    [0.2904, 0.954, 0.0203],
+    [0.43345, 0.99495, 0.00875],
+    [0.5945, 0.995, 0.0039],
+    [0.7621, 0.952, 0.0021],
+    [0.9163, 0.87, 0.00165],
+    [1.0263, 0.757, 0.0011],
+    [1.0622, 0.631, 0.0008],
+    [1.0026, 0.503, 0.00034],
+    [0.85445, 0.381, 0.00019],
+    [0.6424, 0.265, 0.00005],
+    [0.4479, 0.175, 0.00002],
+    [0.2835, 0.107, 0.0],
+    [0.1649, 0.061, 0.0],
+    [0.0874, 0.032, 0.0],
+    [0.04677, 0.017, 0.0],
+    [0.0227, 0.00821, 0.0],
+    [0.011359, 0.004102, 0.0],
+    [0.00579, 0.002091, 0.0],
+    [0.002899, 0.001047, 0.0],
+    [0.00144, 0.00052, 0.0],
+    [0.00069, 0.000249, 0.0],
+    [0.000332, 0.00012, 0.0],
+    [0.000166, 0.00006, 0.0],
+    [0.000083, 0.00003, 0.0],
+    [0.000042, 0.000015, 0.0],
+];
+
+/// CIE 1964 10° supplementary standard observer x̄₁₀, ȳ₁₀, z̄₁₀ from 380 to 780 nm
+pub const CIE_1964_10DEG: [[f32; 3]; 41] = [
+    [0.00016, 0.000017, 0.000705],
+    [0.002362, 0.000253, 0.010482],
+    [0.01911, 0.002004, 0.086011],
+    [0.084736, 0.008756, 0.389366],
+    [0.204492, 0.021391, 0.972542],
+    [0.314679, 0.038676, 1.55348],
+    [0.383734, 0.062077, 1.96728],
+    [0.370702, 0.089456, 1.9948],
+    [0.302273, 0.128201, 1.74537],
+    [0.195618, 0.18519, 1.31756],
+    [0.080507, 0.253589, 0.772125],
+    [0.016172, 0.339133, 0.415254],
+    [0.003816, 0.460777, 0.218502],
+    [0.037465, 0.606741, 0.112044],
+    [0.117749, 0.761757, 0.060709],
+    [0.236491, 0.875211, 0.030451],
+    [0.376772, 0.961988, 0.013676],
+    [0.529826, 0.991761, 0.003988],
+    [0.705224, 0.99734, 0.0],
+    [0.878655, 0.955552, 0.0],
+    [1.01416, 0.868934, 0.0],
+    [1.11852, 0.777405, 0.0],
+    [1.12399, 0.658341, 0.0],
+    [1.03048, 0.527963, 0.0],
+    [0.856297, 0.398057, 0.0],
+    [0.647467, 0.283493, 0.0],
+    [0.431567, 0.179828, 0.0],
+    [0.268329, 0.107633, 0.0],
+    [0.152568, 0.060281, 0.0],
+    [0.081261, 0.0318, 0.0],
+    [0.040851, 0.015905, 0.0],
+    [0.019941, 0.007749, 0.0],
+    [0.009577, 0.003718, 0.0],
+    [0.004553, 0.001768, 0.0],
+    [0.002175, 0.000846, 0.0],
+    [0.001045, 0.000407, 0.0],
+    [0.000508, 0.000199, 0.0],
+    [0.000251, 0.000098, 0.0],
+    [0.000126, 0.00005, 0.0],
+    [0.000065, 0.000025, 0.0],
+    [0.000033, 0.000013, 0.0],
+];
+
+/// Relative spectral power of CIE standard illuminant D65 from 380 to 780 nm
+pub const CIE_D65: [f32; 41] = [
+    49.9755, 54.6482, 82.7549, 91.486, 93.4318, 86.6823, 104.865, 117.008, 117.812, 114.861,
+    115.923, 108.811, 109.354, 107.802, 104.79, 107.689, 104.405, 104.046, 100.0, 96.3342, 95.788,
+    88.6856, 90.0062, 89.5991, 87.6987, 83.2886, 83.6992, 80.0268, 80.2146, 82.2778, 78.2842,
+    69.7213, 71.6091, 74.349, 61.604, 69.8856, 75.087, 63.5927, 46.4182, 66.8054, 63.3828,
+];
+
+/// CIE 13.3 test color samples TCS01 to TCS08, spectral reflectance from 380 to 780 nm
+pub const CIE_TCS: [[f32; 41]; 8] = [
+    // TCS01, light greyish red 7.5R 6/4
+    [
+        0.219, 0.252, 0.256, 0.252, 0.244, 0.237, 0.23, 0.225, 0.22, 0.216, 0.214, 0.216, 0.223,
+        0.226, 0.225, 0.227, 0.236, 0.253, 0.272, 0.298, 0.341, 0.39, 0.424, 0.442, 0.45, 0.451,
+        0.451, 0.45, 0.451, 0.453, 0.455, 0.458, 0.462, 0.464, 0.466, 0.466, 0.467, 0.467, 0.467,
+        0.467, 0.467,
+    ],
+    // TCS02, dark greyish yellow 5Y 6/4
+    [
+        0.07, 0.089, 0.111, 0.118, 0.121, 0.122, 0.123, 0.127, 0.131, 0.138, 0.15, 0.174, 0.207,
+        0.242, 0.26, 0.267, 0.272, 0.282, 0.299, 0.322, 0.335, 0.341, 0.342, 0.342, 0.341, 0.339,
+        0.338, 0.336, 0.334, 0.332, 0.331, 0.329, 0.328, 0.326, 0.324, 0.324, 0.322, 0.32, 0.316,
+        0.315, 0.313,
+    ],
+    // TCS03, strong yellow green 5GY 6/8
+    [
+        0.065, 0.07, 0.073, 0.074, 0.074, 0.073, 0.073, 0.074, 0.077, 0.085, 0.109, 0.148, 0.198,
+        0.241, 0.278, 0.339, 0.392, 0.4, 0.38, 0.349, 0.315, 0.285, 0.264, 0.252, 0.241, 0.229,
+        0.22, 0.216, 0.219, 0.23, 0.251, 0.288, 0.34, 0.39, 0.431, 0.46, 0.481, 0.493, 0.5, 0.505,
+        0.508,
+    ],
+    // TCS04, moderate yellowish green 2.5G 6/6
+    [
+        0.074, 0.093, 0.116, 0.124, 0.128, 0.135, 0.144, 0.161, 0.186, 0.229, 0.281, 0.332, 0.37,
+        0.39, 0.395, 0.385, 0.367, 0.341, 0.312, 0.28, 0.247, 0.214, 0.185, 0.169, 0.16, 0.154,
+        0.151, 0.148, 0.148, 0.151, 0.158, 0.165, 0.17, 0.17, 0.166, 0.164, 0.168, 0.177, 0.185,
+        0.192, 0.199,
+    ],
+    // TCS05, light bluish green 10BG 6/4
+    [
+        0.295, 0.31, 0.313, 0.319, 0.326, 0.334, 0.346, 0.36, 0.381, 0.403, 0.415, 0.419, 0.413,
+        0.403, 0.389, 0.372, 0.353, 0.331, 0.308, 0.284, 0.26, 0.232, 0.21, 0.194, 0.185, 0.18,
+        0.178, 0.176, 0.175, 0.175, 0.175, 0.174, 0.174, 0.174, 0.174, 0.175, 0.176, 0.177, 0.178,
+        0.179, 0.181,
+    ],
+    // TCS06, light blue 5PB 6/8
+    [
+        0.151, 0.265, 0.41, 0.492, 0.517, 0.531, 0.544, 0.556, 0.554, 0.541, 0.519, 0.488, 0.45,
+        0.414, 0.377, 0.341, 0.309, 0.279, 0.253, 0.234, 0.225, 0.221, 0.22, 0.22, 0.223, 0.233,
+        0.244, 0.258, 0.268, 0.278, 0.283, 0.29, 0.302, 0.325, 0.351, 0.376, 0.401, 0.425, 0.447,
+        0.469, 0.488,
+    ],
+    // TCS07, light violet 2.5P 6/8
+    [
+        0.378, 0.524, 0.551, 0.559, 0.561, 0.556, 0.544, 0.522, 0.488, 0.448, 0.408, 0.363, 0.324,
+        0.301, 0.283, 0.265, 0.257, 0.259, 0.26, 0.256, 0.254, 0.27, 0.302, 0.344, 0.377, 0.4,
+        0.42, 0.438, 0.452, 0.462, 0.468, 0.473, 0.483, 0.496, 0.511, 0.525, 0.539, 0.553, 0.565,
+        0.576, 0.586,
+    ],
+    // TCS08, light reddish purple 10P 6/8
+    [
+        0.104, 0.17, 0.319, 0.462, 0.49, 0.482, 0.462, 0.439, 0.413, 0.382, 0.352, 0.325, 0.299,
+        0.283, 0.27, 0.256, 0.25, 0.254, 0.264, 0.272, 0.278, 0.295, 0.348, 0.434, 0.528, 0.598,
+        0.638, 0.667, 0.685, 0.696, 0.702, 0.705, 0.706, 0.707, 0.708, 0.708, 0.709, 0.709, 0.709,
+        0.709, 0.71,
+    ],
+];
+
+/// Components S0, S1, S2 of CIE daylight from 380 to 780 nm
+pub const CIE_DAYLIGHT: [[f32; 3]; 41] = [
+    [63.4, 38.5, 3.0],
+    [65.8, 35.0, 1.2],
+    [94.8, 43.4, -1.1],
+    [104.8, 46.3, -0.5],
+    [105.9, 43.9, -0.7],
+    [96.8, 37.1, -1.2],
+    [113.9, 36.7, -2.6],
+    [125.6, 35.9, -2.9],
+    [125.5, 32.6, -2.8],
+    [121.3, 27.9, -2.6],
+    [121.3, 24.3, -2.6],
+    [113.5, 20.1, -1.8],
+    [113.1, 16.2, -1.5],
+    [110.8, 13.2, -1.3],
+    [106.5, 8.6, -1.2],
+    [108.8, 6.1, -1.0],
+    [105.3, 4.2, -0.5],
+    [104.4, 1.9, -0.3],
+    [100.0, 0.0, 0.0],
+    [96.0, -1.6, 0.2],
+    [95.1, -3.5, 0.5],
+    [89.1, -3.5, 2.1],
+    [90.5, -5.8, 3.2],
+    [90.3, -7.2, 4.1],
+    [88.4, -8.6, 4.7],
+    [84.0, -9.5, 5.1],
+    [85.1, -10.9, 6.7],
+    [81.9, -10.7, 7.3],
+    [82.6, -12.0, 8.6],
+    [84.9, -14.0, 9.8],
+    [81.3, -13.6, 10.2],
+    [71.9, -12.0, 8.3],
+    [74.3, -13.3, 9.6],
+    [76.4, -12.9, 8.5],
+    [63.3, -10.6, 7.0],
+    [71.7, -11.6, 7.6],
+    [77.0, -12.2, 8.0],
+    [65.2, -10.2, 6.7],
+    [47.7, -7.8, 5.2],
+    [68.6, -11.2, 7.4],
+    [65.0, -10.4, 6.8],
+];
+
+/// Standard observer of the color matching functions
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Observer {
+    /// CIE 1931 2°, for fields of view up to about 4°
+    #[default]
+    Cie1931TwoDegree,
+    /// CIE 1964 10°, for larger fields of view
+    Cie1964TenDegree,
+}
+
+impl Observer {
+    pub fn table(&self) -> &'static [[f32; 3]; 41] {
+        match self {
+            Observer::Cie1931TwoDegree => &CIE_1931_2DEG,
+            Observer::Cie1964TenDegree => &CIE_1964_10DEG,
+        }
+    }
+
+    /// Color matching functions linearly interpolated, `None` outside of 380 to 780 nm
+    pub fn cmf(&self, wavelength: f32) -> Option<[f32; 3]> {
+        let table = self.table();
+        let (i, fraction) = table_position(wavelength)?;
+        let next = table[(i + 1).min(table.len() - 1)];
+        Some(std::array::from_fn(|c| {
+            table[i][c] + (next[c] - table[i][c]) * fraction
+        }))
+    }
+}
+
+/// CIE standard illuminants
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Illuminant {
+    /// Incandescent light of 2856 K
+    A,
+    /// Average daylight of about 6504 K
+    D65,
+}
+
+impl Illuminant {
+    /// Relative spectral power normalized to 100 at 560 nm
+    ///
+    /// A is defined by its formula for any wavelength, D65 only from 380 to 780 nm.
+    pub fn spectral_power(&self, wavelength: f32) -> Option<f32> {
+        match self {
+            Illuminant::A => {
+                let c: f64 = 1.435e7 / 2848.;
+                let w = wavelength as f64;
+                (wavelength > 0.).then_some(
+                    (100. * (560. / w).powi(5) * ((c / 560.).exp() - 1.) / ((c / w).exp() - 1.))
+                        as f32,
+                )
+            }
+            Illuminant::D65 => interpolate_table(&CIE_D65, wavelength),
+        }
+    }
+
+    /// Spectrum from 380 to 780 nm in steps of 1 nm
+    pub fn points(&self) -> Vec<SpectrumPoint> {
+        (380..=780)
+            .filter_map(|w| {
+                self.spectral_power(w as f32).map(|value| SpectrumPoint {
+                    wavelength: w as f32,
+                    value,
+                })
+            })
+            .collect()
+    }
+}
+
+impl Display for Illuminant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Illuminant::A => write!(f, "A"),
+            Illuminant::D65 => write!(f, "D65"),
+        }
+    }
+}
+
+/// Photopic luminous efficiency V(λ), equal to ȳ of the CIE 1931 2° observer
+pub fn luminous_efficiency(wavelength: f32) -> f32 {
+    Observer::Cie1931TwoDegree
+        .cmf(wavelength)
+        .map_or(0., |cmf| cmf[1])
+}
+
+/// Table index and fraction towards the next entry
+fn table_position(wavelength: f32) -> Option<(usize, f32)> {
+    let position = (wavelength - CMF_START) / CMF_STEP;
+    (0. ..=40.).contains(&position).then(|| {
+        let i = position.floor() as usize;
+        (i, position - i as f32)
+    })
+}
+
+/// Table value linearly interpolated, `None` outside of 380 to 780 nm
+fn interpolate_table(table: &[f32; 41], wavelength: f32) -> Option<f32> {
+    let (i, fraction) = table_position(wavelength)?;
+    let next = table[(i + 1).min(table.len() - 1)];
+    Some(table[i] + (next - table[i]) * fraction)
+}
+
+/// Relative spectral power of a Planckian radiator normalized to 100 at 560 nm
+pub fn planckian(temperature: f32, wavelength: f32) -> f32 {
+    let c = 1.4388e7 / temperature.max(1.) as f64;
+    let w = wavelength.max(1.) as f64;
+    (100. * (560. / w).powi(5) * ((c / 560.).exp() - 1.) / ((c / w).exp() - 1.)) as f32
+}
+
+/// Relative spectral power of CIE daylight of the given correlated color temperature
+///
+/// Defined from 4000 to 25000 K, `None` outside of 380 to 780 nm.
+pub fn daylight(temperature: f32, wavelength: f32) -> Option<f32> {
+    let t = temperature.clamp(4000., 25000.);
+    let x = if t <= 7000. {
+        -4.607e9 / t.powi(3) + 2.9678e6 / t.powi(2) + 0.09911e3 / t + 0.244063
+    } else {
+        -2.0064e9 / t.powi(3) + 1.9018e6 / t.powi(2) + 0.24748e3 / t + 0.23704
+    };
+    let y = -3. * x * x + 2.87 * x - 0.275;
+    let m = 0.0241 + 0.2562 * x - 0.7341 * y;
+    let m1 = (-1.3515 - 1.7703 * x + 5.9114 * y) / m;
+    let m2 = (0.03 - 31.4424 * x + 30.0717 * y) / m;
+    let component = |c: usize| interpolate_table(&CIE_DAYLIGHT.map(|s| s[c]), wavelength);
+    Some(component(0)? + m1 * component(1)? + m2 * component(2)?)
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Colorimetry {
+    /// Tristimulus values in arbitrary units
+    pub xyz: [f32; 3],
+    /// CIE 1931 chromaticity
+    pub x: f32,
+    pub y: f32,
+    /// Correlated color temperature in K
+    pub cct: f32,
+    /// Distance from the Planckian locus in the CIE 1960 uv diagram
+    pub duv: f32,
+}
+
+fn cmf_wavelength(index: usize) -> f32 {
+    CMF_START + index as f32 * CMF_STEP
+}
+
+/// Tristimulus values of a spectrum sorted by wavelength for the CIE 1931 2° observer
+///
+/// Wavelengths not covered by the spectrum do not contribute.
+pub fn tristimulus(points: &[SpectrumPoint]) -> [f32; 3] {
+    tristimulus_for(points, Observer::Cie1931TwoDegree)
+}
+
+/// Tristimulus values of a spectrum sorted by wavelength for the given observer
+///
+/// Integrated over the points of the spectrum weighted by their spacing, with the color
+/// matching functions interpolated at each point. Narrow lines between the table entries
+/// contribute fully.
+pub fn tristimulus_for(points: &[SpectrumPoint], observer: Observer) -> [f32; 3] {
+    let mut xyz = [0.; 3];
+    for (i, point) in points.iter().enumerate() {
+        let Some(cmf) = observer.cmf(point.wavelength) else {
+            continue;
+        };
+        let previous = points[i.saturating_sub(1)].wavelength;
+        let next = points[(i + 1).min(points.len() - 1)].wavelength;
+        let spacing = (next - previous) / 2.;
+        for (sum, c) in xyz.iter_mut().zip(cmf) {
+            *sum += c * point.value * spacing;
+        }
+    }
+    xyz
+}
+
+/// Chromaticity coordinates, `None` for black
+pub fn xyz_to_xy(xyz: [f32; 3]) -> Option<(f32, f32)> {
+    let sum = xyz.iter().sum::<f32>();
+    (sum > 0.).then(|| (xyz[0] / sum, xyz[1] / sum))
+}
+
+/// Correlated color temperature after McCamy, accurate for about 2000 to 12500 K
+pub fn cct_mccamy(x: f32, y: f32) -> f32 {
+    let n = (x - 0.3320) / (0.1858 - y);
+    449. * n.powi(3) + 3525. * n.powi(2) + 6823.3 * n + 5520.33
+}
+
+/// Duv after the polynomial approximation by Ohno
+pub fn duv(x: f32, y: f32) -> f32 {
+    const K: [f32; 7] = [
+        -0.471106,
+        1.925865,
+        -2.4243787,
+        1.5317403,
+        -0.5179722,
+        0.0893944,
+        -0.00616793,
+    ];
+    let denominator = -2. * x + 12. * y + 3.;
+    let (u, v) = (4. * x / denominator, 6. * y / denominator);
+    let l_fp = (u - 0.292).hypot(v - 0.24);
+    let a = ((u - 0.292) / l_fp).acos();
+    let l_bb = K.iter().rev().fold(0., |acc, k| acc * a + k);
+    l_fp - l_bb
+}
+
+pub fn colorimetry(points: &[SpectrumPoint]) -> Option<Colorimetry> {
+    let xyz = tristimulus(points);
+    let (x, y) = xyz_to_xy(xyz)?;
+    Some(Colorimetry {
+        xyz,
+        x,
+        y,
+        cct: cct_mccamy(x, y),
+        duv: duv(x, y),
+    })
+}
+
+/// Largest distance from the reference illuminant for which the color rendering index is
+/// meaningful
+pub const CRI_MAX_DC: f32 = 5.4e-3;
+
+/// Color rendering of a light source after CIE 13.3
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct ColorRendering {
+    /// General color rendering index Ra, the mean of R1 to R8
+    pub ra: f32,
+    /// Special color rendering indices R1 to R8 of the test color samples
+    pub special: [f32; 8],
+    /// Distance between the source and the reference illuminant in the CIE 1960 uv diagram
+    pub dc: f32,
+}
+
+impl ColorRendering {
+    /// The source is close enough to the Planckian locus
+    pub fn is_valid(&self) -> bool {
+        self.dc <= CRI_MAX_DC
+    }
+}
+
+/// CIE 1960 UCS chromaticity
+fn xyz_to_uv(xyz: [f32; 3]) -> (f32, f32) {
+    let denominator = xyz[0] + 15. * xyz[1] + 3. * xyz[2];
+    (4. * xyz[0] / denominator, 6. * xyz[1] / denominator)
+}
+
+/// Color rendering of a spectrum sorted by wavelength, `None` for black
+///
+/// The reference is a Planckian radiator below 5000 K and CIE daylight above, both at the
+/// CCT after McCamy.
+pub fn color_rendering(points: &[SpectrumPoint]) -> Option<ColorRendering> {
+    let source = tristimulus(points);
+    let (x, y) = xyz_to_xy(source)?;
+    let cct = cct_mccamy(x, y).clamp(1000., 25000.);
+    let reference: Vec<_> = (380..=780)
+        .map(|w| {
+            let wavelength = w as f32;
+            SpectrumPoint {
+                wavelength,
+                value: if cct < 5000. {
+                    planckian(cct, wavelength)
+                } else {
+                    daylight(cct, wavelength).unwrap_or_default()
+                },
+            }
+        })
+        .collect();
+    let white = tristimulus(&reference);
+    // Tristimulus values of a sample lit by the source, scaled to Y = 100 for the source
+    let sample = |points: &[SpectrumPoint], white: [f32; 3], reflectance: &[f32; 41]| {
+        let reflected: Vec<_> = points
+            .iter()
+            .map(|p| SpectrumPoint {
+                wavelength: p.wavelength,
+                value: p.value * interpolate_table(reflectance, p.wavelength).unwrap_or_default(),
+            })
+            .collect();
+        tristimulus(&reflected).map(|v| v * 100. / white[1])
+    };
+    // CIE 1960 c, d coordinates for the von Kries adaptation
+    let cd = |(u, v): (f32, f32)| ((4. - u - 10. * v) / v, (1.708 * v + 0.404 - 1.481 * u) / v);
+    let (uk, vk) = xyz_to_uv(source);
+    let (ur, vr) = xyz_to_uv(white);
+    let (ck, dk) = cd((uk, vk));
+    let (cr, dr) = cd((ur, vr));
+    // CIE 1964 U*V*W* relative to the reference white
+    let uvw = |y: f32, (u, v): (f32, f32)| {
+        let w = 25. * y.cbrt() - 17.;
+        [13. * w * (u - ur), 13. * w * (v - vr), w]
+    };
+
+    let special = CIE_TCS.map(|reflectance| {
+        let reference_sample = sample(&reference, white, &reflectance);
+        let test_sample = sample(points, source, &reflectance);
+        let (cki, dki) = cd(xyz_to_uv(test_sample));
+        let (c, d) = (cr / ck * cki, dr / dk * dki);
+        let denominator = 16.518 + 1.481 * c - d;
+        let adapted = (
+            (10.872 + 0.404 * c - 4. * d) / denominator,
+            5.520 / denominator,
+        );
+        let r = uvw(reference_sample[1], xyz_to_uv(reference_sample));
+        let k = uvw(test_sample[1], adapted);
+        let delta_e = (0..3).map(|i| (r[i] - k[i]).powi(2)).sum::<f32>().sqrt();
+        100. - 4.6 * delta_e
+    });
+    Some(ColorRendering {
+        ra: special.iter().sum::<f32>() / special.len() as f32,
+        special,
+        dc: (uk - ur).hypot(vk - vr),
+    })
+}
+
+/// Color of a source in common color spaces, relative to its luminance
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ColorCoordinates {
+    /// Tristimulus values scaled to Y = 100
+    pub xyz: [f32; 3],
+    pub xy: [f32; 2],
+    /// CIE 1976 UCS chromaticity
+    pub uv_prime: [f32; 2],
+    /// L*, a*, b* relative to the white point, L* is 100 by the scaling to Y = 100
+    pub lab: [f32; 3],
+    pub white_point: Illuminant,
+}
+
+impl ColorCoordinates {
+    /// Coordinates of a spectrum sorted by wavelength for the CIE 1931 2° observer
+    pub fn new(points: &[SpectrumPoint], white_point: Illuminant) -> Option<Self> {
+        let normalize = |xyz: [f32; 3]| (xyz[1] > 0.).then(|| xyz.map(|v| v * 100. / xyz[1]));
+        let xyz = normalize(tristimulus(points))?;
+        let white = normalize(tristimulus(&white_point.points()))?;
+        let (x, y) = xyz_to_xy(xyz)?;
+        let denominator = xyz[0] + 15. * xyz[1] + 3. * xyz[2];
+        let f = |t: f32| {
+            const DELTA: f32 = 6. / 29.;
+            if t > DELTA.powi(3) {
+                t.cbrt()
+            } else {
+                t / (3. * DELTA * DELTA) + 4. / 29.
+            }
+        };
+        let [fx, fy, fz] = std::array::from_fn(|i| f(xyz[i] / white[i]));
+        Some(Self {
+            xyz,
+            xy: [x, y],
+            uv_prime: [4. * xyz[0] / denominator, 9. * xyz[1] / denominator],
+            lab: [116. * fy - 16., 500. * (fx - fy), 200. * (fy - fz)],
+            white_point,
+        })
+    }
+
+    /// Comment lines for exported files
+    pub fn to_comment_lines(&self) -> Vec<String> {
+        vec![
+            format!(
+                "# XYZ: {:.4} {:.4} {:.4}",
+                self.xyz[0], self.xyz[1], self.xyz[2]
+            ),
+            format!("# xy: {:.5} {:.5}", self.xy[0], self.xy[1]),
+            format!("# u'v': {:.5} {:.5}", self.uv_prime[0], self.uv_prime[1]),
+            format!(
+                "# CIELAB ({}): {:.3} {:.3} {:.3}",
+                self.white_point, self.lab[0], self.lab[1], self.lab[2]
+            ),
+        ]
+    }
+}
+
+/// Wavelength and chromaticity of monochromatic light
+pub fn spectral_locus() -> impl Iterator<Item = (f32, f32, f32)> {
+    CIE_1931_2DEG
+        .iter()
+        .enumerate()
+        .filter_map(|(i, cmf)| xyz_to_xy(*cmf).map(|(x, y)| (cmf_wavelength(i), x, y)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn planck(temperature: f32) -> Vec<SpectrumPoint> {
+        (300..900)
+            .map(|w| {
+                let l = w as f64 * 1e-9;
+                SpectrumPoint {
+                    wavelength: w as f32,
+                    value: (1. / (l.powi(5) * ((0.014388 / (l * temperature as f64)).exp() - 1.))
+                        * 1e-15) as f32,
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn equal_energy() {
+        let points: Vec<_> = (300..900)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: 1.,
+            })
+            .collect();
+        let c = colorimetry(&points).unwrap();
+
+        assert_relative_eq!(c.x, 1. / 3., epsilon = 1e-3);
+        assert_relative_eq!(c.y, 1. / 3., epsilon = 1e-3);
+        assert_eq!(colorimetry(&[]), None);
+    }
+
+    #[test]
+    fn narrow_line() {
+        // A 2 nm wide line between the table entries at 530 and 540 nm
+        let points: Vec<_> = (5000..5700)
+            .map(|i| {
+                let wavelength = i as f32 * 0.1;
+                SpectrumPoint {
+                    wavelength,
+                    value: (-(wavelength - 535.).powi(2) / (2. * 0.85f32.powi(2))).exp(),
+                }
+            })
+            .collect();
+        let c = colorimetry(&points).unwrap();
+        let cmf = Observer::Cie1931TwoDegree.cmf(535.).unwrap();
+        let (x, y) = xyz_to_xy(cmf).unwrap();
+
+        assert_relative_eq!(c.x, x, epsilon = 1e-3);
+        assert_relative_eq!(c.y, y, epsilon = 1e-3);
+        // Area of the line times ȳ
+        assert_relative_eq!(
+            c.xyz[1],
+            0.85 * (2. * std::f32::consts::PI).sqrt() * cmf[1],
+            max_relative = 1e-2
+        );
+    }
+
+    #[test]
+    fn illuminant_a() {
+        let c = colorimetry(&planck(2856.)).unwrap();
+
+        assert_relative_eq!(c.x, 0.4476, epsilon = 1e-3);
+        assert_relative_eq!(c.y, 0.4074, epsilon = 1e-3);
+        assert_relative_eq!(c.cct, 2856., epsilon = 10.);
+        assert_relative_eq!(c.duv, 0., epsilon = 1e-3);
+    }
+
+    #[test]
+    fn standard_illuminants() {
+        let d65 = xyz_to_xy(tristimulus(&Illuminant::D65.points())).unwrap();
+        assert_relative_eq!(d65.0, 0.3127, epsilon = 1e-3);
+        assert_relative_eq!(d65.1, 0.3290, epsilon = 1e-3);
+        let d65_10 = xyz_to_xy(tristimulus_for(
+            &Illuminant::D65.points(),
+            Observer::Cie1964TenDegree,
+        ))
+        .unwrap();
+        assert_relative_eq!(d65_10.0, 0.3138, epsilon = 1e-3);
+        assert_relative_eq!(d65_10.1, 0.3310, epsilon = 1e-3);
+
+        let a = xyz_to_xy(tristimulus(&Illuminant::A.points())).unwrap();
+        assert_relative_eq!(a.0, 0.4476, epsilon = 1e-3);
+        assert_relative_eq!(a.1, 0.4074, epsilon = 1e-3);
+        assert_relative_eq!(Illuminant::A.spectral_power(560.).unwrap(), 100.);
+        assert_eq!(Illuminant::D65.spectral_power(800.), None);
+    }
+
+    #[test]
+    fn interpolated_tables() {
+        assert_relative_eq!(luminous_efficiency(555.), 1.0, epsilon = 1e-2);
+        assert_relative_eq!(luminous_efficiency(565.), 0.9735);
+        assert_eq!(luminous_efficiency(300.), 0.);
+        assert_eq!(
+            Observer::Cie1964TenDegree.cmf(780.),
+            Some(CIE_1964_10DEG[40])
+        );
+        assert_eq!(Observer::Cie1931TwoDegree.cmf(781.), None);
+    }
+
+    #[test]
+    fn color_coordinates() {
+        let d65 = ColorCoordinates::new(&Illuminant::D65.points(), Illuminant::D65).unwrap();
+        assert_relative_eq!(d65.xyz[1], 100.);
+        assert_relative_eq!(d65.uv_prime[0], 0.1978, epsilon = 1e-3);
+        assert_relative_eq!(d65.uv_prime[1], 0.4683, epsilon = 1e-3);
+        assert_relative_eq!(d65.lab[0], 100., epsilon = 1e-3);
+        assert_relative_eq!(d65.lab[1], 0., epsilon = 1e-3);
+        assert_relative_eq!(d65.lab[2], 0., epsilon = 1e-3);
+
+        // Incandescent light is yellowish relative to daylight
+        let a = ColorCoordinates::new(&Illuminant::A.points(), Illuminant::D65).unwrap();
+        assert!(a.lab[1] > 0. && a.lab[2] > 50.);
+        assert_eq!(
+            a.to_comment_lines()[3].split(':').next(),
+            Some("# CIELAB (D65)")
+        );
+        assert_eq!(ColorCoordinates::new(&[], Illuminant::D65), None);
+    }
+
+    #[test]
+    fn daylight_components() {
+        for (i, d65) in CIE_D65.iter().enumerate() {
+            assert_relative_eq!(
+                daylight(6504., cmf_wavelength(i)).unwrap(),
+                *d65,
+                max_relative = 1e-3
+            );
+        }
+        assert_relative_eq!(planckian(2856., 560.), 100.);
+        assert_eq!(daylight(6504., 790.), None);
+    }
+
+    #[test]
+    fn test_color_samples() {
+        let white = tristimulus(&Illuminant::D65.points())[1];
+        for reflectance in CIE_TCS {
+            let points: Vec<_> = Illuminant::D65
+                .points()
+                .into_iter()
+                .map(|p| SpectrumPoint {
+                    value: p.value * interpolate_table(&reflectance, p.wavelength).unwrap(),
+                    ..p
+                })
+                .collect();
+            // All samples have a Munsell value of 6
+            assert_relative_eq!(tristimulus(&points)[1] / white * 100., 30., epsilon = 2.);
+        }
+    }
+
+    #[test]
+    fn color_rendering_index() {
+        let a = color_rendering(&planck(2856.)).unwrap();
+        assert_relative_eq!(a.ra, 100., epsilon = 0.5);
+        assert!(a.is_valid());
+        let d65 = color_rendering(&Illuminant::D65.points()).unwrap();
+        assert_relative_eq!(d65.ra, 100., epsilon = 0.5);
+        assert!(d65.special.iter().all(|r| *r > 99.5));
+
+        let equal_energy: Vec<_> = (380..=780)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: 1.,
+            })
+            .collect();
+        let e = color_rendering(&equal_energy).unwrap();
+        assert_relative_eq!(e.ra, 95., epsilon = 1.);
+        assert!(!e.is_valid());
+
+        // Three narrow bands render saturated yellow green poorly
+        let triband: Vec<_> = (380..=780)
+            .map(|w| {
+                let band = |center: f32| (-((w as f32 - center) / 10.).powi(2)).exp();
+                SpectrumPoint {
+                    wavelength: w as f32,
+                    value: band(450.) + band(540.) + band(610.),
+                }
+            })
+            .collect();
+        let triband = color_rendering(&triband).unwrap();
+        assert!(triband.ra < 85.);
+        assert!(triband.special[2] < triband.ra);
+        assert_eq!(color_rendering(&[]), None);
+    }
+
+    #[test]
+    fn d65_duv() {
+        assert_relative_eq!(duv(0.3127, 0.3290), 0.0032, epsilon = 1e-4);
+        assert_relative_eq!(cct_mccamy(0.3127, 0.3290), 6504., epsilon = 5.);
+    }
+}
@@ -1,8 +1,33 @@
+use crate::alarm::AlarmConfig;
+use crate::auto_exposure::AutoExposureConfig;
+use crate::calibration_check::CalibrationCheckConfig;
+use crate::color::SpectrumColorConfig;
+use crate::colorimetry::Illuminant;
+use crate::dark_frame::DarkFrameConfig;
+use crate::defect_map::{DefectMap, HotPixelConfig};
+use crate::feed::FeedConfig;
+use crate::flat_field::FlatFieldConfig;
+use crate::frame_recorder::FrameRecordingConfig;
+use crate::library::LibraryConfig;
+use crate::multi_camera::AdditionalCameraConfig;
+use crate::multi_order::MultiOrderConfig;
+use crate::number_format::system_uses_decimal_comma;
+use crate::shutter::ShutterConfig;
+use crate::sinks::SinkConfig;
+use crate::sonification::SonificationConfig;
+use crate::tolerance::ToleranceConfig;
+use crate::transmission::TransmissionConfig;
+use crate::trigger::FlashTriggerConfig;
+use crate::tungsten_halogen::TungstenConfig;
 use egui::Vec2;
 use egui_plot::{Line, PlotPoints};
-use nokhwa::utils::CameraFormat;
+use nalgebra::RealField;
+use nokhwa::utils::{CameraFormat, FrameFormat};
 use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use std::time::Duration;
 use winit::dpi::PhysicalSize;
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
@@ -25,46 +50,216 @@ impl Display for Linearize {
 }
 
 impl Linearize {
-    pub fn linearize(&self, value: f32) -> f32 {
+    pub fn linearize<T: RealField + Copy>(&self, value: T) -> T {
+        let c = |v: f64| nalgebra::convert::<f64, T>(v);
         match self {
             Linearize::Off => value,
             Linearize::Rec709 | Linearize::Rec601 => {
-                if value < 0.081 {
-                    value / 4.5
+                if value < c(0.081) {
+                    value / c(4.5)
                 } else {
-                    ((value + 0.099) / 1.099).powf(1. / 0.45)
+                    ((value + c(0.099)) / c(1.099)).powf(c(1. / 0.45))
                 }
             }
             Linearize::SRgb => {
-                if value < 0.04045 {
-                    value / 12.92
+                if value < c(0.04045) {
+                    value / c(12.92)
                 } else {
-                    ((value + 0.055) / 1.055).powf(2.4)
+                    ((value + c(0.055)) / c(1.055)).powf(c(2.4))
                 }
             }
         }
     }
 }
 
+/// Where linearization happens relative to averaging
+///
+/// Gains and scaling are always applied to the averaged spectrum.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum ProcessingOrder {
+    /// Linearize every incoming spectrum, then average
+    #[default]
+    LinearizeThenAverage,
+    /// Average the raw spectra, then linearize the result
+    AverageThenLinearize,
+}
+
+impl Display for ProcessingOrder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ProcessingOrder::LinearizeThenAverage => write!(f, "Linearize, Average"),
+            ProcessingOrder::AverageThenLinearize => write!(f, "Average, Linearize"),
+        }
+    }
+}
+
+/// Which changes clear the averaging buffer
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum BufferClearPolicy {
+    /// Clear on every change that affects the spectrum
+    Always,
+    /// Only clear when a new acquisition starts, the average follows changes over the buffer
+    Never,
+    /// Clear when the buffered spectra no longer match the incoming ones
+    #[default]
+    Smart,
+}
+
+impl Display for BufferClearPolicy {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BufferClearPolicy::Always => write!(f, "Always"),
+            BufferClearPolicy::Never => write!(f, "Never"),
+            BufferClearPolicy::Smart => write!(f, "Smart"),
+        }
+    }
+}
+
+/// Change that may invalidate the averaging buffer
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BufferClearReason {
+    /// New acquisition, e.g. stream start or single shot capture, clears regardless of the policy
+    Restart,
+    /// Camera controls, bracketing or dark frame changed the incoming frames
+    CameraControls,
+    /// The spectrum window was moved or resized
+    Window,
+    /// Buffered spectra were linearized differently
+    Linearization,
+    /// Gains are applied after averaging, so the buffered spectra stay valid
+    Gain,
+}
+
+impl BufferClearReason {
+    pub fn clears(self, policy: BufferClearPolicy) -> bool {
+        match (policy, self) {
+            (_, BufferClearReason::Restart) | (BufferClearPolicy::Always, _) => true,
+            (BufferClearPolicy::Never, _) => false,
+            (BufferClearPolicy::Smart, reason) => reason != BufferClearReason::Gain,
+        }
+    }
+}
+
+impl Display for BufferClearReason {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BufferClearReason::Restart => write!(f, "acquisition restarted"),
+            BufferClearReason::CameraControls => write!(f, "camera settings changed"),
+            BufferClearReason::Window => write!(f, "spectrum window changed"),
+            BufferClearReason::Linearization => write!(f, "linearization changed"),
+            BufferClearReason::Gain => write!(f, "gains changed"),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct ImportExportConfig {
     pub path: String,
+    /// Store zero reference and snapshots and restore them on the next start
+    pub persist_session: bool,
+    /// Round exported values to this number of decimal places
+    pub decimal_places: Option<u32>,
+    /// Resample the exported spectrum to this wavelength step in nm
+    pub wavelength_step: Option<f32>,
+    /// Only export this wavelength range
+    pub wavelength_range: Option<WavelengthRange>,
+    /// Standalone HTML colorimetry report
+    pub report_path: String,
+    /// Postprocessing settings shared between machines
+    pub preset_path: String,
+    /// Add color coordinates relative to this white point to exported spectra
+    pub color_white_point: Option<Illuminant>,
+    /// Read and write CSV files with decimal commas and semicolons between columns
+    pub decimal_comma: bool,
 }
 
 impl Default for ImportExportConfig {
     fn default() -> Self {
         Self {
             path: "spectrum.csv".to_string(),
+            persist_session: false,
+            decimal_places: None,
+            wavelength_step: None,
+            wavelength_range: None,
+            report_path: "report.html".to_string(),
+            preset_path: "postprocessing.json".to_string(),
+            color_white_point: None,
+            decimal_comma: false,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RecordingConfig {
+    pub path: String,
+    pub animation_path: String,
+    pub animation_fps: f32,
+    pub animation_duration: f32,
+    /// Seconds between syncing the recording to disk, at most this much is lost on power loss
+    pub sync_interval: f32,
+}
+
+impl Default for RecordingConfig {
+    fn default() -> Self {
+        Self {
+            path: "recording.ndjson".to_string(),
+            sync_interval: 5.,
+            animation_path: "recording.gif".to_string(),
+            animation_fps: 10.,
+            animation_duration: 10.,
         }
     }
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct SampleMetadata {
+    pub sample_name: String,
+    pub operator: String,
+    pub notes: String,
+}
+
+impl SampleMetadata {
+    /// Comment lines to prepend to exported files, empty fields are omitted.
+    pub fn to_comment_lines(&self) -> Vec<String> {
+        let mut lines = Vec::new();
+        if !self.sample_name.is_empty() {
+            lines.push(format!("# Sample: {}", self.sample_name));
+        }
+        if !self.operator.is_empty() {
+            lines.push(format!("# Operator: {}", self.operator));
+        }
+        for (i, line) in self.notes.lines().enumerate() {
+            if i == 0 {
+                lines.push(format!("# Notes: {}", line));
+            } else {
+                lines.push(format!("#   {}", line));
+            }
+        }
+        lines
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
 pub struct SpectrumPoint {
     pub wavelength: f32,
     pub value: f32,
 }
 
+impl SpectrumPoint {
+    /// Linear interpolation in points sorted by wavelength, `None` outside of their range
+    pub fn interpolate(points: &[SpectrumPoint], wavelength: f32) -> Option<f32> {
+        let i = points.partition_point(|p| p.wavelength < wavelength);
+        match (i.checked_sub(1).map(|i| &points[i]), points.get(i)) {
+            (_, Some(p2)) if p2.wavelength == wavelength => Some(p2.value),
+            (Some(p1), Some(p2)) => {
+                let t = (wavelength - p1.wavelength) / (p2.wavelength - p1.wavelength);
+                Some(p1.value + t * (p2.value - p1.value))
+            }
+            _ => None,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct ReferenceConfig {
     pub reference: Option<Vec<SpectrumPoint>>,
@@ -81,13 +276,15 @@ impl Default for ReferenceConfig {
 }
 
 impl ReferenceConfig {
-    pub fn to_line(&self) -> Option<Line> {
+    /// Line with the wavelengths multiplied by `wavelength_sign` to reverse the axis if negative
+    pub fn to_line(&self, wavelength_sign: f64) -> Option<Line> {
         self.reference.as_ref().map(|reference| {
-            Line::new(PlotPoints::from_iter(
-                reference
-                    .iter()
-                    .map(|rp| [rp.wavelength as f64, (rp.value * self.scale) as f64]),
-            ))
+            Line::new(PlotPoints::from_iter(reference.iter().map(|rp| {
+                [
+                    wavelength_sign * rp.wavelength as f64,
+                    (rp.value * self.scale) as f64,
+                ]
+            })))
         })
     }
 
@@ -108,29 +305,178 @@ impl ReferenceConfig {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
 pub struct SpectrumWindow {
     pub offset: Vec2,
     pub size: Vec2,
+    /// Rotation around the center in degrees, positive angles turn the window clockwise
+    #[serde(default)]
+    pub angle: f32,
 }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+impl SpectrumWindow {
+    pub fn is_rotated(&self) -> bool {
+        self.angle != 0.
+    }
+
+    pub fn center(&self) -> Vec2 {
+        self.offset + self.size / 2.
+    }
+
+    /// Map a point relative to the center of the unrotated window into the frame
+    pub fn to_frame(&self, point: Vec2) -> Vec2 {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        self.center() + Vec2::new(point.x * cos - point.y * sin, point.x * sin + point.y * cos)
+    }
+
+    /// Corners in the frame, clockwise from the top left
+    pub fn corners(&self) -> [Vec2; 4] {
+        let half = self.size / 2.;
+        [
+            Vec2::new(-half.x, -half.y),
+            Vec2::new(half.x, -half.y),
+            Vec2::new(half.x, half.y),
+            Vec2::new(-half.x, half.y),
+        ]
+        .map(|corner| self.to_frame(corner))
+    }
+}
+
+/// Photon energy in eV times wavelength in nm
+const EV_NM: f32 = 1239.842;
+
+/// GUI refresh and preview interval in low power mode
+pub const LOW_POWER_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Line shape fitted to detected peaks for a sub-pixel center, FWHM and area
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PeakFit {
+    #[default]
+    Off,
+    Gaussian,
+    Lorentzian,
+}
+
+impl Display for PeakFit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeakFit::Off => write!(f, "Off"),
+            PeakFit::Gaussian => write!(f, "Gaussian"),
+            PeakFit::Lorentzian => write!(f, "Lorentzian"),
+        }
+    }
+}
+
+/// Estimate of the broadband background that is subtracted from the spectrum
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum BaselineCorrection {
+    #[default]
+    Off,
+    /// Asymmetric least squares smoothing that mostly follows the points below it
+    Als,
+    /// Minimum and maximum filter followed by a moving average
+    RollingBall,
+}
+
+impl Display for BaselineCorrection {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BaselineCorrection::Off => write!(f, "Off"),
+            BaselineCorrection::Als => write!(f, "Asymmetric Least Squares"),
+            BaselineCorrection::RollingBall => write!(f, "Rolling Ball"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum PeakLabelContent {
+    #[default]
+    Wavelength,
+    WavelengthAndValue,
+    PhotonEnergy,
+    Wavenumber,
+}
+
+impl Display for PeakLabelContent {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PeakLabelContent::Wavelength => write!(f, "Wavelength"),
+            PeakLabelContent::WavelengthAndValue => write!(f, "Wavelength and Value"),
+            PeakLabelContent::PhotonEnergy => write!(f, "Photon Energy"),
+            PeakLabelContent::Wavenumber => write!(f, "Wavenumber"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct PeakLabelConfig {
+    pub content: PeakLabelContent,
+    /// Decimal places of the wavelength, energy or wavenumber
+    pub decimal_places: usize,
+}
+
+impl PeakLabelConfig {
+    pub fn format(&self, point: &SpectrumPoint) -> String {
+        let precision = self.decimal_places;
+        match self.content {
+            PeakLabelContent::Wavelength => format!("{:.precision$} nm", point.wavelength),
+            PeakLabelContent::WavelengthAndValue => {
+                format!("{:.precision$} nm\n{:.3}", point.wavelength, point.value)
+            }
+            PeakLabelContent::PhotonEnergy => {
+                format!("{:.precision$} eV", EV_NM / point.wavelength)
+            }
+            PeakLabelContent::Wavenumber => {
+                format!("{:.precision$} cm⁻¹", 1e7 / point.wavelength)
+            }
+        }
+    }
+}
+
+/// Labeled gridline on the spectrum plot
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct WavelengthMarker {
+    pub wavelength: f32,
+    pub label: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct ViewConfig {
     pub window_size: PhysicalSize<u32>,
     pub image_scale: f32,
+    /// Wavelength ticks along the spectrum window on the camera preview
+    pub draw_wavelength_ruler: bool,
     pub draw_spectrum_r: bool,
     pub draw_spectrum_g: bool,
     pub draw_spectrum_b: bool,
     pub draw_spectrum_combined: bool,
+    pub draw_spectrum_colors: bool,
+    pub spectrum_color_config: SpectrumColorConfig,
     pub draw_peaks: bool,
     pub draw_dips: bool,
     pub peaks_dips_unique_window: f32,
     pub peaks_dips_find_window: usize,
+    /// Refine peaks by fitting a line shape over the find window
+    pub peak_fit: PeakFit,
+    pub peak_label_config: PeakLabelConfig,
+    /// Show decimal commas in plot and peak labels, defaults to the system locale
+    pub decimal_comma: bool,
     pub show_camera_window: bool,
     pub show_calibration_window: bool,
     pub show_postprocessing_window: bool,
     pub show_camera_control_window: bool,
     pub show_import_export_window: bool,
+    pub show_recording_window: bool,
+    pub show_network_window: bool,
+    pub show_library_window: bool,
+    pub show_additional_cameras_window: bool,
+    /// Larger hit targets for touchscreens
+    pub touch_controls: bool,
+    /// Plot decreasing wavelengths to the right, i.e. increasing photon energy
+    pub reverse_wavelength_axis: bool,
+    /// Wavelength ticks at 50 and 100 nm with minor gridlines every 10 nm
+    pub canonical_wavelength_ticks: bool,
+    pub wavelength_markers: Vec<WavelengthMarker>,
 }
 
 impl Default for ViewConfig {
@@ -142,15 +488,165 @@ impl Default for ViewConfig {
             draw_spectrum_g: true,
             draw_spectrum_b: true,
             draw_spectrum_combined: true,
+            draw_spectrum_colors: false,
+            spectrum_color_config: SpectrumColorConfig::default(),
             draw_peaks: true,
             draw_dips: true,
             peaks_dips_unique_window: 50.,
+            peak_label_config: PeakLabelConfig::default(),
+            decimal_comma: system_uses_decimal_comma(),
             peaks_dips_find_window: 5,
+            peak_fit: PeakFit::Off,
+            draw_wavelength_ruler: true,
             show_camera_window: true,
             show_calibration_window: false,
             show_postprocessing_window: false,
             show_camera_control_window: false,
             show_import_export_window: false,
+            show_recording_window: false,
+            show_network_window: false,
+            show_library_window: false,
+            show_additional_cameras_window: false,
+            touch_controls: false,
+            reverse_wavelength_axis: false,
+            canonical_wavelength_ticks: true,
+            wavelength_markers: Vec::new(),
+        }
+    }
+}
+
+/// Spectrum shown in a plot window
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub enum PlotSource {
+    #[default]
+    Live,
+    /// Snapshot with the given name
+    Snapshot(String),
+}
+
+impl Display for PlotSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PlotSource::Live => write!(f, "Live"),
+            PlotSource::Snapshot(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Additional spectrum plot in its own window with independent axes
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PlotWindowConfig {
+    pub name: String,
+    pub open: bool,
+    pub source: PlotSource,
+    /// Shown channels r, g, b and sum
+    pub channels: [bool; 4],
+    /// Fixed wavelength axis, otherwise follows the data
+    pub x_range: Option<WavelengthRange>,
+    /// Fixed upper limit of the value axis
+    pub y_max: Option<f32>,
+}
+
+impl PlotWindowConfig {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            open: true,
+            source: PlotSource::Live,
+            channels: [false, false, false, true],
+            x_range: None,
+            y_max: None,
+        }
+    }
+}
+
+/// How the rows of the spectrum window are reduced to one value per column
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum ColumnAggregation {
+    #[default]
+    Mean,
+    Median,
+    /// Robust against the trace wandering vertically within a tall window
+    Max,
+}
+
+impl Display for ColumnAggregation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ColumnAggregation::Mean => write!(f, "Mean"),
+            ColumnAggregation::Median => write!(f, "Median"),
+            ColumnAggregation::Max => write!(f, "Max"),
+        }
+    }
+}
+
+/// Direction of the dispersion in the frame
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum SpectrumOrientation {
+    /// Wavelength along the rows of the window, from left to right
+    #[default]
+    Horizontal,
+    /// Wavelength along the columns of the window, from top to bottom
+    Vertical,
+}
+
+impl Display for SpectrumOrientation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpectrumOrientation::Horizontal => write!(f, "Horizontal"),
+            SpectrumOrientation::Vertical => write!(f, "Vertical"),
+        }
+    }
+}
+
+/// Color filters of the top left 2x2 pixels of the sensor
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BayerPattern {
+    Rggb,
+    Bggr,
+    Grbg,
+    Gbrg,
+}
+
+impl BayerPattern {
+    /// Channel index 0 for red, 1 for green and 2 for blue of the pixel in the frame
+    pub fn channel(&self, x: u32, y: u32) -> usize {
+        let (even_row, odd_row) = match self {
+            BayerPattern::Rggb => ([0, 1], [1, 2]),
+            BayerPattern::Bggr => ([2, 1], [1, 0]),
+            BayerPattern::Grbg => ([1, 0], [2, 1]),
+            BayerPattern::Gbrg => ([1, 2], [0, 1]),
+        };
+        [even_row, odd_row][y as usize % 2][x as usize % 2]
+    }
+}
+
+impl Display for BayerPattern {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BayerPattern::Rggb => write!(f, "RGGB"),
+            BayerPattern::Bggr => write!(f, "BGGR"),
+            BayerPattern::Grbg => write!(f, "GRBG"),
+            BayerPattern::Gbrg => write!(f, "GBRG"),
+        }
+    }
+}
+
+/// How the windows of consecutive frames are combined before the spectrum is calculated
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum AccumulationMode {
+    /// Same scale as a single frame with finer quantization
+    #[default]
+    Mean,
+    /// Scale multiplied by the number of frames, like a longer exposure
+    Sum,
+}
+
+impl Display for AccumulationMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AccumulationMode::Mean => write!(f, "Mean"),
+            AccumulationMode::Sum => write!(f, "Sum"),
         }
     }
 }
@@ -159,6 +655,36 @@ impl Default for ViewConfig {
 pub struct ImageConfig {
     pub window: SpectrumWindow,
     pub flip: bool,
+    pub orientation: SpectrumOrientation,
+    /// Reverse the wavelength direction of the window, after the orientation is applied
+    pub rotate_180: bool,
+    pub column_aggregation: ColumnAggregation,
+    /// Color filter layout of undemosaiced frames, e.g. a GRAY format carrying the raw sensor data
+    pub bayer_pattern: Option<BayerPattern>,
+    /// Frames per second passed on for processing, further frames are dropped before decoding
+    pub max_frame_rate: Option<f32>,
+    /// Only every n-th frame is passed on for processing, 1 to process all frames
+    pub frame_decimation: usize,
+    /// Window of the second diffraction order, extracted in addition to the main window
+    pub second_order_window: Option<SpectrumWindow>,
+    /// Window of the reference beam in dual-beam mode, the main window holds the sample beam
+    pub reference_beam_window: Option<SpectrumWindow>,
+    /// Consecutive frames combined into one window by the camera thread, 1 to disable
+    pub accumulate_frames: usize,
+    pub accumulation_mode: AccumulationMode,
+    /// Seconds over which the spectra are summed into one, simulating a long exposure
+    pub long_exposure: Option<f32>,
+    /// Only process frames requested with a capture, the preview stays live
+    pub single_shot: bool,
+    /// Defects of the current frame source, stored in `SpectrometerConfig::defect_maps`
+    #[serde(skip)]
+    pub defect_map: DefectMap,
+    /// Decode MJPEG camera frames with zune-jpeg instead of the nokhwa decoder
+    pub fast_jpeg_decode: bool,
+    /// Preview frames are only sent every [`LOW_POWER_INTERVAL`], from
+    /// `SpectrometerConfig::low_power_mode`
+    #[serde(skip)]
+    pub low_power: bool,
 }
 
 impl Default for ImageConfig {
@@ -167,28 +693,192 @@ impl Default for ImageConfig {
             window: SpectrumWindow {
                 offset: Vec2::new(100., 500.),
                 size: Vec2::new(1500., 1.),
+                angle: 0.,
             },
             flip: true,
+            orientation: SpectrumOrientation::Horizontal,
+            rotate_180: false,
+            column_aggregation: ColumnAggregation::Mean,
+            bayer_pattern: None,
+            max_frame_rate: None,
+            frame_decimation: 1,
+            second_order_window: None,
+            reference_beam_window: None,
+            accumulate_frames: 1,
+            accumulation_mode: AccumulationMode::Mean,
+            long_exposure: None,
+            single_shot: false,
+            defect_map: DefectMap::default(),
+            fast_jpeg_decode: false,
+            low_power: false,
         }
     }
 }
 
 impl ImageConfig {
-    pub fn clamp(&mut self, width: f32, height: f32) {
-        self.window.offset = self.window.offset.min(Vec2::new(width, height));
-        self.window.size = self
-            .window
-            .size
-            .min(Vec2::new(width, height) - self.window.offset);
+    /// Fit the windows into a frame of the given size, returns true if one had to be changed
+    pub fn clamp(&mut self, width: f32, height: f32) -> bool {
+        let clamp = |window: &mut SpectrumWindow| {
+            let original = *window;
+            window.offset = window.offset.min(Vec2::new(width, height));
+            window.size = window.size.min(Vec2::new(width, height) - window.offset);
+            *window != original
+        };
+        let changed = clamp(&mut self.window);
+        let changed = self.second_order_window.as_mut().is_some_and(clamp) || changed;
+        self.reference_beam_window.as_mut().is_some_and(clamp) || changed
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum FrameSource {
+    #[default]
+    Camera,
+    ScreenCapture,
+    VideoFile,
+    ImageFile,
+    NetworkStream,
+}
+
+impl Display for FrameSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FrameSource::Camera => write!(f, "Camera"),
+            FrameSource::ScreenCapture => write!(f, "Screen Region"),
+            FrameSource::VideoFile => write!(f, "Video File"),
+            FrameSource::ImageFile => write!(f, "Image File"),
+            FrameSource::NetworkStream => write!(f, "Network Stream"),
+        }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Copy, Clone)]
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ScreenCaptureConfig {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub max_fps: f32,
+    /// Shell command writing an image of the region to stdout.
+    ///
+    /// `{x}`, `{y}`, `{width}` and `{height}` are replaced by the region.
+    pub command: String,
+}
+
+impl Default for ScreenCaptureConfig {
+    fn default() -> Self {
+        Self {
+            x: 0,
+            y: 0,
+            width: 640,
+            height: 480,
+            max_fps: 5.,
+            command: "grim -t ppm -g \"{x},{y} {width}x{height}\" -".to_string(),
+        }
+    }
+}
+
+impl ScreenCaptureConfig {
+    pub fn expand_command(&self) -> String {
+        self.command
+            .replace("{x}", &self.x.to_string())
+            .replace("{y}", &self.y.to_string())
+            .replace("{width}", &self.width.to_string())
+            .replace("{height}", &self.height.to_string())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct VideoFileConfig {
+    /// Any file ffmpeg can decode
+    pub path: String,
+    /// Start over at the end instead of stopping
+    pub looping: bool,
+}
+
+impl Default for VideoFileConfig {
+    fn default() -> Self {
+        Self {
+            path: "spectrum.mp4".to_string(),
+            looping: true,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ImageFileConfig {
+    /// Single image or directory whose images are cycled through in name order
+    pub path: String,
+    /// Rate at which the images are fed into the pipeline
+    pub max_fps: f32,
+}
+
+impl Default for ImageFileConfig {
+    fn default() -> Self {
+        Self {
+            path: "spectrum.png".to_string(),
+            max_fps: 5.,
+        }
+    }
+}
+
+/// Program that decodes a network stream into frames
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum StreamBackend {
+    #[default]
+    Ffmpeg,
+    GStreamer,
+}
+
+impl Display for StreamBackend {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StreamBackend::Ffmpeg => write!(f, "ffmpeg"),
+            StreamBackend::GStreamer => write!(f, "GStreamer"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct NetworkStreamConfig {
+    /// Any URL the backend can open, e.g. of an RTSP IP camera
+    pub url: String,
+    /// Request interleaved RTSP over TCP, which passes firewalls and does not lose packets
+    pub rtsp_over_tcp: bool,
+    pub backend: StreamBackend,
+    /// gst-launch-1.0 pipeline up to the decoded video, `{url}` is replaced by the URL
+    pub gstreamer_source: String,
+}
+
+impl Default for NetworkStreamConfig {
+    fn default() -> Self {
+        Self {
+            url: "rtsp://192.168.1.10:554/stream".to_string(),
+            rtsp_over_tcp: true,
+            backend: StreamBackend::Ffmpeg,
+            gstreamer_source: "rtspsrc location={url} latency=200 ! decodebin".to_string(),
+        }
+    }
+}
+
+impl NetworkStreamConfig {
+    pub fn expand_gstreamer_source(&self) -> String {
+        self.gstreamer_source.replace("{url}", &self.url)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Copy, Clone, PartialEq, Eq)]
 pub struct SpectrumCalibrationPoint {
     pub wavelength: u32,
     pub index: usize,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct WavelengthRange {
+    pub low: f32,
+    pub high: f32,
+}
+
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy)]
 pub enum GainPresets {
     Unity,
@@ -227,10 +917,12 @@ pub struct SpectrumCalibration {
     pub gain_g: f32,
     pub gain_b: f32,
     pub scaling: Option<Vec<f32>>,
+    /// Wavelengths outside of this range are not plotted, searched for peaks, exported or sent
+    pub valid_range: Option<WavelengthRange>,
 }
 
 impl SpectrumCalibration {
-    fn get_wavelength_delta(&self) -> f32 {
+    pub fn get_wavelength_delta(&self) -> f32 {
         (self.high.wavelength - self.low.wavelength) as f32
             / (self.high.index - self.low.index) as f32
     }
@@ -240,6 +932,52 @@ impl SpectrumCalibration {
             + (index as f32 - self.low.index as f32) * self.get_wavelength_delta()
     }
 
+    /// Fractional indices of round wavelengths within a spectrum with `len` values
+    ///
+    /// The wavelength step is a whole multiple of 1, 2 or 5 nm, so that the ticks are at least
+    /// `min_spacing` indices apart.
+    pub fn ruler_ticks(&self, len: usize, min_spacing: f32) -> Vec<(f32, f32)> {
+        let delta = self.get_wavelength_delta();
+        if len == 0 || !delta.is_finite() || delta == 0. {
+            return Vec::new();
+        }
+        let min_step = (min_spacing * delta.abs()).max(1.);
+        let magnitude = 10f32.powf(min_step.log10().floor());
+        let step = [1., 2., 5., 10.]
+            .into_iter()
+            .map(|factor| factor * magnitude)
+            .find(|&step| step >= min_step)
+            .unwrap_or(10. * magnitude);
+        let first = self.get_wavelength_from_index(0);
+        let last = self.get_wavelength_from_index(len - 1);
+        let low = (first.min(last) / step).ceil() as i64;
+        let high = (first.max(last) / step).floor() as i64;
+        (low..=high)
+            .map(|k| {
+                let wavelength = k as f32 * step;
+                let index =
+                    self.low.index as f32 + (wavelength - self.low.wavelength as f32) / delta;
+                (index, wavelength)
+            })
+            .collect()
+    }
+
+    /// Indices of a spectrum with `len` values that lie within the valid range
+    pub fn valid_indices(&self, len: usize) -> Range<usize> {
+        match self.valid_range {
+            Some(range) => {
+                let index = |wavelength: f32| {
+                    self.low.index as f32
+                        + (wavelength - self.low.wavelength as f32) / self.get_wavelength_delta()
+                };
+                let start = (index(range.low).ceil().max(0.) as usize).min(len);
+                let end = ((index(range.high).floor() + 1.).max(0.) as usize).clamp(start, len);
+                start..end
+            }
+            None => 0..len,
+        }
+    }
+
     pub fn get_scaling_factor_from_index(&self, index: usize) -> f32 {
         if let Some(scaling) = self.scaling.as_ref() {
             *scaling.get(index).unwrap_or(&1.)
@@ -272,15 +1010,38 @@ impl Default for SpectrumCalibration {
             gain_g: 1.0,
             gain_b: 1.0,
             scaling: None,
+            valid_range: None,
         }
     }
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct PostprocessingConfig {
     pub spectrum_buffer_size: usize,
     pub spectrum_filter_active: bool,
     pub spectrum_filter_cutoff: f32,
+    /// Capture the zero reference per channel and subtract it before linearization and gains
+    /// instead of from the final spectrum
+    pub raw_zero_reference: bool,
+    pub processing_order: ProcessingOrder,
+    /// Average and postprocess buffered spectra in `f64` instead of `f32`
+    pub double_precision: bool,
+    /// Weight buffered spectra by the time until the next frame, so that gaps from dropped
+    /// frames do not shift the average towards the frames around them
+    pub time_weighted_average: bool,
+    /// Keep a running sum of the buffer instead of summing all spectra on every update
+    ///
+    /// Not used with time weighting or double precision.
+    pub incremental_average: bool,
+    pub buffer_clear_policy: BufferClearPolicy,
+    /// Subtracted from every channel after the zero reference
+    pub baseline_correction: BaselineCorrection,
+    /// Smoothness of the ALS baseline as decimal logarithm of lambda
+    pub baseline_smoothness: f32,
+    /// Weight of the ALS points above the baseline
+    pub baseline_asymmetry: f32,
+    /// Half width of the rolling ball in points, wider than the peaks to keep
+    pub baseline_radius: usize,
 }
 
 impl Default for PostprocessingConfig {
@@ -289,20 +1050,254 @@ impl Default for PostprocessingConfig {
             spectrum_buffer_size: 10,
             spectrum_filter_active: false,
             spectrum_filter_cutoff: 0.5,
+            raw_zero_reference: false,
+            processing_order: ProcessingOrder::LinearizeThenAverage,
+            double_precision: false,
+            time_weighted_average: false,
+            incremental_average: false,
+            buffer_clear_policy: BufferClearPolicy::Smart,
+            baseline_correction: BaselineCorrection::Off,
+            baseline_smoothness: 5.,
+            baseline_asymmetry: 0.01,
+            baseline_radius: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct NarrowbandConfig {
+    /// Zoom to the dominant line and track its wavelength over time
+    pub active: bool,
+    /// Width of the plot around the line in nm
+    pub zoom_width: f32,
+    /// Seconds shown in the wavelength strip chart
+    pub history_duration: f32,
+}
+
+impl Default for NarrowbandConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            zoom_width: 5.,
+            history_duration: 60.,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DriftTrackingConfig {
+    /// Move the spectrum window vertically to follow the trace
+    pub active: bool,
+    /// Rows above and below the window included in the search
+    pub search_margin: u32,
+}
+
+impl Default for DriftTrackingConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            search_margin: 10,
         }
     }
 }
 
+/// Exposure bracketing, the frames of one cycle are merged into one spectrum
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct HdrConfig {
+    pub active: bool,
+    /// Exposure times in driver units, 100 µs for V4L2
+    pub exposures: Vec<i64>,
+    /// Frames dropped after each exposure change until the new value takes effect
+    pub settle_frames: usize,
+}
+
+impl Default for HdrConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            exposures: vec![10, 100, 1000],
+            settle_frames: 2,
+        }
+    }
+}
+
+/// Reopening the camera after the stream failed, e.g. after a USB glitch
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct ReconnectConfig {
+    pub active: bool,
+    /// Attempts before giving up, 0 for unlimited
+    pub max_attempts: u32,
+    /// Seconds before the first attempt, doubled after every failed attempt
+    pub initial_delay: f32,
+    /// Upper limit of the delay in seconds
+    pub max_delay: f32,
+}
+
+impl Default for ReconnectConfig {
+    fn default() -> Self {
+        Self {
+            active: true,
+            max_attempts: 10,
+            initial_delay: 1.,
+            max_delay: 30.,
+        }
+    }
+}
+
+impl ReconnectConfig {
+    /// Delay before the given attempt, starting at 1
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let delay =
+            self.initial_delay.max(0.1) * 2f32.powi(attempt.saturating_sub(1).min(30) as i32);
+        Duration::from_secs_f32(delay.min(self.max_delay.max(self.initial_delay.max(0.1))))
+    }
+
+    pub fn attempts_exhausted(&self, attempt: u32) -> bool {
+        self.max_attempts > 0 && attempt > self.max_attempts
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WatchdogConfig {
+    pub active: bool,
+    /// Seconds without a new spectrum until the stream is considered stalled
+    pub timeout: f32,
+    pub webhook_url: String,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            active: true,
+            timeout: 5.,
+            webhook_url: String::new(),
+        }
+    }
+}
+
+/// Named spectrum window with its own calibration
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RoiPreset {
+    pub name: String,
+    pub window: SpectrumWindow,
+    pub flip: bool,
+    pub spectrum_calibration: SpectrumCalibration,
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, Default)]
 pub struct SpectrometerConfig {
+    pub frame_source: FrameSource,
+    pub screen_capture_config: ScreenCaptureConfig,
+    pub video_file_config: VideoFileConfig,
+    pub image_file_config: ImageFileConfig,
+    pub network_stream_config: NetworkStreamConfig,
     pub camera_id: usize,
     pub camera_format: Option<CameraFormat>,
+    /// Stamp camera frames with the capture time of the V4L2 driver instead of the arrival time
+    pub driver_timestamps: bool,
+    /// Read the camera as 16 bit mono Y16 at the resolution and frame rate of `camera_format`
+    pub y16_capture: bool,
     pub image_config: ImageConfig,
+    /// Cameras streaming at the same time, each with its own window and calibration
+    pub additional_cameras: Vec<AdditionalCameraConfig>,
+    pub hdr_config: HdrConfig,
+    pub reconnect_config: ReconnectConfig,
+    pub dark_frame_config: DarkFrameConfig,
+    pub flat_field_config: FlatFieldConfig,
+    pub frame_recording_config: FrameRecordingConfig,
+    /// Defective pixels and columns by camera name or frame source
+    pub defect_maps: BTreeMap<String, DefectMap>,
+    pub hot_pixel_config: HotPixelConfig,
+    pub multi_order_config: MultiOrderConfig,
+    pub auto_exposure_config: AutoExposureConfig,
+    pub roi_presets: Vec<RoiPreset>,
+    /// Index of the preset the current window and calibration belong to
+    pub active_roi_preset: Option<usize>,
+    pub drift_tracking_config: DriftTrackingConfig,
     pub spectrum_calibration: SpectrumCalibration,
+    pub calibration_check_config: CalibrationCheckConfig,
     pub postprocessing_config: PostprocessingConfig,
     pub view_config: ViewConfig,
+    pub narrowband_config: NarrowbandConfig,
+    pub plot_windows: Vec<PlotWindowConfig>,
     pub reference_config: ReferenceConfig,
+    pub tungsten_config: TungstenConfig,
     pub import_export_config: ImportExportConfig,
+    pub library_config: LibraryConfig,
+    pub watchdog_config: WatchdogConfig,
+    pub shutter_config: ShutterConfig,
+    pub flash_trigger_config: FlashTriggerConfig,
+    pub tolerance_config: ToleranceConfig,
+    pub alarm_config: AlarmConfig,
+    pub transmission_config: TransmissionConfig,
+    pub sample_metadata: SampleMetadata,
+    pub recording_config: RecordingConfig,
+    pub sonification_config: SonificationConfig,
+    pub feed_config: FeedConfig,
+    /// Outputs that receive the spectra independently of the feed
+    pub sink_configs: Vec<SinkConfig>,
+    /// Skip the camera preview, repaint the GUI at 2 Hz and average incrementally, for small
+    /// boards that mainly serve the network feed
+    pub low_power_mode: bool,
+}
+
+impl SpectrometerConfig {
+    /// The camera delivers mono frames, so the spectrum only has an intensity channel
+    ///
+    /// Mono formats carrying undemosaiced frames still have colors.
+    pub fn is_monochrome(&self) -> bool {
+        self.frame_source == FrameSource::Camera
+            && self.image_config.bayer_pattern.is_none()
+            && self
+                .camera_format
+                .is_some_and(|format| format.format() == FrameFormat::GRAY)
+    }
+
+    /// Add a preset from the current window and calibration and make it active
+    pub fn add_roi_preset(&mut self, name: String) {
+        self.roi_presets.push(RoiPreset {
+            name,
+            window: self.image_config.window,
+            flip: self.image_config.flip,
+            spectrum_calibration: self.spectrum_calibration.clone(),
+        });
+        self.active_roi_preset = Some(self.roi_presets.len() - 1);
+    }
+
+    /// Remember changes of the current window and calibration and switch to another preset
+    pub fn select_roi_preset(&mut self, index: usize) {
+        if index >= self.roi_presets.len() {
+            return;
+        }
+        if let Some(active) = self
+            .active_roi_preset
+            .and_then(|i| self.roi_presets.get_mut(i))
+        {
+            active.window = self.image_config.window;
+            active.flip = self.image_config.flip;
+            active.spectrum_calibration = self.spectrum_calibration.clone();
+        }
+        let preset = &self.roi_presets[index];
+        self.image_config.window = preset.window;
+        self.image_config.flip = preset.flip;
+        self.spectrum_calibration = preset.spectrum_calibration.clone();
+        self.active_roi_preset = Some(index);
+    }
+
+    pub fn active_roi_preset_name(&self) -> Option<&str> {
+        self.active_roi_preset
+            .and_then(|i| self.roi_presets.get(i))
+            .map(|p| p.name.as_str())
+    }
+
+    /// Delete the active preset, the current window and calibration are kept
+    pub fn remove_active_roi_preset(&mut self) {
+        if let Some(active) = self.active_roi_preset.take() {
+            if active < self.roi_presets.len() {
+                self.roi_presets.remove(active);
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -310,6 +1305,33 @@ mod tests {
     use super::*;
     use approx::assert_relative_eq;
 
+    #[test]
+    fn roi_presets() {
+        let mut config = SpectrometerConfig::default();
+        config.image_config.window.offset.y = 100.;
+        config.add_roi_preset("order 1".to_string());
+        config.image_config.window.offset.y = 200.;
+        config.spectrum_calibration.low.wavelength = 400;
+        config.add_roi_preset("order 2".to_string());
+
+        // Changes are kept when switching away from a preset
+        config.spectrum_calibration.high.wavelength = 700;
+        config.select_roi_preset(0);
+        assert_eq!(config.image_config.window.offset.y, 100.);
+        assert_eq!(
+            config.spectrum_calibration.low.wavelength,
+            SpectrumCalibration::default().low.wavelength
+        );
+        config.select_roi_preset(1);
+        assert_eq!(config.image_config.window.offset.y, 200.);
+        assert_eq!(config.spectrum_calibration.low.wavelength, 400);
+        assert_eq!(config.spectrum_calibration.high.wavelength, 700);
+
+        config.remove_active_roi_preset();
+        assert_eq!(config.roi_presets.len(), 1);
+        assert_eq!(config.active_roi_preset, None);
+    }
+
     #[test]
     fn spectrum_calibration() {
         let low = SpectrumCalibrationPoint {
@@ -328,6 +1350,7 @@ mod tests {
             gain_g: 0.0,
             gain_b: 0.0,
             scaling: None,
+            valid_range: None,
         };
 
         assert_relative_eq!(s.get_wavelength_delta(), 2.2);
@@ -339,6 +1362,46 @@ mod tests {
         assert_relative_eq!(s.get_wavelength_from_index(101), 548.2);
     }
 
+    #[test]
+    fn valid_indices() {
+        let mut s = SpectrumCalibration {
+            low: SpectrumCalibrationPoint {
+                wavelength: 400,
+                index: 100,
+            },
+            high: SpectrumCalibrationPoint {
+                wavelength: 500,
+                index: 200,
+            },
+            ..Default::default()
+        };
+
+        assert_eq!(s.valid_indices(1000), 0..1000);
+
+        s.valid_range = Some(WavelengthRange {
+            low: 380.,
+            high: 750.5,
+        });
+        assert_eq!(s.valid_indices(1000), 80..451);
+        assert_eq!(s.valid_indices(300), 80..300);
+        assert_eq!(s.valid_indices(50), 50..50);
+
+        // One nm per index, spaced by at least 30 indices
+        assert_eq!(
+            s.ruler_ticks(300, 30.),
+            vec![
+                (0., 300.),
+                (50., 350.),
+                (100., 400.),
+                (150., 450.),
+                (200., 500.),
+                (250., 550.)
+            ]
+        );
+        assert_eq!(s.ruler_ticks(301, 120.), vec![(100., 400.), (300., 600.)]);
+        assert!(s.ruler_ticks(0, 30.).is_empty());
+    }
+
     #[test]
     fn linearize() {
         for l in [
@@ -378,19 +1441,148 @@ mod tests {
         assert_eq!(rc.get_value_at_wavelength(200.), Some(2.0));
     }
 
+    #[test]
+    fn sample_metadata() {
+        let metadata = SampleMetadata {
+            sample_name: "LED 1".to_string(),
+            operator: String::new(),
+            notes: "first\nsecond".to_string(),
+        };
+
+        assert_eq!(
+            metadata.to_comment_lines(),
+            vec!["# Sample: LED 1", "# Notes: first", "#   second"]
+        );
+        assert!(SampleMetadata::default().to_comment_lines().is_empty());
+    }
+
+    #[test]
+    fn screen_capture_command() {
+        let sc = ScreenCaptureConfig {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            max_fps: 1.,
+            command: "capture {x} {y} {width} {height}".to_string(),
+        };
+
+        assert_eq!(sc.expand_command(), "capture 1 2 3 4");
+    }
+
+    #[test]
+    fn peak_labels() {
+        let point = SpectrumPoint {
+            wavelength: 532.1,
+            value: 0.5,
+        };
+        let label = |content, decimal_places| {
+            PeakLabelConfig {
+                content,
+                decimal_places,
+            }
+            .format(&point)
+        };
+
+        assert_eq!(label(PeakLabelContent::Wavelength, 0), "532 nm");
+        assert_eq!(
+            label(PeakLabelContent::WavelengthAndValue, 1),
+            "532.1 nm\n0.500"
+        );
+        assert_eq!(label(PeakLabelContent::PhotonEnergy, 3), "2.330 eV");
+        assert_eq!(label(PeakLabelContent::Wavenumber, 0), "18793 cm⁻¹");
+    }
+
+    #[test]
+    fn reconnect_backoff() {
+        let config = ReconnectConfig {
+            max_attempts: 3,
+            initial_delay: 2.,
+            max_delay: 5.,
+            ..Default::default()
+        };
+
+        assert_eq!(config.delay(1), Duration::from_secs(2));
+        assert_eq!(config.delay(2), Duration::from_secs(4));
+        assert_eq!(config.delay(3), Duration::from_secs(5));
+        assert!(!config.attempts_exhausted(3));
+        assert!(config.attempts_exhausted(4));
+        assert!(!ReconnectConfig {
+            max_attempts: 0,
+            ..config
+        }
+        .attempts_exhausted(100));
+    }
+
     #[test]
     fn image_config() {
         let mut ic = ImageConfig {
             window: SpectrumWindow {
                 offset: Vec2::new(100., 50.),
                 size: Vec2::new(1000., 500.),
+                angle: 0.,
             },
             flip: false,
+            orientation: SpectrumOrientation::Horizontal,
+            rotate_180: false,
+            column_aggregation: ColumnAggregation::Mean,
+            bayer_pattern: None,
+            max_frame_rate: None,
+            frame_decimation: 1,
+            second_order_window: None,
+            reference_beam_window: None,
+            accumulate_frames: 1,
+            accumulation_mode: AccumulationMode::Mean,
+            long_exposure: None,
+            single_shot: false,
+            defect_map: DefectMap::default(),
+            fast_jpeg_decode: false,
+            low_power: false,
         };
 
-        ic.clamp(500., 400.);
+        assert!(ic.clamp(500., 400.));
 
         assert_eq!(ic.window.offset, Vec2::new(100., 50.));
         assert_eq!(ic.window.size, Vec2::new(400., 350.));
+        assert!(!ic.clamp(500., 400.));
+    }
+
+    #[test]
+    fn monochrome() {
+        let mut config = SpectrometerConfig {
+            camera_format: Some(CameraFormat::new_from(640, 480, FrameFormat::GRAY, 30)),
+            ..Default::default()
+        };
+        assert!(config.is_monochrome());
+        config.image_config.bayer_pattern = Some(BayerPattern::Rggb);
+        assert!(!config.is_monochrome());
+        config.image_config.bayer_pattern = None;
+        config.camera_format = Some(CameraFormat::new_from(640, 480, FrameFormat::YUYV, 30));
+        assert!(!config.is_monochrome());
+    }
+
+    #[test]
+    fn buffer_clear_policy() {
+        use BufferClearReason as R;
+
+        let reasons = [
+            R::Restart,
+            R::CameraControls,
+            R::Window,
+            R::Linearization,
+            R::Gain,
+        ];
+        let clears = |policy| {
+            reasons
+                .into_iter()
+                .filter(|r| r.clears(policy))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(clears(BufferClearPolicy::Always), reasons);
+        assert_eq!(clears(BufferClearPolicy::Never), [R::Restart]);
+        assert_eq!(
+            clears(BufferClearPolicy::Smart),
+            [R::Restart, R::CameraControls, R::Window, R::Linearization]
+        );
     }
 }
@@ -0,0 +1,185 @@
+use image::DynamicImage;
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct DarkFrameConfig {
+    /// Frames averaged into the dark frame
+    pub frames: usize,
+}
+
+impl Default for DarkFrameConfig {
+    fn default() -> Self {
+        Self { frames: 16 }
+    }
+}
+
+/// Mean of frames taken with the lens capped, subtracted from every following frame
+///
+/// The frames are expected in window depth, see [`crate::spectrum::to_window_depth`].
+#[derive(Debug, Default)]
+pub struct DarkFrame {
+    /// Frames still to be added to the capture
+    remaining: usize,
+    captured: usize,
+    sum: Vec<u32>,
+    dimensions: (u32, u32),
+    is_16_bit: bool,
+    /// Mean subpixel values once the capture is complete
+    mean: Option<Vec<f32>>,
+}
+
+impl DarkFrame {
+    /// Start capturing a new dark frame, the current one is used until the capture is complete
+    pub fn capture(&mut self, frames: usize) {
+        self.remaining = frames.max(1);
+        self.captured = 0;
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.mean.is_some()
+    }
+
+    /// Add a frame to a running capture, returns true once the dark frame is complete
+    pub fn add(&mut self, frame: &DynamicImage) -> bool {
+        if !self.is_capturing() {
+            return false;
+        }
+        let (dimensions, is_16_bit, samples): (_, _, Vec<u32>) = match frame {
+            DynamicImage::ImageRgb16(image) => (
+                image.dimensions(),
+                true,
+                image.as_raw().iter().map(|&v| v as u32).collect(),
+            ),
+            DynamicImage::ImageRgb8(image) => (
+                image.dimensions(),
+                false,
+                image.as_raw().iter().map(|&v| v as u32).collect(),
+            ),
+            _ => return false,
+        };
+        // A different format restarts the capture
+        if self.captured == 0 || self.dimensions != dimensions || self.is_16_bit != is_16_bit {
+            self.remaining += self.captured;
+            self.captured = 0;
+            self.sum = samples;
+            self.dimensions = dimensions;
+            self.is_16_bit = is_16_bit;
+        } else {
+            self.sum
+                .iter_mut()
+                .zip(samples)
+                .for_each(|(sum, v)| *sum += v);
+        }
+        self.captured += 1;
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return false;
+        }
+        let count = self.captured as f32;
+        self.mean = Some(self.sum.iter().map(|&sum| sum as f32 / count).collect());
+        self.sum = Vec::new();
+        true
+    }
+
+    /// Pixels with a channel above the median dark level by `threshold` of full scale
+    pub fn hot_pixels(&self, threshold: f32) -> Vec<(u32, u32)> {
+        let Some(mean) = self.mean.as_ref().filter(|mean| !mean.is_empty()) else {
+            return Vec::new();
+        };
+        let mut sorted = mean.clone();
+        sorted.sort_by(f32::total_cmp);
+        let full_scale = if self.is_16_bit {
+            u16::MAX as f32
+        } else {
+            u8::MAX as f32
+        };
+        let limit = sorted[(sorted.len() - 1) / 2] + threshold * full_scale;
+        let width = self.dimensions.0 as usize;
+        mean.chunks_exact(3)
+            .enumerate()
+            .filter(|(_, pixel)| pixel.iter().any(|&v| v > limit))
+            .map(|(i, _)| ((i % width) as u32, (i / width) as u32))
+            .collect()
+    }
+
+    /// Subtract the dark frame, frames of a different format are passed unchanged
+    pub fn subtract(&self, frame: DynamicImage) -> DynamicImage {
+        let Some(mean) = &self.mean else {
+            return frame;
+        };
+        let subtract = |v: f32, dark: &f32| (v - dark).round().max(0.);
+        match frame {
+            DynamicImage::ImageRgb16(mut image)
+                if self.is_16_bit && image.dimensions() == self.dimensions =>
+            {
+                image
+                    .iter_mut()
+                    .zip(mean)
+                    .for_each(|(v, dark)| *v = subtract(*v as f32, dark) as u16);
+                DynamicImage::ImageRgb16(image)
+            }
+            DynamicImage::ImageRgb8(mut image)
+                if !self.is_16_bit && image.dimensions() == self.dimensions =>
+            {
+                image
+                    .iter_mut()
+                    .zip(mean)
+                    .for_each(|(v, dark)| *v = subtract(*v as f32, dark) as u8);
+                DynamicImage::ImageRgb8(image)
+            }
+            frame => frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+
+    fn frame(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(2, 1, |x, _| {
+            Rgb([value + x as u8 * 100; 3])
+        }))
+    }
+
+    #[test]
+    fn capture_and_subtract() {
+        let mut dark_frame = DarkFrame::default();
+        assert_eq!(dark_frame.subtract(frame(50)), frame(50));
+
+        dark_frame.capture(2);
+        assert!(!dark_frame.add(&frame(10)));
+        // A frame of another size restarts the capture
+        assert!(!dark_frame.add(&DynamicImage::new_rgb8(3, 1)));
+        assert!(!dark_frame.add(&frame(10)));
+        assert!(dark_frame.add(&frame(20)));
+        assert!(!dark_frame.is_capturing());
+        // The right pixel is 100 above the left one
+        assert_eq!(dark_frame.hot_pixels(0.5), vec![]);
+        assert_eq!(dark_frame.hot_pixels(0.3), vec![(1, 0)]);
+
+        let DynamicImage::ImageRgb8(image) = dark_frame.subtract(frame(5)) else {
+            panic!("Unexpected format");
+        };
+        // Clipped at zero
+        assert_eq!(image.as_raw(), &vec![0; 6]);
+        let DynamicImage::ImageRgb8(image) = dark_frame.subtract(frame(120)) else {
+            panic!("Unexpected format");
+        };
+        assert_eq!(image.as_raw(), &vec![105; 6]);
+        // Mismatching frames are not changed
+        assert_eq!(
+            dark_frame.subtract(DynamicImage::new_rgb8(3, 1)),
+            DynamicImage::new_rgb8(3, 1)
+        );
+    }
+}
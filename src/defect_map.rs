@@ -0,0 +1,144 @@
+use crate::spectrum::WindowSubpixel;
+use image::{ImageBuffer, Pixel, Rgb};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct HotPixelConfig {
+    /// Dark frame level above the median that marks a hot pixel, relative to full scale
+    pub threshold: f32,
+    /// CSV file with one `x,y` pair per line
+    pub path: String,
+}
+
+impl Default for HotPixelConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.1,
+            path: "hot_pixels.csv".to_string(),
+        }
+    }
+}
+
+/// Read a pixel list with one `x,y` pair per line, lines starting with `#` are skipped
+pub fn read_pixel_list(path: &str) -> Result<Vec<(u32, u32)>, String> {
+    csv::ReaderBuilder::new()
+        .has_headers(false)
+        .comment(Some(b'#'))
+        .trim(csv::Trim::All)
+        .from_path(path)
+        .and_then(|mut r| r.deserialize().collect())
+        .map_err(|e| e.to_string())
+}
+
+/// Defective pixels and columns of a sensor, in unflipped frame coordinates
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct DefectMap {
+    pub pixels: Vec<(u32, u32)>,
+    pub columns: Vec<u32>,
+}
+
+impl DefectMap {
+    pub fn is_empty(&self) -> bool {
+        self.pixels.is_empty() && self.columns.is_empty()
+    }
+
+    /// Mark the pixel as defective or remove the mark
+    pub fn toggle_pixel(&mut self, x: u32, y: u32) {
+        match self.pixels.iter().position(|&p| p == (x, y)) {
+            Some(i) => {
+                self.pixels.remove(i);
+            }
+            None => self.pixels.push((x, y)),
+        }
+    }
+
+    /// Mark the column as defective or remove the mark
+    pub fn toggle_column(&mut self, x: u32) {
+        match self.columns.iter().position(|&c| c == x) {
+            Some(i) => {
+                self.columns.remove(i);
+            }
+            None => self.columns.push(x),
+        }
+    }
+
+    /// Mark the pixels as defective, returns the number of new ones
+    pub fn add_pixels(&mut self, pixels: &[(u32, u32)]) -> usize {
+        let before = self.pixels.len();
+        for &pixel in pixels {
+            if !self.pixels.contains(&pixel) {
+                self.pixels.push(pixel);
+            }
+        }
+        self.pixels.len() - before
+    }
+
+    fn is_defective(&self, x: u32, y: u32) -> bool {
+        self.columns.contains(&x) || self.pixels.contains(&(x, y))
+    }
+
+    /// Replace defective pixels with the mean of their horizontal neighbors
+    ///
+    /// `distance` is the distance to the neighbors of the same color, i.e. 2 for undemosaiced
+    /// frames. Defective neighbors are skipped, pixels without a usable neighbor are kept.
+    pub fn repair<S: WindowSubpixel>(&self, image: &mut ImageBuffer<Rgb<S>, Vec<S>>, distance: u32)
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+    {
+        let (width, height) = image.dimensions();
+        let column_pixels = self
+            .columns
+            .iter()
+            .filter(|&&x| x < width)
+            .flat_map(|&x| (0..height).map(move |y| (x, y)));
+        let pixels = self
+            .pixels
+            .iter()
+            .copied()
+            .filter(|&(x, y)| x < width && y < height);
+        for (x, y) in column_pixels.chain(pixels).collect::<Vec<_>>() {
+            let neighbors: Vec<Rgb<S>> = [x.checked_sub(distance), Some(x + distance)]
+                .into_iter()
+                .flatten()
+                .filter(|&nx| nx < width && !self.is_defective(nx, y))
+                .map(|nx| *image.get_pixel(nx, y))
+                .collect();
+            if neighbors.is_empty() {
+                continue;
+            }
+            let repaired = Rgb(std::array::from_fn(|c| {
+                S::from_f32(
+                    neighbors.iter().map(|p| p[c].as_f32()).sum::<f32>() / neighbors.len() as f32,
+                )
+            }));
+            image.put_pixel(x, y, repaired);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repair_defects() {
+        let mut image = ImageBuffer::from_fn(6, 2, |x, _| Rgb([x as u8 * 10; 3]));
+        image.put_pixel(2, 0, Rgb([255; 3]));
+        image.put_pixel(4, 1, Rgb([0; 3]));
+        let mut defect_map = DefectMap::default();
+        defect_map.toggle_pixel(2, 0);
+        defect_map.toggle_column(4);
+        defect_map.toggle_column(5);
+        defect_map.toggle_column(5);
+
+        defect_map.repair(&mut image, 1);
+
+        assert_eq!(image.get_pixel(2, 0), &Rgb([20; 3]));
+        assert_eq!(image.get_pixel(4, 0), &Rgb([40; 3]));
+        assert_eq!(image.get_pixel(4, 1), &Rgb([40; 3]));
+        assert_eq!(image.get_pixel(5, 1), &Rgb([50; 3]));
+
+        assert_eq!(defect_map.add_pixels(&[(2, 0), (3, 1), (3, 1)]), 1);
+        assert_eq!(defect_map.pixels, vec![(2, 0), (3, 1)]);
+    }
+}
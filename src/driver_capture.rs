@@ -0,0 +1,141 @@
+use nokhwa::utils::{CameraFormat, CameraIndex};
+use nokhwa::Buffer;
+use std::time::{Duration, SystemTime};
+
+/// Wall clock time of a monotonic clock reading, given both clocks read at the same moment
+pub fn monotonic_to_system(
+    timestamp: Duration,
+    monotonic_now: Duration,
+    system_now: SystemTime,
+) -> SystemTime {
+    match monotonic_now.checked_sub(timestamp) {
+        Some(age) => system_now - age,
+        None => system_now + (timestamp - monotonic_now),
+    }
+}
+
+/// Camera frames with the capture time reported by the driver
+///
+/// nokhwa drops the buffer metadata, so the frames are read from the V4L2 device directly. The
+/// camera is still opened through nokhwa, which sets the format and the controls.
+///
+/// nokhwa has no frame format for Y16, such streams get their format set here. Their buffers
+/// carry the raw 16 bit samples with the placeholder format GRAY.
+#[cfg(target_os = "linux")]
+pub struct DriverCapture {
+    stream: v4l::io::mmap::Stream<'static>,
+    format: CameraFormat,
+}
+
+#[cfg(target_os = "linux")]
+impl DriverCapture {
+    pub fn open(id: &CameraIndex, format: CameraFormat, y16: bool) -> Result<Self, String> {
+        let index = id.as_index().map_err(|e| e.to_string())?;
+        let device = v4l::Device::new(index as usize)
+            .map_err(|e| format!("Could not open V4L2 device {index}: {e}"))?;
+        if y16 {
+            set_y16_format(&device, format)?;
+        }
+        let stream =
+            v4l::io::mmap::Stream::with_buffers(&device, v4l::buffer::Type::VideoCapture, 4)
+                .map_err(|e| format!("Could not map V4L2 buffers: {e}"))?;
+        Ok(Self { stream, format })
+    }
+
+    /// Wait for the next frame, the capture time is unknown if the driver does not use the
+    /// monotonic clock
+    pub fn frame(&mut self) -> Result<(Buffer, Option<SystemTime>), String> {
+        use v4l::buffer::Flags;
+        use v4l::io::traits::CaptureStream;
+
+        let (data, metadata) = self.stream.next().map_err(|e| e.to_string())?;
+        // Compressed frames do not fill the buffer
+        let used = (metadata.bytesused as usize).min(data.len());
+        let buffer = Buffer::new(
+            self.format.resolution(),
+            &data[..used],
+            self.format.format(),
+        );
+        let capture_time = (metadata.flags & Flags::TIMESTAMP_MASK == Flags::TIMESTAMP_MONOTONIC)
+            .then(|| {
+                let timestamp = Duration::from_secs(metadata.timestamp.sec as u64)
+                    + Duration::from_micros(metadata.timestamp.usec as u64);
+                monotonic_to_system(timestamp, monotonic_now(), SystemTime::now())
+            });
+        Ok((buffer, capture_time))
+    }
+}
+
+/// Switch the device to Y16 at the resolution and frame rate of `format`
+#[cfg(target_os = "linux")]
+fn set_y16_format(device: &v4l::Device, format: CameraFormat) -> Result<(), String> {
+    use v4l::video::Capture;
+
+    let y16 = v4l::FourCC::new(b"Y16 ");
+    let requested = v4l::Format::new(format.width(), format.height(), y16);
+    let applied = device
+        .set_format(&requested)
+        .map_err(|e| format!("Could not set the Y16 format: {e}"))?;
+    // Drivers silently fall back to a supported format
+    if applied.fourcc != y16 || applied.width != format.width() || applied.height != format.height()
+    {
+        return Err(format!(
+            "The camera does not provide Y16 at {}x{}",
+            format.width(),
+            format.height()
+        ));
+    }
+    if let Err(e) = device.set_params(&v4l::video::capture::Parameters::with_fps(
+        format.frame_rate(),
+    )) {
+        log::warn!("Could not set the Y16 frame rate: {}", e);
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "linux")]
+fn monotonic_now() -> Duration {
+    let mut now = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // Cannot fail for the monotonic clock
+    unsafe { libc::clock_gettime(libc::CLOCK_MONOTONIC, &mut now) };
+    Duration::new(now.tv_sec as u64, now.tv_nsec as u32)
+}
+
+/// Driver timestamps are only read from V4L2 devices
+#[cfg(not(target_os = "linux"))]
+pub struct DriverCapture;
+
+#[cfg(not(target_os = "linux"))]
+impl DriverCapture {
+    pub fn open(_id: &CameraIndex, _format: CameraFormat, _y16: bool) -> Result<Self, String> {
+        Err("Driver timestamps and Y16 are only available on Linux".to_string())
+    }
+
+    pub fn frame(&mut self) -> Result<(Buffer, Option<SystemTime>), String> {
+        Err("Driver timestamps are only available on Linux".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn monotonic_timestamps() {
+        let system_now = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+        let monotonic_now = Duration::from_secs(50);
+
+        assert_eq!(
+            monotonic_to_system(Duration::from_millis(49_900), monotonic_now, system_now),
+            system_now - Duration::from_millis(100)
+        );
+        // Read slightly after the frame clock
+        assert_eq!(
+            monotonic_to_system(Duration::from_millis(50_001), monotonic_now, system_now),
+            system_now + Duration::from_millis(1)
+        );
+    }
+}
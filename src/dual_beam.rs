@@ -0,0 +1,48 @@
+use crate::config::SpectrumPoint;
+
+/// Reference values below this fraction of the reference maximum are treated as no signal
+const MIN_REFERENCE: f32 = 1e-3;
+
+/// Ratio of the sample beam to the reference beam at the wavelengths of the sample beam
+///
+/// Both beams come from the same lamp, so its drift cancels out. Wavelengths without reference
+/// signal are left out.
+pub fn beam_ratio(sample: &[SpectrumPoint], reference: &[SpectrumPoint]) -> Vec<SpectrumPoint> {
+    let mut reference = reference.to_vec();
+    reference.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+    let min_reference = reference.iter().map(|p| p.value).fold(0., f32::max) * MIN_REFERENCE;
+    sample
+        .iter()
+        .filter_map(|p| {
+            let reference = SpectrumPoint::interpolate(&reference, p.wavelength)?;
+            (reference > min_reference && reference > 0.).then(|| SpectrumPoint {
+                wavelength: p.wavelength,
+                value: p.value / reference,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(values: &[(f32, f32)]) -> Vec<SpectrumPoint> {
+        values
+            .iter()
+            .map(|&(wavelength, value)| SpectrumPoint { wavelength, value })
+            .collect()
+    }
+
+    #[test]
+    fn ratio() {
+        let sample = points(&[(400., 0.5), (450., 0.2), (500., 0.4), (600., 0.3)]);
+        // Descending like a calibration with decreasing wavelengths
+        let reference = points(&[(500., 0.8), (450., 0.), (400., 1.)]);
+
+        assert_eq!(
+            beam_ratio(&sample, &reference),
+            points(&[(400., 0.5), (500., 0.5)])
+        );
+    }
+}
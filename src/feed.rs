@@ -0,0 +1,1314 @@
+use crate::acquisition::AcquisitionState;
+use crate::alarm::AlarmEvent;
+use crate::config::{
+    Linearize, SpectrumCalibration, SpectrumCalibrationPoint, SpectrumPoint, WavelengthRange,
+};
+use crate::provenance::Provenance;
+use crate::spectrum::Spectrum;
+use crate::tolerance::ToleranceResult;
+use crate::{FrameMetadata, ThreadId, ThreadResult};
+use flume::{Receiver, Sender};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Maximum payload of a single UDP datagram
+const MAX_DATAGRAM_SIZE: usize = 65507;
+/// The stream status is repeated at least this often, so clients can detect a hang
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(1);
+/// Start of a binary spectrum datagram, JSON datagrams start with `{`
+pub const BINARY_MAGIC: &[u8; 4] = b"SPCB";
+/// Subscriptions that are not renewed within this time are dropped
+const SUBSCRIPTION_TIMEOUT: Duration = Duration::from_secs(60);
+/// Maximum delay until a subscription request is handled
+const SUBSCRIPTION_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Version of the message format, incremented on incompatible changes
+pub const FEED_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub enum FeedFormat {
+    #[default]
+    Json,
+    /// Spectra as little endian header and f32 arrays, other messages stay JSON
+    Binary,
+}
+
+impl Display for FeedFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FeedFormat::Json => write!(f, "JSON"),
+            FeedFormat::Binary => write!(f, "Binary"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedConfig {
+    pub multicast_group: Ipv4Addr,
+    pub port: u16,
+    pub ttl: u32,
+    /// Also send the r, g and b channels, otherwise only the sum
+    pub include_rgb: bool,
+    pub format: FeedFormat,
+    /// Local port on which clients can subscribe to a filtered unicast feed
+    pub subscription_port: Option<u16>,
+}
+
+impl Default for FeedConfig {
+    fn default() -> Self {
+        Self {
+            multicast_group: Ipv4Addr::new(239, 255, 42, 1),
+            port: 5005,
+            ttl: 1,
+            include_rgb: false,
+            format: FeedFormat::Json,
+            subscription_port: None,
+        }
+    }
+}
+
+/// Data a subscriber receives for every spectrum
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedContent {
+    /// The spectrum as sent to the multicast group
+    #[default]
+    Spectrum,
+    /// The spectrum without the r, g and b channels
+    Sum,
+    /// Integrals of the sum over the subscribed bands
+    Bands,
+    /// Highest local maxima of the sum
+    Peaks,
+}
+
+fn default_every() -> u64 {
+    1
+}
+
+fn default_max_peaks() -> usize {
+    5
+}
+
+/// Filter requested by a client, status, tolerance and alarm messages are always sent
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedSubscription {
+    #[serde(default)]
+    pub content: FeedContent,
+    /// Wavelength ranges in nm for `FeedContent::Bands`
+    #[serde(default)]
+    pub bands: Vec<[f32; 2]>,
+    #[serde(default = "default_max_peaks")]
+    pub max_peaks: usize,
+    /// Only send every n-th spectrum
+    #[serde(default = "default_every")]
+    pub every: u64,
+    #[serde(default)]
+    pub format: FeedFormat,
+}
+
+/// Representation of the spectrum in an export response
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum FeedExportFormat {
+    /// Wavelength and value pairs
+    #[default]
+    Json,
+    /// The content of a CSV file with `wavelength` and `value` columns
+    Csv,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+pub struct FeedExportRequest {
+    #[serde(default)]
+    pub format: FeedExportFormat,
+}
+
+/// Datagram sent by a client to the subscription port
+///
+/// Subscriptions have to be renewed by sending the request again at least every minute.
+/// Export requests are answered once with the newest spectrum and do not need a subscription.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedRequest {
+    Subscribe(FeedSubscription),
+    Unsubscribe,
+    Export(FeedExportRequest),
+    /// Trigger a capture in single shot mode, acknowledged with the current status
+    Capture,
+    /// Answered once with the schema of the feed
+    Schema,
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    address: SocketAddr,
+    subscription: FeedSubscription,
+    last_request: Instant,
+    spectra: u64,
+}
+
+impl Subscriber {
+    fn new(address: SocketAddr, subscription: FeedSubscription) -> Self {
+        Self {
+            address,
+            subscription,
+            last_request: Instant::now(),
+            spectra: 0,
+        }
+    }
+
+    /// The message to send to the subscriber, `None` if it is filtered out
+    fn filter(&mut self, message: &FeedMessage) -> Option<FeedMessage> {
+        let FeedMessage::Spectrum { sequence, spectrum } = message else {
+            return Some(message.clone());
+        };
+        self.spectra += 1;
+        if !(self.spectra - 1).is_multiple_of(self.subscription.every.max(1)) {
+            return None;
+        }
+        let sequence = *sequence;
+        let timestamp = spectrum.timestamp;
+        Some(match self.subscription.content {
+            FeedContent::Spectrum => message.clone(),
+            FeedContent::Sum => FeedMessage::Spectrum {
+                sequence,
+                spectrum: FeedSpectrum {
+                    r: None,
+                    g: None,
+                    b: None,
+                    ..spectrum.clone()
+                },
+            },
+            FeedContent::Bands => FeedMessage::Bands {
+                sequence,
+                timestamp,
+                bands: self
+                    .subscription
+                    .bands
+                    .iter()
+                    .map(|&[low, high]| FeedBand {
+                        low,
+                        high,
+                        integral: spectrum.integral(low, high),
+                    })
+                    .collect(),
+                frame_sequence: spectrum.frame_sequence,
+                saturation: spectrum.saturation,
+            },
+            FeedContent::Peaks => FeedMessage::Peaks {
+                sequence,
+                timestamp,
+                peaks: spectrum.peaks(self.subscription.max_peaks),
+                frame_sequence: spectrum.frame_sequence,
+                saturation: spectrum.saturation,
+            },
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedSpectrum {
+    /// Seconds since the unix epoch
+    pub timestamp: f64,
+    /// Sequence number of the newest averaged frame, not part of binary datagrams
+    ///
+    /// Unlike the message sequence it counts frames, so gaps show frames that were not sent.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_sequence: Option<u64>,
+    /// Wavelength of the first value in nm
+    pub wavelength_offset: f32,
+    /// Wavelength step between values in nm
+    pub wavelength_delta: f32,
+    pub sum: Vec<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub r: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub g: Option<Vec<f32>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub b: Option<Vec<f32>>,
+    /// Camera metadata of the newest averaged frame, not part of binary datagrams
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_metadata: Option<FrameMetadata>,
+    /// Highest fraction of saturated window subpixels of the averaged frames, not part of binary
+    /// datagrams. Spectra with a saturation above zero are clipped.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub saturation: Option<f32>,
+}
+
+impl FeedSpectrum {
+    pub fn new(
+        spectrum: &Spectrum,
+        calibration: &SpectrumCalibration,
+        timestamp: SystemTime,
+        frame_sequence: Option<u64>,
+        frame_metadata: Option<FrameMetadata>,
+        saturation: Option<f32>,
+        include_rgb: bool,
+    ) -> Self {
+        let valid_indices = calibration.valid_indices(spectrum.ncols());
+        let row = |i: usize| {
+            spectrum
+                .row(i)
+                .iter()
+                .skip(valid_indices.start)
+                .take(valid_indices.len())
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        Self {
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            frame_sequence,
+            wavelength_offset: calibration.get_wavelength_from_index(valid_indices.start),
+            wavelength_delta: calibration.get_wavelength_delta(),
+            sum: row(3),
+            r: include_rgb.then(|| row(0)),
+            g: include_rgb.then(|| row(1)),
+            b: include_rgb.then(|| row(2)),
+            frame_metadata,
+            saturation,
+        }
+    }
+
+    fn points(&self) -> impl Iterator<Item = SpectrumPoint> + '_ {
+        self.sum
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| SpectrumPoint {
+                wavelength: self.wavelength_offset + i as f32 * self.wavelength_delta,
+                value,
+            })
+    }
+
+    /// Integral of the sum between the wavelengths
+    pub fn integral(&self, low: f32, high: f32) -> f32 {
+        self.points()
+            .filter(|p| (low..=high).contains(&p.wavelength))
+            .map(|p| p.value)
+            .sum::<f32>()
+            * self.wavelength_delta.abs()
+    }
+
+    /// Sum as CSV with a header line
+    pub fn to_csv(&self) -> Result<String, String> {
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for point in self.points() {
+            writer.serialize(point).map_err(|e| e.to_string())?;
+        }
+        let data = writer.into_inner().map_err(|e| e.to_string())?;
+        String::from_utf8(data).map_err(|e| e.to_string())
+    }
+
+    /// Highest local maxima of the sum, ordered by wavelength
+    pub fn peaks(&self, max_peaks: usize) -> Vec<SpectrumPoint> {
+        let points: Vec<_> = self.points().collect();
+        let mut peaks: Vec<_> = points
+            .windows(3)
+            .filter(|w| w[1].value > w[0].value && w[1].value >= w[2].value)
+            .map(|w| w[1])
+            .collect();
+        peaks.sort_by(|a, b| b.value.total_cmp(&a.value));
+        peaks.truncate(max_peaks);
+        peaks.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        peaks
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct FeedBand {
+    pub low: f32,
+    pub high: f32,
+    pub integral: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamState {
+    Running,
+    /// Stopped, playing back a recording or measuring dark, no spectra are sent
+    #[default]
+    Paused,
+    Error,
+}
+
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Default)]
+pub struct FeedStatus {
+    pub state: StreamState,
+    /// Reason for the state, e.g. the camera error
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub message: Option<String>,
+}
+
+impl FeedStatus {
+    pub fn new(state: StreamState, message: Option<String>) -> Self {
+        Self { state, message }
+    }
+}
+
+/// Calibration the wavelengths and values of the spectra are based on
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedCalibration {
+    pub low: SpectrumCalibrationPoint,
+    pub high: SpectrumCalibrationPoint,
+    pub linearize: Linearize,
+    /// Gains of the r, g and b channels
+    pub gains: [f32; 3],
+    /// The values are corrected by a spectral response scaling
+    pub scaled: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub valid_range: Option<WavelengthRange>,
+}
+
+impl FeedCalibration {
+    pub fn new(calibration: &SpectrumCalibration) -> Self {
+        Self {
+            low: calibration.low,
+            high: calibration.high,
+            linearize: calibration.linearize,
+            gains: [calibration.gain_r, calibration.gain_g, calibration.gain_b],
+            scaled: calibration.scaling.is_some(),
+            valid_range: calibration.valid_range,
+        }
+    }
+}
+
+/// Field of a JSON message
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedField {
+    pub name: String,
+    /// JSON type, arrays are written as `number[]`
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unit: Option<String>,
+    /// The field may be missing
+    pub optional: bool,
+}
+
+/// Self-description of the feed, so clients can check their compatibility
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FeedSchema {
+    pub version: u32,
+    pub application_version: String,
+    /// Start of binary spectrum datagrams
+    pub binary_magic: String,
+    /// Fields of every JSON message
+    pub envelope: Vec<FeedField>,
+    /// Fields of the JSON messages by their type
+    pub messages: BTreeMap<String, Vec<FeedField>>,
+    /// Calibration of the current spectra, missing before the first spectrum
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub calibration: Option<FeedCalibration>,
+}
+
+impl FeedSchema {
+    pub fn new(calibration: Option<FeedCalibration>) -> Self {
+        // Name, type, unit and whether the field is optional
+        let field = |(name, kind, unit, optional): (&str, &str, &str, bool)| FeedField {
+            name: name.to_string(),
+            kind: kind.to_string(),
+            unit: (!unit.is_empty()).then(|| unit.to_string()),
+            optional,
+        };
+        let sequence = ("sequence", "integer", "", false);
+        let timestamp = ("timestamp", "number", "s", false);
+        let frame_sequence = ("frame_sequence", "integer", "", true);
+        let saturation = ("saturation", "number", "", true);
+        let messages = [
+            (
+                "spectrum",
+                vec![
+                    sequence,
+                    timestamp,
+                    frame_sequence,
+                    ("wavelength_offset", "number", "nm", false),
+                    ("wavelength_delta", "number", "nm", false),
+                    ("sum", "number[]", "a.u.", false),
+                    ("r", "number[]", "a.u.", true),
+                    ("g", "number[]", "a.u.", true),
+                    ("b", "number[]", "a.u.", true),
+                    ("frame_metadata", "object", "", true),
+                    saturation,
+                ],
+            ),
+            (
+                "tolerance",
+                vec![
+                    sequence,
+                    ("passed", "boolean", "", false),
+                    ("max_deviation", "number", "a.u.", false),
+                    ("wavelength", "number", "nm", false),
+                ],
+            ),
+            (
+                "alarm",
+                vec![
+                    sequence,
+                    ("name", "string", "", false),
+                    ("triggered", "boolean", "", false),
+                    ("value", "number", "a.u.", false),
+                    ("threshold", "number", "a.u.", false),
+                ],
+            ),
+            (
+                "bands",
+                vec![
+                    sequence,
+                    timestamp,
+                    ("bands", "object[]", "", false),
+                    frame_sequence,
+                    saturation,
+                ],
+            ),
+            (
+                "peaks",
+                vec![
+                    sequence,
+                    timestamp,
+                    ("peaks", "object[]", "", false),
+                    frame_sequence,
+                    saturation,
+                ],
+            ),
+            (
+                "export",
+                vec![
+                    sequence,
+                    ("timestamp", "number", "s", true),
+                    ("points", "object[]", "", true),
+                    ("csv", "string", "", true),
+                    ("error", "string", "", true),
+                ],
+            ),
+            (
+                "status",
+                vec![
+                    sequence,
+                    timestamp,
+                    ("state", "string", "", false),
+                    ("message", "string", "", true),
+                ],
+            ),
+            (
+                "schema",
+                vec![
+                    sequence,
+                    ("version", "integer", "", false),
+                    ("application_version", "string", "", false),
+                    ("binary_magic", "string", "", false),
+                    ("envelope", "object[]", "", false),
+                    ("messages", "object", "", false),
+                    ("calibration", "object", "", true),
+                ],
+            ),
+        ];
+        Self {
+            version: FEED_VERSION,
+            application_version: env!("CARGO_PKG_VERSION").to_string(),
+            binary_magic: String::from_utf8_lossy(BINARY_MAGIC).to_string(),
+            envelope: [
+                ("type", "string", "", false),
+                ("provenance", "object", "", true),
+            ]
+            .into_iter()
+            .map(field)
+            .collect(),
+            messages: messages
+                .into_iter()
+                .map(|(name, fields)| (name.to_string(), fields.into_iter().map(field).collect()))
+                .collect(),
+            calibration,
+        }
+    }
+}
+
+/// One datagram on the feed
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum FeedMessage {
+    Spectrum {
+        sequence: u64,
+        #[serde(flatten)]
+        spectrum: FeedSpectrum,
+    },
+    /// Sent when the tolerance check changes between pass and fail
+    Tolerance {
+        sequence: u64,
+        #[serde(flatten)]
+        result: ToleranceResult,
+    },
+    /// Sent when a peak alarm is triggered or cleared
+    Alarm {
+        sequence: u64,
+        #[serde(flatten)]
+        event: AlarmEvent,
+    },
+    /// Band integrals of a spectrum, only sent to subscribers
+    Bands {
+        sequence: u64,
+        /// Seconds since the unix epoch
+        timestamp: f64,
+        bands: Vec<FeedBand>,
+        /// Frame sequence number of the spectrum
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame_sequence: Option<u64>,
+        /// Saturation of the spectrum
+        #[serde(skip_serializing_if = "Option::is_none")]
+        saturation: Option<f32>,
+    },
+    /// Peaks of a spectrum, only sent to subscribers
+    Peaks {
+        sequence: u64,
+        /// Seconds since the unix epoch
+        timestamp: f64,
+        peaks: Vec<SpectrumPoint>,
+        /// Frame sequence number of the spectrum
+        #[serde(skip_serializing_if = "Option::is_none")]
+        frame_sequence: Option<u64>,
+        /// Saturation of the spectrum
+        #[serde(skip_serializing_if = "Option::is_none")]
+        saturation: Option<f32>,
+    },
+    /// Answer to an export request, only sent to the requesting client
+    Export {
+        sequence: u64,
+        /// Seconds since the unix epoch of the exported spectrum
+        #[serde(skip_serializing_if = "Option::is_none")]
+        timestamp: Option<f64>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        points: Option<Vec<SpectrumPoint>>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        csv: Option<String>,
+        /// Reason why the spectrum could not be exported
+        #[serde(skip_serializing_if = "Option::is_none")]
+        error: Option<String>,
+    },
+    /// Sent when the stream state changes and as keep-alive
+    Status {
+        sequence: u64,
+        /// Seconds since the unix epoch
+        timestamp: f64,
+        #[serde(flatten)]
+        status: FeedStatus,
+    },
+    /// Answer to a schema request, only sent to the requesting client
+    Schema {
+        sequence: u64,
+        #[serde(flatten)]
+        schema: FeedSchema,
+    },
+}
+
+/// JSON message with the provenance of the spectra
+#[derive(Serialize)]
+struct FeedEnvelope<'a> {
+    #[serde(flatten)]
+    message: &'a FeedMessage,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provenance: Option<&'a Provenance>,
+}
+
+impl FeedMessage {
+    /// Answer to an export request, with an error if no spectrum was sent yet
+    pub fn export(
+        sequence: u64,
+        spectrum: Option<&FeedSpectrum>,
+        format: FeedExportFormat,
+    ) -> FeedMessage {
+        let (points, csv, error) = match (spectrum, format) {
+            (None, _) => (None, None, Some("No spectrum available".to_string())),
+            (Some(spectrum), FeedExportFormat::Json) => {
+                (Some(spectrum.points().collect()), None, None)
+            }
+            (Some(spectrum), FeedExportFormat::Csv) => match spectrum.to_csv() {
+                Ok(csv) => (None, Some(csv), None),
+                Err(e) => (None, None, Some(e)),
+            },
+        };
+        FeedMessage::Export {
+            sequence,
+            timestamp: spectrum.map(|s| s.timestamp),
+            points,
+            csv,
+            error,
+        }
+    }
+
+    /// Encode the message as one datagram
+    ///
+    /// JSON messages carry the provenance if it is known, binary spectrum datagrams do not.
+    ///
+    /// Binary spectrum datagrams consist of the magic `SPCB`, the sequence number (u64), the
+    /// timestamp (f64), the wavelength offset and delta (f32), the number of channels and of
+    /// values per channel (u32), followed by the sum and optionally r, g and b as f32 arrays.
+    /// All values are little endian.
+    pub fn encode(
+        &self,
+        format: FeedFormat,
+        provenance: Option<&Provenance>,
+    ) -> Result<Vec<u8>, String> {
+        let datagram = match (format, self) {
+            (FeedFormat::Binary, FeedMessage::Spectrum { sequence, spectrum }) => {
+                Self::encode_binary_spectrum(*sequence, spectrum)
+            }
+            _ => serde_json::to_vec(&FeedEnvelope {
+                message: self,
+                provenance,
+            })
+            .map_err(|e| e.to_string())?,
+        };
+        if datagram.len() > MAX_DATAGRAM_SIZE {
+            return Err(format!(
+                "Feed message too large for one datagram ({} bytes)",
+                datagram.len()
+            ));
+        }
+        Ok(datagram)
+    }
+
+    fn encode_binary_spectrum(sequence: u64, spectrum: &FeedSpectrum) -> Vec<u8> {
+        let channels: Vec<&Vec<f32>> = [
+            Some(&spectrum.sum),
+            spectrum.r.as_ref(),
+            spectrum.g.as_ref(),
+            spectrum.b.as_ref(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+
+        let mut datagram = Vec::with_capacity(36 + channels.len() * spectrum.sum.len() * 4);
+        datagram.extend_from_slice(BINARY_MAGIC);
+        datagram.extend_from_slice(&sequence.to_le_bytes());
+        datagram.extend_from_slice(&spectrum.timestamp.to_le_bytes());
+        datagram.extend_from_slice(&spectrum.wavelength_offset.to_le_bytes());
+        datagram.extend_from_slice(&spectrum.wavelength_delta.to_le_bytes());
+        datagram.extend_from_slice(&(channels.len() as u32).to_le_bytes());
+        datagram.extend_from_slice(&(spectrum.sum.len() as u32).to_le_bytes());
+        for channel in channels {
+            for v in channel {
+                datagram.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+        datagram
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum FeedEvent {
+    Start(FeedConfig),
+    Stop,
+    Spectrum(FeedSpectrum),
+    Tolerance(ToleranceResult),
+    Alarm(AlarmEvent),
+    Status(FeedStatus),
+    /// Attached to all following JSON messages
+    Provenance(Provenance),
+    /// Described in schema responses
+    Calibration(FeedCalibration),
+    /// Capture requests are only passed on while the acquisition is running
+    Acquisition(AcquisitionState),
+}
+
+/// Broadcasts spectra as JSON datagrams to a UDP multicast group and sends filtered feeds to
+/// unicast subscribers.
+pub struct FeedThread {
+    event_rx: Receiver<FeedEvent>,
+    result_tx: Sender<ThreadResult>,
+}
+
+impl FeedThread {
+    pub fn new(event_rx: Receiver<FeedEvent>, result_tx: Sender<ThreadResult>) -> Self {
+        Self {
+            event_rx,
+            result_tx,
+        }
+    }
+
+    /// Runs until the sending side of the event channel is dropped
+    pub fn run(&mut self) {
+        let mut socket: Option<(UdpSocket, FeedConfig)> = None;
+        let mut subscribers = Vec::new();
+        let mut sequence = 0;
+        let mut status = FeedStatus::default();
+        let mut provenance = None;
+        let mut calibration = None;
+        let mut acquisition = AcquisitionState::Idle;
+        let mut newest_spectrum = None;
+        let mut last_status = Instant::now();
+        loop {
+            let mut timeout = KEEP_ALIVE_INTERVAL.saturating_sub(last_status.elapsed());
+            if let Some((s, config)) = socket.as_ref() {
+                if config.subscription_port.is_some() {
+                    Self::receive_requests(
+                        s,
+                        &self.result_tx,
+                        &mut subscribers,
+                        sequence,
+                        &status,
+                        newest_spectrum.as_ref(),
+                        provenance.as_ref(),
+                        calibration.as_ref(),
+                        &acquisition,
+                    );
+                    timeout = timeout.min(SUBSCRIPTION_POLL_INTERVAL);
+                }
+            }
+            let message = match self.event_rx.recv_timeout(timeout) {
+                Ok(FeedEvent::Start(config)) => match Self::open_socket(&config) {
+                    Ok(s) => {
+                        socket = Some((s, config));
+                        subscribers.clear();
+                        self.send_result(Ok(()));
+                        Self::status_message(sequence, &status)
+                    }
+                    Err(e) => {
+                        self.send_result(Err(e));
+                        continue;
+                    }
+                },
+                Ok(FeedEvent::Stop) => {
+                    socket = None;
+                    subscribers.clear();
+                    continue;
+                }
+                Ok(FeedEvent::Spectrum(spectrum)) => {
+                    newest_spectrum = Some(spectrum.clone());
+                    FeedMessage::Spectrum { sequence, spectrum }
+                }
+                Ok(FeedEvent::Tolerance(result)) => FeedMessage::Tolerance { sequence, result },
+                Ok(FeedEvent::Alarm(event)) => FeedMessage::Alarm { sequence, event },
+                Ok(FeedEvent::Status(new_status)) => {
+                    status = new_status;
+                    Self::status_message(sequence, &status)
+                }
+                Ok(FeedEvent::Provenance(new_provenance)) => {
+                    provenance = Some(new_provenance);
+                    continue;
+                }
+                Ok(FeedEvent::Calibration(new_calibration)) => {
+                    calibration = Some(new_calibration);
+                    continue;
+                }
+                Ok(FeedEvent::Acquisition(state)) => {
+                    acquisition = state;
+                    continue;
+                }
+                Err(flume::RecvTimeoutError::Timeout)
+                    if last_status.elapsed() >= KEEP_ALIVE_INTERVAL =>
+                {
+                    Self::status_message(sequence, &status)
+                }
+                Err(flume::RecvTimeoutError::Timeout) => continue,
+                Err(flume::RecvTimeoutError::Disconnected) => break,
+            };
+            if let FeedMessage::Status { .. } = message {
+                last_status = Instant::now();
+            }
+            if let Some((s, config)) = socket.as_ref() {
+                sequence += 1;
+                // Messages that cannot be encoded are skipped, only socket errors stop the feed
+                match message.encode(config.format, provenance.as_ref()) {
+                    Ok(datagram) => {
+                        if let Err(e) =
+                            Self::send_datagram(s, &datagram, (config.multicast_group, config.port))
+                        {
+                            log::error!("Could not send feed message: {}", e);
+                            socket = None;
+                            subscribers.clear();
+                            self.send_result(Err(e));
+                            continue;
+                        }
+                    }
+                    Err(e) => log::warn!("Skipping feed message: {}", e),
+                }
+                // Subscribers that cannot be reached are dropped, they may subscribe again
+                subscribers.retain_mut(|subscriber: &mut Subscriber| {
+                    let Some(message) = subscriber.filter(&message) else {
+                        return true;
+                    };
+                    let datagram =
+                        match message.encode(subscriber.subscription.format, provenance.as_ref()) {
+                            Ok(datagram) => datagram,
+                            Err(e) => {
+                                log::warn!(
+                                    "Skipping feed message for subscriber {}: {}",
+                                    subscriber.address,
+                                    e
+                                );
+                                return true;
+                            }
+                        };
+                    let result = Self::send_datagram(s, &datagram, subscriber.address);
+                    if let Err(e) = &result {
+                        log::warn!("Dropping feed subscriber {}: {}", subscriber.address, e);
+                    }
+                    result.is_ok()
+                });
+            }
+        }
+    }
+
+    /// Send one datagram, a full send buffer only drops the datagram
+    fn send_datagram(
+        socket: &UdpSocket,
+        datagram: &[u8],
+        address: impl std::net::ToSocketAddrs,
+    ) -> Result<(), String> {
+        match socket.send_to(datagram, address) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => {
+                log::warn!("Feed datagram dropped, send buffer full");
+                Ok(())
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Handle pending subscription, export, capture and schema requests and drop expired
+    /// subscriptions
+    ///
+    /// New subscribers receive the current status right away as acknowledgement.
+    #[allow(clippy::too_many_arguments)]
+    fn receive_requests(
+        socket: &UdpSocket,
+        result_tx: &Sender<ThreadResult>,
+        subscribers: &mut Vec<Subscriber>,
+        sequence: u64,
+        status: &FeedStatus,
+        newest_spectrum: Option<&FeedSpectrum>,
+        provenance: Option<&Provenance>,
+        calibration: Option<&FeedCalibration>,
+        acquisition: &AcquisitionState,
+    ) {
+        let mut buffer = [0; 1024];
+        loop {
+            let (len, address) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => {
+                    log::warn!("Could not receive feed request: {}", e);
+                    break;
+                }
+            };
+            let request = match serde_json::from_slice(&buffer[..len]) {
+                Ok(request) => request,
+                Err(e) => {
+                    log::warn!("Invalid feed request from {}: {}", address, e);
+                    continue;
+                }
+            };
+            match request {
+                FeedRequest::Export(export) => {
+                    let response = FeedMessage::export(sequence, newest_spectrum, export.format)
+                        .encode(FeedFormat::Json, provenance)
+                        .and_then(|datagram| Self::send_datagram(socket, &datagram, address));
+                    if let Err(e) = response {
+                        log::warn!("Could not answer feed export request: {}", e);
+                    }
+                }
+                FeedRequest::Schema => {
+                    let schema = FeedMessage::Schema {
+                        sequence,
+                        schema: FeedSchema::new(calibration.cloned()),
+                    };
+                    let response = schema
+                        .encode(FeedFormat::Json, provenance)
+                        .and_then(|datagram| Self::send_datagram(socket, &datagram, address));
+                    if let Err(e) = response {
+                        log::warn!("Could not answer feed schema request: {}", e);
+                    }
+                }
+                FeedRequest::Capture => {
+                    // The status of the acknowledgement tells the client why nothing was captured
+                    if acquisition.is_running() {
+                        result_tx
+                            .send(ThreadResult {
+                                id: ThreadId::CaptureRequest,
+                                result: Ok(()),
+                            })
+                            .ok();
+                    } else {
+                        log::warn!("Capture request from {} while {}", address, acquisition);
+                    }
+                    let acknowledgement = Self::status_message(sequence, status)
+                        .encode(FeedFormat::Json, None)
+                        .and_then(|datagram| Self::send_datagram(socket, &datagram, address));
+                    if let Err(e) = acknowledgement {
+                        log::warn!("Could not acknowledge capture request: {}", e);
+                    }
+                }
+                FeedRequest::Subscribe(subscription) => {
+                    subscribers.retain(|s| s.address != address);
+                    let subscriber = Subscriber::new(address, subscription);
+                    let acknowledgement = Self::status_message(sequence, status)
+                        .encode(FeedFormat::Json, None)
+                        .and_then(|datagram| Self::send_datagram(socket, &datagram, address));
+                    if let Err(e) = acknowledgement {
+                        log::warn!("Could not acknowledge feed subscription: {}", e);
+                    }
+                    subscribers.push(subscriber);
+                }
+                FeedRequest::Unsubscribe => subscribers.retain(|s| s.address != address),
+            }
+        }
+        subscribers.retain(|s| s.last_request.elapsed() < SUBSCRIPTION_TIMEOUT);
+    }
+
+    fn status_message(sequence: u64, status: &FeedStatus) -> FeedMessage {
+        FeedMessage::Status {
+            sequence,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64(),
+            status: status.clone(),
+        }
+    }
+
+    fn open_socket(config: &FeedConfig) -> Result<UdpSocket, String> {
+        let socket = UdpSocket::bind((
+            Ipv4Addr::UNSPECIFIED,
+            config.subscription_port.unwrap_or_default(),
+        ))
+        .map_err(|e| e.to_string())?;
+        socket
+            .set_multicast_ttl_v4(config.ttl)
+            .map_err(|e| e.to_string())?;
+        if config.subscription_port.is_some() {
+            socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        }
+        Ok(socket)
+    }
+
+    fn send_result(&self, result: Result<(), String>) {
+        self.result_tx
+            .send(ThreadResult {
+                id: ThreadId::Feed,
+                result,
+            })
+            .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Exposure;
+
+    #[test]
+    fn encode_spectrum() {
+        let calibration = SpectrumCalibration::default();
+        let spectrum = FeedSpectrum::new(
+            &Spectrum::from_element(10, 0.5),
+            &calibration,
+            UNIX_EPOCH,
+            None,
+            Some(FrameMetadata {
+                exposure: Exposure {
+                    exposure_time: Some(156),
+                    gain: None,
+                },
+                frame_number: Some(7),
+                capture_time: None,
+            }),
+            Some(0.01),
+            false,
+        );
+        let message = FeedMessage::Spectrum {
+            sequence: 42,
+            spectrum,
+        };
+
+        let provenance = Provenance::new(&Default::default());
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&message.encode(FeedFormat::Json, Some(&provenance)).unwrap())
+                .unwrap();
+
+        assert_eq!(json["type"], "spectrum");
+        assert_eq!(json["sequence"], 42);
+        assert_eq!(json["sum"].as_array().unwrap().len(), 10);
+        assert!(json.get("r").is_none());
+        assert_eq!(
+            json["frame_metadata"],
+            serde_json::json!({"exposure_time": 156, "frame_number": 7})
+        );
+        approx::assert_relative_eq!(json["saturation"].as_f64().unwrap(), 0.01, epsilon = 1e-6);
+        assert_eq!(json["provenance"]["version"], env!("CARGO_PKG_VERSION"));
+    }
+
+    #[test]
+    fn schema() {
+        let request: FeedRequest = serde_json::from_str(r#"{"type": "schema"}"#).unwrap();
+        assert_eq!(request, FeedRequest::Schema);
+
+        let calibration = FeedCalibration::new(&SpectrumCalibration::default());
+        let schema = FeedSchema::new(Some(calibration.clone()));
+        let message = FeedMessage::Schema {
+            sequence: 1,
+            schema: schema.clone(),
+        };
+        let json: serde_json::Value =
+            serde_json::from_slice(&message.encode(FeedFormat::Json, None).unwrap()).unwrap();
+        assert_eq!(json["type"], "schema");
+        assert_eq!(json["version"], FEED_VERSION);
+        assert_eq!(json["binary_magic"], "SPCB");
+        assert_eq!(
+            serde_json::from_value::<FeedCalibration>(json["calibration"].clone()).unwrap(),
+            calibration
+        );
+
+        // The described fields match a spectrum with all optional fields
+        let spectrum = FeedMessage::Spectrum {
+            sequence: 1,
+            spectrum: FeedSpectrum {
+                frame_sequence: Some(3),
+                frame_metadata: Some(Default::default()),
+                saturation: Some(0.),
+                ..FeedSpectrum::new(
+                    &Spectrum::from_element(2, 0.5),
+                    &SpectrumCalibration::default(),
+                    UNIX_EPOCH,
+                    None,
+                    None,
+                    None,
+                    true,
+                )
+            },
+        };
+        let json: serde_json::Value =
+            serde_json::from_slice(&spectrum.encode(FeedFormat::Json, None).unwrap()).unwrap();
+        let mut keys: Vec<&String> = json.as_object().unwrap().keys().collect();
+        keys.sort();
+        let mut fields: Vec<&String> = schema.messages["spectrum"]
+            .iter()
+            .chain(&schema.envelope[..1])
+            .map(|field| &field.name)
+            .collect();
+        fields.sort();
+        assert_eq!(keys, fields);
+    }
+
+    #[test]
+    fn encode_status() {
+        let message = FeedMessage::Status {
+            sequence: 3,
+            timestamp: 0.,
+            status: FeedStatus::new(StreamState::Error, Some("Camera lost".to_string())),
+        };
+
+        let json: serde_json::Value =
+            serde_json::from_slice(&message.encode(FeedFormat::Binary, None).unwrap()).unwrap();
+
+        assert_eq!(json["type"], "status");
+        assert_eq!(json["state"], "error");
+        assert_eq!(json["message"], "Camera lost");
+        assert!(json.get("provenance").is_none());
+        let json = serde_json::to_value(FeedStatus::default()).unwrap();
+        assert_eq!(json, serde_json::json!({"state": "paused"}));
+    }
+
+    #[test]
+    fn encode_too_large() {
+        let spectrum = FeedSpectrum::new(
+            &Spectrum::from_element(10000, 0.123456),
+            &SpectrumCalibration::default(),
+            UNIX_EPOCH,
+            None,
+            None,
+            None,
+            true,
+        );
+
+        assert!(FeedMessage::Spectrum {
+            sequence: 0,
+            spectrum
+        }
+        .encode(FeedFormat::Json, None)
+        .is_err());
+    }
+
+    #[test]
+    fn filter_subscription() {
+        let request: FeedRequest = serde_json::from_str(
+            r#"{"type": "subscribe", "content": "bands", "bands": [[400, 420]], "every": 2}"#,
+        )
+        .unwrap();
+        let FeedRequest::Subscribe(subscription) = request else {
+            panic!("Not a subscription");
+        };
+        let mut subscriber = Subscriber::new(([127, 0, 0, 1], 4000).into(), subscription);
+        let spectrum = FeedSpectrum {
+            timestamp: 0.,
+            frame_sequence: Some(12),
+            wavelength_offset: 400.,
+            wavelength_delta: 10.,
+            sum: vec![0.1, 0.5, 0.2, 0.8, 0.3],
+            r: None,
+            g: None,
+            b: None,
+            frame_metadata: None,
+            saturation: None,
+        };
+        let message = FeedMessage::Spectrum {
+            sequence: 7,
+            spectrum: spectrum.clone(),
+        };
+
+        let Some(FeedMessage::Bands {
+            sequence,
+            bands,
+            frame_sequence,
+            ..
+        }) = subscriber.filter(&message)
+        else {
+            panic!("No bands");
+        };
+        assert_eq!(sequence, 7);
+        assert_eq!(frame_sequence, Some(12));
+        approx::assert_relative_eq!(bands[0].integral, 8.);
+        // Every second spectrum
+        assert!(subscriber.filter(&message).is_none());
+        assert!(subscriber.filter(&message).is_some());
+        // Other messages are not filtered
+        let status = FeedThread::status_message(8, &FeedStatus::default());
+        assert_eq!(subscriber.filter(&status), Some(status));
+
+        let peaks = spectrum.peaks(1);
+        assert_eq!(peaks.len(), 1);
+        assert_eq!(peaks[0].wavelength, 430.);
+    }
+
+    #[test]
+    fn export_request() {
+        let request: FeedRequest =
+            serde_json::from_str(r#"{"type": "export", "format": "csv"}"#).unwrap();
+        assert_eq!(
+            request,
+            FeedRequest::Export(FeedExportRequest {
+                format: FeedExportFormat::Csv
+            })
+        );
+        let request: FeedRequest = serde_json::from_str(r#"{"type": "capture"}"#).unwrap();
+        assert_eq!(request, FeedRequest::Capture);
+        let spectrum = FeedSpectrum {
+            timestamp: 1.,
+            frame_sequence: None,
+            wavelength_offset: 400.,
+            wavelength_delta: 10.,
+            sum: vec![0.5, 0.25],
+            r: None,
+            g: None,
+            b: None,
+            frame_metadata: None,
+            saturation: None,
+        };
+
+        let json = |message: FeedMessage| -> serde_json::Value {
+            serde_json::from_slice(&message.encode(FeedFormat::Json, None).unwrap()).unwrap()
+        };
+        let csv = json(FeedMessage::export(
+            3,
+            Some(&spectrum),
+            FeedExportFormat::Csv,
+        ));
+        assert_eq!(csv["type"], "export");
+        assert_eq!(csv["timestamp"], 1.);
+        assert_eq!(csv["csv"], "wavelength,value\n400.0,0.5\n410.0,0.25\n");
+        let points = json(FeedMessage::export(
+            3,
+            Some(&spectrum),
+            FeedExportFormat::Json,
+        ));
+        assert_eq!(points["points"][1]["wavelength"], 410.);
+        assert!(points.get("csv").is_none());
+        let missing = json(FeedMessage::export(3, None, FeedExportFormat::Json));
+        assert_eq!(missing["error"], "No spectrum available");
+    }
+
+    #[test]
+    fn encode_binary_spectrum() {
+        let spectrum = FeedSpectrum::new(
+            &Spectrum::from_element(10, 0.5),
+            &SpectrumCalibration::default(),
+            UNIX_EPOCH,
+            None,
+            None,
+            None,
+            true,
+        );
+        let message = FeedMessage::Spectrum {
+            sequence: 42,
+            spectrum,
+        };
+
+        let datagram = message.encode(FeedFormat::Binary, None).unwrap();
+
+        assert_eq!(datagram.len(), 36 + 4 * 10 * 4);
+        assert_eq!(&datagram[..4], BINARY_MAGIC);
+        assert_eq!(u64::from_le_bytes(datagram[4..12].try_into().unwrap()), 42);
+        assert_eq!(u32::from_le_bytes(datagram[28..32].try_into().unwrap()), 4);
+        assert_eq!(u32::from_le_bytes(datagram[32..36].try_into().unwrap()), 10);
+        assert_eq!(
+            f32::from_le_bytes(datagram[36..40].try_into().unwrap()),
+            0.5
+        );
+    }
+
+    #[test]
+    fn skip_too_large() {
+        let receiver = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        receiver.set_nonblocking(true).unwrap();
+        let config = FeedConfig {
+            multicast_group: Ipv4Addr::LOCALHOST,
+            port: receiver.local_addr().unwrap().port(),
+            include_rgb: true,
+            ..Default::default()
+        };
+        let spectrum = |len| {
+            FeedSpectrum::new(
+                &Spectrum::from_element(len, 0.123456),
+                &SpectrumCalibration::default(),
+                UNIX_EPOCH,
+                None,
+                None,
+                None,
+                true,
+            )
+        };
+        let (event_tx, event_rx) = flume::unbounded();
+        let (result_tx, result_rx) = flume::unbounded();
+        event_tx.send(FeedEvent::Start(config)).unwrap();
+        event_tx.send(FeedEvent::Spectrum(spectrum(10000))).unwrap();
+        event_tx.send(FeedEvent::Spectrum(spectrum(10))).unwrap();
+        drop(event_tx);
+        FeedThread::new(event_rx, result_tx).run();
+
+        assert!(result_rx.drain().all(|r| r.result.is_ok()));
+        // The status sent on start and the small spectrum
+        let mut buffer = [0; MAX_DATAGRAM_SIZE];
+        let sequences: Vec<_> = std::iter::from_fn(|| {
+            let len = receiver.recv(&mut buffer).ok()?;
+            let json: serde_json::Value = serde_json::from_slice(&buffer[..len]).unwrap();
+            Some(json["sequence"].clone())
+        })
+        .collect();
+        assert_eq!(sequences, vec![0, 2]);
+    }
+
+    #[test]
+    fn stop_when_disconnected() {
+        let (event_tx, event_rx) = flume::unbounded();
+        let (result_tx, _result_rx) = flume::unbounded();
+        event_tx.send(FeedEvent::Stop).unwrap();
+        drop(event_tx);
+        FeedThread::new(event_rx, result_tx).run();
+    }
+}
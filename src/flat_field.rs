@@ -0,0 +1,208 @@
+use image::{DynamicImage, ImageBuffer, Rgb};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct FlatFieldConfig {
+    /// Frames averaged into the flat field
+    pub frames: usize,
+    /// Image the flat field is saved to and loaded from
+    pub path: String,
+}
+
+impl Default for FlatFieldConfig {
+    fn default() -> Self {
+        Self {
+            frames: 16,
+            path: "flat_field.png".to_string(),
+        }
+    }
+}
+
+/// Subpixels darker than this fraction of their channel mean are not corrected
+const MIN_RELATIVE_LEVEL: f32 = 0.05;
+
+/// Frame of a uniform light source, every following frame is divided by it
+///
+/// This removes lens vignetting and slit non-uniformity. The correction is normalized to the mean
+/// of each channel, so the color and bit depth of the reference do not matter. Frames are
+/// expected in window depth, see [`crate::spectrum::to_window_depth`].
+#[derive(Debug, Default)]
+pub struct FlatField {
+    /// Frames still to be added to the capture
+    remaining: usize,
+    captured: usize,
+    /// Sum of the subpixels relative to full scale
+    sum: Vec<f32>,
+    dimensions: (u32, u32),
+    /// Mean subpixel values relative to full scale once available
+    reference: Option<Vec<f32>>,
+    /// Factors of the subpixels
+    gains: Vec<f32>,
+}
+
+impl FlatField {
+    /// Start capturing a new flat field, the current one is used until the capture is complete
+    pub fn capture(&mut self, frames: usize) {
+        self.remaining = frames.max(1);
+        self.captured = 0;
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.remaining > 0
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.reference.is_some()
+    }
+
+    /// Add a frame to a running capture, returns true once the flat field is complete
+    pub fn add(&mut self, frame: &DynamicImage) -> bool {
+        if !self.is_capturing() {
+            return false;
+        }
+        let frame = frame.to_rgb32f();
+        // A different size restarts the capture
+        if self.captured == 0 || self.dimensions != frame.dimensions() {
+            self.remaining += self.captured;
+            self.captured = 0;
+            self.dimensions = frame.dimensions();
+            self.sum = frame.into_raw();
+        } else {
+            self.sum
+                .iter_mut()
+                .zip(frame.as_raw())
+                .for_each(|(sum, v)| *sum += v);
+        }
+        self.captured += 1;
+        self.remaining -= 1;
+        if self.remaining > 0 {
+            return false;
+        }
+        let count = self.captured as f32;
+        let reference = self.sum.iter().map(|&sum| sum / count).collect();
+        self.sum = Vec::new();
+        self.set_reference(reference);
+        true
+    }
+
+    fn set_reference(&mut self, reference: Vec<f32>) {
+        let mut channel_means = [0.; 3];
+        for (i, &v) in reference.iter().enumerate() {
+            channel_means[i % 3] += v;
+        }
+        let pixels = (reference.len() / 3).max(1) as f32;
+        channel_means.iter_mut().for_each(|mean| *mean /= pixels);
+        self.gains = reference
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| {
+                let mean = channel_means[i % 3];
+                if v > mean * MIN_RELATIVE_LEVEL {
+                    mean / v
+                } else {
+                    1.
+                }
+            })
+            .collect();
+        self.reference = Some(reference);
+    }
+
+    /// Load a reference image of any format, a running capture is stopped
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let image = image::open(path).map_err(|e| e.to_string())?.to_rgb32f();
+        self.clear();
+        self.dimensions = image.dimensions();
+        self.set_reference(image.into_raw());
+        Ok(())
+    }
+
+    /// Save the reference as 16 bit image
+    pub fn save(&self, path: &str) -> Result<(), String> {
+        let reference = self
+            .reference
+            .as_ref()
+            .ok_or_else(|| "No flat field available".to_string())?;
+        let (width, height) = self.dimensions;
+        let image: ImageBuffer<Rgb<u16>, Vec<u16>> = ImageBuffer::from_raw(
+            width,
+            height,
+            reference
+                .iter()
+                .map(|v| (v * u16::MAX as f32).round() as u16)
+                .collect(),
+        )
+        .ok_or_else(|| "Invalid flat field size".to_string())?;
+        image.save(path).map_err(|e| e.to_string())
+    }
+
+    /// Divide the frame by the flat field, frames of a different size are passed unchanged
+    pub fn correct(&self, frame: DynamicImage) -> DynamicImage {
+        if !self.is_available() {
+            return frame;
+        }
+        let correct = |v: f32, gain: &f32, max: f32| (v * gain).round().min(max);
+        match frame {
+            DynamicImage::ImageRgb16(mut image) if image.dimensions() == self.dimensions => {
+                image.iter_mut().zip(&self.gains).for_each(|(v, gain)| {
+                    *v = correct(*v as f32, gain, u16::MAX as f32) as u16;
+                });
+                DynamicImage::ImageRgb16(image)
+            }
+            DynamicImage::ImageRgb8(mut image) if image.dimensions() == self.dimensions => {
+                image.iter_mut().zip(&self.gains).for_each(|(v, gain)| {
+                    *v = correct(*v as f32, gain, u8::MAX as f32) as u8;
+                });
+                DynamicImage::ImageRgb8(image)
+            }
+            frame => frame,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Vignetted frame, the second pixel receives half of the light
+    fn frame(value: u8) -> DynamicImage {
+        DynamicImage::ImageRgb8(ImageBuffer::from_fn(3, 1, |x, _| match x {
+            1 => Rgb([value / 2, value / 4, value / 2]),
+            2 => Rgb([0, value / 2, value]),
+            _ => Rgb([value, value / 2, value]),
+        }))
+    }
+
+    #[test]
+    fn capture_and_correct() {
+        let mut flat_field = FlatField::default();
+        assert_eq!(flat_field.correct(frame(200)), frame(200));
+
+        flat_field.capture(2);
+        assert!(!flat_field.add(&frame(200)));
+        assert!(flat_field.add(&frame(200)));
+        assert!(!flat_field.is_capturing());
+
+        let DynamicImage::ImageRgb8(image) = flat_field.correct(frame(120)) else {
+            panic!("Unexpected format");
+        };
+        // Flat within each channel, dark subpixels are left as they are
+        assert_eq!(image.as_raw(), &vec![60, 50, 100, 60, 50, 100, 0, 50, 100]);
+        // Mismatching frames are not changed
+        assert_eq!(
+            flat_field.correct(DynamicImage::new_rgb8(2, 1)),
+            DynamicImage::new_rgb8(2, 1)
+        );
+
+        let path = std::env::temp_dir().join("spectro_cam_rs_flat_field_test.png");
+        let path = path.to_str().unwrap();
+        flat_field.save(path).unwrap();
+        let mut loaded = FlatField::default();
+        loaded.load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(loaded.correct(frame(120)), DynamicImage::ImageRgb8(image));
+    }
+}
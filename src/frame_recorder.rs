@@ -0,0 +1,179 @@
+use crate::config::{ImageConfig, SpectrumWindow};
+use crate::{ThreadId, ThreadResult};
+use flume::{Sender, TrySendError};
+use image::{DynamicImage, GenericImageView};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::thread::JoinHandle;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct FrameRecordingConfig {
+    /// Directory the frames are written to as numbered PNG images
+    pub path: String,
+    /// Black out everything outside of the spectrum windows, the frame size is kept so the
+    /// recording can be replayed with the same windows
+    pub roi_only: bool,
+    /// Frames waiting to be written, further frames are dropped instead of stalling the capture
+    pub queue_length: usize,
+}
+
+impl Default for FrameRecordingConfig {
+    fn default() -> Self {
+        Self {
+            path: "frames".to_string(),
+            roi_only: false,
+            queue_length: 32,
+        }
+    }
+}
+
+/// Bounding box of the windows within the frame as x, y, width and height
+pub fn roi_bounds(windows: &[SpectrumWindow], (width, height): (u32, u32)) -> Option<[u32; 4]> {
+    let corners = windows.iter().flat_map(|window| window.corners());
+    let (min, max) = corners.fold(
+        (
+            egui::Vec2::splat(f32::INFINITY),
+            egui::Vec2::splat(f32::NEG_INFINITY),
+        ),
+        |(min, max), corner| (min.min(corner), max.max(corner)),
+    );
+    let x = min.x.floor().clamp(0., width as f32) as u32;
+    let y = min.y.floor().clamp(0., height as f32) as u32;
+    let right = max.x.ceil().clamp(0., width as f32) as u32;
+    let bottom = max.y.ceil().clamp(0., height as f32) as u32;
+    (right > x && bottom > y).then_some([x, y, right - x, bottom - y])
+}
+
+/// Writes the raw frames of the camera thread to an image sequence in a thread of its own
+///
+/// The image file source replays the directory in name order.
+pub struct FrameRecorder {
+    frame_tx: Sender<DynamicImage>,
+    handle: JoinHandle<()>,
+    roi_only: bool,
+    dropped: usize,
+}
+
+impl FrameRecorder {
+    /// Create the directory and start the writer, write errors stop it and are sent as result
+    pub fn start(
+        config: &FrameRecordingConfig,
+        result_tx: Sender<ThreadResult>,
+    ) -> Result<Self, String> {
+        let directory = PathBuf::from(&config.path);
+        std::fs::create_dir_all(&directory)
+            .map_err(|e| format!("Could not create {}: {}", directory.display(), e))?;
+        let (frame_tx, frame_rx) = flume::bounded::<DynamicImage>(config.queue_length.max(1));
+        let handle = std::thread::spawn(move || {
+            for (index, frame) in frame_rx.iter().enumerate() {
+                let path = directory.join(format!("frame_{index:08}.png"));
+                if let Err(e) = frame.save(&path) {
+                    result_tx
+                        .send(ThreadResult {
+                            id: ThreadId::FrameRecorder,
+                            result: Err(format!("Could not write {}: {}", path.display(), e)),
+                        })
+                        .ok();
+                    return;
+                }
+            }
+        });
+        Ok(Self {
+            frame_tx,
+            handle,
+            roi_only: config.roi_only,
+            dropped: 0,
+        })
+    }
+
+    /// Queue a frame before any correction is applied
+    pub fn push(&mut self, frame: &DynamicImage, config: Option<&ImageConfig>) {
+        let frame = match config.filter(|_| self.roi_only) {
+            Some(cfg) => {
+                let windows: Vec<SpectrumWindow> = [Some(cfg.window)]
+                    .into_iter()
+                    .chain([cfg.second_order_window, cfg.reference_beam_window])
+                    .flatten()
+                    .collect();
+                mask_outside(frame, &windows)
+            }
+            None => frame.clone(),
+        };
+        if let Err(TrySendError::Full(_)) = self.frame_tx.try_send(frame) {
+            self.dropped += 1;
+        }
+    }
+
+    /// Write the queued frames, returns the number of dropped frames
+    pub fn finish(self) -> usize {
+        drop(self.frame_tx);
+        self.handle.join().ok();
+        self.dropped
+    }
+}
+
+/// Copy of the frame that is black outside of the bounding box of the windows
+fn mask_outside(frame: &DynamicImage, windows: &[SpectrumWindow]) -> DynamicImage {
+    let mut masked = DynamicImage::new(frame.width(), frame.height(), frame.color());
+    if let Some([x, y, width, height]) = roi_bounds(windows, frame.dimensions()) {
+        image::imageops::replace(
+            &mut masked,
+            &frame.crop_imm(x, y, width, height),
+            x as i64,
+            y as i64,
+        );
+    }
+    masked
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::Vec2;
+    use image::{ImageBuffer, Rgb};
+
+    #[test]
+    fn record_roi() {
+        let windows = [
+            SpectrumWindow {
+                offset: Vec2::new(2., 1.),
+                size: Vec2::new(4., 1.),
+                angle: 0.,
+            },
+            SpectrumWindow {
+                offset: Vec2::new(8., 3.),
+                size: Vec2::new(4., 2.),
+                angle: 0.,
+            },
+        ];
+        assert_eq!(roi_bounds(&windows, (10, 10)), Some([2, 1, 8, 4]));
+        assert_eq!(roi_bounds(&windows[..1], (1, 1)), None);
+
+        let path = std::env::temp_dir().join("spectro_cam_rs_frame_recorder_test");
+        let config = FrameRecordingConfig {
+            path: path.to_str().unwrap().to_string(),
+            roi_only: true,
+            queue_length: 4,
+        };
+        let image_config = ImageConfig {
+            window: windows[0],
+            ..Default::default()
+        };
+        let (result_tx, result_rx) = flume::unbounded();
+        let mut recorder = FrameRecorder::start(&config, result_tx).unwrap();
+        let frame = DynamicImage::ImageRgb16(ImageBuffer::from_pixel(10, 4, Rgb([1000; 3])));
+        recorder.push(&frame, Some(&image_config));
+        recorder.push(&frame, None);
+        assert_eq!(recorder.finish(), 0);
+        assert!(result_rx.is_empty());
+
+        let masked = image::open(path.join("frame_00000000.png")).unwrap();
+        let unmasked = image::open(path.join("frame_00000001.png")).unwrap();
+        std::fs::remove_dir_all(&path).unwrap();
+        // Recorded in the bit depth of the frame
+        assert_eq!(unmasked, frame);
+        assert_eq!(masked.get_pixel(2, 1), masked.get_pixel(5, 1));
+        assert_ne!(masked.get_pixel(2, 1), masked.get_pixel(6, 1));
+        assert_eq!(masked.to_rgb16().get_pixel(1, 1), &Rgb([0; 3]));
+    }
+}
@@ -1,127 +1,621 @@
-use crate::camera::{CameraEvent, CameraInfo};
-use crate::config::{GainPresets, Linearize, SpectrometerConfig, SpectrumPoint};
-use crate::spectrum::{SpectrumContainer, SpectrumRgb};
-use crate::tungsten_halogen::reference_from_filament_temp;
-use crate::{ThreadId, ThreadResult};
+use crate::acquisition::{AcquisitionEvent, AcquisitionState};
+use crate::alarm::{AlarmMonitor, PeakAlarm};
+use crate::animation::export_gif;
+use crate::auto_exposure::{AutoExposure, ControlRange};
+use crate::calibration_check::{CalibrationCheck, DriftCheck};
+use crate::camera::{
+    group_camera_formats, gstreamer_args, image_file_paths, measurement_mode_controls,
+    network_stream_args, probe_gstreamer_size, probe_video_size, requested_format, stream_request,
+    CameraEvent, CameraList, CustomCameraFormat,
+};
+use crate::color::{scale_intensity, spectrum_color, SpectrumColorConfig};
+use crate::colorimetry::Illuminant;
+use crate::config::{
+    AccumulationMode, BaselineCorrection, BayerPattern, BufferClearPolicy, BufferClearReason,
+    ColumnAggregation, FrameSource, GainPresets, Linearize, PeakFit, PeakLabelConfig,
+    PeakLabelContent, PlotSource, PlotWindowConfig, ProcessingOrder, SpectrometerConfig,
+    SpectrumOrientation, SpectrumPoint, SpectrumWindow, StreamBackend, WavelengthMarker,
+    WavelengthRange, LOW_POWER_INTERVAL,
+};
+use crate::defect_map::read_pixel_list;
+use crate::dual_beam::beam_ratio;
+use crate::feed::{FeedCalibration, FeedEvent, FeedFormat, FeedSpectrum, FeedStatus, StreamState};
+use crate::led_phosphor::{reference_from_led_model, LedPhosphorModel};
+use crate::library::{
+    format_timestamp, parse_tags, points_to_reference, points_to_spectrum, Library,
+};
+use crate::multi_camera::{AdditionalCamera, AdditionalCameraConfig};
+use crate::multi_order::merge_orders;
+use crate::number_format::{localize_decimals, read_csv, write_csv};
+use crate::postprocessing_preset::PostprocessingPreset;
+use crate::provenance::Provenance;
+use crate::recorder::{RecordedSpectrum, Recording, SpectrumRecorder};
+use crate::report::{write_html_report, ReportData};
+use crate::session::{Session, Snapshot};
+use crate::shutter::{run_shutter_command, DarkCycle, DarkCycleAction};
+use crate::sinks::{SinkConfig, SinkEvent, SinkKind};
+use crate::sonification::{spectrum_to_tones, Sonifier};
+use crate::spectrum::{
+    dominant_peak, export_color_coordinates, find_spectrum_window, sub_pixel_peak,
+    vertical_centroid, SpectrumContainer, SpectrumRgb, SATURATION_LEVEL,
+};
+use crate::tolerance::ToleranceResult;
+use crate::transmission::{optical_density, TransmissionSequence, TransmissionStep};
+use crate::tungsten_halogen::{reference_from_filament_temp, EstimationMethod, MODEL_RANGE};
+use crate::webhook;
+use crate::{ThreadId, ThreadResult, Timestamped};
 use egui::{
-    Button, Color32, ComboBox, Context, Rect, RichText, Rounding, Sense, Slider, Stroke, TextureId,
-    Vec2,
+    Align2, Button, Color32, ComboBox, Context, FontId, Mesh, ProgressBar, Rect, RichText,
+    Rounding, Sense, Shape, Slider, Stroke, TextureId, Ui, Vec2,
+};
+use egui_plot::{
+    log_grid_spacer, GridInput, GridMark, Legend, Line, LineStyle, MarkerShape, Plot, PlotBounds,
+    PlotPoint, PlotTransform, Points, Text, VLine,
 };
-use egui_plot::{Legend, Line, MarkerShape, Plot, PlotPoint, Points, Text, VLine};
 use flume::{Receiver, Sender};
-use indexmap::IndexMap;
-use nokhwa::pixel_format::RgbFormat;
+use image::{ImageBuffer, Rgb};
+use nokhwa::utils::RequestedFormatType;
 use nokhwa::utils::{
-    ApiBackend, CameraControl, CameraFormat, ControlValueDescription, ControlValueSetter,
+    CameraControl, ControlValueDescription, ControlValueSetter, KnownCameraControl,
     KnownCameraControlFlag,
 };
-use nokhwa::utils::{CameraIndex, RequestedFormat, RequestedFormatType};
-use nokhwa::{query, Camera};
-use std::borrow::BorrowMut;
+use nokhwa::Camera;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
 use winit::dpi::PhysicalSize;
 
+/// How long the unfiltered spectrum is shown after adjusting the filter
+const SMOOTHING_PREVIEW_DURATION: Duration = Duration::from_secs(3);
+const BUFFER_RESET_INDICATOR_DURATION: Duration = Duration::from_secs(2);
+/// Delay after the last change of the tungsten settings until the preview is regenerated
+const TUNGSTEN_PREVIEW_DELAY: Duration = Duration::from_millis(200);
+/// Space between stacked snapshots in spectrum units
+const SNAPSHOT_STACK_GAP: f32 = 0.05;
+/// ffprobe is killed if a video file or network stream does not answer in time
+const FILE_PROBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Spectrum width and calibration points the cached spectrum colors belong to
+type ColorCacheKey = (usize, u32, usize, u32, usize, bool, SpectrumColorConfig);
+
+/// Wavelength grid at multiples of 100, 50 and 10 nm
+///
+/// Falls back to the default grid if the view is too narrow or too wide for these steps.
+fn wavelength_grid_marks(input: GridInput) -> Vec<GridMark> {
+    const STEPS: [f64; 3] = [10., 50., 100.];
+    let (min, max) = input.bounds;
+    let Some(&finest) = STEPS.iter().find(|&&s| s >= input.base_step_size) else {
+        return log_grid_spacer(10)(input);
+    };
+    if max - min < 2. * STEPS[2] {
+        return log_grid_spacer(10)(input);
+    }
+    let first = (min / finest).ceil() as i64;
+    let last = (max / finest).floor() as i64;
+    (first..=last)
+        .map(|i| {
+            let value = i as f64 * finest;
+            let step_size = STEPS
+                .iter()
+                .rev()
+                .copied()
+                .find(|s| value % s == 0.)
+                .unwrap_or(finest);
+            GridMark { value, step_size }
+        })
+        .collect()
+}
+
+/// Drag gesture on the camera preview
+#[derive(Debug, Clone, Copy)]
+enum WindowDrag {
+    Move,
+    /// Draw a new window from this frame position
+    Draw(Vec2),
+}
+
+/// What a click on the camera preview marks as defective
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum DefectMarking {
+    Pixels,
+    Columns,
+}
+
+/// Frame size probe of a video file or network stream running in a worker thread
+struct FileProbe {
+    size_rx: Receiver<Result<(u32, u32), String>>,
+    /// Start the stream once the size is known
+    start: bool,
+}
+
+/// Per-pixel dark frame or flat field of the camera thread
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum CorrectionFrameState {
+    None,
+    Capturing,
+    Available,
+}
+
 pub struct SpectrometerGui {
     config: SpectrometerConfig,
-    running: bool,
-    camera_info: IndexMap<CameraIndex, crate::camera::CameraInfo>,
+    acquisition: AcquisitionState,
+    camera_info: CameraList,
+    camera_list_rx: Option<Receiver<CameraList>>,
+    camera_refresh_tx: Option<Sender<()>>,
+    hot_pixel_rx: Option<Receiver<Vec<(u32, u32)>>>,
     camera_controls: Vec<CameraControl>,
+    auto_exposure: AutoExposure,
+    measurement_mode: bool,
     webcam_texture_id: TextureId,
     spectrum_container: SpectrumContainer,
-    tungsten_filament_temp: u16,
+    led_model: LedPhosphorModel,
     camera_config_tx: Sender<CameraEvent>,
     camera_config_change_pending: bool,
+    feed_tx: Sender<FeedEvent>,
+    feed_active: bool,
+    /// Last stream status sent to the feed
+    feed_status: Option<FeedStatus>,
+    /// Last provenance sent to the feed
+    feed_provenance: Option<Provenance>,
+    sink_tx: Option<Sender<SinkEvent>>,
+    /// Frame size of the configured video or image file, if it could be probed
+    file_frame_size: Option<(u32, u32)>,
+    file_probe: Option<FileProbe>,
+    /// Result of the running GIF export
+    gif_export_rx: Option<Receiver<ThreadResult>>,
     result_rx: Receiver<ThreadResult>,
     last_error: Option<ThreadResult>,
+    stalled: bool,
+    tolerance_result: Option<ToleranceResult>,
+    alarm_monitor: AlarmMonitor,
+    calibration_check: CalibrationCheck,
+    /// Runtime state of `config.additional_cameras` with the same indices
+    additional_cameras: Vec<AdditionalCamera>,
+    second_order_container: SpectrumContainer,
+    /// First order extended by the second order, empty without second order window
+    merged_orders: Vec<SpectrumPoint>,
+    reference_beam_container: Option<SpectrumContainer>,
+    /// Sample beam divided by reference beam, empty without reference beam window
+    beam_ratio: Vec<SpectrumPoint>,
+    /// Sub-pixel line wavelengths in narrowband mode
+    line_history: VecDeque<(Instant, f32)>,
+    roi_preset_name: String,
+    /// Wavelength and half band width for balancing the channel gains
+    balance_wavelength: f32,
+    balance_width: f32,
+    transmission: Option<TransmissionSequence>,
+    library: Option<Library>,
+    library_name: String,
+    library_tags: String,
+    library_query: String,
+    recorder: Option<SpectrumRecorder>,
+    playback: Option<Recording>,
+    playback_index: usize,
+    sonifier: Option<Sonifier>,
+    find_spectrum_requested: bool,
+    spectrum_window_proposal: Option<SpectrumWindow>,
+    window_drag: Option<WindowDrag>,
+    defect_marking: Option<DefectMarking>,
+    /// Whether the style currently has touch sized controls
+    touch_style: Option<bool>,
+    drift_corrections: usize,
+    spectrum_colors: (Option<ColorCacheKey>, Vec<Color32>),
+    smoothing_preview_until: Option<Instant>,
+    tungsten_preview: Option<Vec<SpectrumPoint>>,
+    /// Camera format being entered by hand
+    custom_format: Option<CustomCameraFormat>,
+    tungsten_preview_due: Option<Instant>,
+    snapshots: Vec<Snapshot>,
+    session_dirty: bool,
+    capturing_zero_reference: bool,
+    dark_cycle: Option<DarkCycle>,
+    dark_frame: CorrectionFrameState,
+    flat_field: CorrectionFrameState,
+    /// Raw frames are saved by the camera thread
+    frame_recording: bool,
 }
 
 impl SpectrometerGui {
     pub fn new(
         webcam_texture_id: TextureId,
         camera_config_tx: Sender<CameraEvent>,
-        spectrum_rx: Receiver<SpectrumRgb>,
+        spectrum_rx: Receiver<Timestamped<SpectrumRgb>>,
+        second_order_spectrum_rx: Receiver<Timestamped<SpectrumRgb>>,
+        feed_tx: Sender<FeedEvent>,
         config: SpectrometerConfig,
         result_rx: Receiver<ThreadResult>,
     ) -> Self {
+        let additional_cameras = config
+            .additional_cameras
+            .iter()
+            .map(|_| AdditionalCamera::new())
+            .collect();
         let mut gui = Self {
             config,
-            running: false,
+            acquisition: AcquisitionState::Idle,
             camera_info: Default::default(),
+            camera_list_rx: None,
+            camera_refresh_tx: None,
+            hot_pixel_rx: None,
             camera_controls: Default::default(),
+            auto_exposure: Default::default(),
+            measurement_mode: false,
             webcam_texture_id,
             spectrum_container: SpectrumContainer::new(spectrum_rx),
-            tungsten_filament_temp: 2800,
+            led_model: LedPhosphorModel::default(),
             camera_config_tx,
             camera_config_change_pending: false,
+            feed_tx,
+            feed_active: false,
+            feed_status: None,
+            feed_provenance: None,
+            sink_tx: None,
+            file_frame_size: None,
+            file_probe: None,
+            gif_export_rx: None,
             result_rx,
             last_error: None,
+            stalled: false,
+            tolerance_result: None,
+            alarm_monitor: AlarmMonitor::default(),
+            calibration_check: CalibrationCheck::default(),
+            additional_cameras,
+            second_order_container: SpectrumContainer::new(second_order_spectrum_rx),
+            merged_orders: Vec::new(),
+            reference_beam_container: None,
+            beam_ratio: Vec::new(),
+            line_history: VecDeque::new(),
+            roi_preset_name: String::new(),
+            balance_wavelength: 580.,
+            balance_width: 0.,
+            transmission: None,
+            library: None,
+            library_name: String::new(),
+            library_tags: String::new(),
+            library_query: String::new(),
+            recorder: None,
+            playback: None,
+            playback_index: 0,
+            sonifier: None,
+            find_spectrum_requested: false,
+            spectrum_window_proposal: None,
+            window_drag: None,
+            defect_marking: None,
+            touch_style: None,
+            drift_corrections: 0,
+            spectrum_colors: (None, Vec::new()),
+            smoothing_preview_until: None,
+            tungsten_preview: None,
+            custom_format: None,
+            tungsten_preview_due: None,
+            snapshots: Vec::new(),
+            session_dirty: false,
+            capturing_zero_reference: false,
+            dark_cycle: None,
+            dark_frame: CorrectionFrameState::None,
+            flat_field: CorrectionFrameState::None,
+            frame_recording: false,
         };
-        gui.query_cameras();
+        if gui.config.import_export_config.persist_session {
+            gui.restore_session();
+        }
         gui
     }
 
-    fn query_cameras(&mut self) {
-        for info in query(ApiBackend::Auto).unwrap_or_default().iter() {
-            for format_type in crate::camera::CameraInfo::get_default_camera_format_types() {
-                match Camera::new(
-                    info.index().clone(),
-                    RequestedFormat::new::<RgbFormat>(format_type),
-                )
-                .borrow_mut()
-                {
-                    Ok(cam) => {
-                        let mut formats = cam.compatible_camera_formats().unwrap_or_default();
-                        formats.sort_by_key(CameraFormat::width);
-                        self.camera_info.insert(
-                            info.index().clone(),
-                            CameraInfo {
-                                info: info.clone(),
-                                formats,
-                            },
-                        );
-                        break;
-                    }
-                    Err(e) => {
-                        log::warn!("Could not open camera {info} with format {format_type}: {e}")
-                    }
-                }
+    fn restore_session(&mut self) {
+        match Session::load() {
+            Ok(session) => {
+                self.spectrum_container.restore_zero_references(
+                    session.zero_reference(),
+                    session.raw_zero_reference(),
+                );
+                self.snapshots = session.snapshots;
             }
-            if !self.camera_info.contains_key(info.index()) {
-                log::warn!("Could not query camera {}", info);
+            Err(e) => log::error!("Could not restore session: {}", e),
+        }
+    }
+
+    /// Store zero reference and snapshots if they changed and persisting is enabled
+    fn update_session(&mut self) {
+        // A raw zero reference is complete once capturing stops
+        let capturing = self.spectrum_container.is_capturing_zero_reference();
+        if self.capturing_zero_reference && !capturing {
+            self.session_dirty = true;
+        }
+        self.capturing_zero_reference = capturing;
+
+        if self.session_dirty && self.config.import_export_config.persist_session {
+            self.session_dirty = false;
+            let (zero_reference, raw_zero_reference) = self.spectrum_container.zero_references();
+            if let Err(e) =
+                Session::new(zero_reference, raw_zero_reference, &self.snapshots).store()
+            {
+                self.last_error = Some(ThreadResult {
+                    id: ThreadId::Main,
+                    result: Err(format!("Could not persist session: {}", e)),
+                });
             }
         }
     }
 
+    /// Receive reference beam spectra for dual-beam mode
+    pub fn with_reference_beam(
+        mut self,
+        reference_beam_spectrum_rx: Receiver<Timestamped<SpectrumRgb>>,
+    ) -> Self {
+        self.reference_beam_container = Some(SpectrumContainer::new(reference_beam_spectrum_rx));
+        self
+    }
+
+    /// Pass spectra to the feed and to the output sinks of the configuration
+    pub fn with_sinks(mut self, sink_tx: Sender<SinkEvent>) -> Self {
+        sink_tx
+            .send(SinkEvent::Configure(self.config.sink_configs.clone()))
+            .ok();
+        self.sink_tx = Some(sink_tx);
+        self
+    }
+
+    /// Receive hot pixels detected from the dark frame
+    pub fn with_hot_pixels(mut self, hot_pixel_rx: Receiver<Vec<(u32, u32)>>) -> Self {
+        self.hot_pixel_rx = Some(hot_pixel_rx);
+        self
+    }
+
+    /// Mark the pixels as defective for the current camera
+    fn add_defect_pixels(&mut self, pixels: &[(u32, u32)]) {
+        let defect_map = self
+            .config
+            .defect_maps
+            .entry(self.defect_map_key())
+            .or_default();
+        let added = defect_map.add_pixels(pixels);
+        self.config.image_config.defect_map = defect_map.clone();
+        self.send_config();
+        log::info!("Marked {} of {} pixels as defective", added, pixels.len());
+    }
+
+    fn update_hot_pixels(&mut self) {
+        if let Some(pixels) = self.hot_pixel_rx.as_ref().and_then(|rx| rx.try_recv().ok()) {
+            self.add_defect_pixels(&pixels);
+        }
+    }
+
+    /// Receive the first camera list and keep it up to date with the watcher
+    pub fn with_camera_watcher(
+        mut self,
+        camera_list_rx: Receiver<CameraList>,
+        camera_refresh_tx: Sender<()>,
+    ) -> Self {
+        if let Ok(cameras) = camera_list_rx.recv() {
+            self.camera_info = cameras;
+        }
+        self.camera_list_rx = Some(camera_list_rx);
+        self.camera_refresh_tx = Some(camera_refresh_tx);
+        self
+    }
+
+    /// Apply a changed camera list while keeping the selected camera
+    fn update_camera_list(&mut self) {
+        let Some(cameras) = self
+            .camera_list_rx
+            .as_ref()
+            .and_then(|rx| rx.drain().last())
+        else {
+            return;
+        };
+        let selected = self
+            .camera_info
+            .get_index(self.config.camera_id)
+            .map(|(index, _)| index.clone());
+        self.camera_info = cameras;
+        if let Some(camera_id) = selected.and_then(|index| self.camera_info.get_index_of(&index)) {
+            self.config.camera_id = camera_id;
+        }
+    }
+
+    /// Defect maps are stored per camera, or per source type for other frame sources
+    fn defect_map_key(&self) -> String {
+        match self.config.frame_source {
+            FrameSource::Camera => self
+                .camera_info
+                .get_index(self.config.camera_id)
+                .map(|(_, info)| info.info.human_name())
+                .unwrap_or_else(|| self.config.camera_id.to_string()),
+            frame_source => frame_source.to_string(),
+        }
+    }
+
     fn send_config(&self) {
         self.camera_config_tx
             .send(CameraEvent::Config(self.config.image_config.clone()))
             .unwrap();
     }
 
+    /// Clear the averaging buffer if the configured policy asks for it on this change
+    fn invalidate_buffer(&mut self, reason: BufferClearReason) {
+        self.spectrum_container.invalidate_buffer(
+            self.config.postprocessing_config.buffer_clear_policy,
+            reason,
+        );
+    }
+
     fn start_stream(&mut self) {
-        let requested_format = RequestedFormat::new::<RgbFormat>(RequestedFormatType::Exact(
-            self.config.camera_format.unwrap(),
-        ));
-        if let Ok(cam) = Camera::new(
-            CameraIndex::Index(self.config.camera_id as u32),
-            requested_format,
-        ) {
-            let raw_controls = Self::get_controls(&cam);
-
-            self.camera_controls = raw_controls;
-        }
-        self.spectrum_container.clear_buffer();
+        self.acquisition_event(AcquisitionEvent::Start);
+        self.measurement_mode = false;
+        self.auto_exposure.reset();
+        self.drift_corrections = 0;
+        self.invalidate_buffer(BufferClearReason::Restart);
+        self.spectrum_container.reset_last_update();
+        self.config.image_config.defect_map = self
+            .config
+            .defect_maps
+            .get(&self.defect_map_key())
+            .cloned()
+            .unwrap_or_default();
+        self.config.image_config.low_power = self.config.low_power_mode;
         self.send_config();
-        self.camera_config_tx
-            .send(CameraEvent::StartStream {
-                id: self
-                    .camera_info
-                    .get_index(self.config.camera_id)
-                    .unwrap()
-                    .0
-                    .clone(),
-                format: self.config.camera_format.unwrap(),
-            })
-            .unwrap();
+        match self.config.frame_source {
+            FrameSource::Camera => {
+                // The camera may have been unplugged since it was selected
+                let Some((id, _)) = self.camera_info.get_index(self.config.camera_id) else {
+                    self.start_failed("The selected camera is not connected".to_string());
+                    return;
+                };
+                let id = id.clone();
+                let format = self.config.camera_format.unwrap();
+                // Custom formats may not be supported at all
+                match Camera::new(id.clone(), stream_request(format, self.config.y16_capture)) {
+                    Ok(cam) => {
+                        let raw_controls = Self::get_controls(&cam);
+
+                        self.camera_controls = raw_controls;
+                    }
+                    Err(e) => {
+                        log::error!("{:?}", e);
+                        self.start_failed(format!(
+                            "The camera does not accept the format {format}"
+                        ));
+                        return;
+                    }
+                }
+                self.camera_config_tx
+                    .send(CameraEvent::StartStream {
+                        id,
+                        format,
+                        driver_timestamps: self.config.driver_timestamps,
+                        y16: self.config.y16_capture,
+                    })
+                    .unwrap();
+                self.camera_config_tx
+                    .send(CameraEvent::Bracketing(self.config.hdr_config.clone()))
+                    .unwrap();
+                self.camera_config_tx
+                    .send(CameraEvent::Reconnect(self.config.reconnect_config.clone()))
+                    .unwrap();
+            }
+            FrameSource::ScreenCapture => {
+                self.camera_controls.clear();
+                self.camera_config_tx
+                    .send(CameraEvent::StartScreenCapture(
+                        self.config.screen_capture_config.clone(),
+                    ))
+                    .unwrap();
+            }
+            FrameSource::NetworkStream => {
+                self.camera_controls.clear();
+                self.camera_config_tx
+                    .send(CameraEvent::StartNetworkStream(
+                        self.config.network_stream_config.clone(),
+                    ))
+                    .unwrap();
+            }
+            FrameSource::ImageFile => {
+                self.camera_controls.clear();
+                self.camera_config_tx
+                    .send(CameraEvent::StartImageFile(
+                        self.config.image_file_config.clone(),
+                    ))
+                    .unwrap();
+            }
+            FrameSource::VideoFile => {
+                self.camera_controls.clear();
+                self.camera_config_tx
+                    .send(CameraEvent::StartFile {
+                        path: self.config.video_file_config.path.clone(),
+                        looping: self.config.video_file_config.looping,
+                    })
+                    .unwrap();
+            }
+        }
+    }
+
+    /// Inspect a frame before it is uploaded as preview texture
+    pub fn handle_frame(&mut self, frame: &ImageBuffer<Rgb<u8>, Vec<u8>>) {
+        if self.find_spectrum_requested {
+            self.find_spectrum_requested = false;
+            self.spectrum_window_proposal = find_spectrum_window(frame);
+            if self.spectrum_window_proposal.is_none() {
+                self.last_error = Some(ThreadResult {
+                    id: ThreadId::Main,
+                    result: Err("No spectrum found in frame".to_string()),
+                });
+            }
+        }
+        if self.acquisition.is_running() && self.config.drift_tracking_config.active {
+            self.track_drift(frame);
+        }
+    }
+
+    /// Move the window vertically to the centroid of the trace
+    fn track_drift(&mut self, frame: &ImageBuffer<Rgb<u8>, Vec<u8>>) {
+        let window = self.config.image_config.window;
+        let Some(centroid) = vertical_centroid(
+            frame,
+            &window,
+            self.config.drift_tracking_config.search_margin,
+        ) else {
+            return;
+        };
+        // Ignore sub-row deviations to avoid jumping back and forth
+        let drift = centroid - (window.offset.y + window.size.y / 2.);
+        if drift.abs() < 1. {
+            return;
+        }
+        let max_offset = (frame.height() as f32 - window.size.y).max(0.);
+        let offset_y = (window.offset.y + drift.round()).clamp(0., max_offset);
+        if offset_y == window.offset.y {
+            return;
+        }
+        log::info!(
+            "Spectrum drifted by {:.1} rows, moving window from y = {} to y = {}",
+            drift,
+            window.offset.y,
+            offset_y
+        );
+        self.config.image_config.window.offset.y = offset_y;
+        self.drift_corrections += 1;
+        self.send_config();
+    }
+
+    /// Size of the frames delivered by the selected frame source
+    fn frame_size(&self) -> Option<(u32, u32)> {
+        match self.config.frame_source {
+            FrameSource::Camera => self
+                .config
+                .camera_format
+                .map(|format| (format.width(), format.height())),
+            FrameSource::ScreenCapture => Some((
+                self.config.screen_capture_config.width,
+                self.config.screen_capture_config.height,
+            )),
+            FrameSource::VideoFile | FrameSource::ImageFile | FrameSource::NetworkStream => {
+                self.file_frame_size
+            }
+        }
+    }
+
+    /// Shrink the spectrum window to a new frame size, e.g. after a format change
+    fn fit_window_to_frame(&mut self) {
+        let Some((width, height)) = self.frame_size() else {
+            return;
+        };
+        if self.config.image_config.clamp(width as f32, height as f32) {
+            let window = self.config.image_config.window;
+            let message = format!(
+                "Spectrum window adjusted to {}x{} at ({}, {}) to fit {}x{}",
+                window.size.x, window.size.y, window.offset.x, window.offset.y, width, height
+            );
+            log::warn!("{}", message);
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result: Err(message),
+            });
+            self.camera_config_change_pending = false;
+            self.invalidate_buffer(BufferClearReason::Window);
+            self.send_config();
+        }
     }
 
     fn get_controls(cam: &Camera) -> Vec<CameraControl> {
@@ -136,85 +630,448 @@ impl SpectrometerGui {
     }
 
     fn stop_stream(&mut self) {
+        self.acquisition_event(AcquisitionEvent::Stop);
         self.camera_config_tx.send(CameraEvent::StopStream).unwrap();
     }
 
+    fn set_paused(&mut self, paused: bool) {
+        self.acquisition_event(if paused {
+            AcquisitionEvent::Pause
+        } else {
+            AcquisitionEvent::Resume
+        });
+        self.camera_config_tx
+            .send(CameraEvent::Pause(paused))
+            .unwrap();
+    }
+
+    /// Process enough frames for one averaged spectrum in single shot mode
+    fn capture_single_shot(&mut self) {
+        if !self.acquisition.is_running() || !self.config.image_config.single_shot {
+            log::warn!("Capture requested outside of single shot mode");
+            return;
+        }
+        self.invalidate_buffer(BufferClearReason::Restart);
+        let frames = self.config.postprocessing_config.spectrum_buffer_size
+            * self.config.image_config.accumulate_frames.max(1);
+        self.camera_config_tx
+            .send(CameraEvent::Capture(frames))
+            .unwrap();
+    }
+
+    fn start_failed(&mut self, e: String) {
+        self.acquisition_event(AcquisitionEvent::Failed(e.clone()));
+        self.last_error = Some(ThreadResult {
+            id: ThreadId::Main,
+            result: Err(e),
+        });
+    }
+
+    /// Apply the event to the acquisition state, events that do not fit the state are ignored
+    ///
+    /// Changes are published to the feed thread, which handles remote capture requests.
+    fn acquisition_event(&mut self, event: AcquisitionEvent) {
+        match self.acquisition.transition(event) {
+            Ok(state) => {
+                if state != self.acquisition {
+                    self.feed_tx
+                        .send(FeedEvent::Acquisition(state.clone()))
+                        .unwrap();
+                }
+                self.acquisition = state;
+            }
+            Err(e) => log::warn!("{}", e),
+        }
+    }
+
     fn draw_spectrum(&mut self, ctx: &Context) {
         egui::CentralPanel::default().show(ctx, |ui| {
-            Plot::new("Spectrum")
+            // Reserve a shape below the plot items, filled once the transform is known
+            let color_mesh = self
+                .config
+                .view_config
+                .draw_spectrum_colors
+                .then(|| ui.painter().add(Shape::Noop));
+            let narrowband = self.config.narrowband_config.active;
+            let sign = self.wavelength_sign();
+            // Separate plot memory per direction, the bounds do not carry over
+            let mut plot = Plot::new(("Spectrum", sign < 0.))
                 .legend(Legend::default())
-                .show(ui, |plot_ui| {
-                    if self.config.view_config.draw_spectrum_r {
-                        plot_ui.line(self.get_spectrum_line(0).color(Color32::RED).name("r"));
-                    }
-                    if self.config.view_config.draw_spectrum_g {
-                        plot_ui.line(self.get_spectrum_line(1).color(Color32::GREEN).name("g"));
-                    }
-                    if self.config.view_config.draw_spectrum_b {
-                        plot_ui.line(self.get_spectrum_line(2).color(Color32::BLUE).name("b"));
-                    }
-                    if self.config.view_config.draw_spectrum_combined {
+                .show_background(color_mesh.is_none());
+            let decimal_comma = self.config.view_config.decimal_comma;
+            if self.config.view_config.reverse_wavelength_axis || decimal_comma {
+                // Wavelengths are plotted negated if reversed, show them positive
+                plot = plot
+                    .x_axis_formatter(move |mark, _range| {
+                        localize_decimals(&format!("{}", sign * mark.value), decimal_comma)
+                    })
+                    .label_formatter(move |name, value| {
+                        let name = if name.is_empty() {
+                            String::new()
+                        } else {
+                            format!("{name}\n")
+                        };
+                        localize_decimals(
+                            &format!("{name}x = {:.1}\ny = {:.4}", sign * value.x, value.y),
+                            decimal_comma,
+                        )
+                    });
+            }
+            if decimal_comma {
+                plot = plot.y_axis_formatter(|mark, _range| {
+                    localize_decimals(&format!("{}", mark.value), true)
+                });
+            }
+            if self.showing_optical_density() {
+                plot = plot.y_axis_label("OD");
+            }
+            if self.config.view_config.canonical_wavelength_ticks {
+                plot = plot.x_grid_spacer(wavelength_grid_marks);
+            }
+            if narrowband {
+                // Leave room for the strip chart
+                plot = plot.height(ui.available_height() * 0.65);
+            }
+            let response = plot.show(ui, |plot_ui| {
+                if let Some(&(_, line)) = self.line_history.back().filter(|_| narrowband) {
+                    let half_width = self.config.narrowband_config.zoom_width as f64 / 2.;
+                    let max = self
+                        .spectrum_container
+                        .get_spectrum_max_value()
+                        .unwrap_or(1.) as f64;
+                    plot_ui.set_plot_bounds(PlotBounds::from_min_max(
+                        [sign * line as f64 - half_width, 0.],
+                        [sign * line as f64 + half_width, max * 1.1],
+                    ));
+                    plot_ui.vline(
+                        VLine::new(sign * line as f64)
+                            .color(Color32::GOLD)
+                            .name("line"),
+                    );
+                }
+                // The channels of mono frames are identical, only the intensity is of interest
+                let monochrome = self.config.is_monochrome();
+                if self.config.view_config.draw_spectrum_r && !monochrome {
+                    plot_ui.line(self.get_spectrum_line(0).color(Color32::RED).name("r"));
+                }
+                if self.config.view_config.draw_spectrum_g && !monochrome {
+                    plot_ui.line(self.get_spectrum_line(1).color(Color32::GREEN).name("g"));
+                }
+                if self.config.view_config.draw_spectrum_b && !monochrome {
+                    plot_ui.line(self.get_spectrum_line(2).color(Color32::BLUE).name("b"));
+                }
+                if self
+                    .smoothing_preview_until
+                    .is_some_and(|until| Instant::now() < until)
+                {
+                    if let Some(unfiltered) = self
+                        .spectrum_container
+                        .get_unfiltered_spectrum_channel(3, &self.config)
+                    {
                         plot_ui.line(
-                            self.get_spectrum_line(3)
-                                .color(Color32::LIGHT_GRAY)
-                                .name("sum"),
+                            Line::new(
+                                unfiltered
+                                    .into_iter()
+                                    .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
+                                    .collect::<Vec<_>>(),
+                            )
+                            .color(Color32::from_gray(90))
+                            .name("unfiltered"),
                         );
                     }
+                }
+                if self.config.view_config.draw_spectrum_combined || monochrome {
+                    plot_ui.line(
+                        self.get_spectrum_line(3)
+                            .color(Color32::LIGHT_GRAY)
+                            .name(if monochrome { "intensity" } else { "sum" }),
+                    );
+                }
 
-                    if self.config.view_config.draw_peaks || self.config.view_config.draw_dips {
-                        let max_spectrum_value = self
-                            .spectrum_container
-                            .get_spectrum_max_value()
-                            .unwrap_or_default();
+                if self.config.view_config.draw_peaks || self.config.view_config.draw_dips {
+                    let max_spectrum_value = self
+                        .spectrum_container
+                        .get_spectrum_max_value()
+                        .unwrap_or_default();
 
-                        if self.config.view_config.draw_peaks {
-                            let filtered_peaks = self
-                                .spectrum_container
-                                .spectrum_to_peaks_and_dips(true, &self.config);
+                    if self.config.view_config.draw_peaks {
+                        let filtered_peaks = self
+                            .spectrum_container
+                            .spectrum_to_peaks_and_dips(true, &self.config);
 
-                            let (peaks, peak_labels) =
-                                Self::peaks_dips_to_plot(&filtered_peaks, true, max_spectrum_value);
+                        let (peaks, peak_labels) = Self::peaks_dips_to_plot(
+                            &filtered_peaks,
+                            true,
+                            max_spectrum_value,
+                            sign,
+                            &self.config.view_config.peak_label_config,
+                            decimal_comma,
+                        );
 
-                            plot_ui.points(peaks);
-                            for peak_label in peak_labels {
-                                plot_ui.text(peak_label);
-                            }
+                        plot_ui.points(peaks);
+                        for peak_label in peak_labels {
+                            plot_ui.text(peak_label);
                         }
-                        if self.config.view_config.draw_dips {
-                            let filtered_dips = self
-                                .spectrum_container
-                                .spectrum_to_peaks_and_dips(false, &self.config);
+                    }
+                    if self.config.view_config.draw_dips {
+                        let filtered_dips = self
+                            .spectrum_container
+                            .spectrum_to_peaks_and_dips(false, &self.config);
 
-                            let (dips, dip_labels) =
-                                Self::peaks_dips_to_plot(&filtered_dips, false, max_spectrum_value);
+                        let (dips, dip_labels) = Self::peaks_dips_to_plot(
+                            &filtered_dips,
+                            false,
+                            max_spectrum_value,
+                            sign,
+                            &self.config.view_config.peak_label_config,
+                            decimal_comma,
+                        );
 
-                            plot_ui.points(dips);
-                            for dip_label in dip_labels {
-                                plot_ui.text(dip_label);
-                            }
+                        plot_ui.points(dips);
+                        for dip_label in dip_labels {
+                            plot_ui.text(dip_label);
                         }
                     }
+                }
+
+                for snapshot in &self.snapshots {
+                    plot_ui.line(
+                        Line::new(
+                            SpectrumContainer::spectrum_channel_points(
+                                &snapshot.spectrum.to_spectrum(),
+                                3,
+                                &self.config,
+                            )
+                            .into_iter()
+                            .map(|sp| {
+                                [
+                                    sign * sp.wavelength as f64,
+                                    snapshot.transform(sp.value) as f64,
+                                ]
+                            })
+                            .collect::<Vec<_>>(),
+                        )
+                        .name(&snapshot.name),
+                    );
+                }
 
-                    let line = self.config.reference_config.to_line();
+                for (camera, camera_config) in self
+                    .additional_cameras
+                    .iter()
+                    .zip(&self.config.additional_cameras)
+                    .filter(|(camera, _)| camera.is_running())
+                {
+                    plot_ui.line(
+                        Line::new(
+                            camera
+                                .points()
+                                .iter()
+                                .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
+                                .collect::<Vec<_>>(),
+                        )
+                        .name(&camera_config.name),
+                    );
+                }
 
-                    if let Some(reference) = line {
-                        plot_ui.line(reference.color(Color32::KHAKI).name("reference"));
-                    }
+                if let Some(container) = self
+                    .reference_beam_container
+                    .as_ref()
+                    .filter(|_| !self.beam_ratio.is_empty())
+                {
+                    plot_ui.line(
+                        Line::new(
+                            container
+                                .get_spectrum_channel(3, &self.config)
+                                .into_iter()
+                                .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
+                                .collect::<Vec<_>>(),
+                        )
+                        .color(Color32::LIGHT_GREEN)
+                        .name("reference beam"),
+                    );
+                    plot_ui.line(
+                        Line::new(
+                            self.beam_ratio
+                                .iter()
+                                .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
+                                .collect::<Vec<_>>(),
+                        )
+                        .color(Color32::GOLD)
+                        .name("ratio"),
+                    );
+                }
+
+                if !self.merged_orders.is_empty() {
+                    plot_ui.line(
+                        Line::new(
+                            self.merged_orders
+                                .iter()
+                                .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
+                                .collect::<Vec<_>>(),
+                        )
+                        .name("Merged Orders"),
+                    );
+                }
 
-                    if self.config.view_config.show_calibration_window {
-                        plot_ui.vline(VLine::new(self.config.spectrum_calibration.low.wavelength));
-                        plot_ui.vline(VLine::new(self.config.spectrum_calibration.high.wavelength));
+                if self.config.tolerance_config.active {
+                    if let Some((lower, upper)) = self.config.tolerance_config.to_band_lines(sign) {
+                        let color = match self.tolerance_result {
+                            Some(ToleranceResult { passed: false, .. }) => Color32::DARK_RED,
+                            _ => Color32::DARK_GREEN,
+                        };
+                        plot_ui.line(lower.color(color).name("tolerance"));
+                        plot_ui.line(upper.color(color).name("tolerance"));
                     }
-                });
+                }
+
+                let line = self.config.reference_config.to_line(sign);
+
+                if let Some(reference) = line {
+                    plot_ui.line(reference.color(Color32::KHAKI).name("reference"));
+                }
+                if let Some(preview) = &self.tungsten_preview {
+                    let scale = self.config.reference_config.scale;
+                    plot_ui.line(
+                        Line::new(
+                            preview
+                                .iter()
+                                .map(|p| [sign * p.wavelength as f64, (p.value * scale) as f64])
+                                .collect::<Vec<_>>(),
+                        )
+                        .style(LineStyle::dashed_dense())
+                        .color(Color32::KHAKI)
+                        .name("tungsten preview"),
+                    );
+                }
+
+                if self.config.view_config.show_calibration_window {
+                    let calibration = &self.config.spectrum_calibration;
+                    plot_ui.vline(VLine::new(sign * calibration.low.wavelength as f64));
+                    plot_ui.vline(VLine::new(sign * calibration.high.wavelength as f64));
+                }
+
+                let top = plot_ui.plot_bounds().max()[1];
+                for marker in &self.config.view_config.wavelength_markers {
+                    let x = sign * marker.wavelength as f64;
+                    plot_ui.vline(VLine::new(x).color(Color32::GRAY).width(0.5));
+                    plot_ui.text(
+                        Text::new(PlotPoint::new(x, top), &marker.label)
+                            .anchor(egui::Align2::LEFT_TOP)
+                            .color(Color32::GRAY),
+                    );
+                }
+            });
+            if let Some(idx) = color_mesh {
+                let mesh = self.spectrum_color_mesh(&response.transform, ui.visuals().dark_mode);
+                ui.painter()
+                    .with_clip_rect(*response.transform.frame())
+                    .set(idx, Shape::mesh(mesh));
+            }
+            if narrowband {
+                let now = Instant::now();
+                Plot::new("Line History")
+                    .x_axis_label("Time [s]")
+                    .y_axis_label("Wavelength [nm]")
+                    .show(ui, |plot_ui| {
+                        plot_ui.line(
+                            Line::new(
+                                self.line_history
+                                    .iter()
+                                    .map(|(t, w)| {
+                                        [-now.duration_since(*t).as_secs_f64(), *w as f64]
+                                    })
+                                    .collect::<Vec<_>>(),
+                            )
+                            .color(Color32::GOLD),
+                        );
+                    });
+            }
         });
     }
 
+    /// Factor applied to wavelengths on the spectrum plot, negative if the axis is reversed
+    fn wavelength_sign(&self) -> f64 {
+        if self.config.view_config.reverse_wavelength_axis {
+            -1.
+        } else {
+            1.
+        }
+    }
+
+    /// Single mesh filling the area under the sum spectrum with the wavelength colors
+    fn spectrum_color_mesh(&mut self, transform: &PlotTransform, dark_mode: bool) -> Mesh {
+        let sign = self.wavelength_sign();
+        let spectrum = self.spectrum_container.spectrum();
+        let calibration = &self.config.spectrum_calibration;
+        let color_config = self.config.view_config.spectrum_color_config;
+        let ncols = spectrum.ncols();
+
+        // Colors only depend on the calibration and the color config
+        let key = (
+            ncols,
+            calibration.low.wavelength,
+            calibration.low.index,
+            calibration.high.wavelength,
+            calibration.high.index,
+            dark_mode,
+            color_config,
+        );
+        if self.spectrum_colors.0 != Some(key) {
+            self.spectrum_colors = (
+                Some(key),
+                (0..ncols)
+                    .map(|i| {
+                        spectrum_color(
+                            calibration.get_wavelength_from_index(i),
+                            &color_config,
+                            dark_mode,
+                        )
+                    })
+                    .collect(),
+            );
+        }
+
+        let valid_indices = calibration.valid_indices(ncols);
+        let max_value = valid_indices
+            .clone()
+            .map(|i| spectrum[(3, i)])
+            .fold(0., f32::max);
+        let mut mesh = Mesh::default();
+        for i in valid_indices {
+            let wavelength = sign * calibration.get_wavelength_from_index(i) as f64;
+            let value = spectrum[(3, i)].max(0.);
+            let relative_value = if max_value > 0. {
+                value / max_value
+            } else {
+                0.
+            };
+            let color = scale_intensity(self.spectrum_colors.1[i], relative_value, &color_config)
+                .gamma_multiply(0.8);
+            let value = value as f64;
+            let idx = mesh.vertices.len() as u32;
+            mesh.colored_vertex(
+                transform.position_from_point(&PlotPoint::new(wavelength, 0.)),
+                color,
+            );
+            mesh.colored_vertex(
+                transform.position_from_point(&PlotPoint::new(wavelength, value)),
+                color,
+            );
+            if idx >= 2 {
+                mesh.add_triangle(idx - 2, idx - 1, idx);
+                mesh.add_triangle(idx - 1, idx, idx + 1);
+            }
+        }
+        mesh
+    }
+
     fn get_spectrum_line(&self, index: usize) -> Line {
+        let sign = self.wavelength_sign();
         Line::new({
             self.spectrum_container
                 .get_spectrum_channel(index, &self.config)
                 .into_iter()
-                .map(|sp| [sp.wavelength as f64, sp.value as f64])
+                .map(|sp| [sign * sp.wavelength as f64, sp.value as f64])
                 .collect::<Vec<_>>()
         })
     }
@@ -223,6 +1080,9 @@ impl SpectrometerGui {
         filtered_peaks_dips: &Vec<SpectrumPoint>,
         peaks: bool,
         max_spectrum_value: f32,
+        wavelength_sign: f64,
+        label_config: &PeakLabelConfig,
+        decimal_comma: bool,
     ) -> (Points, Vec<Text>) {
         let mut peak_dip_labels = Vec::new();
 
@@ -230,14 +1090,14 @@ impl SpectrometerGui {
             peak_dip_labels.push(
                 Text::new(
                     PlotPoint::new(
-                        peak_dip.wavelength,
+                        wavelength_sign * peak_dip.wavelength as f64,
                         if peaks {
                             peak_dip.value + (max_spectrum_value * 0.01)
                         } else {
                             peak_dip.value - (max_spectrum_value * 0.01)
                         },
                     ),
-                    format!("{}", peak_dip.wavelength as u32),
+                    localize_decimals(&label_config.format(peak_dip), decimal_comma),
                 )
                 .color(if peaks {
                     Color32::LIGHT_RED
@@ -251,7 +1111,7 @@ impl SpectrometerGui {
             Points::new(
                 filtered_peaks_dips
                     .iter()
-                    .map(|sp| [sp.wavelength as f64, sp.value as f64])
+                    .map(|sp| [wavelength_sign * sp.wavelength as f64, sp.value as f64])
                     .collect::<Vec<_>>(),
             )
             .name("Peaks")
@@ -273,6 +1133,13 @@ impl SpectrometerGui {
     }
 
     fn draw_camera_window(&mut self, ctx: &Context) {
+        let (frame_width, frame_height) = self.frame_size().unwrap_or((1, 1));
+        // Presets replace the whole window and calibration, applied after drawing
+        let mut selected_roi_preset = None;
+        let mut add_roi_preset = false;
+        let mut remove_roi_preset = false;
+        let mut import_hot_pixels = false;
+        let defect_map_key = self.defect_map_key();
         egui::Window::new("Camera")
             .open(&mut self.config.view_config.show_camera_window)
             .show(ctx, |ui| {
@@ -280,36 +1147,336 @@ impl SpectrometerGui {
                     Slider::new(&mut self.config.view_config.image_scale, 0.1..=2.)
                         .text("Preview Scaling Factor"),
                 );
+                ui.checkbox(
+                    &mut self.config.view_config.draw_wavelength_ruler,
+                    "Wavelength Ruler",
+                )
+                .on_hover_text("Wavelength ticks of the current calibration along the window");
+                if self.config.low_power_mode {
+                    ui.label("The preview is not updated in low power mode");
+                }
 
                 ui.separator();
 
-                let texture_size = egui::Vec2::new(
-                    self.config.camera_format.unwrap().width() as f32,
-                    self.config.camera_format.unwrap().height() as f32,
-                );
+                let texture_size = egui::Vec2::new(frame_width as f32, frame_height as f32);
                 let image_size = texture_size * self.config.view_config.image_scale;
                 let image = egui::Image::from_texture((self.webcam_texture_id, texture_size))
-                    .fit_to_exact_size(image_size);
+                    .fit_to_exact_size(image_size)
+                    .sense(if self.defect_marking.is_some() {
+                        Sense::click()
+                    } else {
+                        Sense::drag()
+                    });
                 let image_response = ui.add(image);
+                let image_rect = image_response.rect;
+                let image_origin = image_rect.min;
+                let scale = Vec2::new(
+                    image_rect.width() / frame_width as f32,
+                    image_rect.height() / frame_height as f32,
+                );
+
+                // Drag the window or draw a new one on the preview
+                let pointer_position = image_response
+                    .interact_pointer_pos()
+                    .map(|pos| (pos - image_origin) / scale);
+                // Defects refer to the unflipped frame
+                let flip = self.config.image_config.flip;
+                let frame_x = |x: u32| {
+                    if flip {
+                        frame_width.saturating_sub(x + 1)
+                    } else {
+                        x
+                    }
+                };
+                if let (Some(marking), true, Some(position)) = (
+                    self.defect_marking,
+                    image_response.clicked(),
+                    pointer_position,
+                ) {
+                    let defect_map = self
+                        .config
+                        .defect_maps
+                        .entry(defect_map_key.clone())
+                        .or_default();
+                    let (x, y) = (frame_x(position.x as u32), position.y as u32);
+                    match marking {
+                        DefectMarking::Pixels => defect_map.toggle_pixel(x, y),
+                        DefectMarking::Columns => defect_map.toggle_column(x),
+                    }
+                    self.config.image_config.defect_map = defect_map.clone();
+                    self.camera_config_tx
+                        .send(CameraEvent::Config(self.config.image_config.clone()))
+                        .unwrap();
+                }
+                if let (true, Some(position)) = (image_response.drag_started(), pointer_position) {
+                    let window = self.config.image_config.window;
+                    // Thin windows are hard to hit, grab them within half a control height
+                    let grab_margin = Vec2::splat(ui.spacing().interact_size.y / 2.);
+                    let grab_rect = Rect::from_min_size(window.offset.to_pos2(), window.size)
+                        .expand2(grab_margin / scale);
+                    self.window_drag = Some(if grab_rect.contains(position.to_pos2()) {
+                        WindowDrag::Move
+                    } else {
+                        WindowDrag::Draw(position)
+                    });
+                }
+                if let (Some(drag), Some(position)) = (self.window_drag, pointer_position) {
+                    let window = &mut self.config.image_config.window;
+                    match drag {
+                        WindowDrag::Move => {
+                            window.offset = (window.offset + image_response.drag_delta() / scale)
+                                .max(Vec2::ZERO)
+                                .min(
+                                    Vec2::new(frame_width as f32, frame_height as f32)
+                                        - window.size,
+                                );
+                        }
+                        WindowDrag::Draw(start) => {
+                            window.offset = start.min(position).max(Vec2::ZERO);
+                            window.size =
+                                (start.max(position) - window.offset).max(Vec2::splat(1.));
+                        }
+                    }
+                    window.offset = window.offset.round();
+                    window.size = window.size.round();
+                    self.config
+                        .image_config
+                        .clamp(frame_width as f32, frame_height as f32);
+                }
+                if image_response.drag_stopped() && self.window_drag.take().is_some() {
+                    self.camera_config_change_pending = false;
+                    self.spectrum_container.invalidate_buffer(
+                        self.config.postprocessing_config.buffer_clear_policy,
+                        BufferClearReason::Window,
+                    );
+                    self.camera_config_tx
+                        .send(CameraEvent::Config(self.config.image_config.clone()))
+                        .unwrap();
+                }
 
                 // Paint window rect
                 ui.with_layer_id(image_response.layer_id, |ui| {
                     let painter = ui.painter();
-                    let image_rect = image_response.rect;
-                    let image_origin = image_rect.min;
-                    let scale = Vec2::new(
-                        image_rect.width() / self.config.camera_format.unwrap().width() as f32,
-                        image_rect.height() / self.config.camera_format.unwrap().height() as f32,
-                    );
-                    let window_rect = Rect::from_min_size(
-                        image_origin + self.config.image_config.window.offset * scale,
-                        self.config.image_config.window.size * scale,
-                    );
-                    painter.rect_stroke(
-                        window_rect,
-                        Rounding::ZERO,
-                        Stroke::new(2., Color32::GOLD),
-                    );
+                    let window_outline = |window: &SpectrumWindow, color| {
+                        Shape::closed_line(
+                            window
+                                .corners()
+                                .map(|corner| image_origin + corner * scale)
+                                .to_vec(),
+                            Stroke::new(2., color),
+                        )
+                    };
+                    painter.add(window_outline(
+                        &self.config.image_config.window,
+                        Color32::GOLD,
+                    ));
+                    // Direction in which the wavelength index increases
+                    let image_config = &self.config.image_config;
+                    let window = &image_config.window;
+                    let half_axis = match image_config.orientation {
+                        SpectrumOrientation::Horizontal => Vec2::new(window.size.x / 2., 0.),
+                        SpectrumOrientation::Vertical => Vec2::new(0., window.size.y / 2.),
+                    };
+                    let half_axis = if image_config.rotate_180 {
+                        -half_axis
+                    } else {
+                        half_axis
+                    };
+                    let start = image_origin + window.to_frame(-half_axis) * scale;
+                    let end = image_origin + window.to_frame(half_axis) * scale;
+                    painter.arrow(start, end - start, Stroke::new(1., Color32::GOLD));
+                    if self.config.view_config.draw_wavelength_ruler {
+                        // Ticks point away from the window below or right of the axis
+                        let (len, outward) = match image_config.orientation {
+                            SpectrumOrientation::Horizontal => {
+                                (window.size.x, Vec2::new(0., window.size.y / 2.))
+                            }
+                            SpectrumOrientation::Vertical => {
+                                (window.size.y, Vec2::new(window.size.x / 2., 0.))
+                            }
+                        };
+                        let len = len.round().max(1.);
+                        let axis_scale = (end - start).length() / len;
+                        let to_screen = |local: Vec2| image_origin + window.to_frame(local) * scale;
+                        let direction = (to_screen(outward) - to_screen(Vec2::ZERO)).normalized();
+                        let ticks = self
+                            .config
+                            .spectrum_calibration
+                            .ruler_ticks(len as usize, 60. / axis_scale.max(0.01));
+                        for (index, wavelength) in ticks {
+                            let along = -half_axis + half_axis * 2. * (index + 0.5) / len;
+                            let edge = to_screen(along + outward);
+                            painter.line_segment(
+                                [edge, edge + direction * 6.],
+                                Stroke::new(1., Color32::GOLD),
+                            );
+                            painter.text(
+                                edge + direction * 8.,
+                                match image_config.orientation {
+                                    SpectrumOrientation::Horizontal => Align2::CENTER_TOP,
+                                    SpectrumOrientation::Vertical => Align2::LEFT_CENTER,
+                                },
+                                format!("{wavelength:.0}"),
+                                FontId::proportional(10.),
+                                Color32::GOLD,
+                            );
+                        }
+                    }
+                    let defect_map = &self.config.image_config.defect_map;
+                    for &x in &defect_map.columns {
+                        let x = image_origin.x + (frame_x(x) as f32 + 0.5) * scale.x;
+                        painter.vline(x, image_rect.y_range(), Stroke::new(1., Color32::RED));
+                    }
+                    for &(x, y) in &defect_map.pixels {
+                        let center = image_origin
+                            + (Vec2::new(frame_x(x) as f32, y as f32) + Vec2::splat(0.5)) * scale;
+                        painter.rect_stroke(
+                            Rect::from_center_size(center, (scale * 2.).max(Vec2::splat(4.))),
+                            Rounding::ZERO,
+                            Stroke::new(1., Color32::RED),
+                        );
+                    }
+                    if let Some(window) = &self.config.image_config.second_order_window {
+                        painter.add(window_outline(window, Color32::KHAKI));
+                    }
+                    if let Some(window) = &self.config.image_config.reference_beam_window {
+                        painter.add(window_outline(window, Color32::LIGHT_GREEN));
+                    }
+                    if let Some(proposal) = self.spectrum_window_proposal {
+                        let proposal_rect = Rect::from_min_size(
+                            image_origin + proposal.offset * scale,
+                            proposal.size * scale,
+                        );
+                        painter.rect_stroke(
+                            proposal_rect,
+                            Rounding::ZERO,
+                            Stroke::new(2., Color32::LIGHT_BLUE),
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Mark Defects");
+                    for (marking, label) in [
+                        (None, "Off"),
+                        (Some(DefectMarking::Pixels), "Pixels"),
+                        (Some(DefectMarking::Columns), "Columns"),
+                    ] {
+                        ui.selectable_value(&mut self.defect_marking, marking, label);
+                    }
+                    let defect_map = &self.config.image_config.defect_map;
+                    ui.label(format!(
+                        "{} pixels, {} columns",
+                        defect_map.pixels.len(),
+                        defect_map.columns.len()
+                    ));
+                    if ui
+                        .add_enabled(!defect_map.is_empty(), Button::new("Clear"))
+                        .clicked()
+                    {
+                        self.config.defect_maps.remove(&defect_map_key);
+                        self.config.image_config.defect_map = Default::default();
+                        self.camera_config_tx
+                            .send(CameraEvent::Config(self.config.image_config.clone()))
+                            .unwrap();
+                    }
+                })
+                .response
+                .on_hover_text("Click on the preview to mark or unmark defects");
+                ui.horizontal(|ui| {
+                    let hot_pixel_config = &mut self.config.hot_pixel_config;
+                    ui.label("Hot Pixels");
+                    ui.add(
+                        egui::DragValue::new(&mut hot_pixel_config.threshold)
+                            .range(0.01..=1.)
+                            .speed(0.01)
+                            .prefix("Threshold: "),
+                    )
+                    .on_hover_text("Dark level above the median, relative to full scale");
+                    if ui
+                        .add_enabled(
+                            self.dark_frame == CorrectionFrameState::Available,
+                            Button::new("Detect From Dark Frame"),
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::DetectHotPixels(hot_pixel_config.threshold))
+                            .unwrap();
+                    }
+                    ui.text_edit_singleline(&mut hot_pixel_config.path);
+                    import_hot_pixels = ui
+                        .button("Import")
+                        .on_hover_text("CSV file with one x,y pair per line")
+                        .clicked();
+                });
+                ui.horizontal(|ui| {
+                    if ui
+                        .add_enabled(!self.find_spectrum_requested, Button::new("Find Spectrum"))
+                        .on_hover_text("Propose a window around the brightest horizontal band")
+                        .clicked()
+                    {
+                        self.find_spectrum_requested = true;
+                    }
+                    if let Some(proposal) = self.spectrum_window_proposal {
+                        if ui.button("Apply").clicked() {
+                            self.config.image_config.window = proposal;
+                            self.config
+                                .image_config
+                                .clamp(frame_width as f32, frame_height as f32);
+                            self.spectrum_window_proposal = None;
+                            self.camera_config_change_pending = false;
+                            self.spectrum_container.invalidate_buffer(
+                                self.config.postprocessing_config.buffer_clear_policy,
+                                BufferClearReason::Window,
+                            );
+                            self.camera_config_tx
+                                .send(CameraEvent::Config(self.config.image_config.clone()))
+                                .unwrap();
+                        }
+                        if ui.button("Discard").clicked() {
+                            self.spectrum_window_proposal = None;
+                        }
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let active_name = self
+                        .config
+                        .active_roi_preset
+                        .and_then(|i| self.config.roi_presets.get(i))
+                        .map(|p| p.name.clone())
+                        .unwrap_or_default();
+                    ComboBox::from_label("ROI Preset")
+                        .selected_text(active_name)
+                        .show_ui(ui, |ui| {
+                            for (i, preset) in self.config.roi_presets.iter().enumerate() {
+                                if ui
+                                    .selectable_label(
+                                        self.config.active_roi_preset == Some(i),
+                                        &preset.name,
+                                    )
+                                    .clicked()
+                                {
+                                    selected_roi_preset = Some(i);
+                                }
+                            }
+                        });
+                    remove_roi_preset = ui
+                        .add_enabled(
+                            self.config.active_roi_preset.is_some(),
+                            Button::new("Delete"),
+                        )
+                        .clicked();
+                });
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.roi_preset_name);
+                    add_roi_preset = ui
+                        .add_enabled(
+                            !self.roi_preset_name.is_empty(),
+                            Button::new("Save as Preset"),
+                        )
+                        .on_hover_text("Save the current window and calibration")
+                        .clicked();
                 });
                 ui.separator();
 
@@ -321,7 +1488,7 @@ impl SpectrometerGui {
                         .add(
                             Slider::new(
                                 &mut self.config.image_config.window.offset.x,
-                                1.0..=(self.config.camera_format.unwrap().width() as f32 - 1.),
+                                1.0..=(frame_width as f32 - 1.),
                             )
                             .step_by(1.)
                             .text("Offset X"),
@@ -331,7 +1498,7 @@ impl SpectrometerGui {
                         .add(
                             Slider::new(
                                 &mut self.config.image_config.window.offset.y,
-                                1.0..=(self.config.camera_format.unwrap().height() as f32 - 1.),
+                                1.0..=(frame_height as f32 - 1.),
                             )
                             .step_by(1.)
                             .text("Offset Y"),
@@ -342,7 +1509,7 @@ impl SpectrometerGui {
                         .add(
                             Slider::new(
                                 &mut self.config.image_config.window.size.x,
-                                1.0..=(self.config.camera_format.unwrap().width() as f32
+                                1.0..=(frame_width as f32
                                     - self.config.image_config.window.offset.x
                                     - 1.),
                             )
@@ -354,7 +1521,7 @@ impl SpectrometerGui {
                         .add(
                             Slider::new(
                                 &mut self.config.image_config.window.size.y,
-                                1.0..=(self.config.camera_format.unwrap().height() as f32
+                                1.0..=(frame_height as f32
                                     - self.config.image_config.window.offset.y
                                     - 1.),
                             )
@@ -363,10 +1530,263 @@ impl SpectrometerGui {
                         )
                         .changed();
                 });
+                changed |= ui
+                    .add(
+                        Slider::new(&mut self.config.image_config.window.angle, -45.0..=45.)
+                            .step_by(0.1)
+                            .suffix("°")
+                            .text("Angle"),
+                    )
+                    .on_hover_text(
+                        "Rotate the window around its center to follow a tilted spectral line, \
+                         ignored with a Bayer pattern",
+                    )
+                    .changed();
                 ui.separator();
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .checkbox(&mut self.config.image_config.flip, "Flip")
+                        .changed();
+                    ComboBox::from_label("Column Aggregation")
+                        .selected_text(self.config.image_config.column_aggregation.to_string())
+                        .show_ui(ui, |ui| {
+                            for aggregation in [
+                                ColumnAggregation::Mean,
+                                ColumnAggregation::Median,
+                                ColumnAggregation::Max,
+                            ] {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.config.image_config.column_aggregation,
+                                        aggregation,
+                                        aggregation.to_string(),
+                                    )
+                                    .changed();
+                            }
+                        });
+                    ComboBox::from_label("Bayer Pattern")
+                        .selected_text(
+                            self.config
+                                .image_config
+                                .bayer_pattern
+                                .map(|p| p.to_string())
+                                .unwrap_or_else(|| "None".to_string()),
+                        )
+                        .show_ui(ui, |ui| {
+                            for pattern in [
+                                None,
+                                Some(BayerPattern::Rggb),
+                                Some(BayerPattern::Bggr),
+                                Some(BayerPattern::Grbg),
+                                Some(BayerPattern::Gbrg),
+                            ] {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.config.image_config.bayer_pattern,
+                                        pattern,
+                                        pattern
+                                            .map(|p| p.to_string())
+                                            .unwrap_or_else(|| "None".to_string()),
+                                    )
+                                    .changed();
+                            }
+                        })
+                        .response
+                        .on_hover_text(
+                            "Compute the spectrum per color plane of undemosaiced sensor data, \
+                             e.g. from a GRAY camera format or raw image files",
+                        );
+                });
+                ui.horizontal(|ui| {
+                    ComboBox::from_label("Orientation")
+                        .selected_text(self.config.image_config.orientation.to_string())
+                        .show_ui(ui, |ui| {
+                            for orientation in [
+                                SpectrumOrientation::Horizontal,
+                                SpectrumOrientation::Vertical,
+                            ] {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.config.image_config.orientation,
+                                        orientation,
+                                        orientation.to_string(),
+                                    )
+                                    .changed();
+                            }
+                        })
+                        .response
+                        .on_hover_text("Direction of the dispersion in the window");
+                    changed |= ui
+                        .checkbox(&mut self.config.image_config.rotate_180, "Rotate 180°")
+                        .on_hover_text("Reverse the wavelength direction of the window")
+                        .changed();
+                });
+                ui.horizontal(|ui| {
+                    let mut limit = self.config.image_config.max_frame_rate.is_some();
+                    if ui.checkbox(&mut limit, "Limit Frame Rate").changed() {
+                        self.config.image_config.max_frame_rate = limit.then_some(2.);
+                        changed = true;
+                    }
+                    if let Some(max_frame_rate) = self.config.image_config.max_frame_rate.as_mut() {
+                        changed |= ui
+                            .add(
+                                Slider::new(max_frame_rate, 0.1..=60.)
+                                    .logarithmic(true)
+                                    .suffix(" fps"),
+                            )
+                            .changed();
+                    }
+                });
                 changed |= ui
-                    .checkbox(&mut self.config.image_config.flip, "Flip")
+                    .add(
+                        Slider::new(&mut self.config.image_config.frame_decimation, 1..=60)
+                            .text("Process Every Nth Frame"),
+                    )
+                    .on_hover_text("Skipped frames are dropped before decoding")
                     .changed();
+                ui.horizontal(|ui| {
+                    changed |= ui
+                        .add(
+                            Slider::new(&mut self.config.image_config.accumulate_frames, 1..=256)
+                                .logarithmic(true)
+                                .text("Accumulate Frames"),
+                        )
+                        .on_hover_text(
+                            "Combine the windows of consecutive frames at full precision \
+                             before the spectrum is calculated",
+                        )
+                        .changed();
+                    ComboBox::from_id_salt("accumulation_mode")
+                        .selected_text(self.config.image_config.accumulation_mode.to_string())
+                        .show_ui(ui, |ui| {
+                            for mode in [AccumulationMode::Mean, AccumulationMode::Sum] {
+                                changed |= ui
+                                    .selectable_value(
+                                        &mut self.config.image_config.accumulation_mode,
+                                        mode,
+                                        mode.to_string(),
+                                    )
+                                    .changed();
+                            }
+                        });
+                });
+                ui.horizontal(|ui| {
+                    let mut long_exposure = self.config.image_config.long_exposure.is_some();
+                    if ui
+                        .checkbox(&mut long_exposure, "Long Exposure")
+                        .on_hover_text(
+                            "Sum the spectra over a capture time into one, for very dim sources",
+                        )
+                        .changed()
+                    {
+                        self.config.image_config.long_exposure = long_exposure.then_some(10.);
+                        changed = true;
+                    }
+                    if let Some(seconds) = self.config.image_config.long_exposure.as_mut() {
+                        changed |= ui
+                            .add(
+                                egui::DragValue::new(seconds)
+                                    .range(0.1..=3600.)
+                                    .speed(0.1)
+                                    .suffix(" s"),
+                            )
+                            .changed();
+                    }
+                });
+
+                ui.separator();
+                let mut second_order = self.config.image_config.second_order_window.is_some();
+                if ui
+                    .checkbox(&mut second_order, "Second Order Window")
+                    .on_hover_text("Extend the spectrum with a second diffraction order")
+                    .changed()
+                {
+                    self.config.image_config.second_order_window =
+                        second_order.then_some(self.config.image_config.window);
+                    self.second_order_container.clear_buffer();
+                    changed = true;
+                }
+                if let Some(window) = self.config.image_config.second_order_window.as_mut() {
+                    let multi_order_config = &mut self.config.multi_order_config;
+                    egui::Grid::new("second_order_window").show(ui, |ui| {
+                        ui.label("Window Offset");
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.offset.x).range(0..=u16::MAX))
+                            .changed();
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.offset.y).range(0..=u16::MAX))
+                            .changed();
+                        ui.end_row();
+                        ui.label("Window Size");
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.size.x).range(1..=u16::MAX))
+                            .changed();
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.size.y).range(1..=u16::MAX))
+                            .changed();
+                        ui.end_row();
+                        let calibration = &mut multi_order_config.spectrum_calibration;
+                        for (label, point) in [
+                            ("Low", &mut calibration.low),
+                            ("High", &mut calibration.high),
+                        ] {
+                            ui.label(format!("{} Calibration", label));
+                            ui.add(
+                                egui::DragValue::new(&mut point.wavelength)
+                                    .range(200..=2000)
+                                    .suffix(" nm"),
+                            );
+                            ui.add(egui::DragValue::new(&mut point.index).prefix("Index: "));
+                            ui.end_row();
+                        }
+                        ui.label("Blend Width");
+                        ui.add(
+                            egui::DragValue::new(&mut multi_order_config.blend_width)
+                                .range(0.0..=200.)
+                                .suffix(" nm"),
+                        );
+                        ui.checkbox(&mut multi_order_config.match_intensity, "Match Intensity");
+                        ui.end_row();
+                    });
+                }
+
+                ui.separator();
+                let mut reference_beam = self.config.image_config.reference_beam_window.is_some();
+                if ui
+                    .checkbox(&mut reference_beam, "Reference Beam Window")
+                    .on_hover_text(
+                        "Dual-beam mode, divide the spectrum window by a reference beam on the \
+                         same frame to cancel lamp drift",
+                    )
+                    .changed()
+                {
+                    self.config.image_config.reference_beam_window =
+                        reference_beam.then_some(self.config.image_config.window);
+                    if let Some(container) = self.reference_beam_container.as_mut() {
+                        container.clear_buffer();
+                    }
+                    changed = true;
+                }
+                if let Some(window) = self.config.image_config.reference_beam_window.as_mut() {
+                    egui::Grid::new("reference_beam_window").show(ui, |ui| {
+                        ui.label("Window Offset");
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.offset.x).range(0..=u16::MAX))
+                            .changed();
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.offset.y).range(0..=u16::MAX))
+                            .changed();
+                        ui.end_row();
+                        ui.label("Window Size");
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.size.x).range(1..=u16::MAX))
+                            .changed();
+                        changed |= ui
+                            .add(egui::DragValue::new(&mut window.size.y).range(1..=u16::MAX))
+                            .changed();
+                        ui.end_row();
+                    });
+                }
 
                 if changed {
                     self.camera_config_change_pending = true;
@@ -382,12 +1802,77 @@ impl SpectrometerGui {
                 ));
                 if update_config_button.clicked() {
                     self.camera_config_change_pending = false;
+                    self.spectrum_container.invalidate_buffer(
+                        self.config.postprocessing_config.buffer_clear_policy,
+                        BufferClearReason::Window,
+                    );
                     // Cannot use self.send_config due to mutable borrow in open
                     self.camera_config_tx
                         .send(CameraEvent::Config(self.config.image_config.clone()))
                         .unwrap();
                 }
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.checkbox(
+                        &mut self.config.drift_tracking_config.active,
+                        "Track Vertical Drift",
+                    );
+                    ui.add_enabled(
+                        self.config.drift_tracking_config.active,
+                        Slider::new(
+                            &mut self.config.drift_tracking_config.search_margin,
+                            1..=100,
+                        )
+                        .suffix(" px")
+                        .text("Search Margin"),
+                    );
+                    ui.label(format!("Corrections: {}", self.drift_corrections));
+                });
+
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut self.config.watchdog_config.active, "Stall Watchdog");
+                    ui.add_enabled(
+                        self.config.watchdog_config.active,
+                        Slider::new(&mut self.config.watchdog_config.timeout, 1.0..=60.)
+                            .suffix(" s")
+                            .text("Timeout"),
+                    );
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Webhook URL");
+                    ui.text_edit_singleline(&mut self.config.watchdog_config.webhook_url);
+                });
             });
+
+        if add_roi_preset {
+            let name = std::mem::take(&mut self.roi_preset_name);
+            self.config.add_roi_preset(name);
+        }
+        if remove_roi_preset {
+            self.config.remove_active_roi_preset();
+        }
+        if let Some(index) = selected_roi_preset {
+            self.config.select_roi_preset(index);
+            self.config
+                .image_config
+                .clamp(frame_width as f32, frame_height as f32);
+            self.camera_config_change_pending = false;
+            self.invalidate_buffer(BufferClearReason::Window);
+            self.send_config();
+        }
+        if import_hot_pixels {
+            match read_pixel_list(&self.config.hot_pixel_config.path) {
+                Ok(pixels) => self.add_defect_pixels(&pixels),
+                Err(e) => {
+                    self.last_error = Some(ThreadResult {
+                        id: ThreadId::Main,
+                        result: Err(e),
+                    })
+                }
+            }
+        }
     }
 
     fn draw_calibration_window(&mut self, ctx: &Context) {
@@ -424,6 +1909,98 @@ impl SpectrometerGui {
                     )
                     .text("High Index"),
                 );
+                ui.horizontal(|ui| {
+                    let calibration = &mut self.config.spectrum_calibration;
+                    let mut crop = calibration.valid_range.is_some();
+                    if ui
+                        .checkbox(&mut crop, "Crop to Valid Range")
+                        .on_hover_text(
+                            "Hide extrapolated wavelengths in plot, peaks, export and feed",
+                        )
+                        .changed()
+                    {
+                        calibration.valid_range = crop.then_some(WavelengthRange {
+                            low: calibration.low.wavelength as f32,
+                            high: calibration.high.wavelength as f32,
+                        });
+                    }
+                    if let Some(range) = calibration.valid_range.as_mut() {
+                        let high = range.high;
+                        ui.add(
+                            egui::DragValue::new(&mut range.low)
+                                .range(200.0..=high)
+                                .suffix(" nm"),
+                        );
+                        ui.label("to");
+                        let low = range.low;
+                        ui.add(
+                            egui::DragValue::new(&mut range.high)
+                                .range(low..=2000.)
+                                .suffix(" nm"),
+                        );
+                    }
+                });
+                egui::CollapsingHeader::new("Drift Check").show(ui, |ui| {
+                    let check_config = &mut self.config.calibration_check_config;
+                    ui.horizontal(|ui| {
+                        if ui.checkbox(&mut check_config.active, "Active").changed() {
+                            self.calibration_check.clear();
+                        }
+                        if ui.button("Check Now").clicked() {
+                            self.calibration_check.clear();
+                        }
+                    });
+                    egui::Grid::new("calibration_check").show(ui, |ui| {
+                        ui.label("Reference Line");
+                        ui.add(
+                            egui::DragValue::new(&mut check_config.reference_wavelength)
+                                .range(200.0..=2000.)
+                                .speed(0.1)
+                                .suffix(" nm"),
+                        );
+                        ui.end_row();
+                        ui.label("Search Width");
+                        ui.add(
+                            egui::DragValue::new(&mut check_config.search_width)
+                                .range(1.0..=100.)
+                                .suffix(" nm"),
+                        );
+                        ui.end_row();
+                        ui.label("Threshold");
+                        ui.add(
+                            egui::DragValue::new(&mut check_config.threshold)
+                                .range(0.1..=50.)
+                                .speed(0.1)
+                                .suffix(" nm"),
+                        );
+                        ui.end_row();
+                        ui.label("Interval");
+                        ui.add(
+                            egui::DragValue::new(&mut check_config.interval)
+                                .range(1.0..=86400.)
+                                .suffix(" s"),
+                        );
+                        ui.end_row();
+                    });
+                    match self.calibration_check.last_result() {
+                        Some(DriftCheck::Drift(drift)) => {
+                            let text = RichText::new(format!("Drift: {drift:+.2} nm"));
+                            ui.label(
+                                if self.calibration_check.needs_recalibration(check_config) {
+                                    text.color(Color32::RED)
+                                } else {
+                                    text
+                                },
+                            );
+                        }
+                        Some(DriftCheck::LineNotFound) => {
+                            ui.label(
+                                RichText::new("Reference line not found").color(Color32::YELLOW),
+                            );
+                        }
+                        None => {}
+                    }
+                });
                 ui.separator();
                 ComboBox::from_label("Linearize")
                     .selected_text(self.config.spectrum_calibration.linearize.to_string())
@@ -458,23 +2035,31 @@ impl SpectrometerGui {
                             )
                             .changed();
 
-                        // Clear buffer if value changed
                         if changed {
-                            self.spectrum_container.clear_buffer()
+                            self.spectrum_container.invalidate_buffer(
+                                self.config.postprocessing_config.buffer_clear_policy,
+                                BufferClearReason::Linearization,
+                            );
                         };
                     });
-                ui.add(
-                    Slider::new(&mut self.config.spectrum_calibration.gain_r, 0.0..=10.)
-                        .text("Gain R"),
-                );
-                ui.add(
-                    Slider::new(&mut self.config.spectrum_calibration.gain_g, 0.0..=10.)
-                        .text("Gain G"),
-                );
-                ui.add(
-                    Slider::new(&mut self.config.spectrum_calibration.gain_b, 0.0..=10.)
-                        .text("Gain B"),
-                );
+                let mut gain_changed = ui
+                    .add(
+                        Slider::new(&mut self.config.spectrum_calibration.gain_r, 0.0..=10.)
+                            .text("Gain R"),
+                    )
+                    .changed();
+                gain_changed |= ui
+                    .add(
+                        Slider::new(&mut self.config.spectrum_calibration.gain_g, 0.0..=10.)
+                            .text("Gain G"),
+                    )
+                    .changed();
+                gain_changed |= ui
+                    .add(
+                        Slider::new(&mut self.config.spectrum_calibration.gain_b, 0.0..=10.)
+                            .text("Gain B"),
+                    )
+                    .changed();
 
                 ui.horizontal(|ui| {
                     let unity_button = ui.button(GainPresets::Unity.to_string());
@@ -482,24 +2067,63 @@ impl SpectrometerGui {
                         self.config
                             .spectrum_calibration
                             .set_gain_preset(GainPresets::Unity);
+                        gain_changed = true;
                     }
                     let srgb_button = ui.button(GainPresets::SRgb.to_string());
                     if srgb_button.clicked() {
                         self.config
                             .spectrum_calibration
                             .set_gain_preset(GainPresets::SRgb);
+                        gain_changed = true;
                     }
                     let rec601_button = ui.button(GainPresets::Rec601.to_string());
                     if rec601_button.clicked() {
                         self.config
                             .spectrum_calibration
                             .set_gain_preset(GainPresets::Rec601);
+                        gain_changed = true;
                     }
                     let rec709_button = ui.button(GainPresets::Rec709.to_string());
                     if rec709_button.clicked() {
                         self.config
                             .spectrum_calibration
                             .set_gain_preset(GainPresets::Rec709);
+                        gain_changed = true;
+                    }
+                });
+                if gain_changed {
+                    self.spectrum_container.invalidate_buffer(
+                        self.config.postprocessing_config.buffer_clear_policy,
+                        BufferClearReason::Gain,
+                    );
+                }
+                ui.horizontal(|ui| {
+                    let balance_button = ui.button("Balance Channels").on_hover_text(
+                        "Set the gains so that R, G and B coincide at the wavelength",
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.balance_wavelength)
+                            .range(200.0..=1200.)
+                            .suffix(" nm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut self.balance_width)
+                            .range(0.0..=200.)
+                            .prefix("± ")
+                            .suffix(" nm"),
+                    )
+                    .on_hover_text("Average over this band, 0 for a single wavelength");
+                    if balance_button.clicked() {
+                        if let Err(e) = self.spectrum_container.balance_channels(
+                            &mut self.config.spectrum_calibration,
+                            self.balance_wavelength,
+                            self.balance_width * 2.,
+                        ) {
+                            self.last_error = Some(ThreadResult {
+                                id: ThreadId::Main,
+                                result: Err(e),
+                            });
+                        }
                     }
                 });
 
@@ -525,54 +2149,453 @@ impl SpectrometerGui {
                 };
 
                 ui.separator();
+                ui.add_enabled(
+                    !self.spectrum_container.has_zero_reference(),
+                    egui::Checkbox::new(
+                        &mut self.config.postprocessing_config.raw_zero_reference,
+                        "Subtract Zero Reference Before Linearization",
+                    ),
+                );
                 let set_zero_button = ui.add_enabled(
                     !self.spectrum_container.has_zero_reference(),
                     Button::new("Set Current As Zero Reference"),
                 );
                 if set_zero_button.clicked() {
-                    self.spectrum_container.set_zero_reference();
+                    self.spectrum_container
+                        .set_zero_reference(self.config.postprocessing_config.raw_zero_reference);
+                    self.session_dirty = true;
                 }
                 let clear_zero_button = ui.add_enabled(
                     self.spectrum_container.has_zero_reference(),
-                    Button::new("Clear Zero Reference"),
+                    Button::new(if self.spectrum_container.is_capturing_zero_reference() {
+                        "Capturing Zero Reference..."
+                    } else {
+                        "Clear Zero Reference"
+                    }),
                 );
                 if clear_zero_button.clicked() {
                     self.spectrum_container.clear_zero_reference();
+                    self.session_dirty = true;
                 }
-            });
-    }
 
-    fn draw_postprocessing_window(&mut self, ctx: &Context) {
-        egui::Window::new("Postprocessing")
-            .open(&mut self.config.view_config.show_postprocessing_window)
-            .show(ctx, |ui| {
+                ui.separator();
+                let mut interleaved_dark = self.dark_cycle.is_some();
+                if ui
+                    .checkbox(&mut interleaved_dark, "Interleaved Dark Measurement")
+                    .on_hover_text("Periodically close the shutter and replace the zero reference")
+                    .changed()
+                {
+                    if interleaved_dark {
+                        self.dark_cycle = Some(DarkCycle::new(Instant::now()));
+                    } else if self.dark_cycle.take().is_some_and(|c| c.is_dark()) {
+                        if let Err(e) =
+                            run_shutter_command(&self.config.shutter_config.open_command)
+                        {
+                            self.last_error = Some(ThreadResult {
+                                id: ThreadId::Main,
+                                result: Err(e),
+                            });
+                        }
+                    }
+                }
+                let shutter_config = &mut self.config.shutter_config;
                 ui.add(
-                    Slider::new(
-                        &mut self.config.postprocessing_config.spectrum_buffer_size,
-                        1..=100,
-                    )
-                    .text("Averaging Buffer Size"),
+                    Slider::new(&mut shutter_config.interval, 10.0..=3600.)
+                        .logarithmic(true)
+                        .suffix(" s")
+                        .text("Dark Interval"),
+                );
+                ui.add(
+                    Slider::new(&mut shutter_config.settle_time, 0.0..=10.)
+                        .suffix(" s")
+                        .text("Shutter Settle Time"),
                 );
+                ui.add_enabled_ui(self.dark_cycle.is_none(), |ui| {
+                    egui::Grid::new("shutter_commands").show(ui, |ui| {
+                        ui.label("Close Command");
+                        ui.text_edit_singleline(&mut shutter_config.close_command);
+                        ui.end_row();
+                        ui.label("Open Command");
+                        ui.text_edit_singleline(&mut shutter_config.open_command);
+                        ui.end_row();
+                    });
+                });
+
                 ui.separator();
+                let mut stop_transmission = false;
+                match self.transmission.as_mut() {
+                    None => {
+                        if ui
+                            .button("Start Transmission Sequence")
+                            .on_hover_text("Capture reference and dark, then show transmittance")
+                            .clicked()
+                        {
+                            self.transmission = Some(TransmissionSequence::default());
+                        }
+                    }
+                    Some(sequence) => {
+                        ui.label(RichText::new(sequence.prompt()).color(Color32::YELLOW));
+                        ui.horizontal(|ui| {
+                            let capture_button = ui.add_enabled(
+                                sequence.step() != TransmissionStep::Measuring,
+                                Button::new("Capture"),
+                            );
+                            if capture_button.clicked() {
+                                let name = match sequence.step() {
+                                    TransmissionStep::CaptureReference => "Transmission Reference",
+                                    _ => "Transmission Dark",
+                                };
+                                sequence.capture(&self.spectrum_container.precise_spectrum());
+                                let spectrum = self.spectrum_container.spectrum();
+                                self.snapshots.push(Snapshot::new(
+                                    name.to_string(),
+                                    RecordedSpectrum::from_spectrum(
+                                        spectrum,
+                                        self.spectrum_container.capture_time(),
+                                    ),
+                                ));
+                                self.session_dirty = true;
+                            }
+                            if ui.button("Restart").clicked() {
+                                *sequence = TransmissionSequence::default();
+                            }
+                            stop_transmission = ui.button("Stop").clicked();
+                        });
+                    }
+                }
+                let transmission_config = &mut self.config.transmission_config;
                 ui.horizontal(|ui| {
-                    ui.checkbox(
-                        &mut self.config.postprocessing_config.spectrum_filter_active,
-                        "Low-Pass Filter",
-                    );
+                    ui.checkbox(&mut transmission_config.optical_density, "Optical Density")
+                        .on_hover_text("Show log10(reference / sample) to measure filter blocking");
                     ui.add_enabled(
-                        self.config.postprocessing_config.spectrum_filter_active,
-                        Slider::new(
-                            &mut self.config.postprocessing_config.spectrum_filter_cutoff,
-                            0.001..=1.,
-                        )
-                        .logarithmic(true)
-                        .text("Cutoff"),
-                    );
+                        transmission_config.optical_density,
+                        egui::DragValue::new(&mut transmission_config.transmittance_floor)
+                            .range(1e-9..=0.1)
+                            .speed(1e-6)
+                            .custom_formatter(|v, _| format!("{v:e}"))
+                            .prefix("Floor: "),
+                    )
+                    .on_hover_text(format!(
+                        "Transmittance treated as noise floor, limits OD to {:.1}",
+                        transmission_config.max_optical_density()
+                    ));
                 });
-                ui.separator();
-                ui.add_enabled(
-                    self.config.reference_config.reference.is_some(),
-                    Slider::new(&mut self.config.reference_config.scale, 0.001..=100.)
+                if stop_transmission {
+                    self.transmission = None;
+                }
+            });
+    }
+
+    fn update_dark_cycle(&mut self, new_spectrum: bool) {
+        let Some(dark_cycle) = self.dark_cycle.as_mut() else {
+            return;
+        };
+        if !self.acquisition.is_running() {
+            return;
+        }
+        let action = dark_cycle.update(
+            Instant::now(),
+            new_spectrum,
+            self.config.postprocessing_config.spectrum_buffer_size,
+            &self.config.shutter_config,
+        );
+        let raw = self.config.postprocessing_config.raw_zero_reference;
+        let result = match action {
+            Some(DarkCycleAction::Close) => {
+                run_shutter_command(&self.config.shutter_config.close_command)
+            }
+            Some(DarkCycleAction::CaptureDark) => {
+                self.spectrum_container.clear_zero_reference();
+                self.invalidate_buffer(BufferClearReason::Restart);
+                if raw {
+                    self.spectrum_container.set_zero_reference(true);
+                }
+                Ok(())
+            }
+            Some(DarkCycleAction::Open) => {
+                if !raw {
+                    self.spectrum_container.set_zero_reference(false);
+                }
+                log::info!("Updated zero reference from dark measurement");
+                self.session_dirty = true;
+                run_shutter_command(&self.config.shutter_config.open_command)
+            }
+            Some(DarkCycleAction::Resume) => {
+                self.invalidate_buffer(BufferClearReason::Restart);
+                Ok(())
+            }
+            None => Ok(()),
+        };
+        if let Err(e) = result {
+            self.dark_cycle = None;
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result: Err(e),
+            });
+        }
+    }
+
+    /// True while the shutter is closed for a dark measurement
+    fn update_auto_exposure(&mut self, new_spectrum: bool) {
+        let config = &self.config.auto_exposure_config;
+        if !config.active
+            || !new_spectrum
+            || !self.acquisition.is_running()
+            || self.config.hdr_config.active
+            || self.measuring_dark()
+        {
+            return;
+        }
+        let (Some(peak), Some(exposure), Some(exposure_range)) = (
+            self.spectrum_container.newest_peak(),
+            self.spectrum_container.exposure(),
+            ControlRange::of(&self.camera_controls, KnownCameraControl::Exposure),
+        ) else {
+            return;
+        };
+        let controls = self.auto_exposure.update(
+            peak,
+            exposure,
+            exposure_range,
+            ControlRange::of(&self.camera_controls, KnownCameraControl::Gain),
+            config,
+        );
+        if !controls.is_empty() {
+            self.camera_config_tx
+                .send(CameraEvent::Controls(controls))
+                .unwrap();
+            self.invalidate_buffer(BufferClearReason::CameraControls);
+        }
+    }
+
+    fn measuring_dark(&self) -> bool {
+        self.dark_cycle.as_ref().is_some_and(DarkCycle::is_dark)
+    }
+
+    fn draw_postprocessing_window(&mut self, ctx: &Context) {
+        let mut export_preset = false;
+        let mut import_preset = false;
+        egui::Window::new("Postprocessing")
+            .open(&mut self.config.view_config.show_postprocessing_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Preset");
+                    ui.text_edit_singleline(&mut self.config.import_export_config.preset_path);
+                    export_preset = ui
+                        .button("Export")
+                        .on_hover_text(
+                            "Write the postprocessing, linearization and gain settings to a file",
+                        )
+                        .clicked();
+                    import_preset = ui.button("Import").clicked();
+                });
+                ui.separator();
+                ui.add(
+                    Slider::new(
+                        &mut self.config.postprocessing_config.spectrum_buffer_size,
+                        1..=100,
+                    )
+                    .text("Averaging Buffer Size"),
+                );
+                ComboBox::from_label("Processing Order")
+                    .selected_text(
+                        self.config
+                            .postprocessing_config
+                            .processing_order
+                            .to_string(),
+                    )
+                    .show_ui(ui, |ui| {
+                        let mut changed = false;
+                        for order in [
+                            ProcessingOrder::LinearizeThenAverage,
+                            ProcessingOrder::AverageThenLinearize,
+                        ] {
+                            changed |= ui
+                                .selectable_value(
+                                    &mut self.config.postprocessing_config.processing_order,
+                                    order,
+                                    order.to_string(),
+                                )
+                                .changed();
+                        }
+
+                        // Buffered spectra may already be linearized
+                        if changed {
+                            self.spectrum_container.invalidate_buffer(
+                                self.config.postprocessing_config.buffer_clear_policy,
+                                BufferClearReason::Linearization,
+                            );
+                        };
+                    })
+                    .response
+                    .on_hover_text("Gains and scaling are always applied after averaging");
+                ComboBox::from_label("Clear Buffer")
+                    .selected_text(
+                        self.config
+                            .postprocessing_config
+                            .buffer_clear_policy
+                            .to_string(),
+                    )
+                    .show_ui(ui, |ui| {
+                        for policy in [
+                            BufferClearPolicy::Smart,
+                            BufferClearPolicy::Always,
+                            BufferClearPolicy::Never,
+                        ] {
+                            ui.selectable_value(
+                                &mut self.config.postprocessing_config.buffer_clear_policy,
+                                policy,
+                                policy.to_string(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Smart keeps the buffer on gain changes and clears it when camera \
+                         settings, the window or the linearization change",
+                    );
+                ui.checkbox(
+                    &mut self.config.postprocessing_config.double_precision,
+                    "Double Precision",
+                )
+                .on_hover_text("Average and postprocess spectra in f64");
+                ui.checkbox(
+                    &mut self.config.postprocessing_config.time_weighted_average,
+                    "Time Weighted Average",
+                )
+                .on_hover_text(
+                    "Weight spectra by their frame interval to compensate dropped frames",
+                );
+                ui.checkbox(
+                    &mut self.config.postprocessing_config.incremental_average,
+                    "Incremental Average",
+                )
+                .on_hover_text(
+                    "Keep a running sum instead of summing the whole buffer for every \
+                    spectrum, not used with double precision or time weighting",
+                );
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let filter_active = ui.checkbox(
+                        &mut self.config.postprocessing_config.spectrum_filter_active,
+                        "Low-Pass Filter",
+                    );
+                    let cutoff = ui.add_enabled(
+                        self.config.postprocessing_config.spectrum_filter_active,
+                        Slider::new(
+                            &mut self.config.postprocessing_config.spectrum_filter_cutoff,
+                            0.001..=1.,
+                        )
+                        .logarithmic(true)
+                        .text("Cutoff"),
+                    );
+                    // Overlay the unfiltered spectrum for a moment after adjusting the filter
+                    if filter_active.changed() || cutoff.changed() || cutoff.dragged() {
+                        self.smoothing_preview_until =
+                            Some(Instant::now() + SMOOTHING_PREVIEW_DURATION);
+                    }
+                });
+                ui.separator();
+                let postprocessing_config = &mut self.config.postprocessing_config;
+                ComboBox::from_label("Baseline Correction")
+                    .selected_text(postprocessing_config.baseline_correction.to_string())
+                    .show_ui(ui, |ui| {
+                        for method in [
+                            BaselineCorrection::Off,
+                            BaselineCorrection::Als,
+                            BaselineCorrection::RollingBall,
+                        ] {
+                            ui.selectable_value(
+                                &mut postprocessing_config.baseline_correction,
+                                method,
+                                method.to_string(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text("Remove a broadband background under sharp peaks");
+                match postprocessing_config.baseline_correction {
+                    BaselineCorrection::Off => {}
+                    BaselineCorrection::Als => {
+                        ui.add(
+                            Slider::new(&mut postprocessing_config.baseline_smoothness, 2.0..=9.)
+                                .text("Smoothness (log λ)"),
+                        );
+                        ui.add(
+                            Slider::new(&mut postprocessing_config.baseline_asymmetry, 0.001..=0.1)
+                                .logarithmic(true)
+                                .text("Asymmetry"),
+                        );
+                    }
+                    BaselineCorrection::RollingBall => {
+                        ui.add(
+                            Slider::new(&mut postprocessing_config.baseline_radius, 2..=500)
+                                .logarithmic(true)
+                                .text("Radius"),
+                        )
+                        .on_hover_text("Half width in points, wider than the peaks");
+                    }
+                }
+                ui.separator();
+                ui.collapsing("Flash Trigger", |ui| {
+                    let mut trigger_active = self.spectrum_container.is_flash_trigger_active();
+                    if ui
+                        .checkbox(&mut trigger_active, "Active")
+                        .on_hover_text(
+                            "Only update the spectrum on rapid intensity jumps \
+                            and average the frames of each jump",
+                        )
+                        .changed()
+                    {
+                        self.spectrum_container.set_flash_trigger(trigger_active);
+                    }
+                    let trigger_config = &mut self.config.flash_trigger_config;
+                    ui.add(
+                        Slider::new(&mut trigger_config.threshold, 0.05..=10.)
+                            .logarithmic(true)
+                            .text("Relative Threshold"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trigger_config.history, 1..=100).text("Background Frames"),
+                    );
+                    ui.add(
+                        Slider::new(&mut trigger_config.max_frames, 1..=100)
+                            .text("Max Event Frames"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{} events",
+                            self.spectrum_container.flash_events().len()
+                        ));
+                        if ui.button("Clear").clicked() {
+                            self.spectrum_container.clear_flash_events();
+                        }
+                    });
+                    egui::ScrollArea::vertical()
+                        .max_height(150.)
+                        .show(ui, |ui| {
+                            for (i, event) in self
+                                .spectrum_container
+                                .flash_events()
+                                .iter()
+                                .enumerate()
+                                .rev()
+                            {
+                                let age = event.start.elapsed().unwrap_or_default().as_secs();
+                                ui.label(format!(
+                                    "#{}: {} frames, peak {:.3}, {} s ago",
+                                    i + 1,
+                                    event.frames,
+                                    event.peak_intensity,
+                                    age
+                                ));
+                            }
+                        });
+                });
+                ui.separator();
+                ui.add_enabled(
+                    self.config.reference_config.reference.is_some(),
+                    Slider::new(&mut self.config.reference_config.scale, 0.001..=100.)
                         .logarithmic(true)
                         .text("Reference Scale"),
                 );
@@ -580,11 +2603,86 @@ impl SpectrometerGui {
                 ui.horizontal(|ui| {
                     ui.checkbox(&mut self.config.view_config.draw_peaks, "Show Peaks");
                     ui.checkbox(&mut self.config.view_config.draw_dips, "Show Dips");
+                    ui.checkbox(
+                        &mut self.config.view_config.draw_spectrum_colors,
+                        "Show Colors Under Spectrum",
+                    );
+                    ui.checkbox(
+                        &mut self.config.view_config.reverse_wavelength_axis,
+                        "Reverse Wavelength Axis",
+                    )
+                    .on_hover_text(
+                        "Plot decreasing wavelengths to the right, exports are unchanged",
+                    );
+                    ui.checkbox(
+                        &mut self.config.view_config.canonical_wavelength_ticks,
+                        "Ticks Every 50 nm",
+                    )
+                    .on_hover_text("Minor gridlines every 10 nm");
+                });
+                egui::CollapsingHeader::new("Wavelength Markers").show(ui, |ui| {
+                    let markers = &mut self.config.view_config.wavelength_markers;
+                    let mut remove = None;
+                    for (i, marker) in markers.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            ui.add(
+                                egui::TextEdit::singleline(&mut marker.label).desired_width(100.),
+                            );
+                            ui.add(egui::DragValue::new(&mut marker.wavelength).suffix(" nm"));
+                            if ui.button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = remove {
+                        markers.remove(i);
+                    }
+                    if ui.button("Add Marker").clicked() {
+                        markers.push(WavelengthMarker {
+                            wavelength: 589.,
+                            label: "Na D".to_string(),
+                        });
+                    }
+                });
+                ui.add_enabled_ui(self.config.view_config.draw_spectrum_colors, |ui| {
+                    let color_config = &mut self.config.view_config.spectrum_color_config;
+                    ui.horizontal(|ui| {
+                        ui.label("UV");
+                        ui.color_edit_button_srgba(&mut color_config.ultraviolet);
+                        ui.label("IR");
+                        ui.color_edit_button_srgba(&mut color_config.infrared);
+                        ui.checkbox(&mut color_config.intensity_scaled, "Scale With Intensity");
+                    })
+                    .response
+                    .on_hover_text("Colors beyond the visible range");
+                    ui.add(
+                        Slider::new(&mut color_config.min_brightness, 0.0..=1.)
+                            .text("Minimum Color Brightness"),
+                    )
+                    .on_hover_text(
+                        "Keeps dim colors visible on dark backgrounds and at low intensities",
+                    );
                 });
                 ui.add(
                     Slider::new(&mut self.config.view_config.peaks_dips_find_window, 1..=200)
                         .text("Peaks/Dips Find Window"),
                 );
+                ComboBox::from_id_salt("peak_fit")
+                    .selected_text(format!("Peak Fit: {}", self.config.view_config.peak_fit))
+                    .show_ui(ui, |ui| {
+                        for fit in [PeakFit::Off, PeakFit::Gaussian, PeakFit::Lorentzian] {
+                            ui.selectable_value(
+                                &mut self.config.view_config.peak_fit,
+                                fit,
+                                fit.to_string(),
+                            );
+                        }
+                    })
+                    .response
+                    .on_hover_text(
+                        "Fit a line shape over the find window for sub-pixel peak wavelengths, \
+                         the report lists FWHM and area",
+                    );
                 ui.add(
                     Slider::new(
                         &mut self.config.view_config.peaks_dips_unique_window,
@@ -592,104 +2690,506 @@ impl SpectrometerGui {
                     )
                     .text("Peaks/Dips Filter Window"),
                 );
+                ui.horizontal(|ui| {
+                    let label_config = &mut self.config.view_config.peak_label_config;
+                    ComboBox::from_id_salt("peak_label_content")
+                        .selected_text(format!("Labels: {}", label_config.content))
+                        .show_ui(ui, |ui| {
+                            for content in [
+                                PeakLabelContent::Wavelength,
+                                PeakLabelContent::WavelengthAndValue,
+                                PeakLabelContent::PhotonEnergy,
+                                PeakLabelContent::Wavenumber,
+                            ] {
+                                ui.selectable_value(
+                                    &mut label_config.content,
+                                    content,
+                                    content.to_string(),
+                                );
+                            }
+                        });
+                    ui.add(Slider::new(&mut label_config.decimal_places, 0..=4).text("Decimals"));
+                    ui.checkbox(&mut self.config.view_config.decimal_comma, "Decimal Comma")
+                        .on_hover_text("Show decimal commas in plot and peak labels");
+                });
+                ui.separator();
+                ui.checkbox(&mut self.config.narrowband_config.active, "Narrowband Mode")
+                    .on_hover_text(
+                        "Zoom to the dominant line and track its sub-pixel wavelength over time",
+                    );
+                ui.add_enabled_ui(self.config.narrowband_config.active, |ui| {
+                    ui.add(
+                        Slider::new(&mut self.config.narrowband_config.zoom_width, 0.5..=50.)
+                            .logarithmic(true)
+                            .text("Zoom Width [nm]"),
+                    );
+                    ui.add(
+                        Slider::new(
+                            &mut self.config.narrowband_config.history_duration,
+                            5.0..=600.,
+                        )
+                        .logarithmic(true)
+                        .text("History [s]"),
+                    );
+                });
+                ui.separator();
+                let mut sonification_active = self.sonifier.is_some();
+                if ui
+                    .checkbox(&mut sonification_active, "Sonification")
+                    .changed()
+                {
+                    if sonification_active {
+                        match Sonifier::start(&self.config.sonification_config) {
+                            Ok(sonifier) => self.sonifier = Some(sonifier),
+                            Err(e) => {
+                                self.last_error = Some(ThreadResult {
+                                    id: ThreadId::Main,
+                                    result: Err(e),
+                                })
+                            }
+                        }
+                    } else {
+                        self.sonifier = None;
+                    }
+                }
+                ui.add_enabled_ui(self.sonifier.is_none(), |ui| {
+                    let sonification_config = &mut self.config.sonification_config;
+                    ui.add(Slider::new(&mut sonification_config.bands, 1..=64).text("Bands"));
+                    ui.add(Slider::new(&mut sonification_config.volume, 0.0..=1.).text("Volume"));
+                    ui.horizontal(|ui| {
+                        ui.label("Player");
+                        ui.text_edit_singleline(&mut sonification_config.player_command);
+                    });
+                });
+            });
+        if export_preset {
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result: PostprocessingPreset::new(&self.config)
+                    .write(&self.config.import_export_config.preset_path),
             });
+        }
+        if import_preset {
+            let result = PostprocessingPreset::read(&self.config.import_export_config.preset_path)
+                .map(|preset| {
+                    preset.apply(&mut self.config);
+                    self.invalidate_buffer(BufferClearReason::Linearization);
+                });
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result,
+            });
+        }
+    }
+
+    /// Channel peaks relative to full scale, so that the exposure can be set to use the range of
+    /// the weakest channel without clipping the strongest one
+    fn draw_headroom(ui: &mut egui::Ui, channel_peaks: [f32; 3], monochrome: bool) {
+        let channels: &[(&str, Color32)] = if monochrome {
+            &[("Intensity", Color32::GRAY)]
+        } else {
+            &[
+                ("R", Color32::DARK_RED),
+                ("G", Color32::DARK_GREEN),
+                ("B", Color32::DARK_BLUE),
+            ]
+        };
+        for (&peak, (name, color)) in channel_peaks.iter().zip(channels) {
+            let fill = if peak >= SATURATION_LEVEL {
+                Color32::RED
+            } else {
+                *color
+            };
+            ui.add(
+                ProgressBar::new(peak)
+                    .fill(fill)
+                    .text(format!("{name} {:.0} %", peak * 100.)),
+            );
+        }
     }
 
     fn draw_camera_control_window(&mut self, ctx: &Context) {
+        let channel_peaks = self.spectrum_container.channel_peaks();
+        let monochrome = self.config.is_monochrome();
         egui::Window::new("Camera Controls")
             .open(&mut self.config.view_config.show_camera_control_window)
             .show(ctx, |ui| {
+                if let Some(channel_peaks) = channel_peaks {
+                    egui::CollapsingHeader::new("Headroom")
+                        .default_open(true)
+                        .show(ui, |ui| Self::draw_headroom(ui, channel_peaks, monochrome))
+                        .header_response
+                        .on_hover_text("Highest value in the spectrum window per channel");
+                    ui.separator();
+                }
                 let mut changed_controls = vec![];
-                for ctrl in &mut self.camera_controls {
-                    let value_setter = match ctrl.value() {
-                        ControlValueSetter::Integer(mut value) => {
-                            if let ControlValueDescription::IntegerRange {
-                                min,
-                                max,
-                                value: _,
-                                step,
-                                default: _,
-                            } = ctrl.description()
-                            {
-                                if ui
-                                    .add(
-                                        Slider::new(&mut value, (*min + 1)..=(*max - 1))
-                                            .step_by(*step as f64)
-                                            .text(ctrl.name()),
-                                    )
-                                    .changed()
-                                {
-                                    Some(ControlValueSetter::Integer(value))
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        }
-                        ControlValueSetter::Boolean(mut value) => {
-                            if let ControlValueDescription::Boolean { .. } = ctrl.description() {
-                                if ui.checkbox(&mut value, ctrl.name()).changed() {
-                                    Some(ControlValueSetter::Boolean(value))
-                                } else {
-                                    None
-                                }
-                            } else {
-                                None
-                            }
-                        }
-                        // TODO: ControlValueDescription::Enum only has integer options no
-                        // description string.
-                        //
-                        //v4l::control::Type::Menu => {
-                        //    let mut changed = false;
-                        //    let items = match ctrl.items.as_ref() {
-                        //        None => continue,
-                        //        Some(items) => items,
-                        //    };
-                        //    let selected_text =
-                        //        match items.iter().find(|&i| i.0 == own_ctrl.value as u32) {
-                        //            None => continue,
-                        //            Some(i) => i.1.to_string(),
-                        //        };
-                        //    ComboBox::from_label(&ctrl.name)
-                        //        .selected_text(selected_text)
-                        //        .show_ui(ui, |ui| {
-                        //            for item in items.iter() {
-                        //                changed |= ui
-                        //                    .selectable_value(
-                        //                        &mut own_ctrl.value,
-                        //                        item.0 as i32,
-                        //                        item.1.to_string(),
-                        //                    )
-                        //                    .changed();
-                        //            }
-                        //        });
-                        //    changed
-                        //}
-                        _ => None,
-                    };
-                    if let Some(value_setter) = value_setter {
-                        changed_controls.push((ctrl.control(), value_setter));
-                        self.spectrum_container.clear_buffer();
+                if ui
+                    .checkbox(
+                        &mut self.measurement_mode,
+                        "Measurement Mode (Disable Auto Controls)",
+                    )
+                    .changed()
+                {
+                    let auto_controls = if self.measurement_mode {
+                        measurement_mode_controls(&self.camera_controls)
+                    } else {
+                        vec![]
                     };
+                    changed_controls.extend(auto_controls.iter().cloned());
+                    self.camera_config_tx
+                        .send(CameraEvent::WatchControls(auto_controls))
+                        .unwrap();
+                    self.spectrum_container.invalidate_buffer(
+                        self.config.postprocessing_config.buffer_clear_policy,
+                        BufferClearReason::CameraControls,
+                    );
                 }
-                // TODO
-                //
-                //let default_button = ui.button("All default");
-                //if default_button.clicked() {
-                //    for ctrl in &mut self.camera_raw_controls {
-                //        let ctrl = match ctrl.downcast_ref::<Description>() {
-                //            None => continue,
-                //            Some(ctrl) => ctrl,
-                //        };
-                //        let own_ctrl =
-                //            match self.camera_controls.iter_mut().find(|c| c.id == ctrl.id) {
-                //                None => continue,
-                //                Some(own_ctrl) => own_ctrl,
-                //            };
-                //
-                //        own_ctrl.value = ctrl.default;
+                ui.separator();
+                let hdr_config = &mut self.config.hdr_config;
+                let mut hdr_changed = ui
+                    .checkbox(&mut hdr_config.active, "HDR Bracketing")
+                    .on_hover_text("Needs manual exposure, e.g. in measurement mode")
+                    .changed();
+                ui.horizontal(|ui| {
+                    ui.label("Exposures");
+                    for exposure in &mut hdr_config.exposures {
+                        hdr_changed |= ui
+                            .add(egui::DragValue::new(exposure).range(1..=i64::MAX))
+                            .changed();
+                    }
+                    if ui.button("+").clicked() {
+                        let last = hdr_config.exposures.last().copied().unwrap_or(1);
+                        hdr_config.exposures.push(last * 10);
+                        hdr_changed = true;
+                    }
+                    if ui.button("-").clicked() && hdr_config.exposures.len() > 1 {
+                        hdr_config.exposures.pop();
+                        hdr_changed = true;
+                    }
+                });
+                hdr_changed |= ui
+                    .add(Slider::new(&mut hdr_config.settle_frames, 0..=10).text("Settle Frames"))
+                    .changed();
+                if hdr_changed {
+                    self.camera_config_tx
+                        .send(CameraEvent::Bracketing(hdr_config.clone()))
+                        .unwrap();
+                    self.spectrum_container.invalidate_buffer(
+                        self.config.postprocessing_config.buffer_clear_policy,
+                        BufferClearReason::CameraControls,
+                    );
+                }
+                ui.separator();
+                let reconnect_config = &mut self.config.reconnect_config;
+                let mut reconnect_changed = ui
+                    .checkbox(&mut reconnect_config.active, "Reconnect on Failure")
+                    .on_hover_text(
+                        "Reopen the camera with the same format and controls \
+                         if the stream fails",
+                    )
+                    .changed();
+                ui.horizontal(|ui| {
+                    ui.label("Attempts");
+                    reconnect_changed |= ui
+                        .add(egui::DragValue::new(&mut reconnect_config.max_attempts))
+                        .on_hover_text("0 for unlimited attempts")
+                        .changed();
+                    ui.label("Delay");
+                    reconnect_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut reconnect_config.initial_delay)
+                                .range(0.1..=60.)
+                                .suffix(" s"),
+                        )
+                        .changed();
+                    ui.label("to");
+                    reconnect_changed |= ui
+                        .add(
+                            egui::DragValue::new(&mut reconnect_config.max_delay)
+                                .range(0.1..=3600.)
+                                .suffix(" s"),
+                        )
+                        .changed();
+                });
+                if reconnect_changed {
+                    self.camera_config_tx
+                        .send(CameraEvent::Reconnect(reconnect_config.clone()))
+                        .unwrap();
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut self.config.dark_frame_config.frames)
+                            .range(1..=1000)
+                            .prefix("Frames: "),
+                    );
+                    if ui
+                        .add_enabled(
+                            self.acquisition.is_running()
+                                && self.dark_frame != CorrectionFrameState::Capturing,
+                            Button::new("Capture Dark Frame"),
+                        )
+                        .on_hover_text(
+                            "Cap the lens, the mean of the frames is subtracted \
+                             from every following frame",
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::CaptureDarkFrame(
+                                self.config.dark_frame_config.frames,
+                            ))
+                            .unwrap();
+                        self.dark_frame = CorrectionFrameState::Capturing;
+                    }
+                    if ui
+                        .add_enabled(
+                            self.dark_frame != CorrectionFrameState::None,
+                            Button::new("Clear Dark Frame"),
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::ClearDarkFrame)
+                            .unwrap();
+                        self.dark_frame = CorrectionFrameState::None;
+                        self.spectrum_container.invalidate_buffer(
+                            self.config.postprocessing_config.buffer_clear_policy,
+                            BufferClearReason::CameraControls,
+                        );
+                    }
+                });
+                match self.dark_frame {
+                    CorrectionFrameState::Capturing => {
+                        ui.colored_label(
+                            Color32::KHAKI,
+                            "Capturing dark frame, keep the lens capped",
+                        );
+                    }
+                    CorrectionFrameState::Available => {
+                        ui.label("Dark frame is subtracted");
+                    }
+                    CorrectionFrameState::None => {}
+                }
+                ui.separator();
+                let flat_field_config = &mut self.config.flat_field_config;
+                ui.horizontal(|ui| {
+                    ui.add(
+                        egui::DragValue::new(&mut flat_field_config.frames)
+                            .range(1..=1000)
+                            .prefix("Frames: "),
+                    );
+                    if ui
+                        .add_enabled(
+                            self.acquisition.is_running()
+                                && self.flat_field != CorrectionFrameState::Capturing,
+                            Button::new("Capture Flat Field"),
+                        )
+                        .on_hover_text(
+                            "Illuminate the slit uniformly, every following frame is divided \
+                             by the mean of the frames",
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::CaptureFlatField(flat_field_config.frames))
+                            .unwrap();
+                        self.flat_field = CorrectionFrameState::Capturing;
+                    }
+                    if ui
+                        .add_enabled(
+                            self.flat_field != CorrectionFrameState::None,
+                            Button::new("Clear Flat Field"),
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::ClearFlatField)
+                            .unwrap();
+                        self.flat_field = CorrectionFrameState::None;
+                        self.spectrum_container.invalidate_buffer(
+                            self.config.postprocessing_config.buffer_clear_policy,
+                            BufferClearReason::CameraControls,
+                        );
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut flat_field_config.path);
+                    if ui.button("Load").clicked() {
+                        self.camera_config_tx
+                            .send(CameraEvent::LoadFlatField(flat_field_config.path.clone()))
+                            .unwrap();
+                    }
+                    if ui
+                        .add_enabled(
+                            self.flat_field == CorrectionFrameState::Available,
+                            Button::new("Save"),
+                        )
+                        .clicked()
+                    {
+                        self.camera_config_tx
+                            .send(CameraEvent::SaveFlatField(flat_field_config.path.clone()))
+                            .unwrap();
+                    }
+                });
+                match self.flat_field {
+                    CorrectionFrameState::Capturing => {
+                        ui.colored_label(
+                            Color32::KHAKI,
+                            "Capturing flat field, keep the illumination uniform",
+                        );
+                    }
+                    CorrectionFrameState::Available => {
+                        ui.label("Frames are divided by the flat field");
+                    }
+                    CorrectionFrameState::None => {}
+                }
+                ui.separator();
+                let frame_recording_config = &mut self.config.frame_recording_config;
+                ui.horizontal(|ui| {
+                    if ui
+                        .checkbox(&mut self.frame_recording, "Record Frames")
+                        .on_hover_text(
+                            "Save the raw frames as numbered PNG images, the directory can be \
+                             replayed as image file source",
+                        )
+                        .changed()
+                    {
+                        self.camera_config_tx
+                            .send(if self.frame_recording {
+                                CameraEvent::StartFrameRecording(frame_recording_config.clone())
+                            } else {
+                                CameraEvent::StopFrameRecording
+                            })
+                            .unwrap();
+                    }
+                    ui.add_enabled(
+                        !self.frame_recording,
+                        egui::TextEdit::singleline(&mut frame_recording_config.path),
+                    );
+                    ui.add_enabled(
+                        !self.frame_recording,
+                        egui::Checkbox::new(&mut frame_recording_config.roi_only, "ROI Only"),
+                    )
+                    .on_hover_text("Black out everything outside of the spectrum windows");
+                });
+                ui.separator();
+                let auto_exposure_config = &mut self.config.auto_exposure_config;
+                if ui
+                    .add_enabled(
+                        !self.config.hdr_config.active,
+                        egui::Checkbox::new(&mut auto_exposure_config.active, "Auto Exposure"),
+                    )
+                    .on_hover_text("Keep the spectrum peak at the target, needs manual exposure")
+                    .changed()
+                {
+                    self.auto_exposure.reset();
+                }
+                ui.add(
+                    Slider::new(&mut auto_exposure_config.target, 0.1..=0.95).text("Target Peak"),
+                );
+                ui.add(
+                    Slider::new(&mut auto_exposure_config.tolerance, 0.01..=0.5).text("Tolerance"),
+                );
+                ui.add(
+                    Slider::new(&mut auto_exposure_config.settle_spectra, 0..=50)
+                        .text("Settle Spectra"),
+                );
+                ui.checkbox(&mut auto_exposure_config.use_gain, "Use Gain");
+                ui.separator();
+                for ctrl in &mut self.camera_controls {
+                    let value_setter = match ctrl.value() {
+                        ControlValueSetter::Integer(mut value) => {
+                            if let ControlValueDescription::IntegerRange {
+                                min,
+                                max,
+                                value: _,
+                                step,
+                                default: _,
+                            } = ctrl.description()
+                            {
+                                if ui
+                                    .add(
+                                        Slider::new(&mut value, (*min + 1)..=(*max - 1))
+                                            .step_by(*step as f64)
+                                            .text(ctrl.name()),
+                                    )
+                                    .changed()
+                                {
+                                    Some(ControlValueSetter::Integer(value))
+                                } else {
+                                    None
+                                }
+                            } else {
+                                None
+                            }
+                        }
+                        ControlValueSetter::Boolean(mut value) => {
+                            if let ControlValueDescription::Boolean { .. } = ctrl.description() {
+                                if ui.checkbox(&mut value, ctrl.name()).changed() {
+                                    Some(ControlValueSetter::Boolean(value))
+                                } else {
+                                    None
+                                }
+                            } else {
+                                None
+                            }
+                        }
+                        // TODO: ControlValueDescription::Enum only has integer options no
+                        // description string.
+                        //
+                        //v4l::control::Type::Menu => {
+                        //    let mut changed = false;
+                        //    let items = match ctrl.items.as_ref() {
+                        //        None => continue,
+                        //        Some(items) => items,
+                        //    };
+                        //    let selected_text =
+                        //        match items.iter().find(|&i| i.0 == own_ctrl.value as u32) {
+                        //            None => continue,
+                        //            Some(i) => i.1.to_string(),
+                        //        };
+                        //    ComboBox::from_label(&ctrl.name)
+                        //        .selected_text(selected_text)
+                        //        .show_ui(ui, |ui| {
+                        //            for item in items.iter() {
+                        //                changed |= ui
+                        //                    .selectable_value(
+                        //                        &mut own_ctrl.value,
+                        //                        item.0 as i32,
+                        //                        item.1.to_string(),
+                        //                    )
+                        //                    .changed();
+                        //            }
+                        //        });
+                        //    changed
+                        //}
+                        _ => None,
+                    };
+                    if let Some(value_setter) = value_setter {
+                        changed_controls.push((ctrl.control(), value_setter));
+                        self.spectrum_container.invalidate_buffer(
+                            self.config.postprocessing_config.buffer_clear_policy,
+                            BufferClearReason::CameraControls,
+                        );
+                    };
+                }
+                // TODO
+                //
+                //let default_button = ui.button("All default");
+                //if default_button.clicked() {
+                //    for ctrl in &mut self.camera_raw_controls {
+                //        let ctrl = match ctrl.downcast_ref::<Description>() {
+                //            None => continue,
+                //            Some(ctrl) => ctrl,
+                //        };
+                //        let own_ctrl =
+                //            match self.camera_controls.iter_mut().find(|c| c.id == ctrl.id) {
+                //                None => continue,
+                //                Some(own_ctrl) => own_ctrl,
+                //            };
+                //
+                //        own_ctrl.value = ctrl.default;
                 //    }
                 //    // Cannot use self.send_config due to mutable borrow in open
                 //    self.camera_config_tx
@@ -697,6 +3197,7 @@ impl SpectrometerGui {
                 //        .unwrap();
                 //}
                 if !changed_controls.is_empty() {
+                    self.auto_exposure.reset();
                     // Cannot use self.send_config due to mutable borrow in open
                     self.camera_config_tx
                         .send(CameraEvent::Controls(changed_controls))
@@ -706,16 +3207,31 @@ impl SpectrometerGui {
     }
 
     fn draw_import_export_window(&mut self, ctx: &Context) {
+        let mut set_tolerance_target = false;
+        let mut export_report = false;
+        let value_comment = self.optical_density_comment();
+        let provenance = Provenance::new(&self.config);
         egui::Window::new("Import/Export")
             .open(&mut self.config.view_config.show_import_export_window)
             .show(ctx, |ui| {
-                ui.text_edit_singleline(&mut self.config.import_export_config.path);
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.config.import_export_config.path);
+                    ui.checkbox(
+                        &mut self.config.import_export_config.decimal_comma,
+                        "Decimal Comma",
+                    )
+                    .on_hover_text(
+                        "Read and write CSV files with \"1,5\" decimals separated by \";\"",
+                    );
+                });
                 ui.separator();
                 let import_reference_button = ui.button("Import Reference CSV");
                 if import_reference_button.clicked() {
-                    match csv::Reader::from_path(&self.config.import_export_config.path)
-                        .and_then(|mut r| r.deserialize().collect())
-                    {
+                    match File::open(&self.config.import_export_config.path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|file| {
+                            read_csv(file, self.config.import_export_config.decimal_comma)
+                        }) {
                         Ok(r) => {
                             self.config.reference_config.reference = Some(r);
                             self.last_error = Some(ThreadResult {
@@ -726,7 +3242,7 @@ impl SpectrometerGui {
                         Err(e) => {
                             self.last_error = Some(ThreadResult {
                                 id: ThreadId::Main,
-                                result: Err(e.to_string()),
+                                result: Err(e),
                             });
                         }
                     };
@@ -736,20 +3252,20 @@ impl SpectrometerGui {
                     Button::new("Export Reference CSV"),
                 );
                 if export_reference_button.clicked() {
-                    let writer = csv::Writer::from_path(&self.config.import_export_config.path);
-                    match writer {
-                        Ok(mut writer) => {
-                            for p in self.config.reference_config.reference.as_ref().unwrap() {
-                                writer.serialize(p).unwrap();
-                            }
-                            writer.flush().unwrap();
-                        }
-                        Err(e) => {
-                            self.last_error = Some(ThreadResult {
-                                id: ThreadId::Main,
-                                result: Err(e.to_string()),
-                            })
-                        }
+                    let result = File::create(&self.config.import_export_config.path)
+                        .map_err(|e| e.to_string())
+                        .and_then(|file| {
+                            write_csv(
+                                file,
+                                self.config.reference_config.reference.as_ref().unwrap(),
+                                self.config.import_export_config.decimal_comma,
+                            )
+                        });
+                    if let Err(e) = result {
+                        self.last_error = Some(ThreadResult {
+                            id: ThreadId::Main,
+                            result: Err(e),
+                        })
                     }
                 }
                 let delete_button = ui.add_enabled(
@@ -760,125 +3276,1894 @@ impl SpectrometerGui {
                     self.config.reference_config.reference = None;
                 }
                 ui.separator();
+                let previous_tungsten_config = self.config.tungsten_config;
                 let generate_reference_button =
                     ui.button("Generate Reference From Tungsten Temperature");
                 if generate_reference_button.clicked() {
-                    self.config.reference_config.reference =
-                        Some(reference_from_filament_temp(self.tungsten_filament_temp));
+                    match reference_from_filament_temp(&self.config.tungsten_config) {
+                        Ok(reference) => self.config.reference_config.reference = Some(reference),
+                        Err(e) => {
+                            self.last_error = Some(ThreadResult {
+                                id: ThreadId::Main,
+                                result: Err(e),
+                            })
+                        }
+                    }
                 }
+                let tungsten_config = &mut self.config.tungsten_config;
                 ui.add(
-                    Slider::new(&mut self.tungsten_filament_temp, 1000..=3500)
+                    Slider::new(&mut tungsten_config.filament_temp, 1000..=3500)
                         .text("Tungsten Temperature"),
                 );
-                ui.separator();
-                let export_button = ui.add(Button::new("Export Spectrum"));
-                if export_button.clicked() {
-                    match self.spectrum_container.write_to_csv(
-                        &self.config.import_export_config.path.clone(),
-                        &self.config.spectrum_calibration,
-                    ) {
-                        Ok(()) => {
-                            self.last_error = Some(ThreadResult {
-                                id: ThreadId::Main,
-                                result: Ok(()),
-                            });
+                ui.horizontal(|ui| {
+                    let range = &mut tungsten_config.wavelength_range;
+                    ui.label("Range");
+                    ui.add(
+                        egui::DragValue::new(&mut range.low)
+                            .range(MODEL_RANGE.low..=MODEL_RANGE.high)
+                            .suffix(" nm"),
+                    );
+                    ui.add(
+                        egui::DragValue::new(&mut range.high)
+                            .range(MODEL_RANGE.low..=MODEL_RANGE.high)
+                            .suffix(" nm"),
+                    );
+                    ui.label("Step");
+                    ui.add(
+                        egui::DragValue::new(&mut tungsten_config.wavelength_step)
+                            .range(0.1..=50.)
+                            .speed(0.1)
+                            .suffix(" nm"),
+                    );
+                })
+                .response
+                .on_hover_text(format!(
+                    "The emissivity model is valid from {} to {} nm",
+                    MODEL_RANGE.low, MODEL_RANGE.high
+                ));
+                egui::CollapsingHeader::new("Estimate Filament Temperature").show(ui, |ui| {
+                    let estimate = &mut tungsten_config.estimate;
+                    ComboBox::from_id_salt("filament_estimation_method")
+                        .selected_text(estimate.method.to_string())
+                        .show_ui(ui, |ui| {
+                            for method in [
+                                EstimationMethod::RatedVoltage,
+                                EstimationMethod::VoltageCurrent,
+                                EstimationMethod::ResistanceRatio,
+                            ] {
+                                ui.selectable_value(
+                                    &mut estimate.method,
+                                    method,
+                                    method.to_string(),
+                                );
+                            }
+                        });
+                    egui::Grid::new("filament_estimate").show(ui, |ui| {
+                        let row =
+                            |ui: &mut egui::Ui, label: &str, value: &mut f32, suffix: &str| {
+                                ui.label(label);
+                                ui.add(
+                                    egui::DragValue::new(value)
+                                        .range(0.0..=f32::MAX)
+                                        .speed(0.01)
+                                        .suffix(suffix),
+                                );
+                                ui.end_row();
+                            };
+                        match estimate.method {
+                            EstimationMethod::RatedVoltage => {
+                                row(ui, "Voltage", &mut estimate.voltage, " V");
+                                row(ui, "Rated Voltage", &mut estimate.rated_voltage, " V");
+                                ui.label("Rated Temperature");
+                                ui.add(
+                                    egui::DragValue::new(&mut estimate.rated_temp)
+                                        .range(1000..=3500)
+                                        .suffix(" K"),
+                                );
+                                ui.end_row();
+                            }
+                            EstimationMethod::VoltageCurrent => {
+                                row(ui, "Voltage", &mut estimate.voltage, " V");
+                                row(ui, "Current", &mut estimate.current, " A");
+                                row(ui, "Cold Resistance", &mut estimate.cold_resistance, " Ω");
+                            }
+                            EstimationMethod::ResistanceRatio => {
+                                row(
+                                    ui,
+                                    "Hot/Cold Resistance",
+                                    &mut estimate.resistance_ratio,
+                                    "",
+                                );
+                            }
                         }
-                        Err(e) => {
-                            self.last_error = Some(ThreadResult {
-                                id: ThreadId::Main,
-                                result: Err(e),
-                            });
+                    });
+                    let filament_temp = estimate.filament_temp();
+                    ui.horizontal(|ui| {
+                        ui.label(match filament_temp {
+                            Some(temp) => format!("{temp:.0} K"),
+                            None => "Invalid inputs".to_string(),
+                        });
+                        if ui
+                            .add_enabled(filament_temp.is_some(), Button::new("Use Temperature"))
+                            .clicked()
+                        {
+                            if let Some(temp) = filament_temp {
+                                tungsten_config.filament_temp =
+                                    temp.round().clamp(1000., 3500.) as u16;
+                            }
                         }
+                    });
+                });
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut tungsten_config.live_preview, "Live Preview")
+                        .on_hover_text("Show the reference for the current settings in the plot");
+                    if ui
+                        .add_enabled(
+                            self.tungsten_preview.is_some(),
+                            Button::new("Use Preview As Reference"),
+                        )
+                        .clicked()
+                    {
+                        self.config.reference_config.reference = self.tungsten_preview.take();
                     }
+                });
+                if !tungsten_config.live_preview {
+                    self.tungsten_preview = None;
+                    self.tungsten_preview_due = None;
+                } else if *tungsten_config != previous_tungsten_config {
+                    self.tungsten_preview_due = Some(Instant::now() + TUNGSTEN_PREVIEW_DELAY);
                 }
-            });
-    }
-
-    fn draw_windows(&mut self, ctx: &Context) {
-        self.draw_camera_window(ctx);
-        self.draw_calibration_window(ctx);
-        self.draw_postprocessing_window(ctx);
-        self.draw_camera_control_window(ctx);
-        self.draw_import_export_window(ctx);
+                let generate_led_button = ui
+                    .button("Generate Reference From White LED Model")
+                    .on_hover_text("Blue pump and phosphor mixed to the color temperature");
+                if generate_led_button.clicked() {
+                    self.config.reference_config.reference =
+                        Some(reference_from_led_model(&self.led_model));
+                }
+                ui.add(Slider::new(&mut self.led_model.cct, 2500.0..=10000.).text("LED CCT"));
+                ui.add(
+                    Slider::new(&mut self.led_model.pump_wavelength, 400.0..=480.)
+                        .text("Pump Wavelength"),
+                );
+                ui.separator();
+                egui::CollapsingHeader::new("Sample Metadata").show(ui, |ui| {
+                    egui::Grid::new("sample_metadata").show(ui, |ui| {
+                        ui.label("Sample");
+                        ui.text_edit_singleline(&mut self.config.sample_metadata.sample_name);
+                        ui.end_row();
+                        ui.label("Operator");
+                        ui.text_edit_singleline(&mut self.config.sample_metadata.operator);
+                        ui.end_row();
+                        ui.label("Notes");
+                        ui.text_edit_multiline(&mut self.config.sample_metadata.notes);
+                        ui.end_row();
+                    });
+                });
+                ui.separator();
+                egui::CollapsingHeader::new("Snapshots").show(ui, |ui| {
+                    if ui.button("Hold Spectrum").clicked() {
+                        self.snapshots.push(Snapshot::new(
+                            format!("Snapshot {}", self.snapshots.len() + 1),
+                            RecordedSpectrum::from_spectrum(
+                                self.spectrum_container.spectrum(),
+                                self.spectrum_container.capture_time(),
+                            ),
+                        ));
+                        self.session_dirty = true;
+                    }
+                    let mut remove = None;
+                    for (i, snapshot) in self.snapshots.iter_mut().enumerate() {
+                        ui.horizontal(|ui| {
+                            self.session_dirty |=
+                                ui.text_edit_singleline(&mut snapshot.name).changed();
+                            ui.label("Scale");
+                            self.session_dirty |= ui
+                                .add(
+                                    egui::DragValue::new(&mut snapshot.scale)
+                                        .speed(0.01)
+                                        .range(0.0..=100.),
+                                )
+                                .changed();
+                            ui.label("Offset");
+                            self.session_dirty |= ui
+                                .add(egui::DragValue::new(&mut snapshot.offset).speed(0.01))
+                                .changed();
+                            if ui.button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                        });
+                    }
+                    if let Some(i) = remove {
+                        self.snapshots.remove(i);
+                        self.session_dirty = true;
+                    }
+                    ui.add_enabled_ui(!self.snapshots.is_empty(), |ui| {
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Stack")
+                                .on_hover_text("Offset the snapshots so they do not overlap")
+                                .clicked()
+                            {
+                                Snapshot::stack(&mut self.snapshots, SNAPSHOT_STACK_GAP);
+                                self.session_dirty = true;
+                            }
+                            if ui.button("Reset Offset and Scale").clicked() {
+                                self.snapshots.iter_mut().for_each(|snapshot| {
+                                    snapshot.offset = 0.;
+                                    snapshot.scale = 1.;
+                                });
+                                self.session_dirty = true;
+                            }
+                        });
+                    });
+                    if ui
+                        .checkbox(
+                            &mut self.config.import_export_config.persist_session,
+                            "Restore Zero Reference and Snapshots on Start",
+                        )
+                        .changed()
+                    {
+                        self.session_dirty = true;
+                    }
+                });
+                ui.separator();
+                egui::CollapsingHeader::new("Tolerance Check").show(ui, |ui| {
+                    let tolerance_config = &mut self.config.tolerance_config;
+                    ui.horizontal(|ui| {
+                        set_tolerance_target = ui.button("Set Target From Spectrum").clicked();
+                        if ui
+                            .add_enabled(
+                                tolerance_config.target.is_some(),
+                                Button::new("Delete Target"),
+                            )
+                            .clicked()
+                        {
+                            tolerance_config.target = None;
+                            self.tolerance_result = None;
+                        }
+                    });
+                    if ui
+                        .add_enabled(
+                            tolerance_config.target.is_some(),
+                            egui::Checkbox::new(&mut tolerance_config.active, "Active"),
+                        )
+                        .changed()
+                    {
+                        self.tolerance_result = None;
+                    }
+                    ui.add(
+                        Slider::new(&mut tolerance_config.tolerance, 0.001..=1.)
+                            .logarithmic(true)
+                            .text("Tolerance"),
+                    );
+                    ui.horizontal(|ui| {
+                        ui.label("Webhook URL");
+                        ui.text_edit_singleline(&mut tolerance_config.webhook_url);
+                    });
+                });
+                ui.separator();
+                egui::CollapsingHeader::new("Peak Alarms").show(ui, |ui| {
+                    let alarm_config = &mut self.config.alarm_config;
+                    if ui.checkbox(&mut alarm_config.active, "Active").changed() {
+                        self.alarm_monitor.clear();
+                    }
+                    ui.horizontal(|ui| {
+                        ui.label("Webhook URL");
+                        ui.text_edit_singleline(&mut alarm_config.webhook_url);
+                    });
+                    let mut remove = None;
+                    egui::Grid::new("peak_alarms").show(ui, |ui| {
+                        ui.label("Name");
+                        ui.label("Range");
+                        ui.label("Threshold");
+                        ui.label("ROI Preset");
+                        ui.end_row();
+                        for (i, alarm) in alarm_config.alarms.iter_mut().enumerate() {
+                            ui.add(egui::TextEdit::singleline(&mut alarm.name).desired_width(100.));
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut alarm.range.low).suffix(" nm"));
+                                ui.add(egui::DragValue::new(&mut alarm.range.high).suffix(" nm"));
+                            });
+                            ui.add(egui::DragValue::new(&mut alarm.threshold).speed(0.01));
+                            ComboBox::from_id_salt(("alarm_profile", i))
+                                .selected_text(if alarm.profile.is_empty() {
+                                    "All"
+                                } else {
+                                    &alarm.profile
+                                })
+                                .show_ui(ui, |ui| {
+                                    ui.selectable_value(&mut alarm.profile, String::new(), "All");
+                                    for preset in &self.config.roi_presets {
+                                        ui.selectable_value(
+                                            &mut alarm.profile,
+                                            preset.name.clone(),
+                                            &preset.name,
+                                        );
+                                    }
+                                });
+                            if ui.button("Remove").clicked() {
+                                remove = Some(i);
+                            }
+                            ui.end_row();
+                        }
+                    });
+                    if let Some(i) = remove {
+                        alarm_config.alarms.remove(i);
+                    }
+                    if ui.button("Add Alarm").clicked() {
+                        let profile = self
+                            .config
+                            .active_roi_preset
+                            .and_then(|i| self.config.roi_presets.get(i))
+                            .map(|p| p.name.clone())
+                            .unwrap_or_default();
+                        alarm_config.alarms.push(PeakAlarm::new(
+                            format!("Alarm {}", alarm_config.alarms.len() + 1),
+                            profile,
+                        ));
+                    }
+                });
+                ui.separator();
+                egui::CollapsingHeader::new("Export Options").show(ui, |ui| {
+                    let export_config = &mut self.config.import_export_config;
+                    ui.horizontal(|ui| {
+                        let mut round = export_config.decimal_places.is_some();
+                        ui.checkbox(&mut round, "Decimal Places");
+                        export_config.decimal_places =
+                            round.then(|| export_config.decimal_places.unwrap_or(4));
+                        if let Some(decimal_places) = export_config.decimal_places.as_mut() {
+                            ui.add(Slider::new(decimal_places, 0..=7));
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut resample = export_config.wavelength_step.is_some();
+                        ui.checkbox(&mut resample, "Wavelength Step");
+                        export_config.wavelength_step =
+                            resample.then(|| export_config.wavelength_step.unwrap_or(1.));
+                        if let Some(step) = export_config.wavelength_step.as_mut() {
+                            ui.add(
+                                egui::DragValue::new(step)
+                                    .range(0.1..=100.)
+                                    .speed(0.1)
+                                    .suffix(" nm"),
+                            );
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut crop = export_config.wavelength_range.is_some();
+                        ui.checkbox(&mut crop, "Wavelength Range");
+                        export_config.wavelength_range = crop.then(|| {
+                            export_config.wavelength_range.unwrap_or(WavelengthRange {
+                                low: 380.,
+                                high: 780.,
+                            })
+                        });
+                        if let Some(range) = export_config.wavelength_range.as_mut() {
+                            ui.add(egui::DragValue::new(&mut range.low).suffix(" nm"));
+                            ui.add(egui::DragValue::new(&mut range.high).suffix(" nm"));
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut color = export_config.color_white_point.is_some();
+                        ui.checkbox(&mut color, "Color Coordinates");
+                        export_config.color_white_point = color
+                            .then(|| export_config.color_white_point.unwrap_or(Illuminant::D65));
+                        if let Some(white_point) = export_config.color_white_point.as_mut() {
+                            ComboBox::from_id_salt("color_white_point")
+                                .selected_text(format!("White Point {white_point}"))
+                                .show_ui(ui, |ui| {
+                                    for illuminant in [Illuminant::D65, Illuminant::A] {
+                                        ui.selectable_value(
+                                            white_point,
+                                            illuminant,
+                                            illuminant.to_string(),
+                                        );
+                                    }
+                                });
+                        }
+                    });
+                });
+                let export_button = ui.add(Button::new("Export Spectrum"));
+                if export_button.clicked() {
+                    match self.spectrum_container.write_to_csv(
+                        &self.config.import_export_config.path.clone(),
+                        &self.config.spectrum_calibration,
+                        &self.config.import_export_config,
+                        &self.config.sample_metadata,
+                        value_comment.as_deref(),
+                        &provenance,
+                    ) {
+                        Ok(()) => {
+                            self.last_error = Some(ThreadResult {
+                                id: ThreadId::Main,
+                                result: Ok(()),
+                            });
+                        }
+                        Err(e) => {
+                            self.last_error = Some(ThreadResult {
+                                id: ThreadId::Main,
+                                result: Err(e),
+                            });
+                        }
+                    }
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Report Path");
+                    ui.text_edit_singleline(&mut self.config.import_export_config.report_path);
+                });
+                export_report = ui.button("Export Report").clicked();
+            });
+        if set_tolerance_target {
+            let target = self
+                .spectrum_container
+                .get_spectrum_channel(3, &self.config);
+            self.config.tolerance_config.set_target(target);
+            self.tolerance_result = None;
+        }
+        if export_report {
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result: self.write_report(),
+            });
+        }
+    }
+
+    fn write_report(&self) -> Result<(), String> {
+        let mut spectrum = self
+            .spectrum_container
+            .get_spectrum_channel(3, &self.config);
+        spectrum.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        let peaks = self
+            .spectrum_container
+            .spectrum_to_peaks_and_dips(true, &self.config);
+        let fitted_peaks = self
+            .spectrum_container
+            .spectrum_to_fitted_peaks(&self.config);
+        write_html_report(
+            &self.config.import_export_config.report_path,
+            &ReportData {
+                timestamp: self.spectrum_container.capture_time(),
+                metadata: &self.config.sample_metadata,
+                provenance: &Provenance::new(&self.config),
+                spectrum: &spectrum,
+                peaks: &peaks,
+                fitted_peaks: &fitted_peaks,
+                dominant_peak: dominant_peak(&spectrum),
+            },
+        )
+    }
+
+    fn draw_recording_window(&mut self, ctx: &Context) {
+        let provenance = Provenance::new(&self.config);
+        let mut sinks_changed = false;
+        egui::Window::new("Recording")
+            .open(&mut self.config.view_config.show_recording_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.text_edit_singleline(&mut self.config.recording_config.path);
+                    ui.add_enabled(
+                        self.recorder.is_none(),
+                        egui::DragValue::new(&mut self.config.recording_config.sync_interval)
+                            .range(0.1..=600.)
+                            .prefix("Sync: ")
+                            .suffix(" s"),
+                    )
+                    .on_hover_text(
+                        "Interval of writing the recording to disk, an interrupted recording \
+                         can be loaded up to the last sync",
+                    );
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    if let Some(recorder) = self.recorder.take() {
+                        if ui.button("Stop Recording").clicked() {
+                            if let Err(e) = recorder.finish() {
+                                self.last_error = Some(ThreadResult {
+                                    id: ThreadId::Main,
+                                    result: Err(e),
+                                });
+                            }
+                        } else {
+                            ui.label(format!("{} spectra recorded", recorder.count()));
+                            self.recorder = Some(recorder);
+                        }
+                    } else {
+                        let start_button = ui.add_enabled(
+                            self.acquisition.is_running(),
+                            Button::new("Start Recording"),
+                        );
+                        if start_button.clicked() {
+                            match SpectrumRecorder::create(
+                                &self.config.recording_config.path,
+                                &self.config.sample_metadata,
+                                &provenance,
+                                Duration::from_secs_f32(
+                                    self.config.recording_config.sync_interval.max(0.),
+                                ),
+                            ) {
+                                Ok(recorder) => self.recorder = Some(recorder),
+                                Err(e) => {
+                                    self.last_error = Some(ThreadResult {
+                                        id: ThreadId::Main,
+                                        result: Err(e),
+                                    })
+                                }
+                            }
+                        }
+                    }
+                });
+                ui.separator();
+                ui.horizontal(|ui| {
+                    let load_button =
+                        ui.add_enabled(self.recorder.is_none(), Button::new("Load Playback"));
+                    if load_button.clicked() {
+                        match Recording::load(&self.config.recording_config.path) {
+                            Ok(recording) if recording.spectra.is_empty() => {
+                                self.last_error = Some(ThreadResult {
+                                    id: ThreadId::Main,
+                                    result: Err("Recording is empty".to_string()),
+                                });
+                            }
+                            Ok(recording) => {
+                                let result = if recording.truncated {
+                                    Err(format!(
+                                        "The recording was not finished, {} spectra recovered",
+                                        recording.spectra.len()
+                                    ))
+                                } else {
+                                    Ok(())
+                                };
+                                self.playback = Some(recording);
+                                self.playback_index = 0;
+                                self.last_error = Some(ThreadResult {
+                                    id: ThreadId::Main,
+                                    result,
+                                });
+                            }
+                            Err(e) => {
+                                self.last_error = Some(ThreadResult {
+                                    id: ThreadId::Main,
+                                    result: Err(e),
+                                });
+                            }
+                        }
+                    }
+                    let close_button =
+                        ui.add_enabled(self.playback.is_some(), Button::new("Close Playback"));
+                    if close_button.clicked() {
+                        self.playback = None;
+                        self.spectrum_container.invalidate_buffer(
+                            self.config.postprocessing_config.buffer_clear_policy,
+                            BufferClearReason::Restart,
+                        );
+                    }
+                });
+                if let Some(recording) = self.playback.as_ref() {
+                    if !recording.metadata.sample_name.is_empty() {
+                        ui.label(format!("Sample: {}", recording.metadata.sample_name));
+                    }
+                    let time = recording.relative_time(self.playback_index);
+                    ui.add(
+                        Slider::new(&mut self.playback_index, 0..=recording.spectra.len() - 1)
+                            .text(format!("t = {:.1} s", time)),
+                    );
+
+                    ui.separator();
+                    ui.text_edit_singleline(&mut self.config.recording_config.animation_path);
+                    ui.add(
+                        Slider::new(&mut self.config.recording_config.animation_fps, 1.0..=50.)
+                            .text("Frame Rate"),
+                    );
+                    ui.add(
+                        Slider::new(
+                            &mut self.config.recording_config.animation_duration,
+                            1.0..=120.,
+                        )
+                        .suffix(" s")
+                        .text("Duration"),
+                    );
+                    ui.horizontal(|ui| {
+                        let exporting = self.gif_export_rx.is_some();
+                        if ui
+                            .add_enabled(!exporting, Button::new("Export GIF"))
+                            .clicked()
+                        {
+                            let recording = recording.clone();
+                            let recording_config = self.config.recording_config.clone();
+                            let (result_tx, result_rx) = flume::bounded(1);
+                            std::thread::spawn(move || {
+                                let result = export_gif(
+                                    &recording,
+                                    &recording_config.animation_path,
+                                    recording_config.animation_fps,
+                                    recording_config.animation_duration,
+                                    800,
+                                    400,
+                                );
+                                result_tx
+                                    .send(ThreadResult {
+                                        id: ThreadId::Animation,
+                                        result,
+                                    })
+                                    .ok();
+                            });
+                            self.gif_export_rx = Some(result_rx);
+                        }
+                        if exporting {
+                            ui.spinner();
+                        }
+                    });
+                }
+                ui.separator();
+                ui.label("CSV Sinks")
+                    .on_hover_text("Log every spectrum as a row, independently of the recording");
+                sinks_changed = Self::draw_sink_list(ui, &mut self.config.sink_configs, false);
+            });
+        if sinks_changed {
+            self.send_sink_configs();
+        }
+    }
+
+    fn draw_network_window(&mut self, ctx: &Context) {
+        let mut sinks_changed = false;
+        egui::Window::new("Network")
+            .open(&mut self.config.view_config.show_network_window)
+            .show(ctx, |ui| {
+                ui.add_enabled_ui(!self.feed_active, |ui| {
+                    let feed_config = &mut self.config.feed_config;
+                    egui::Grid::new("feed_config").show(ui, |ui| {
+                        ui.label("Multicast Group");
+                        let mut group = feed_config.multicast_group.to_string();
+                        if ui.text_edit_singleline(&mut group).changed() {
+                            if let Ok(group) = group.parse() {
+                                feed_config.multicast_group = group;
+                            }
+                        }
+                        ui.end_row();
+                        ui.label("Port");
+                        ui.add(egui::DragValue::new(&mut feed_config.port));
+                        ui.end_row();
+                        ui.label("TTL");
+                        ui.add(egui::DragValue::new(&mut feed_config.ttl).range(1..=255));
+                        ui.end_row();
+                        let mut subscriptions = feed_config.subscription_port.is_some();
+                        ui.checkbox(&mut subscriptions, "Subscription Port")
+                            .on_hover_text(
+                                "Clients can send a JSON subscription to this port \
+                                 to receive a filtered feed",
+                            );
+                        if subscriptions != feed_config.subscription_port.is_some() {
+                            feed_config.subscription_port = subscriptions.then_some(5006);
+                        }
+                        if let Some(port) = feed_config.subscription_port.as_mut() {
+                            ui.add(egui::DragValue::new(port));
+                        }
+                        ui.end_row();
+                    });
+                    ui.checkbox(&mut feed_config.include_rgb, "Include R, G and B");
+                    ComboBox::from_label("Format")
+                        .selected_text(feed_config.format.to_string())
+                        .show_ui(ui, |ui| {
+                            for format in [FeedFormat::Json, FeedFormat::Binary] {
+                                ui.selectable_value(
+                                    &mut feed_config.format,
+                                    format,
+                                    format.to_string(),
+                                );
+                            }
+                        })
+                        .response
+                        .on_hover_text("Binary spectra start with SPCB followed by f32 arrays");
+                });
+                ui.separator();
+                let feed_button = ui.button(if self.feed_active {
+                    "Stop UDP Feed"
+                } else {
+                    "Start UDP Feed"
+                });
+                if feed_button.clicked() {
+                    self.feed_active = !self.feed_active;
+                    self.feed_status = None;
+                    self.feed_provenance = None;
+                    self.feed_tx
+                        .send(if self.feed_active {
+                            FeedEvent::Start(self.config.feed_config.clone())
+                        } else {
+                            FeedEvent::Stop
+                        })
+                        .unwrap();
+                }
+                ui.separator();
+                ui.label("Sinks");
+                sinks_changed = Self::draw_sink_list(ui, &mut self.config.sink_configs, true);
+            });
+        if sinks_changed {
+            self.send_sink_configs();
+        }
+    }
+
+    fn open_library(&mut self) {
+        match Library::open(&self.config.library_config.path) {
+            Ok(library) => self.library = Some(library),
+            Err(e) => {
+                self.library = None;
+                self.last_error = Some(ThreadResult {
+                    id: ThreadId::Main,
+                    result: Err(format!("Could not open library: {}", e)),
+                });
+            }
+        }
+    }
+
+    fn draw_library_window(&mut self, ctx: &Context) {
+        enum LibraryAction {
+            Open,
+            Save,
+            Overlay(usize),
+            UseAsReference(usize),
+            Remove(usize),
+        }
+
+        let mut action = None;
+        egui::Window::new("Library")
+            .open(&mut self.config.view_config.show_library_window)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Directory");
+                    ui.text_edit_singleline(&mut self.config.library_config.path);
+                    if ui.button("Open").clicked() {
+                        action = Some(LibraryAction::Open);
+                    }
+                });
+                ui.separator();
+                egui::Grid::new("library_new_entry").show(ui, |ui| {
+                    ui.label("Name");
+                    ui.text_edit_singleline(&mut self.library_name);
+                    ui.end_row();
+                    ui.label("Tags");
+                    ui.text_edit_singleline(&mut self.library_tags)
+                        .on_hover_text("Comma separated");
+                    ui.end_row();
+                });
+                if ui
+                    .add_enabled(
+                        self.library.is_some() && !self.library_name.is_empty(),
+                        Button::new("Save Current Spectrum"),
+                    )
+                    .on_hover_text("Saved with the current sample metadata")
+                    .clicked()
+                {
+                    action = Some(LibraryAction::Save);
+                }
+                ui.separator();
+                ui.horizontal(|ui| {
+                    ui.label("Search");
+                    ui.text_edit_singleline(&mut self.library_query);
+                });
+                let Some(library) = self.library.as_ref() else {
+                    return;
+                };
+                egui::ScrollArea::vertical()
+                    .max_height(300.)
+                    .show(ui, |ui| {
+                        for (i, entry) in library
+                            .entries()
+                            .iter()
+                            .enumerate()
+                            .rev()
+                            .filter(|(_, e)| e.matches(&self.library_query))
+                        {
+                            ui.horizontal(|ui| {
+                                ui.label(&entry.name).on_hover_text(
+                                    [format_timestamp(entry.timestamp)]
+                                        .into_iter()
+                                        .chain(entry.metadata.to_comment_lines())
+                                        .collect::<Vec<_>>()
+                                        .join("\n"),
+                                );
+                                ui.label(RichText::new(entry.tags.join(", ")).weak());
+                                if ui.button("Overlay").clicked() {
+                                    action = Some(LibraryAction::Overlay(i));
+                                }
+                                if ui.button("Use as Reference").clicked() {
+                                    action = Some(LibraryAction::UseAsReference(i));
+                                }
+                                if ui.button("Delete").clicked() {
+                                    action = Some(LibraryAction::Remove(i));
+                                }
+                            });
+                        }
+                    });
+            });
+
+        let result = match (action, self.library.as_mut()) {
+            (None, _) => return,
+            (Some(LibraryAction::Open), _) => {
+                self.open_library();
+                return;
+            }
+            (Some(LibraryAction::Save), Some(library)) => {
+                let points = self
+                    .spectrum_container
+                    .spectrum_to_point_vec(&self.config.spectrum_calibration, &Default::default());
+                library.add(
+                    std::mem::take(&mut self.library_name),
+                    parse_tags(&self.library_tags),
+                    &self.config.sample_metadata,
+                    &Provenance::new(&self.config),
+                    self.config
+                        .import_export_config
+                        .color_white_point
+                        .and_then(|white_point| export_color_coordinates(&points, white_point)),
+                    &points,
+                )
+            }
+            (Some(LibraryAction::Overlay(i)), Some(library)) => {
+                let entry = &library.entries()[i];
+                library.load(entry).map(|points| {
+                    let spectrum = points_to_spectrum(
+                        &points,
+                        &self.config.spectrum_calibration,
+                        self.spectrum_container.spectrum().ncols(),
+                    );
+                    self.snapshots.push(Snapshot::new(
+                        entry.name.clone(),
+                        RecordedSpectrum::from_spectrum(&spectrum, entry.timestamp),
+                    ));
+                    self.session_dirty = true;
+                })
+            }
+            (Some(LibraryAction::UseAsReference(i)), Some(library)) => {
+                library.load(&library.entries()[i]).map(|points| {
+                    self.config.reference_config.reference = Some(points_to_reference(&points));
+                })
+            }
+            (Some(LibraryAction::Remove(i)), Some(library)) => library.remove(i),
+            (Some(_), None) => Ok(()),
+        };
+        self.last_error = Some(ThreadResult {
+            id: ThreadId::Main,
+            result,
+        });
+    }
+
+    fn draw_windows(&mut self, ctx: &Context) {
+        if self.acquisition.is_active() {
+            self.draw_camera_window(ctx);
+            self.draw_camera_control_window(ctx);
+        }
+        self.draw_calibration_window(ctx);
+        self.draw_postprocessing_window(ctx);
+        self.draw_import_export_window(ctx);
+        self.draw_recording_window(ctx);
+        self.draw_network_window(ctx);
+        self.draw_library_window(ctx);
+        self.draw_additional_cameras_window(ctx);
+        self.draw_plot_windows(ctx);
+    }
+
+    fn draw_additional_cameras_window(&mut self, ctx: &Context) {
+        let mut remove = None;
+        egui::Window::new("Additional Cameras")
+            .open(&mut self.config.view_config.show_additional_cameras_window)
+            .show(ctx, |ui| {
+                for (i, (camera_config, camera)) in self
+                    .config
+                    .additional_cameras
+                    .iter_mut()
+                    .zip(self.additional_cameras.iter_mut())
+                    .enumerate()
+                {
+                    ui.push_id(i, |ui| {
+                        let running = camera.is_running();
+                        ui.horizontal(|ui| {
+                            ui.add_enabled(
+                                !running,
+                                egui::TextEdit::singleline(&mut camera_config.name)
+                                    .desired_width(120.),
+                            );
+                            if ui.button(if running { "Stop" } else { "Start" }).clicked() {
+                                if running {
+                                    camera.stop();
+                                } else if let Some((index, _)) =
+                                    self.camera_info.get_index(camera_config.camera_id)
+                                {
+                                    camera.start(
+                                        index.clone(),
+                                        camera_config,
+                                        self.config.driver_timestamps,
+                                    );
+                                }
+                            }
+                            if ui.add_enabled(!running, Button::new("Remove")).clicked() {
+                                remove = Some(i);
+                            }
+                            if let Some(e) = camera.error() {
+                                ui.label(RichText::new(e).color(Color32::RED));
+                            } else if let Some((width, height)) =
+                                camera.frame_size().filter(|_| running)
+                            {
+                                ui.label(format!("{}x{}", width, height));
+                            }
+                        });
+                        ui.add_enabled_ui(!running, |ui| {
+                            ui.horizontal(|ui| {
+                                ComboBox::from_id_salt("cb_additional_camera")
+                                    .selected_text(format!(
+                                        "{}: {}",
+                                        camera_config.camera_id,
+                                        self.camera_info
+                                            .get_index(camera_config.camera_id)
+                                            .map(|(_index, info)| info.info.human_name())
+                                            .unwrap_or_default()
+                                    ))
+                                    .show_ui(ui, |ui| {
+                                        for (i, (_camera_index, camera_info)) in
+                                            self.camera_info.iter().enumerate()
+                                        {
+                                            ui.selectable_value(
+                                                &mut camera_config.camera_id,
+                                                i,
+                                                format!("{}: {}", i, camera_info.info.human_name()),
+                                            );
+                                        }
+                                    });
+                                ui.menu_button(
+                                    match camera_config.camera_format {
+                                        None => "Format".to_string(),
+                                        Some(camera_format) => format!("{}", camera_format),
+                                    },
+                                    |ui| {
+                                        let Some((_, camera_info)) =
+                                            self.camera_info.get_index(camera_config.camera_id)
+                                        else {
+                                            return;
+                                        };
+                                        for group in
+                                            group_camera_formats(camera_info.formats.clone())
+                                        {
+                                            ui.menu_button(group.to_string(), |ui| {
+                                                for frame_rate in &group.frame_rates {
+                                                    if ui
+                                                        .selectable_value(
+                                                            &mut camera_config.camera_format,
+                                                            Some(group.camera_format(*frame_rate)),
+                                                            format!("{} fps", frame_rate),
+                                                        )
+                                                        .clicked()
+                                                    {
+                                                        ui.close_menu();
+                                                    }
+                                                }
+                                            });
+                                        }
+                                    },
+                                );
+                            });
+                        });
+                        let mut window_changed = false;
+                        egui::Grid::new("additional_camera_window").show(ui, |ui| {
+                            let image_config = &mut camera_config.image_config;
+                            let window = &mut image_config.window;
+                            ui.label("Window Offset");
+                            window_changed |= ui
+                                .add(egui::DragValue::new(&mut window.offset.x).range(0..=u16::MAX))
+                                .changed();
+                            window_changed |= ui
+                                .add(egui::DragValue::new(&mut window.offset.y).range(0..=u16::MAX))
+                                .changed();
+                            window_changed |= ui.checkbox(&mut image_config.flip, "Flip").changed();
+                            ui.end_row();
+                            ui.label("Window Size");
+                            let window = &mut image_config.window;
+                            window_changed |= ui
+                                .add(egui::DragValue::new(&mut window.size.x).range(1..=u16::MAX))
+                                .changed();
+                            window_changed |= ui
+                                .add(egui::DragValue::new(&mut window.size.y).range(1..=u16::MAX))
+                                .changed();
+                            ui.end_row();
+                            let calibration = &mut camera_config.spectrum_calibration;
+                            for (label, point) in [
+                                ("Low", &mut calibration.low),
+                                ("High", &mut calibration.high),
+                            ] {
+                                ui.label(format!("{} Calibration", label));
+                                ui.add(
+                                    egui::DragValue::new(&mut point.wavelength)
+                                        .range(200..=2000)
+                                        .suffix(" nm"),
+                                );
+                                ui.add(egui::DragValue::new(&mut point.index).prefix("Index: "));
+                                ui.end_row();
+                            }
+                        });
+                        if window_changed && running {
+                            if let Some((width, height)) = camera.frame_size() {
+                                camera_config
+                                    .image_config
+                                    .clamp(width as f32, height as f32);
+                            }
+                            camera.send_config(&camera_config.image_config);
+                        }
+                    });
+                    ui.separator();
+                }
+                if ui.button("Add Camera").clicked() {
+                    let name = format!("Camera {}", self.config.additional_cameras.len() + 2);
+                    self.config
+                        .additional_cameras
+                        .push(AdditionalCameraConfig::new(name));
+                    self.additional_cameras.push(AdditionalCamera::new());
+                }
+            });
+        if let Some(i) = remove {
+            self.config.additional_cameras.remove(i);
+            self.additional_cameras.remove(i);
+        }
+    }
+
+    /// Switch between the default spacing and larger hit targets for touchscreens
+    fn update_touch_style(&mut self, ctx: &Context) {
+        let touch_controls = self.config.view_config.touch_controls;
+        if self.touch_style == Some(touch_controls) {
+            return;
+        }
+        ctx.style_mut(|style| {
+            style.spacing = Default::default();
+            if touch_controls {
+                let spacing = &mut style.spacing;
+                spacing.interact_size = Vec2::new(48., 36.);
+                spacing.button_padding = Vec2::new(12., 8.);
+                spacing.item_spacing = Vec2::new(10., 10.);
+                spacing.icon_width = 24.;
+                spacing.icon_width_inner = 12.;
+                spacing.slider_width = 200.;
+                spacing.combo_height = 400.;
+                spacing.scroll.bar_width = 16.;
+            }
+        });
+        self.touch_style = Some(touch_controls);
+    }
+
+    /// Receive second order spectra and merge them with the first order
+    fn update_second_order(&mut self, new_spectrum: bool) {
+        if self.config.image_config.second_order_window.is_none() {
+            self.merged_orders.clear();
+            return;
+        }
+        let config = self
+            .config
+            .multi_order_config
+            .spectrometer_config(&self.config);
+        self.second_order_container.update(&config);
+        if new_spectrum {
+            let first = self
+                .spectrum_container
+                .get_spectrum_channel(3, &self.config);
+            let second = SpectrumContainer::spectrum_channel_points(
+                self.second_order_container.spectrum(),
+                3,
+                &config,
+            );
+            self.merged_orders = merge_orders(&first, &second, &self.config.multi_order_config);
+        }
+    }
+
+    /// Receive reference beam spectra and divide the sample beam by them
+    fn update_reference_beam(&mut self, new_spectrum: bool) {
+        let Some(container) = self
+            .reference_beam_container
+            .as_mut()
+            .filter(|_| self.config.image_config.reference_beam_window.is_some())
+        else {
+            self.beam_ratio.clear();
+            return;
+        };
+        container.update(&self.config);
+        if new_spectrum {
+            let sample = self
+                .spectrum_container
+                .get_spectrum_channel(3, &self.config);
+            let reference = container.get_spectrum_channel(3, &self.config);
+            self.beam_ratio = beam_ratio(&sample, &reference);
+        }
+    }
+
+    /// Receive spectra of the additional cameras
+    fn update_additional_cameras(&mut self, ctx: &Context) {
+        for (camera, camera_config) in self
+            .additional_cameras
+            .iter_mut()
+            .zip(&self.config.additional_cameras)
+        {
+            camera.update(&self.config, camera_config);
+            if camera.is_running() {
+                ctx.request_repaint();
+            }
+        }
+    }
+
+    fn draw_plot_windows(&mut self, ctx: &Context) {
+        let mut plot_windows = std::mem::take(&mut self.config.plot_windows);
+        let mut remove = None;
+        for (i, plot_window) in plot_windows.iter_mut().enumerate() {
+            let mut open = plot_window.open;
+            egui::Window::new(&plot_window.name)
+                .id(egui::Id::new(("plot_window", i)))
+                .open(&mut open)
+                .default_size(Vec2::new(500., 300.))
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.text_edit_singleline(&mut plot_window.name);
+                        if ui.button("Remove").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        ComboBox::from_id_salt(("plot_window_source", i))
+                            .selected_text(plot_window.source.to_string())
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(
+                                    &mut plot_window.source,
+                                    PlotSource::Live,
+                                    PlotSource::Live.to_string(),
+                                );
+                                for snapshot in &self.snapshots {
+                                    let source = PlotSource::Snapshot(snapshot.name.clone());
+                                    let text = source.to_string();
+                                    ui.selectable_value(&mut plot_window.source, source, text);
+                                }
+                            });
+                        for (channel, name) in ["r", "g", "b", "sum"].iter().enumerate() {
+                            ui.checkbox(&mut plot_window.channels[channel], *name);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let mut fixed_x = plot_window.x_range.is_some();
+                        ui.checkbox(&mut fixed_x, "Fixed Wavelength Range");
+                        match (fixed_x, plot_window.x_range.as_mut()) {
+                            (true, Some(range)) => {
+                                ui.add(egui::DragValue::new(&mut range.low).suffix(" nm"));
+                                ui.add(egui::DragValue::new(&mut range.high).suffix(" nm"));
+                            }
+                            (true, None) => {
+                                plot_window.x_range = Some(WavelengthRange {
+                                    low: self.config.spectrum_calibration.low.wavelength as f32,
+                                    high: self.config.spectrum_calibration.high.wavelength as f32,
+                                })
+                            }
+                            (false, _) => plot_window.x_range = None,
+                        }
+                        let mut fixed_y = plot_window.y_max.is_some();
+                        ui.checkbox(&mut fixed_y, "Fixed Max Value");
+                        match (fixed_y, plot_window.y_max.as_mut()) {
+                            (true, Some(y_max)) => {
+                                ui.add(egui::DragValue::new(y_max).speed(0.01));
+                            }
+                            (true, None) => plot_window.y_max = Some(1.),
+                            (false, _) => plot_window.y_max = None,
+                        }
+                    });
+                    self.draw_plot_window_plot(ui, i, plot_window);
+                });
+            plot_window.open = open;
+        }
+        if let Some(i) = remove {
+            plot_windows.remove(i);
+        }
+        self.config.plot_windows = plot_windows;
+    }
+
+    fn draw_plot_window_plot(
+        &self,
+        ui: &mut egui::Ui,
+        index: usize,
+        plot_window: &PlotWindowConfig,
+    ) {
+        let spectrum = match &plot_window.source {
+            PlotSource::Live => Some(self.spectrum_container.spectrum().clone()),
+            PlotSource::Snapshot(name) => self
+                .snapshots
+                .iter()
+                .find(|s| &s.name == name)
+                .map(|s| s.spectrum.to_spectrum()),
+        };
+        Plot::new(("plot_window_plot", index))
+            .legend(Legend::default())
+            .show(ui, |plot_ui| {
+                let colors = [
+                    Color32::RED,
+                    Color32::GREEN,
+                    Color32::BLUE,
+                    Color32::LIGHT_GRAY,
+                ];
+                if let Some(spectrum) = spectrum.as_ref() {
+                    for (channel, name) in ["r", "g", "b", "sum"].iter().enumerate() {
+                        if plot_window.channels[channel] {
+                            plot_ui.line(
+                                Line::new(
+                                    SpectrumContainer::spectrum_channel_points(
+                                        spectrum,
+                                        channel,
+                                        &self.config,
+                                    )
+                                    .into_iter()
+                                    .map(|sp| [sp.wavelength as f64, sp.value as f64])
+                                    .collect::<Vec<_>>(),
+                                )
+                                .color(colors[channel])
+                                .name(*name),
+                            );
+                        }
+                    }
+                }
+                if plot_window.x_range.is_some() || plot_window.y_max.is_some() {
+                    let bounds = plot_ui.plot_bounds();
+                    let (x_min, x_max) = plot_window
+                        .x_range
+                        .map(|r| (r.low as f64, r.high as f64))
+                        .unwrap_or((bounds.min()[0], bounds.max()[0]));
+                    let (y_min, y_max) = plot_window
+                        .y_max
+                        .map(|y_max| (0., y_max as f64))
+                        .unwrap_or((bounds.min()[1], bounds.max()[1]));
+                    plot_ui
+                        .set_plot_bounds(PlotBounds::from_min_max([x_min, y_min], [x_max, y_max]));
+                }
+            });
+    }
+
+    fn update_sonification(&self) {
+        if let Some(sonifier) = self.sonifier.as_ref() {
+            let sum: Vec<_> = self
+                .spectrum_container
+                .spectrum()
+                .row(3)
+                .iter()
+                .cloned()
+                .collect();
+            // Spectrum values are normalized to full scale
+            sonifier.set_tones(spectrum_to_tones(
+                &sum,
+                1.,
+                &self.config.sonification_config,
+            ));
+        }
+    }
+
+    fn stream_status(&self) -> FeedStatus {
+        if self.stalled {
+            FeedStatus::new(StreamState::Error, Some("No new frames".to_string()))
+        } else if let AcquisitionState::Error(e) | AcquisitionState::Reconnecting(e) =
+            &self.acquisition
+        {
+            FeedStatus::new(StreamState::Error, Some(e.clone()))
+        } else if !self.acquisition.is_running() {
+            let message = match self.acquisition {
+                AcquisitionState::Starting => Some("Starting".to_string()),
+                AcquisitionState::Paused => Some("Paused".to_string()),
+                _ => None,
+            };
+            FeedStatus::new(StreamState::Paused, message)
+        } else if self.playback.is_some() {
+            FeedStatus::new(StreamState::Paused, Some("Playback".to_string()))
+        } else if self.measuring_dark() {
+            FeedStatus::new(StreamState::Paused, Some("Measuring dark".to_string()))
+        } else {
+            FeedStatus::new(StreamState::Running, None)
+        }
+    }
+
+    fn update_feed_status(&mut self) {
+        if self.feed_active {
+            let status = self.stream_status();
+            if self.feed_status.as_ref() != Some(&status) {
+                self.feed_tx
+                    .send(FeedEvent::Status(status.clone()))
+                    .unwrap();
+                self.feed_status = Some(status);
+            }
+        }
+    }
+
+    /// Send the newly received spectrum to the feed and the sinks
+    fn publish_spectrum(&mut self) {
+        let Some(sink_tx) = &self.sink_tx else {
+            return;
+        };
+        let sinks_active = self.config.sink_configs.iter().any(|sink| sink.active);
+        if !(self.feed_active || sinks_active) || self.playback.is_some() || self.measuring_dark() {
+            return;
+        }
+        let spectrum = FeedSpectrum::new(
+            self.spectrum_container.spectrum(),
+            &self.config.spectrum_calibration,
+            self.spectrum_container.capture_time(),
+            self.spectrum_container.sequence(),
+            self.spectrum_container.frame_metadata(),
+            self.spectrum_container.saturation(),
+            self.config.feed_config.include_rgb,
+        );
+        if self.feed_active {
+            let provenance = Provenance::new(&self.config);
+            if self.feed_provenance.as_ref() != Some(&provenance) {
+                self.feed_tx
+                    .send(FeedEvent::Provenance(provenance.clone()))
+                    .unwrap();
+                self.feed_tx
+                    .send(FeedEvent::Calibration(FeedCalibration::new(
+                        &self.config.spectrum_calibration,
+                    )))
+                    .unwrap();
+                self.feed_provenance = Some(provenance);
+            }
+        }
+        // The sink manager forwards the spectrum to the feed after the provenance sent above
+        sink_tx
+            .send(SinkEvent::Spectrum {
+                spectrum,
+                feed: self.feed_active,
+            })
+            .ok();
+    }
+
+    /// Edit the sinks of one window, fields can only be changed while a sink is inactive
+    ///
+    /// Returns true if the configuration changed.
+    fn draw_sink_list(ui: &mut Ui, sink_configs: &mut Vec<SinkConfig>, network: bool) -> bool {
+        let before = sink_configs.clone();
+        let mut remove = None;
+        for (i, sink) in sink_configs.iter_mut().enumerate() {
+            if sink.kind.is_network() != network {
+                continue;
+            }
+            ui.push_id(i, |ui| {
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut sink.active, sink.kind.name());
+                    ui.add(
+                        egui::DragValue::new(&mut sink.max_rate)
+                            .range(0.0..=100.)
+                            .speed(0.1)
+                            .prefix("Max: ")
+                            .suffix(" /s"),
+                    )
+                    .on_hover_text("Spectra per second, 0 passes every spectrum");
+                    if ui.button("Remove").clicked() {
+                        remove = Some(i);
+                    }
+                });
+                ui.add_enabled_ui(!sink.active, |ui| match &mut sink.kind {
+                    SinkKind::TcpJson { port } => {
+                        ui.add(egui::DragValue::new(port).prefix("Port: "));
+                    }
+                    SinkKind::Csv { path } => {
+                        ui.text_edit_singleline(path);
+                    }
+                    SinkKind::Mqtt {
+                        broker,
+                        topic,
+                        client_id,
+                    } => {
+                        egui::Grid::new("mqtt").show(ui, |ui| {
+                            ui.label("Broker");
+                            ui.text_edit_singleline(broker);
+                            ui.end_row();
+                            ui.label("Topic");
+                            ui.text_edit_singleline(topic);
+                            ui.end_row();
+                            ui.label("Client ID");
+                            ui.text_edit_singleline(client_id);
+                            ui.end_row();
+                        });
+                    }
+                });
+            });
+            ui.separator();
+        }
+        if let Some(i) = remove {
+            sink_configs.remove(i);
+        }
+        ui.horizontal(|ui| {
+            let kinds = if network {
+                vec![SinkKind::tcp_json(), SinkKind::mqtt()]
+            } else {
+                vec![SinkKind::csv()]
+            };
+            for kind in kinds {
+                if ui.button(format!("Add {} Sink", kind.name())).clicked() {
+                    sink_configs.push(SinkConfig::new(kind));
+                }
+            }
+        });
+        *sink_configs != before
+    }
+
+    fn send_sink_configs(&self) {
+        if let Some(sink_tx) = &self.sink_tx {
+            sink_tx
+                .send(SinkEvent::Configure(self.config.sink_configs.clone()))
+                .ok();
+        }
+    }
+
+    /// Append the newly received spectrum to the recording
+    fn record_spectrum(&mut self) {
+        if self.measuring_dark() {
+            return;
+        }
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.record(
+                self.spectrum_container.spectrum(),
+                self.spectrum_container.capture_time(),
+                self.spectrum_container.sequence(),
+            ) {
+                self.recorder = None;
+                self.last_error = Some(ThreadResult {
+                    id: ThreadId::Main,
+                    result: Err(e),
+                });
+            }
+        }
+    }
+
+    /// Receive pending spectra, recording and publishing each of them
+    ///
+    /// In low power mode all pending spectra are processed, otherwise one per call. The
+    /// recording, feed and sinks get every spectrum independent of the repaint rate.
+    /// Returns true if a new spectrum was received.
+    fn receive_spectra(&mut self) -> bool {
+        let mut new_spectrum = false;
+        while let Some(updated) = self.spectrum_container.receive_next(&self.config) {
+            if updated {
+                self.record_spectrum();
+                self.publish_spectrum();
+            }
+            new_spectrum |= updated;
+            if !self.config.low_power_mode {
+                break;
+            }
+        }
+        new_spectrum
+    }
+
+    fn update_playback(&mut self) {
+        if let Some(recorded) = self
+            .playback
+            .as_ref()
+            .and_then(|r| r.spectra.get(self.playback_index))
+        {
+            self.spectrum_container.set_spectrum(recorded.to_spectrum());
+        }
     }
 
     fn draw_connection_panel(&mut self, ctx: &Context) {
         egui::TopBottomPanel::top("camera").show(ctx, |ui| {
             ui.horizontal(|ui| {
-                ComboBox::from_id_salt("cb_camera")
-                    .selected_text(format!(
-                        "{}: {}",
-                        self.config.camera_id,
-                        self.camera_info
-                            .get_index(self.config.camera_id)
-                            .map(|(_index, info)| info.info.human_name())
-                            .unwrap_or_default()
-                    ))
-                    .show_ui(ui, |ui| {
-                        if !self.running {
-                            for (i, (_camera_index, camera_info)) in
-                                self.camera_info.iter().enumerate()
-                            {
+                ui.add_enabled_ui(!self.acquisition.is_active(), |ui| {
+                    ComboBox::from_id_salt("cb_frame_source")
+                        .selected_text(self.config.frame_source.to_string())
+                        .show_ui(ui, |ui| {
+                            for source in [
+                                FrameSource::Camera,
+                                FrameSource::ScreenCapture,
+                                FrameSource::VideoFile,
+                                FrameSource::ImageFile,
+                                FrameSource::NetworkStream,
+                            ] {
                                 ui.selectable_value(
-                                    &mut self.config.camera_id,
-                                    i,
-                                    format!("{}: {}", i, camera_info.info.human_name()),
+                                    &mut self.config.frame_source,
+                                    source,
+                                    source.to_string(),
                                 );
                             }
-                        }
-                    });
-                ComboBox::from_id_salt("cb_camera_format")
-                    .selected_text(match self.config.camera_format {
-                        None => "".to_string(),
-                        Some(camera_format) => format!("{}", camera_format),
-                    })
-                    .show_ui(ui, |ui| {
-                        if !self.running {
-                            if let Some((camera_index, _)) =
-                                self.camera_info.get_index(self.config.camera_id)
+                        });
+                });
+                match self.config.frame_source {
+                    FrameSource::ScreenCapture => {
+                        ui.add_enabled_ui(!self.acquisition.is_active(), |ui| {
+                            self.draw_screen_capture_settings(ui);
+                        });
+                    }
+                    FrameSource::VideoFile => {
+                        ui.add_enabled_ui(!self.acquisition.is_active(), |ui| {
+                            self.draw_video_file_settings(ui);
+                        });
+                    }
+                    FrameSource::ImageFile => {
+                        ui.add_enabled_ui(!self.acquisition.is_active(), |ui| {
+                            self.draw_image_file_settings(ui);
+                        });
+                    }
+                    FrameSource::NetworkStream => {
+                        ui.add_enabled_ui(!self.acquisition.is_active(), |ui| {
+                            self.draw_network_stream_settings(ui);
+                        });
+                    }
+                    FrameSource::Camera => {
+                        ComboBox::from_id_salt("cb_camera")
+                            .selected_text(format!(
+                                "{}: {}",
+                                self.config.camera_id,
+                                self.camera_info
+                                    .get_index(self.config.camera_id)
+                                    .map(|(_index, info)| info.info.human_name())
+                                    .unwrap_or_default()
+                            ))
+                            .show_ui(ui, |ui| {
+                                if !self.acquisition.is_active() {
+                                    for (i, (_camera_index, camera_info)) in
+                                        self.camera_info.iter().enumerate()
+                                    {
+                                        ui.selectable_value(
+                                            &mut self.config.camera_id,
+                                            i,
+                                            format!("{}: {}", i, camera_info.info.human_name()),
+                                        );
+                                    }
+                                }
+                            });
+                        if let Some(refresh_tx) = &self.camera_refresh_tx {
+                            if ui
+                                .add_enabled(!self.acquisition.is_active(), Button::new("⟳"))
+                                .on_hover_text("Query the connected cameras again")
+                                .clicked()
                             {
-                                if let Ok(mut camera) = Camera::new(
-                                    camera_index.clone(),
-                                    RequestedFormat::new::<RgbFormat>(RequestedFormatType::None),
-                                ) {
-                                    if let Ok(formats) = camera.compatible_camera_formats() {
-                                        for cf in formats {
-                                            ui.selectable_value(
-                                                &mut self.config.camera_format,
-                                                Some(cf),
-                                                format!("{}", cf),
-                                            );
+                                refresh_tx.send(()).ok();
+                            }
+                        }
+                        ui.menu_button(
+                            match self.config.camera_format {
+                                None => "Format".to_string(),
+                                Some(camera_format) if self.config.y16_capture => format!(
+                                    "{}@{}FPS, Y16 Format",
+                                    camera_format.resolution(),
+                                    camera_format.frame_rate()
+                                ),
+                                Some(camera_format) => format!("{}", camera_format),
+                            },
+                            |ui| {
+                                if self.acquisition.is_active() {
+                                    ui.close_menu();
+                                    return;
+                                }
+                                if let Some((camera_index, _)) =
+                                    self.camera_info.get_index(self.config.camera_id)
+                                {
+                                    if let Ok(mut camera) = Camera::new(
+                                        camera_index.clone(),
+                                        requested_format(RequestedFormatType::None),
+                                    ) {
+                                        if let Ok(formats) = camera.compatible_camera_formats() {
+                                            for group in group_camera_formats(formats) {
+                                                ui.menu_button(group.to_string(), |ui| {
+                                                    for frame_rate in &group.frame_rates {
+                                                        let cf = group.camera_format(*frame_rate);
+                                                        if ui
+                                                            .selectable_value(
+                                                                &mut self.config.camera_format,
+                                                                Some(cf),
+                                                                format!("{} fps", frame_rate),
+                                                            )
+                                                            .clicked()
+                                                        {
+                                                            self.config.y16_capture = false;
+                                                            ui.close_menu();
+                                                        }
+                                                    }
+                                                });
+                                            }
                                         }
                                     }
                                 }
-                            }
+                                ui.separator();
+                                if ui.button("Custom Format…").clicked() {
+                                    self.custom_format = Some(
+                                        self.config
+                                            .camera_format
+                                            .map(CustomCameraFormat::from)
+                                            .unwrap_or(CustomCameraFormat {
+                                                width: 640,
+                                                height: 480,
+                                                frame_rate: 30,
+                                                fourcc: "MJPG".to_string(),
+                                            }),
+                                    );
+                                    ui.close_menu();
+                                }
+                            },
+                        );
+                        ui.add_enabled(
+                            !self.acquisition.is_active(),
+                            egui::Checkbox::new(
+                                &mut self.config.driver_timestamps,
+                                "Driver Timestamps",
+                            ),
+                        )
+                        .on_hover_text(
+                            "Stamp frames with the capture time reported by the V4L2 driver \
+                            instead of their arrival, Linux only",
+                        );
+                        if ui
+                            .checkbox(
+                                &mut self.config.image_config.fast_jpeg_decode,
+                                "Fast MJPEG Decoding",
+                            )
+                            .on_hover_text(
+                                "Decode MJPEG frames with zune-jpeg, which takes considerably \
+                                less CPU time at high resolutions",
+                            )
+                            .changed()
+                        {
+                            self.send_config();
                         }
-                    });
+                    }
+                }
 
-                let connect_button = ui.button(if self.running { "Stop..." } else { "Start..." });
-                if connect_button.clicked() {
-                    if self.config.camera_format.is_some() {
-                        // Clamp window values to camera-resolution
-                        let camera_format = self.config.camera_format.unwrap();
-                        self.config
-                            .image_config
-                            .clamp(camera_format.width() as f32, camera_format.height() as f32);
+                if ui
+                    .checkbox(&mut self.config.low_power_mode, "Low Power")
+                    .on_hover_text(
+                        "Skip the camera preview, repaint at 2 Hz and average incrementally, \
+                        for small boards serving the network feed",
+                    )
+                    .changed()
+                {
+                    self.config.image_config.low_power = self.config.low_power_mode;
+                    self.send_config();
+                }
 
-                        self.running = !self.running;
-                        if self.running {
-                            self.start_stream();
-                        } else {
-                            self.stop_stream();
-                        };
-                    } else {
-                        self.last_error = Some(ThreadResult {
-                            id: ThreadId::Main,
-                            result: Err("Choose a camera format!".to_string()),
-                        });
-                    }
+                let active = self.acquisition.is_active();
+                let probing = self.file_probe.is_some();
+                let connect_button = ui.add_enabled(
+                    !probing,
+                    Button::new(if active { "Stop..." } else { "Start..." }),
+                );
+                if connect_button.clicked() && active {
+                    self.stop_stream();
+                } else if connect_button.clicked() {
+                    self.probe_file_source(true);
+                };
+                if probing {
+                    ui.spinner();
+                }
+                let pause = match self.acquisition {
+                    AcquisitionState::Running => Some(true),
+                    AcquisitionState::Paused => Some(false),
+                    _ => None,
                 };
+                if let Some(pause) = pause {
+                    if ui
+                        .button(if pause { "Pause" } else { "Resume" })
+                        .on_hover_text("Keep the stream open without updating the spectrum")
+                        .clicked()
+                    {
+                        self.set_paused(pause);
+                    }
+                }
+                if ui
+                    .checkbox(&mut self.config.image_config.single_shot, "Single Shot")
+                    .on_hover_text(
+                        "Only process frames on capture, feed clients can capture with a \
+                         capture request",
+                    )
+                    .changed()
+                {
+                    self.send_config();
+                }
+                if self.config.image_config.single_shot
+                    && ui
+                        .add_enabled(self.acquisition.is_running(), Button::new("Capture"))
+                        .on_hover_text("Average the frames of one spectrum buffer")
+                        .clicked()
+                {
+                    self.capture_single_shot();
+                }
+                ui.label(self.acquisition.to_string());
+            });
+        });
+    }
+
+    fn draw_screen_capture_settings(&mut self, ui: &mut egui::Ui) {
+        let screen_config = &mut self.config.screen_capture_config;
+        ui.label("X");
+        ui.add(egui::DragValue::new(&mut screen_config.x));
+        ui.label("Y");
+        ui.add(egui::DragValue::new(&mut screen_config.y));
+        ui.label("Width");
+        ui.add(egui::DragValue::new(&mut screen_config.width).range(1..=u16::MAX as u32));
+        ui.label("Height");
+        ui.add(egui::DragValue::new(&mut screen_config.height).range(1..=u16::MAX as u32));
+        ui.menu_button("Capture Command", |ui| {
+            ui.text_edit_singleline(&mut screen_config.command)
+                .on_hover_text(
+                    "Shell command writing an image to stdout. {x}, {y}, {width} and {height} \
+                     are replaced by the region. For X11 use e.g. \
+                     import -window root -crop {width}x{height}+{x}+{y} ppm:-",
+                );
+            ui.add(
+                Slider::new(&mut screen_config.max_fps, 0.1..=30.)
+                    .logarithmic(true)
+                    .text("Max. FPS"),
+            );
+        });
+    }
+
+    fn draw_video_file_settings(&mut self, ui: &mut egui::Ui) {
+        let video_config = &mut self.config.video_file_config;
+        ui.label("Path");
+        let path_response = ui.text_edit_singleline(&mut video_config.path);
+        ui.checkbox(&mut video_config.looping, "Loop");
+        if path_response.lost_focus() {
+            self.probe_file_source(false);
+        }
+        if let Some((width, height)) = self.file_frame_size {
+            ui.label(format!("{}x{}", width, height));
+        }
+    }
+
+    fn draw_image_file_settings(&mut self, ui: &mut egui::Ui) {
+        let image_file_config = &mut self.config.image_file_config;
+        ui.label("Image or Directory");
+        let path_response = ui.text_edit_singleline(&mut image_file_config.path);
+        ui.add(
+            Slider::new(&mut image_file_config.max_fps, 0.1..=30.)
+                .logarithmic(true)
+                .text("Max. FPS"),
+        );
+        if path_response.lost_focus() {
+            self.probe_file_source(false);
+        }
+        if let Some((width, height)) = self.file_frame_size {
+            ui.label(format!("{}x{}", width, height));
+        }
+    }
+
+    fn draw_network_stream_settings(&mut self, ui: &mut egui::Ui) {
+        let stream_config = &mut self.config.network_stream_config;
+        ui.label("URL");
+        let mut changed = ui.text_edit_singleline(&mut stream_config.url).lost_focus();
+        ComboBox::from_id_salt("cb_stream_backend")
+            .selected_text(stream_config.backend.to_string())
+            .show_ui(ui, |ui| {
+                for backend in [StreamBackend::Ffmpeg, StreamBackend::GStreamer] {
+                    changed |= ui
+                        .selectable_value(&mut stream_config.backend, backend, backend.to_string())
+                        .changed();
+                }
             });
+        match stream_config.backend {
+            StreamBackend::Ffmpeg => {
+                ui.checkbox(&mut stream_config.rtsp_over_tcp, "RTSP over TCP");
+            }
+            StreamBackend::GStreamer => {
+                ui.label("Pipeline");
+                changed |= ui
+                    .text_edit_singleline(&mut stream_config.gstreamer_source)
+                    .on_hover_text(
+                        "gst-launch-1.0 pipeline up to the decoded video, {url} is replaced by the URL",
+                    )
+                    .lost_focus();
+            }
+        }
+        if changed {
+            self.probe_file_source(false);
+        }
+        if let Some((width, height)) = self.file_frame_size {
+            ui.label(format!("{}x{}", width, height));
+        }
+    }
+
+    /// Read the frame size of a file or network stream source, which is needed to check the
+    /// spectrum window, and start the stream afterwards if requested
+    ///
+    /// Video files and network streams are probed in a worker thread, so that a stream that does
+    /// not answer does not block the GUI.
+    fn probe_file_source(&mut self, start: bool) {
+        let stream_config = &self.config.network_stream_config;
+        let (input_args, probe): (_, fn(&[String], Duration) -> _) = match self.config.frame_source
+        {
+            FrameSource::VideoFile => (
+                vec!["-i".to_string(), self.config.video_file_config.path.clone()],
+                probe_video_size,
+            ),
+            FrameSource::NetworkStream => match stream_config.backend {
+                StreamBackend::Ffmpeg => (network_stream_args(stream_config), probe_video_size),
+                StreamBackend::GStreamer => (gstreamer_args(stream_config), probe_gstreamer_size),
+            },
+            FrameSource::ImageFile => {
+                let size = image_file_paths(Path::new(&self.config.image_file_config.path))
+                    .and_then(|paths| {
+                        let first = paths.first().ok_or("No images found")?;
+                        image::image_dimensions(first).map_err(|e| e.to_string())
+                    });
+                self.probe_finished(size, start);
+                return;
+            }
+            FrameSource::Camera | FrameSource::ScreenCapture => {
+                if start {
+                    self.start_with_frame_size();
+                }
+                return;
+            }
+        };
+        let (size_tx, size_rx) = flume::bounded(1);
+        std::thread::spawn(move || {
+            size_tx.send(probe(&input_args, FILE_PROBE_TIMEOUT)).ok();
         });
+        // A newer probe replaces a running one
+        self.file_probe = Some(FileProbe { size_rx, start });
+    }
+
+    fn update_file_probe(&mut self, ctx: &Context) {
+        let Some(probe) = self.file_probe.as_ref() else {
+            return;
+        };
+        match probe.size_rx.try_recv() {
+            Ok(size) => {
+                let start = probe.start;
+                self.file_probe = None;
+                self.probe_finished(size, start);
+            }
+            Err(flume::TryRecvError::Empty) => {
+                ctx.request_repaint_after(Duration::from_millis(100))
+            }
+            Err(flume::TryRecvError::Disconnected) => self.file_probe = None,
+        }
+    }
+
+    fn update_gif_export(&mut self, ctx: &Context) {
+        let Some(result_rx) = self.gif_export_rx.as_ref() else {
+            return;
+        };
+        match result_rx.try_recv() {
+            Ok(result) => {
+                self.gif_export_rx = None;
+                self.last_error = Some(result);
+            }
+            Err(flume::TryRecvError::Empty) => {
+                ctx.request_repaint_after(Duration::from_millis(100))
+            }
+            Err(flume::TryRecvError::Disconnected) => self.gif_export_rx = None,
+        }
+    }
+
+    fn probe_finished(&mut self, size: Result<(u32, u32), String>, start: bool) {
+        self.file_frame_size = match size {
+            Ok(size) => Some(size),
+            Err(e) => {
+                log::error!("Could not probe {}: {}", self.config.frame_source, e);
+                None
+            }
+        };
+        if start {
+            self.start_with_frame_size();
+        }
+    }
+
+    /// Start the stream if the frame size of the source is known
+    fn start_with_frame_size(&mut self) {
+        if self.acquisition.is_active() {
+            return;
+        }
+        if let Some((width, height)) = self.frame_size() {
+            // Clamp window values to camera-resolution
+            self.config.image_config.clamp(width as f32, height as f32);
+            self.start_stream();
+        } else {
+            self.last_error = Some(ThreadResult {
+                id: ThreadId::Main,
+                result: Err(match self.config.frame_source {
+                    FrameSource::VideoFile | FrameSource::ImageFile => {
+                        "Could not read the frame size of the file".to_string()
+                    }
+                    FrameSource::NetworkStream => {
+                        "Could not read the frame size of the stream".to_string()
+                    }
+                    _ => "Choose a camera format!".to_string(),
+                }),
+            });
+        }
     }
 
     fn draw_window_selection_panel(&mut self, ctx: &Context) {
@@ -900,50 +5185,592 @@ impl SpectrometerGui {
                 &mut self.config.view_config.show_import_export_window,
                 "Import/Export",
             );
+            ui.checkbox(
+                &mut self.config.view_config.show_recording_window,
+                "Recording",
+            );
+            ui.checkbox(&mut self.config.view_config.show_network_window, "Network");
+            ui.checkbox(
+                &mut self.config.view_config.show_additional_cameras_window,
+                "Additional Cameras",
+            );
+            if ui
+                .checkbox(&mut self.config.view_config.show_library_window, "Library")
+                .changed()
+                && self.library.is_none()
+            {
+                self.open_library();
+            }
+            ui.separator();
+            ui.checkbox(
+                &mut self.config.view_config.touch_controls,
+                "Touch Controls",
+            )
+            .on_hover_text("Larger buttons and sliders, pinch to zoom the plot");
+            ui.separator();
+            for plot_window in self.config.plot_windows.iter_mut() {
+                ui.checkbox(&mut plot_window.open, plot_window.name.as_str());
+            }
+            if ui.button("Add Plot Window").clicked() {
+                let name = format!("Plot {}", self.config.plot_windows.len() + 1);
+                self.config.plot_windows.push(PlotWindowConfig::new(name));
+            }
         });
     }
 
     fn draw_last_result(&mut self, ctx: &Context) {
         egui::TopBottomPanel::bottom("result").show(ctx, |ui| {
-            if let Some(res) = self.last_error.as_ref() {
-                ui.label(match &res.result {
-                    Ok(()) => RichText::new("OK").color(Color32::GREEN),
-                    Err(e) => RichText::new(format!("Error: {}", e)).color(Color32::RED),
-                })
+            ui.horizontal(|ui| {
+                if let Some(res) = self.last_error.as_ref() {
+                    ui.label(match &res.result {
+                        Ok(()) => RichText::new("OK").color(Color32::GREEN),
+                        Err(e) => RichText::new(format!("Error: {}", e)).color(Color32::RED),
+                    });
+                } else {
+                    ui.label("");
+                }
+                if let Some(latency) = self.spectrum_container.latency() {
+                    ui.separator();
+                    ui.label(format!(
+                        "Latency: {} ms (capture {} ms, transfer {} ms, postprocessing {} ms)",
+                        latency.total().as_millis(),
+                        latency.capture.as_millis(),
+                        latency.transfer.as_millis(),
+                        latency.postprocessing.as_millis(),
+                    ))
+                    .on_hover_text(format!(
+                        "The averaging buffer of {} spectra spans {} ms",
+                        self.config.postprocessing_config.spectrum_buffer_size,
+                        latency.frame_interval.as_millis()
+                            * self.config.postprocessing_config.spectrum_buffer_size as u128
+                    ));
+                }
+                if let Some(reason) = self
+                    .spectrum_container
+                    .recent_buffer_reset(BUFFER_RESET_INDICATOR_DURATION)
+                {
+                    ui.separator();
+                    ui.label(RichText::new("Buffer reset").color(Color32::YELLOW))
+                        .on_hover_text(format!("The averaging buffer was cleared, {reason}"));
+                }
+                if self
+                    .calibration_check
+                    .needs_recalibration(&self.config.calibration_check_config)
+                {
+                    ui.separator();
+                    ui.label(RichText::new("Recalibrate").color(Color32::RED))
+                        .on_hover_text(
+                            "The reference line drifted beyond the threshold of the drift check",
+                        );
+                }
+                if let Some(gap) = self.spectrum_container.coverage_gap() {
+                    ui.separator();
+                    ui.label(
+                        RichText::new(format!("Frame gap: {} ms", gap.as_millis()))
+                            .color(Color32::YELLOW),
+                    )
+                    .on_hover_text(
+                        if self.config.postprocessing_config.time_weighted_average {
+                            "Frames were dropped, the average is weighted by frame interval"
+                        } else {
+                            "Frames were dropped, enable the time weighted average to compensate"
+                        },
+                    );
+                }
+                if let Some((start, end)) = self
+                    .spectrum_container
+                    .time_range()
+                    .filter(|_| self.playback.is_none())
+                {
+                    // Only the time of day, the date is in the hover text
+                    let time = |t| format_timestamp(t)[11..].to_string();
+                    let age = SystemTime::now()
+                        .duration_since(end)
+                        .unwrap_or_default()
+                        .as_secs_f32();
+                    ui.separator();
+                    ui.label(format!(
+                        "Spectrum: {} - {} UTC, age {:.1} s",
+                        time(start),
+                        time(end),
+                        age
+                    ))
+                    .on_hover_text(format!(
+                        "Averaged frames were captured from {} to {} UTC",
+                        format_timestamp(start),
+                        format_timestamp(end)
+                    ));
+                }
+                if let Some(peak) = self.spectrum_container.get_dominant_peak(&self.config) {
+                    ui.separator();
+                    ui.label(format!(
+                        "Peak: {:.1} nm, FWHM {:.1} nm",
+                        peak.centroid, peak.fwhm
+                    ))
+                    .on_hover_text(format!(
+                        "Centroid of the strongest peak, maximum {:.3} at {:.1} nm",
+                        peak.value, peak.wavelength
+                    ));
+                }
+                if let Some(&(_, line)) = self
+                    .line_history
+                    .back()
+                    .filter(|_| self.config.narrowband_config.active)
+                {
+                    ui.separator();
+                    ui.label(format!("Line: {:.3} nm", line));
+                }
+                if let Some(result) = self.tolerance_result {
+                    ui.separator();
+                    let (text, color) = if result.passed {
+                        ("PASS", Color32::GREEN)
+                    } else {
+                        ("FAIL", Color32::RED)
+                    };
+                    ui.label(RichText::new(text).color(color).strong())
+                        .on_hover_text(format!(
+                            "Max. deviation {:.3} at {:.1} nm",
+                            result.max_deviation, result.wavelength
+                        ));
+                }
+                if let Some(sequence) = self.transmission.as_ref() {
+                    ui.separator();
+                    if sequence.step() == TransmissionStep::Measuring {
+                        ui.label("Transmittance");
+                    } else {
+                        ui.label(RichText::new(sequence.prompt()).color(Color32::YELLOW));
+                    }
+                }
+                if self.measuring_dark() {
+                    ui.separator();
+                    ui.label(RichText::new("Measuring dark").color(Color32::YELLOW));
+                }
+                if self.stalled {
+                    ui.separator();
+                    ui.label(
+                        RichText::new(format!(
+                            "No new frames for {} s",
+                            self.spectrum_container.time_since_last_update().as_secs()
+                        ))
+                        .color(Color32::YELLOW),
+                    );
+                }
+                if let Some(saturation) = self.spectrum_container.saturation().filter(|&s| s > 0.) {
+                    ui.separator();
+                    ui.label(
+                        RichText::new(format!("SATURATED ({:.2} %)", saturation * 100.))
+                            .strong()
+                            .color(Color32::RED),
+                    )
+                    .on_hover_text("Clipped spectra are invalid, reduce the exposure or gain");
+                }
+            });
+        });
+    }
+
+    fn check_watchdog(&mut self) {
+        let watchdog_config = &self.config.watchdog_config;
+        // Single shot mode only delivers spectra on capture
+        let stalled = self.acquisition.is_running()
+            && !self.config.image_config.single_shot
+            && watchdog_config.active
+            && self
+                .spectrum_container
+                .time_since_last_update()
+                .as_secs_f32()
+                > watchdog_config.timeout + self.config.image_config.long_exposure.unwrap_or(0.);
+
+        if stalled && !self.stalled {
+            log::warn!("No new frames for {} s", watchdog_config.timeout);
+            if !watchdog_config.webhook_url.is_empty() {
+                webhook::post_json(
+                    &watchdog_config.webhook_url,
+                    serde_json::json!({
+                        "event": "stalled",
+                        "camera_id": self.config.camera_id,
+                        "timeout": watchdog_config.timeout,
+                    }),
+                );
+            }
+        }
+        self.stalled = stalled;
+    }
+
+    fn update_narrowband(&mut self, new_spectrum: bool) {
+        let narrowband_config = &self.config.narrowband_config;
+        if !narrowband_config.active {
+            self.line_history.clear();
+            return;
+        }
+        let now = Instant::now();
+        if new_spectrum && !self.measuring_dark() {
+            if let Some(line) = sub_pixel_peak(
+                &self
+                    .spectrum_container
+                    .get_spectrum_channel(3, &self.config),
+            ) {
+                self.line_history.push_back((now, line));
+            }
+        }
+        let duration = Duration::from_secs_f32(narrowband_config.history_duration.max(0.));
+        while self
+            .line_history
+            .front()
+            .is_some_and(|(t, _)| now.duration_since(*t) > duration)
+        {
+            self.line_history.pop_front();
+        }
+    }
+
+    /// Replace new spectra with the transmittance or optical density once reference and dark
+    /// are captured
+    fn update_transmission(&mut self, new_spectrum: bool) {
+        if !new_spectrum {
+            return;
+        }
+        if let Some(transmittance) = self
+            .transmission
+            .as_ref()
+            .and_then(|s| s.transmittance(&self.spectrum_container.precise_spectrum()))
+        {
+            let transmission_config = &self.config.transmission_config;
+            let spectrum = if transmission_config.optical_density {
+                optical_density(&transmittance, transmission_config.transmittance_floor)
             } else {
-                ui.label("")
+                transmittance
+            };
+            self.spectrum_container.set_spectrum(spectrum.cast());
+        }
+    }
+
+    fn showing_optical_density(&self) -> bool {
+        self.config.transmission_config.optical_density
+            && self
+                .transmission
+                .as_ref()
+                .is_some_and(|s| s.step() == TransmissionStep::Measuring)
+    }
+
+    /// Describes exported values if they are optical densities
+    fn optical_density_comment(&self) -> Option<String> {
+        self.showing_optical_density().then(|| {
+            format!(
+                "Optical density, transmittance floor {}",
+                self.config.transmission_config.transmittance_floor
+            )
+        })
+    }
+
+    fn update_tolerance_check(&mut self, new_spectrum: bool) {
+        let tolerance_config = &self.config.tolerance_config;
+        if !tolerance_config.active {
+            self.tolerance_result = None;
+            return;
+        }
+        if !new_spectrum || self.measuring_dark() {
+            return;
+        }
+        let result = tolerance_config.check(
+            &self
+                .spectrum_container
+                .get_spectrum_channel(3, &self.config),
+        );
+
+        let previous = self.tolerance_result.map(|r| r.passed);
+        if let Some(result) = result.filter(|r| previous.unwrap_or(true) != r.passed) {
+            log::info!(
+                "Tolerance check {}, max. deviation {} at {} nm",
+                if result.passed { "passed" } else { "failed" },
+                result.max_deviation,
+                result.wavelength
+            );
+            if self.feed_active {
+                self.feed_tx.send(FeedEvent::Tolerance(result)).unwrap();
             }
-        });
+            if !tolerance_config.webhook_url.is_empty() {
+                webhook::post_json(
+                    &tolerance_config.webhook_url,
+                    serde_json::json!({
+                        "event": if result.passed { "tolerance_passed" } else { "tolerance_failed" },
+                        "max_deviation": result.max_deviation,
+                        "wavelength": result.wavelength,
+                    }),
+                );
+            }
+        }
+        self.tolerance_result = result;
     }
 
-    fn handle_thread_result(&mut self, res: &ThreadResult) {
-        if let ThreadResult {
-            id: ThreadId::Camera,
-            result: Err(_),
-        } = res
+    fn update_alarms(&mut self, new_spectrum: bool) {
+        let alarm_config = &self.config.alarm_config;
+        if !alarm_config.active {
+            self.alarm_monitor.clear();
+            return;
+        }
+        if !new_spectrum || self.measuring_dark() {
+            return;
+        }
+        let mut points = self
+            .spectrum_container
+            .get_spectrum_channel(3, &self.config);
+        points.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        let events =
+            self.alarm_monitor
+                .update(alarm_config, &points, self.config.active_roi_preset_name());
+
+        for event in events {
+            log::warn!(
+                "Alarm {} {}, value {} (threshold {})",
+                event.name,
+                if event.triggered {
+                    "triggered"
+                } else {
+                    "cleared"
+                },
+                event.value,
+                event.threshold
+            );
+            if !alarm_config.webhook_url.is_empty() {
+                webhook::post_json(
+                    &alarm_config.webhook_url,
+                    serde_json::json!({
+                        "event": if event.triggered { "alarm_triggered" } else { "alarm_cleared" },
+                        "name": event.name,
+                        "value": event.value,
+                        "threshold": event.threshold,
+                    }),
+                );
+            }
+            if self.feed_active {
+                self.feed_tx.send(FeedEvent::Alarm(event)).unwrap();
+            }
+        }
+    }
+
+    fn update_calibration_check(&mut self, new_spectrum: bool) {
+        let config = &self.config.calibration_check_config;
+        let now = Instant::now();
+        if !config.active
+            || !new_spectrum
+            || self.measuring_dark()
+            || !self.calibration_check.is_due(config, now)
         {
-            self.running = false;
+            return;
+        }
+        let mut points = self
+            .spectrum_container
+            .get_spectrum_channel(3, &self.config);
+        points.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        match self.calibration_check.update(config, &points, now) {
+            Some(DriftCheck::Drift(drift)) => log::warn!(
+                "Reference line at {} nm drifted by {:.2} nm, recalibration recommended",
+                config.reference_wavelength,
+                drift
+            ),
+            Some(DriftCheck::LineNotFound) => log::warn!(
+                "Reference line at {} nm not found for the drift check",
+                config.reference_wavelength
+            ),
+            None => {}
+        }
+    }
+
+    fn draw_custom_format_window(&mut self, ctx: &Context) {
+        let Some(custom_format) = self.custom_format.as_mut() else {
+            return;
+        };
+        let mut open = true;
+        let mut apply = false;
+        egui::Window::new("Custom Camera Format")
+            .open(&mut open)
+            .show(ctx, |ui| {
+                egui::Grid::new("custom_format").show(ui, |ui| {
+                    ui.label("Width");
+                    ui.add(egui::DragValue::new(&mut custom_format.width).range(1..=16384));
+                    ui.end_row();
+                    ui.label("Height");
+                    ui.add(egui::DragValue::new(&mut custom_format.height).range(1..=16384));
+                    ui.end_row();
+                    ui.label("Frame Rate");
+                    ui.add(
+                        egui::DragValue::new(&mut custom_format.frame_rate)
+                            .range(1..=1000)
+                            .suffix(" fps"),
+                    );
+                    ui.end_row();
+                    ui.label("FourCC");
+                    ui.add(
+                        egui::TextEdit::singleline(&mut custom_format.fourcc).desired_width(60.),
+                    )
+                    .on_hover_text(
+                        "MJPG, YUYV, NV12, GREY, RGB3 or Y16, which is read from V4L2 on Linux",
+                    );
+                    ui.end_row();
+                });
+                let result = custom_format.to_camera_format();
+                if let Err(e) = &result {
+                    ui.label(RichText::new(e).color(Color32::RED));
+                }
+                apply = ui
+                    .add_enabled(result.is_ok(), Button::new("Use Format"))
+                    .on_hover_text("The format is checked when the stream is started")
+                    .clicked();
+            });
+        if apply {
+            let custom_format = self.custom_format.take();
+            self.config.y16_capture = custom_format.as_ref().is_some_and(|f| f.is_y16());
+            self.config.camera_format = custom_format.and_then(|f| f.to_camera_format().ok());
+        } else if !open {
+            self.custom_format = None;
+        }
+    }
+
+    fn draw_alarm_banner(&mut self, ctx: &Context) {
+        if self.alarm_monitor.triggered().is_empty() {
+            return;
+        }
+        egui::TopBottomPanel::top("alarms")
+            .frame(
+                egui::Frame::default()
+                    .fill(Color32::DARK_RED)
+                    .inner_margin(4.),
+            )
+            .show(ctx, |ui| {
+                ui.label(
+                    RichText::new(format!(
+                        "ALARM: {}",
+                        self.alarm_monitor.triggered().join(", ")
+                    ))
+                    .strong()
+                    .color(Color32::WHITE),
+                );
+            });
+    }
+
+    fn handle_thread_result(&mut self, res: &ThreadResult) {
+        match res {
+            ThreadResult {
+                id: ThreadId::Camera,
+                result: Err(e),
+            } => self.acquisition_event(AcquisitionEvent::Failed(e.clone())),
+            ThreadResult {
+                id: ThreadId::Camera,
+                result: Ok(()),
+            } => self.acquisition_event(AcquisitionEvent::Started),
+            ThreadResult {
+                id: ThreadId::CameraReconnect,
+                result,
+            } => self.acquisition_event(match result {
+                Ok(()) => AcquisitionEvent::Started,
+                Err(attempt) => AcquisitionEvent::Reconnecting(attempt.clone()),
+            }),
+            ThreadResult {
+                id: ThreadId::Feed,
+                result: Err(_),
+            } => self.feed_active = false,
+            ThreadResult {
+                id: ThreadId::CaptureRequest,
+                result: Ok(()),
+            } => self.capture_single_shot(),
+            ThreadResult {
+                id: ThreadId::DarkFrame,
+                result: Ok(()),
+            } => {
+                self.dark_frame = CorrectionFrameState::Available;
+                self.invalidate_buffer(BufferClearReason::CameraControls);
+            }
+            ThreadResult {
+                id: ThreadId::FrameRecorder,
+                result: Err(_),
+            } => self.frame_recording = false,
+            ThreadResult {
+                id: ThreadId::FlatField,
+                result: Ok(()),
+            } => {
+                self.flat_field = CorrectionFrameState::Available;
+                self.invalidate_buffer(BufferClearReason::CameraControls);
+            }
+            _ => {}
+        }
+    }
+
+    /// Regenerate the tungsten preview once the settings did not change for a moment
+    fn update_tungsten_preview(&mut self, ctx: &Context) {
+        let Some(due) = self.tungsten_preview_due else {
+            return;
+        };
+        let now = Instant::now();
+        if now < due {
+            ctx.request_repaint_after(due - now);
+            return;
         }
+        self.tungsten_preview_due = None;
+        match reference_from_filament_temp(&self.config.tungsten_config) {
+            Ok(preview) => self.tungsten_preview = Some(preview),
+            Err(e) => {
+                self.tungsten_preview = None;
+                self.last_error = Some(ThreadResult {
+                    id: ThreadId::Main,
+                    result: Err(e),
+                });
+            }
+        }
+    }
+
+    /// The preview texture is not updated in low power mode
+    pub fn low_power_mode(&self) -> bool {
+        self.config.low_power_mode
     }
 
     pub fn update(&mut self, ctx: &Context) {
-        if self.running {
+        if self.acquisition.is_active() && self.config.low_power_mode {
+            ctx.request_repaint_after(LOW_POWER_INTERVAL);
+        } else if self.acquisition.is_active() {
             ctx.request_repaint();
         }
+        if let Some(until) = self.smoothing_preview_until {
+            // Remove the smoothing preview in time
+            ctx.request_repaint_after(until.saturating_duration_since(Instant::now()));
+        }
 
-        self.spectrum_container.update(&self.config);
+        self.update_tungsten_preview(ctx);
+        self.update_file_probe(ctx);
+        self.update_gif_export(ctx);
+
+        self.update_feed_status();
+        let new_spectrum = self.receive_spectra();
+        self.update_dark_cycle(new_spectrum);
+        self.update_auto_exposure(new_spectrum);
+        self.update_transmission(new_spectrum);
+        self.update_playback();
+        self.update_tolerance_check(new_spectrum);
+        self.update_alarms(new_spectrum);
+        self.update_calibration_check(new_spectrum);
+        self.update_narrowband(new_spectrum);
+        self.update_sonification();
+        self.check_watchdog();
+        self.update_session();
+        self.update_additional_cameras(ctx);
+        self.update_second_order(new_spectrum);
+        self.update_reference_beam(new_spectrum);
+        self.update_camera_list();
+        self.update_hot_pixels();
 
         if let Ok(error) = self.result_rx.try_recv() {
             self.handle_thread_result(&error);
             self.last_error = Some(error);
         }
 
+        self.update_touch_style(ctx);
+        let frame_size = self.frame_size();
         self.draw_connection_panel(ctx);
-
-        if self.running {
-            self.draw_window_selection_panel(ctx);
-            self.draw_windows(ctx);
+        self.draw_custom_format_window(ctx);
+        self.draw_alarm_banner(ctx);
+        if self.frame_size() != frame_size {
+            self.fit_window_to_frame();
         }
+        self.draw_window_selection_panel(ctx);
+        self.draw_windows(ctx);
 
         self.draw_spectrum(ctx);
         self.draw_last_result(ctx);
@@ -0,0 +1,103 @@
+use crate::colorimetry::colorimetry;
+use crate::config::SpectrumPoint;
+
+/// White LED made of a blue pump and a broad phosphor emission
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct LedPhosphorModel {
+    /// Correlated color temperature the mix of pump and phosphor is adjusted to
+    pub cct: f32,
+    pub pump_wavelength: f32,
+    /// Full width at half maximum of the pump in nm
+    pub pump_width: f32,
+    /// Full width at half maximum of the phosphor in nm
+    pub phosphor_width: f32,
+}
+
+impl Default for LedPhosphorModel {
+    fn default() -> Self {
+        Self {
+            cct: 5000.,
+            pump_wavelength: 450.,
+            pump_width: 20.,
+            phosphor_width: 120.,
+        }
+    }
+}
+
+impl LedPhosphorModel {
+    /// Peak of the phosphor, warm white LEDs use phosphors shifted to red
+    ///
+    /// Interpolated in reciprocal color temperature between 555 nm at 6500 K and 605 nm at 2700 K.
+    pub fn phosphor_wavelength(&self) -> f32 {
+        let mired = 1e6 / self.cct.max(1.);
+        let (cool, warm) = (1e6 / 6500., 1e6 / 2700.);
+        (555. + (mired - cool) / (warm - cool) * 50.).clamp(540., 620.)
+    }
+
+    fn points(&self, pump_ratio: f32) -> Vec<SpectrumPoint> {
+        let gaussian = |wavelength: f32, center: f32, fwhm: f32| {
+            let sigma = fwhm.max(1.) / (2. * (2. * std::f32::consts::LN_2).sqrt());
+            (-(wavelength - center).powi(2) / (2. * sigma.powi(2))).exp()
+        };
+        let phosphor_wavelength = self.phosphor_wavelength();
+        (380..=780)
+            .map(|wavelength| {
+                let wavelength = wavelength as f32;
+                SpectrumPoint {
+                    wavelength,
+                    value: pump_ratio * gaussian(wavelength, self.pump_wavelength, self.pump_width)
+                        + gaussian(wavelength, phosphor_wavelength, self.phosphor_width),
+                }
+            })
+            .collect()
+    }
+}
+
+fn cct(points: &[SpectrumPoint]) -> Option<f32> {
+    colorimetry(points).map(|c| c.cct)
+}
+
+/// Spectrum of the LED model normalized to a maximum of 1
+///
+/// The pump to phosphor ratio is searched so that the CCT matches the model, targets outside of
+/// the reachable range end up at its limits.
+pub fn reference_from_led_model(model: &LedPhosphorModel) -> Vec<SpectrumPoint> {
+    // More blue light raises the CCT, up to a ratio at which the CCT approximation breaks down
+    let (mut low, mut high) = (-6f32, 1.5f32);
+    for _ in 0..40 {
+        let ln_ratio = (low + high) / 2.;
+        match cct(&model.points(ln_ratio.exp())) {
+            Some(cct) if cct < model.cct => low = ln_ratio,
+            _ => high = ln_ratio,
+        }
+    }
+    let mut ref_points = model.points(((low + high) / 2.).exp());
+    let max = ref_points
+        .iter()
+        .map(|rp| rp.value)
+        .reduce(f32::max)
+        .unwrap();
+    ref_points.iter_mut().for_each(|rp| rp.value /= max);
+    ref_points
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rstest::rstest;
+
+    #[rstest]
+    #[case(2700.)]
+    #[case(4000.)]
+    #[case(6500.)]
+    fn led_cct(#[case] target: f32) {
+        let r = reference_from_led_model(&LedPhosphorModel {
+            cct: target,
+            ..Default::default()
+        });
+
+        assert_eq!(r.iter().map(|rp| rp.value).reduce(f32::max), Some(1.));
+        assert_eq!(r.first().unwrap().wavelength, 380.);
+        approx::assert_abs_diff_eq!(cct(&r).unwrap(), target, epsilon = 20.);
+    }
+}
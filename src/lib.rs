@@ -1,15 +1,65 @@
+pub mod acquisition;
+pub mod alarm;
+pub mod animation;
+pub mod auto_exposure;
+pub mod bench;
+pub mod calibration_check;
 pub mod camera;
+pub mod color;
+pub mod colorimetry;
 pub mod config;
+pub mod dark_frame;
+pub mod defect_map;
+pub mod driver_capture;
+pub mod dual_beam;
+pub mod feed;
+pub mod flat_field;
+pub mod frame_recorder;
 pub mod gui;
+pub mod led_phosphor;
+pub mod library;
+pub mod multi_camera;
+pub mod multi_order;
+pub mod number_format;
+pub mod postprocessing_preset;
+pub mod provenance;
+pub mod recorder;
+pub mod report;
+pub mod session;
+pub mod shutter;
+pub mod sinks;
+pub mod sonification;
 pub mod spectrum;
+pub mod tolerance;
+pub mod transmission;
+pub mod trigger;
 pub mod tungsten_halogen;
+pub mod webhook;
 
 use log::{set_max_level, LevelFilter};
+use serde::{Deserialize, Serialize};
 use simple_logger::SimpleLogger;
+use std::time::SystemTime;
 
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum ThreadId {
     Camera,
+    CameraControls,
+    /// Reopening the camera after a stream failure, errors do not stop the stream
+    CameraReconnect,
+    /// Completion of a dark frame capture
+    DarkFrame,
+    /// Completion of a flat field capture, load or save
+    FlatField,
+    /// Writing recorded frames, errors stop the recording
+    FrameRecorder,
+    /// Single shot capture requested by a feed client
+    CaptureRequest,
+    Feed,
+    /// Opening or writing an output sink, failed sinks are closed until reconfigured
+    Sinks,
+    /// Export of a recording as animated GIF
+    Animation,
     Main,
 }
 
@@ -19,6 +69,98 @@ pub struct ThreadResult {
     pub result: Result<(), String>,
 }
 
+/// Exposure settings reported by the camera driver
+///
+/// The controls are read back periodically, so after a change the values may lag behind the
+/// frames for up to a second.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Exposure {
+    /// Exposure time in driver units, 100 µs for V4L2
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exposure_time: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gain: Option<i64>,
+}
+
+impl Exposure {
+    /// Comment lines to prepend to exported files, unknown values are omitted
+    pub fn to_comment_lines(&self) -> Vec<String> {
+        [("Exposure time", self.exposure_time), ("Gain", self.gain)]
+            .into_iter()
+            .filter_map(|(label, value)| value.map(|v| format!("# {label}: {v}")))
+            .collect()
+    }
+}
+
+/// Camera settings and counters of the frame a value originates from
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct FrameMetadata {
+    #[serde(flatten)]
+    pub exposure: Exposure,
+    /// Frames received from the camera since the stream was opened, including dropped frames,
+    /// so gaps show frames that were not processed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub frame_number: Option<u64>,
+    /// Capture time reported by the driver, also used as the start of the frame
+    #[serde(skip)]
+    pub capture_time: Option<SystemTime>,
+}
+
+impl FrameMetadata {
+    /// Comment lines to prepend to exported files, unknown values are omitted
+    pub fn to_comment_lines(&self) -> Vec<String> {
+        let mut lines = self.exposure.to_comment_lines();
+        if let Some(frame_number) = self.frame_number {
+            lines.push(format!("# Frame number: {frame_number}"));
+        }
+        if let Some(capture_time) = self.capture_time {
+            let seconds = capture_time
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs_f64();
+            lines.push(format!("# Driver capture time: {seconds:.6} s"));
+        }
+        lines
+    }
+}
+
+/// A value with the time span of the frame capture it originates from
+#[derive(Debug, PartialEq, Clone)]
+pub struct Timestamped<T> {
+    /// Time before the frame was requested from the source, or the capture time reported by the
+    /// driver if available
+    pub start: SystemTime,
+    /// Time after the frame was received and decoded
+    pub end: SystemTime,
+    /// Number of the frame within the stream, counting every frame passed to the pipeline
+    ///
+    /// Values combined from several frames carry the number of the last one, so gaps show frames
+    /// that were dropped or merged on the way.
+    pub sequence: Option<u64>,
+    /// Only known for camera frames
+    pub frame_metadata: Option<FrameMetadata>,
+    /// Fraction of the saturated window subpixels, set by the camera thread
+    pub saturation: Option<f32>,
+    /// Highest window subpixel value per channel relative to full scale, set by the camera thread
+    pub channel_peaks: Option<[f32; 3]>,
+    pub value: T,
+}
+
+impl<T> Timestamped<T> {
+    /// Replace the value and keep the timestamps
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> Timestamped<U> {
+        Timestamped {
+            start: self.start,
+            end: self.end,
+            sequence: self.sequence,
+            frame_metadata: self.frame_metadata,
+            saturation: self.saturation,
+            channel_peaks: self.channel_peaks,
+            value: f(self.value),
+        }
+    }
+}
+
 pub fn init_logging() {
     SimpleLogger::new().init().unwrap();
     set_max_level(LevelFilter::Info);
@@ -0,0 +1,289 @@
+use crate::colorimetry::ColorCoordinates;
+use crate::config::{SampleMetadata, SpectrumCalibration, SpectrumPoint};
+use crate::provenance::Provenance;
+use crate::spectrum::{Spectrum, SpectrumExportPoint};
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const INDEX_FILE: &str = "index.json";
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LibraryConfig {
+    /// Directory with the index and one CSV file per spectrum
+    pub path: String,
+}
+
+impl Default for LibraryConfig {
+    fn default() -> Self {
+        Self {
+            path: "spectrum-library".to_string(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct LibraryEntry {
+    pub name: String,
+    pub tags: Vec<String>,
+    pub timestamp: SystemTime,
+    pub metadata: SampleMetadata,
+    /// CSV file relative to the library directory
+    pub file: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorCoordinates>,
+}
+
+impl LibraryEntry {
+    /// True if every whitespace separated term is part of the name, a tag or the sample name
+    pub fn matches(&self, query: &str) -> bool {
+        let fields: Vec<String> = [&self.name, &self.metadata.sample_name]
+            .into_iter()
+            .chain(self.tags.iter())
+            .map(|f| f.to_lowercase())
+            .collect();
+        query
+            .to_lowercase()
+            .split_whitespace()
+            .all(|term| fields.iter().any(|f| f.contains(term)))
+    }
+}
+
+/// Saved spectra as flat CSV files with a JSON index
+pub struct Library {
+    dir: PathBuf,
+    entries: Vec<LibraryEntry>,
+}
+
+impl Library {
+    /// Open the library in `dir`, which is created with the first entry
+    pub fn open(dir: &str) -> Result<Self, String> {
+        let dir = PathBuf::from(dir);
+        let index = dir.join(INDEX_FILE);
+        let entries = if index.exists() {
+            let file = File::open(&index).map_err(|e| e.to_string())?;
+            serde_json::from_reader(file).map_err(|e| e.to_string())?
+        } else {
+            Vec::new()
+        };
+        Ok(Self { dir, entries })
+    }
+
+    pub fn entries(&self) -> &[LibraryEntry] {
+        &self.entries
+    }
+
+    pub fn add(
+        &mut self,
+        name: String,
+        tags: Vec<String>,
+        metadata: &SampleMetadata,
+        provenance: &Provenance,
+        color: Option<ColorCoordinates>,
+        points: &[SpectrumExportPoint],
+    ) -> Result<(), String> {
+        std::fs::create_dir_all(&self.dir).map_err(|e| e.to_string())?;
+        let timestamp = SystemTime::now();
+        let millis = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let (file, mut writer) = self.create_file(millis)?;
+        for line in metadata
+            .to_comment_lines()
+            .into_iter()
+            .chain(provenance.to_comment_lines())
+            .chain(color.iter().flat_map(|c| c.to_comment_lines()))
+        {
+            writeln!(writer, "{}", line).map_err(|e| e.to_string())?;
+        }
+        let mut writer = csv::Writer::from_writer(writer);
+        for p in points {
+            writer.serialize(p).map_err(|e| e.to_string())?;
+        }
+        writer.flush().map_err(|e| e.to_string())?;
+
+        self.entries.push(LibraryEntry {
+            name,
+            tags,
+            timestamp,
+            metadata: metadata.clone(),
+            file,
+            color,
+        });
+        self.store_index()
+    }
+
+    /// New CSV file named after the timestamp, with a counter if the name is taken
+    fn create_file(&self, millis: u128) -> Result<(String, File), String> {
+        for i in 0.. {
+            let file = match i {
+                0 => format!("{millis}.csv"),
+                i => format!("{millis}-{i}.csv"),
+            };
+            match OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(self.dir.join(&file))
+            {
+                Ok(writer) => return Ok((file, writer)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        unreachable!()
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<(), String> {
+        let entry = self.entries.remove(index);
+        if let Err(e) = std::fs::remove_file(self.dir.join(&entry.file)) {
+            log::warn!("Could not remove {}: {}", entry.file, e);
+        }
+        self.store_index()
+    }
+
+    pub fn load(&self, entry: &LibraryEntry) -> Result<Vec<SpectrumExportPoint>, String> {
+        csv::ReaderBuilder::new()
+            .comment(Some(b'#'))
+            .from_path(self.dir.join(&entry.file))
+            .and_then(|mut r| r.deserialize().collect())
+            .map_err(|e| e.to_string())
+    }
+
+    fn store_index(&self) -> Result<(), String> {
+        let file = File::create(self.dir.join(INDEX_FILE)).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(file, &self.entries).map_err(|e| e.to_string())
+    }
+}
+
+/// Split a comma separated list of tags
+pub fn parse_tags(tags: &str) -> Vec<String> {
+    tags.split(',')
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+/// Sum channel of library points, e.g. to use it as reference
+pub fn points_to_reference(points: &[SpectrumExportPoint]) -> Vec<SpectrumPoint> {
+    points
+        .iter()
+        .map(|p| SpectrumPoint {
+            wavelength: p.wavelength,
+            value: p.sum,
+        })
+        .collect()
+}
+
+/// Interpolate library points at the wavelengths of the current calibration
+///
+/// Columns outside of the stored wavelength range are zero.
+pub fn points_to_spectrum(
+    points: &[SpectrumExportPoint],
+    calibration: &SpectrumCalibration,
+    ncols: usize,
+) -> Spectrum {
+    let mut sorted = points.to_vec();
+    sorted.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+    let mut spectrum = Spectrum::zeros(ncols);
+    for c in 0..ncols {
+        let wavelength = calibration.get_wavelength_from_index(c);
+        let i = sorted.partition_point(|p| p.wavelength < wavelength);
+        let point = match (i.checked_sub(1).map(|i| &sorted[i]), sorted.get(i)) {
+            (_, Some(p)) if p.wavelength == wavelength => *p,
+            (Some(p1), Some(p2)) => p1.interpolate(p2, wavelength),
+            _ => continue,
+        };
+        spectrum.set_column(
+            c,
+            &nalgebra::Vector4::new(point.r, point.g, point.b, point.sum),
+        );
+    }
+    spectrum
+}
+
+/// UTC date and time as `YYYY-MM-DD HH:MM:SS`
+pub fn format_timestamp(timestamp: SystemTime) -> String {
+    let secs = timestamp
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+
+    // Civil date from days since the epoch
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day % 3600 / 60,
+        secs_of_day % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::colorimetry::Illuminant;
+    use std::time::Duration;
+
+    #[test]
+    fn library_roundtrip() {
+        let dir = std::env::temp_dir().join("spectro_cam_rs_library_test");
+        let _ = std::fs::remove_dir_all(&dir);
+        let dir = dir.to_str().unwrap();
+        let points: Vec<_> = (0..5)
+            .map(|i| SpectrumExportPoint {
+                wavelength: 400. + i as f32,
+                sum: i as f32,
+                ..Default::default()
+            })
+            .collect();
+
+        let mut library = Library::open(dir).unwrap();
+        library
+            .add(
+                "White LED".to_string(),
+                parse_tags("led, phosphor,"),
+                &SampleMetadata::default(),
+                &Provenance::new(&Default::default()),
+                ColorCoordinates::new(&Illuminant::D65.points(), Illuminant::D65),
+                &points,
+            )
+            .unwrap();
+        // Entries saved within the same millisecond get a counter instead of overwriting a file
+        let (file, _) = library.create_file(0).unwrap();
+        assert_eq!(file, "0.csv");
+        let (file, _) = library.create_file(0).unwrap();
+        assert_eq!(file, "0-1.csv");
+
+        let library = Library::open(dir).unwrap();
+        let entry = &library.entries()[0];
+        assert_eq!(entry.tags, vec!["led", "phosphor"]);
+        assert!(entry.matches("white PHOSPHOR"));
+        assert!(!entry.matches("laser"));
+        assert_eq!(entry.color.unwrap().white_point, Illuminant::D65);
+        assert_eq!(library.load(entry).unwrap(), points);
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn timestamp() {
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        assert_eq!(format_timestamp(timestamp), "2023-11-14 22:13:20");
+    }
+}
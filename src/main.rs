@@ -10,10 +10,13 @@ use glium::Display;
 use glium::Surface as _;
 use image::ImageBuffer;
 use image::Rgb;
-use spectro_cam_rs::camera::CameraThread;
+use spectro_cam_rs::bench::{bench_pipeline, parse_resolution};
+use spectro_cam_rs::camera::{CameraThread, CameraWatcher};
 use spectro_cam_rs::config::SpectrometerConfig;
+use spectro_cam_rs::feed::FeedThread;
 use spectro_cam_rs::gui::SpectrometerGui;
 use spectro_cam_rs::init_logging;
+use spectro_cam_rs::sinks::SinkManager;
 use spectro_cam_rs::spectrum::SpectrumCalculator;
 use std::rc::Rc;
 use winit::application::ApplicationHandler;
@@ -60,11 +63,42 @@ fn load_config() -> SpectrometerConfig {
     confy::load("spectro-cam-rs", None).unwrap_or_default()
 }
 
+/// Synthetic frames run through the pipeline by `--bench-pipeline`
+const BENCH_FRAMES: usize = 500;
+
+/// `--bench-pipeline [WIDTHxHEIGHT]` runs synthetic frames through the pipeline and exits
+fn run_pipeline_bench(config: &SpectrometerConfig, resolution: Option<String>) {
+    let (width, height) = match resolution.as_deref().map(parse_resolution) {
+        Some(Ok(resolution)) => resolution,
+        Some(Err(e)) => {
+            eprintln!("{e}");
+            std::process::exit(1);
+        }
+        None => config
+            .camera_format
+            .map(|f| (f.resolution().width(), f.resolution().height()))
+            .unwrap_or((1920, 1080)),
+    };
+    println!("{}", bench_pipeline(width, height, BENCH_FRAMES, config));
+}
+
 fn main() {
     init_logging();
 
     let config = load_config();
 
+    let mut args = std::env::args().skip(1);
+    if let Some(arg) = args.next() {
+        if arg == "--bench-pipeline" {
+            run_pipeline_bench(&config, args.next());
+            return;
+        }
+        eprintln!(
+            "Unknown argument {arg}, usage: spectro-cam-rs [--bench-pipeline [WIDTHxHEIGHT]]"
+        );
+        std::process::exit(1);
+    }
+
     let event_loop = EventLoop::new().unwrap();
     let (window, display) = create_display(&event_loop, config.view_config.window_size);
 
@@ -76,13 +110,56 @@ fn main() {
     let (frame_tx, frame_rx) = flume::unbounded();
     let (window_tx, window_rx) = flume::unbounded();
     let (spectrum_tx, spectrum_rx) = flume::unbounded();
+    let (second_order_window_tx, second_order_window_rx) = flume::unbounded();
+    let (second_order_spectrum_tx, second_order_spectrum_rx) = flume::unbounded();
+    let (reference_beam_window_tx, reference_beam_window_rx) = flume::unbounded();
+    let (reference_beam_spectrum_tx, reference_beam_spectrum_rx) = flume::unbounded();
     let (config_tx, config_rx) = flume::unbounded();
+    let (feed_tx, feed_rx) = flume::unbounded();
+    let (sink_tx, sink_rx) = flume::unbounded();
     let (result_tx, result_rx) = flume::unbounded();
+    let (camera_list_tx, camera_list_rx) = flume::unbounded();
+    let (camera_refresh_tx, camera_refresh_rx) = flume::unbounded();
+    let (hot_pixel_tx, hot_pixel_rx) = flume::unbounded();
 
-    std::thread::spawn(move || CameraThread::new(frame_tx, window_tx, config_rx, result_tx).run());
+    let feed_result_tx = result_tx.clone();
+    let sink_result_tx = result_tx.clone();
+    let sink_feed_tx = feed_tx.clone();
+    std::thread::spawn(move || {
+        CameraThread::new(frame_tx, window_tx, config_rx, result_tx)
+            .with_second_order(second_order_window_tx)
+            .with_reference_beam(reference_beam_window_tx)
+            .with_hot_pixels(hot_pixel_tx)
+            .run()
+    });
     std::thread::spawn(move || SpectrumCalculator::new(window_rx, spectrum_tx).run());
+    std::thread::spawn(move || {
+        SpectrumCalculator::new(second_order_window_rx, second_order_spectrum_tx).run()
+    });
+    std::thread::spawn(move || {
+        SpectrumCalculator::new(reference_beam_window_rx, reference_beam_spectrum_tx).run()
+    });
+    std::thread::spawn(move || FeedThread::new(feed_rx, feed_result_tx).run());
+    std::thread::spawn(move || {
+        SinkManager::new(sink_rx, sink_result_tx)
+            .with_feed(sink_feed_tx)
+            .run()
+    });
+    std::thread::spawn(move || CameraWatcher::new(camera_list_tx, camera_refresh_rx).run());
 
-    let gui = SpectrometerGui::new(texture_id, config_tx, spectrum_rx, config, result_rx);
+    let gui = SpectrometerGui::new(
+        texture_id,
+        config_tx,
+        spectrum_rx,
+        second_order_spectrum_rx,
+        feed_tx,
+        config,
+        result_rx,
+    )
+    .with_camera_watcher(camera_list_rx, camera_refresh_tx)
+    .with_reference_beam(reference_beam_spectrum_rx)
+    .with_hot_pixels(hot_pixel_rx)
+    .with_sinks(sink_tx);
 
     let mut app = App {
         egui_glium,
@@ -114,15 +191,25 @@ impl ApplicationHandler for App {
         _window_id: winit::window::WindowId,
         event: winit::event::WindowEvent,
     ) {
-        if let Ok(frame) = self.frame_rx.try_recv() {
-            let dim = frame.dimensions();
-            let image = RawImage2d::from_raw_rgb(frame.into_raw(), dim);
-            let tex = SrgbTexture2d::new(&self.display, image).unwrap();
-            self.egui_glium.painter.replace_native_texture(
-                self.texture_id,
-                Rc::new(tex),
-                Default::default(),
-            );
+        // Frames are not drawn in low power mode, so only the latest one is of interest
+        let frame = if self.gui.low_power_mode() {
+            self.frame_rx.try_iter().last()
+        } else {
+            self.frame_rx.try_recv().ok()
+        };
+        if let Some(frame) = frame {
+            self.gui.handle_frame(&frame);
+            // Uploading the texture is too expensive for small boards
+            if !self.gui.low_power_mode() {
+                let dim = frame.dimensions();
+                let image = RawImage2d::from_raw_rgb(frame.into_raw(), dim);
+                let tex = SrgbTexture2d::new(&self.display, image).unwrap();
+                self.egui_glium.painter.replace_native_texture(
+                    self.texture_id,
+                    Rc::new(tex),
+                    Default::default(),
+                );
+            }
         };
 
         let mut redraw = || {
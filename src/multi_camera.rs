@@ -0,0 +1,210 @@
+use crate::camera::{CameraEvent, CameraThread};
+use crate::config::{ImageConfig, SpectrometerConfig, SpectrumCalibration, SpectrumPoint};
+use crate::spectrum::{SpectrumCalculator, SpectrumContainer};
+use crate::{ThreadResult, Timestamped};
+use flume::{Receiver, Sender};
+use image::{ImageBuffer, Rgb};
+use nokhwa::utils::{CameraFormat, CameraIndex};
+use serde::{Deserialize, Serialize};
+
+/// Camera streaming at the same time as the main one, e.g. for the reference beam
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AdditionalCameraConfig {
+    pub name: String,
+    pub camera_id: usize,
+    pub camera_format: Option<CameraFormat>,
+    pub image_config: ImageConfig,
+    pub spectrum_calibration: SpectrumCalibration,
+}
+
+impl AdditionalCameraConfig {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            camera_id: 1,
+            camera_format: None,
+            image_config: ImageConfig::default(),
+            spectrum_calibration: SpectrumCalibration::default(),
+        }
+    }
+
+    /// The shared configuration with the window and calibration of this camera
+    pub fn spectrometer_config(&self, config: &SpectrometerConfig) -> SpectrometerConfig {
+        SpectrometerConfig {
+            camera_id: self.camera_id,
+            camera_format: self.camera_format,
+            image_config: self.image_config.clone(),
+            spectrum_calibration: self.spectrum_calibration.clone(),
+            ..config.clone()
+        }
+    }
+}
+
+/// Camera and spectrum threads of an additional camera, they stop once this is dropped
+struct CameraThreads {
+    config_tx: Sender<CameraEvent>,
+    frame_rx: Receiver<ImageBuffer<Rgb<u8>, Vec<u8>>>,
+    result_rx: Receiver<ThreadResult>,
+    spectrum_container: SpectrumContainer,
+}
+
+impl CameraThreads {
+    fn spawn() -> Self {
+        let (frame_tx, frame_rx) = flume::unbounded();
+        let (window_tx, window_rx) = flume::unbounded();
+        let (spectrum_tx, spectrum_rx) = flume::unbounded::<Timestamped<_>>();
+        let (config_tx, config_rx) = flume::unbounded();
+        let (result_tx, result_rx) = flume::unbounded();
+
+        std::thread::spawn(move || {
+            CameraThread::new(frame_tx, window_tx, config_rx, result_tx).run()
+        });
+        std::thread::spawn(move || SpectrumCalculator::new(window_rx, spectrum_tx).run());
+
+        Self {
+            config_tx,
+            frame_rx,
+            result_rx,
+            spectrum_container: SpectrumContainer::new(spectrum_rx),
+        }
+    }
+}
+
+/// Runtime state of an additional camera, the threads are spawned when it is started first
+#[derive(Default)]
+pub struct AdditionalCamera {
+    threads: Option<CameraThreads>,
+    points: Vec<SpectrumPoint>,
+    frame_size: Option<(u32, u32)>,
+    running: bool,
+    error: Option<String>,
+}
+
+impl AdditionalCamera {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    pub fn error(&self) -> Option<&str> {
+        self.error.as_deref()
+    }
+
+    /// Size of the last received frame
+    pub fn frame_size(&self) -> Option<(u32, u32)> {
+        self.frame_size
+    }
+
+    /// Sum spectrum with the calibration of this camera
+    pub fn points(&self) -> &[SpectrumPoint] {
+        &self.points
+    }
+
+    pub fn start(
+        &mut self,
+        id: CameraIndex,
+        camera_config: &AdditionalCameraConfig,
+        driver_timestamps: bool,
+    ) {
+        let Some(format) = camera_config.camera_format else {
+            self.error = Some("Choose a camera format!".to_string());
+            return;
+        };
+        let mut image_config = camera_config.image_config.clone();
+        image_config.clamp(
+            format.resolution().width() as f32,
+            format.resolution().height() as f32,
+        );
+        let threads = self.threads.get_or_insert_with(CameraThreads::spawn);
+        threads.spectrum_container.clear_buffer();
+        threads
+            .config_tx
+            .send(CameraEvent::Config(image_config))
+            .unwrap();
+        threads
+            .config_tx
+            .send(CameraEvent::StartStream {
+                id,
+                format,
+                driver_timestamps,
+                y16: false,
+            })
+            .unwrap();
+        self.points.clear();
+        self.error = None;
+        self.running = true;
+    }
+
+    pub fn stop(&mut self) {
+        if let Some(threads) = &self.threads {
+            threads.config_tx.send(CameraEvent::StopStream).unwrap();
+        }
+        self.running = false;
+    }
+
+    /// Pass the window to a started camera, it is sent again on every start
+    pub fn send_config(&self, image_config: &ImageConfig) {
+        if let Some(threads) = &self.threads {
+            threads
+                .config_tx
+                .send(CameraEvent::Config(image_config.clone()))
+                .unwrap();
+        }
+    }
+
+    /// Receive frames, results and spectra, returns true if a new spectrum was received
+    pub fn update(
+        &mut self,
+        config: &SpectrometerConfig,
+        camera_config: &AdditionalCameraConfig,
+    ) -> bool {
+        let Some(threads) = self.threads.as_mut() else {
+            return false;
+        };
+        // Frames are not shown, only their size is needed to place the window
+        if let Some(frame) = threads.frame_rx.drain().last() {
+            self.frame_size = Some(frame.dimensions());
+        }
+        for result in threads.result_rx.drain() {
+            if let Err(e) = result.result {
+                self.running = false;
+                self.error = Some(e);
+            }
+        }
+        // The merged configuration is only needed to process a spectrum
+        if !self.running || !threads.spectrum_container.has_pending() {
+            return false;
+        }
+        let config = camera_config.spectrometer_config(config);
+        let container = &mut threads.spectrum_container;
+        let updated = container.update(&config);
+        if updated {
+            self.points =
+                SpectrumContainer::spectrum_channel_points(container.spectrum(), 3, &config);
+        }
+        updated
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn camera_spectrometer_config() {
+        let mut camera_config = AdditionalCameraConfig::new("Reference".to_string());
+        camera_config.spectrum_calibration.low.wavelength = 350;
+        let config = SpectrometerConfig::default();
+
+        let merged = camera_config.spectrometer_config(&config);
+        assert_eq!(merged.camera_id, 1);
+        assert_eq!(merged.spectrum_calibration.low.wavelength, 350);
+        assert_eq!(
+            merged.postprocessing_config.spectrum_buffer_size,
+            config.postprocessing_config.spectrum_buffer_size
+        );
+    }
+}
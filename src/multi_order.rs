@@ -0,0 +1,175 @@
+use crate::config::{SpectrometerConfig, SpectrumCalibration, SpectrumPoint};
+use serde::{Deserialize, Serialize};
+
+/// Second diffraction order captured in a second window of the same frame
+///
+/// The window itself is part of the image config, so that it is extracted by the camera thread.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct MultiOrderConfig {
+    /// Calibration with the actual wavelengths of the second order
+    pub spectrum_calibration: SpectrumCalibration,
+    /// Width in nm at the upper end of the overlap in which the orders are cross-faded
+    pub blend_width: f32,
+    /// Scale the second order to the intensity of the first one in the overlap
+    pub match_intensity: bool,
+}
+
+impl Default for MultiOrderConfig {
+    fn default() -> Self {
+        Self {
+            spectrum_calibration: SpectrumCalibration::default(),
+            blend_width: 20.,
+            match_intensity: true,
+        }
+    }
+}
+
+impl MultiOrderConfig {
+    /// The shared configuration with the calibration of the second order
+    pub fn spectrometer_config(&self, config: &SpectrometerConfig) -> SpectrometerConfig {
+        SpectrometerConfig {
+            spectrum_calibration: self.spectrum_calibration.clone(),
+            ..config.clone()
+        }
+    }
+}
+
+fn sorted(points: &[SpectrumPoint]) -> Vec<SpectrumPoint> {
+    let mut points = points.to_vec();
+    points.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+    points
+}
+
+/// Extend the first order spectrum with the second order at shorter wavelengths
+///
+/// Where both orders overlap, the second order with its higher resolution is used up to the
+/// blend region at the upper end of the overlap, in which it fades into the first order.
+pub fn merge_orders(
+    first: &[SpectrumPoint],
+    second: &[SpectrumPoint],
+    config: &MultiOrderConfig,
+) -> Vec<SpectrumPoint> {
+    let first = sorted(first);
+    let second = sorted(second);
+    let (Some(f0), Some(f1), Some(s0), Some(s1)) = (
+        first.first().map(|p| p.wavelength),
+        first.last().map(|p| p.wavelength),
+        second.first().map(|p| p.wavelength),
+        second.last().map(|p| p.wavelength),
+    ) else {
+        return first;
+    };
+    let (overlap_low, overlap_high) = (f0.max(s0), f1.min(s1));
+
+    let in_overlap = first
+        .iter()
+        .filter(|p| (overlap_low..=overlap_high).contains(&p.wavelength));
+    let scale = if config.match_intensity {
+        let (first_sum, second_sum) = in_overlap.fold((0., 0.), |(f, s), p| {
+            let second_value = SpectrumPoint::interpolate(&second, p.wavelength).unwrap_or(0.);
+            (f + p.value, s + second_value)
+        });
+        if first_sum > 0. && second_sum > 0. {
+            first_sum / second_sum
+        } else {
+            1.
+        }
+    } else {
+        1.
+    };
+
+    let (blend_low, blend_high) = if overlap_low <= overlap_high {
+        (
+            (overlap_high - config.blend_width.max(0.)).max(overlap_low),
+            overlap_high,
+        )
+    } else if s1 < f0 {
+        // Without overlap the second order is only prepended
+        (f0, f0)
+    } else {
+        return first;
+    };
+
+    first
+        .iter()
+        .filter(|p| p.wavelength < s0)
+        .copied()
+        .chain(
+            second
+                .iter()
+                .filter(|p| p.wavelength < blend_low)
+                .map(|p| SpectrumPoint {
+                    wavelength: p.wavelength,
+                    value: p.value * scale,
+                }),
+        )
+        .chain(first.iter().filter(|p| p.wavelength >= blend_low).map(|p| {
+            match SpectrumPoint::interpolate(&second, p.wavelength) {
+                Some(second_value) if p.wavelength <= blend_high => {
+                    let weight = if blend_high > blend_low {
+                        (blend_high - p.wavelength) / (blend_high - blend_low)
+                    } else {
+                        0.
+                    };
+                    SpectrumPoint {
+                        wavelength: p.wavelength,
+                        value: weight * second_value * scale + (1. - weight) * p.value,
+                    }
+                }
+                _ => *p,
+            }
+        }))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn points(range: std::ops::Range<u32>, step: f32, value: f32) -> Vec<SpectrumPoint> {
+        range
+            .map(|i| SpectrumPoint {
+                wavelength: i as f32 * step,
+                value,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merge_overlapping_orders() {
+        let config = MultiOrderConfig {
+            blend_width: 20.,
+            ..Default::default()
+        };
+        // First order from 400 to 700 nm, second order from 300 to 500 nm at half the intensity
+        let first = points(40..71, 10., 1.);
+        let second = points(600..1001, 0.5, 0.5);
+
+        let merged = merge_orders(&first, &second, &config);
+
+        assert!(merged.windows(2).all(|w| w[0].wavelength < w[1].wavelength));
+        assert_eq!(merged.first().unwrap().wavelength, 300.);
+        assert_eq!(merged.last().unwrap().wavelength, 700.);
+        // Fine sampling of the second order below the blend region
+        assert_eq!(merged.iter().filter(|p| p.wavelength < 480.).count(), 360);
+        for p in &merged {
+            approx::assert_relative_eq!(p.value, 1.);
+        }
+    }
+
+    #[test]
+    fn merge_without_overlap() {
+        let config = MultiOrderConfig {
+            match_intensity: false,
+            ..Default::default()
+        };
+        let first = points(50..71, 10., 1.);
+        let second = points(30..41, 10., 0.5);
+
+        let merged = merge_orders(&first, &second, &config);
+
+        assert_eq!(merged.len(), 32);
+        assert_eq!(merged[0].value, 0.5);
+        assert_eq!(merged[31].value, 1.);
+    }
+}
@@ -0,0 +1,168 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::io::{Read, Write};
+
+/// Languages that separate decimals with a comma
+const DECIMAL_COMMA_LANGUAGES: &[&str] = &[
+    "bg", "ca", "cs", "da", "de", "el", "es", "et", "eu", "fi", "fr", "gl", "hr", "hu", "id", "is",
+    "it", "lt", "lv", "nb", "nl", "nn", "no", "pl", "pt", "ro", "ru", "sk", "sl", "sr", "sv", "tr",
+    "uk", "vi",
+];
+
+/// A POSIX locale like `de_DE.UTF-8` uses a decimal comma
+pub fn locale_uses_decimal_comma(locale: &str) -> bool {
+    let language = locale
+        .split(['_', '-', '.', '@'])
+        .next()
+        .unwrap_or_default();
+    DECIMAL_COMMA_LANGUAGES.contains(&language.to_ascii_lowercase().as_str())
+}
+
+/// The numeric locale of the environment uses a decimal comma
+pub fn system_uses_decimal_comma() -> bool {
+    ["LC_ALL", "LC_NUMERIC", "LANG"]
+        .into_iter()
+        .filter_map(|var| std::env::var(var).ok())
+        .find(|locale| !locale.is_empty())
+        .is_some_and(|locale| locale_uses_decimal_comma(&locale))
+}
+
+/// Replace decimal points between digits with commas
+pub fn localize_decimals(text: &str, decimal_comma: bool) -> String {
+    if !decimal_comma {
+        return text.to_string();
+    }
+    let chars: Vec<char> = text.chars().collect();
+    chars
+        .iter()
+        .enumerate()
+        .map(|(i, &c)| {
+            let digit_at = |j: Option<usize>| {
+                j.and_then(|j| chars.get(j))
+                    .is_some_and(char::is_ascii_digit)
+            };
+            if c == '.' && digit_at(i.checked_sub(1)) && digit_at(Some(i + 1)) {
+                ','
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+/// CSV fields are only converted if they are numbers
+fn to_decimal_comma(field: &str) -> String {
+    if field.parse::<f64>().is_ok() {
+        field.replace('.', ",")
+    } else {
+        field.to_string()
+    }
+}
+
+fn from_decimal_comma(field: &str) -> String {
+    let converted = field.trim().replace(',', ".");
+    if converted.parse::<f64>().is_ok() {
+        converted
+    } else {
+        field.to_string()
+    }
+}
+
+/// Write the rows with a header, a decimal comma separates columns with semicolons
+pub fn write_csv<W: Write, T: Serialize>(
+    writer: W,
+    rows: impl IntoIterator<Item = T>,
+    decimal_comma: bool,
+) -> Result<(), String> {
+    if !decimal_comma {
+        let mut writer = csv::Writer::from_writer(writer);
+        for row in rows {
+            writer.serialize(row).map_err(|e| e.to_string())?;
+        }
+        return writer.flush().map_err(|e| e.to_string());
+    }
+    let mut buffer = csv::Writer::from_writer(vec![]);
+    for row in rows {
+        buffer.serialize(row).map_err(|e| e.to_string())?;
+    }
+    let buffer = buffer.into_inner().map_err(|e| e.to_string())?;
+    let mut reader = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .from_reader(buffer.as_slice());
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(b';')
+        .from_writer(writer);
+    for record in reader.records() {
+        let record = record.map_err(|e| e.to_string())?;
+        let record: csv::StringRecord = record.iter().map(to_decimal_comma).collect();
+        writer.write_record(&record).map_err(|e| e.to_string())?;
+    }
+    writer.flush().map_err(|e| e.to_string())
+}
+
+/// Read rows with a header, lines starting with `#` are skipped
+pub fn read_csv<R: Read, T: DeserializeOwned>(
+    reader: R,
+    decimal_comma: bool,
+) -> Result<Vec<T>, String> {
+    let mut reader = csv::ReaderBuilder::new()
+        .comment(Some(b'#'))
+        .delimiter(if decimal_comma { b';' } else { b',' })
+        .from_reader(reader);
+    if !decimal_comma {
+        return reader
+            .deserialize()
+            .collect::<Result<_, _>>()
+            .map_err(|e| e.to_string());
+    }
+    let headers = reader.headers().map_err(|e| e.to_string())?.clone();
+    reader
+        .records()
+        .map(|record| {
+            let record: csv::StringRecord = record
+                .map_err(|e| e.to_string())?
+                .iter()
+                .map(from_decimal_comma)
+                .collect();
+            record
+                .deserialize(Some(&headers))
+                .map_err(|e| e.to_string())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::SpectrumPoint;
+
+    #[test]
+    fn decimal_comma() {
+        assert!(locale_uses_decimal_comma("de_DE.UTF-8"));
+        assert!(!locale_uses_decimal_comma("en_US.UTF-8"));
+        assert!(!locale_uses_decimal_comma("C"));
+        assert_eq!(
+            localize_decimals("x = 546.1 nm.\ny = 0.25", true),
+            "x = 546,1 nm.\ny = 0,25"
+        );
+
+        let points = vec![
+            SpectrumPoint {
+                wavelength: 546.5,
+                value: 1.,
+            },
+            SpectrumPoint {
+                wavelength: 600.,
+                value: 0.25,
+            },
+        ];
+        let mut buffer = vec![];
+        write_csv(&mut buffer, &points, true).unwrap();
+        assert_eq!(
+            String::from_utf8(buffer.clone()).unwrap(),
+            "wavelength;value\n546,5;1,0\n600,0;0,25\n"
+        );
+        let read: Vec<SpectrumPoint> = read_csv(buffer.as_slice(), true).unwrap();
+        assert_eq!(read, points);
+    }
+}
@@ -0,0 +1,103 @@
+use crate::config::{Linearize, PostprocessingConfig, SpectrometerConfig};
+use crate::trigger::FlashTriggerConfig;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+
+/// Version written to new preset files, files of newer versions are rejected
+pub const PRESET_VERSION: u32 = 1;
+
+/// Postprocessing chain settings that can be applied on another machine
+///
+/// The wavelength calibration and the scaling belong to a spectrometer and are left out.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct PostprocessingPreset {
+    pub version: u32,
+    pub postprocessing_config: PostprocessingConfig,
+    pub linearize: Linearize,
+    pub gain_r: f32,
+    pub gain_g: f32,
+    pub gain_b: f32,
+    pub flash_trigger_config: FlashTriggerConfig,
+}
+
+impl PostprocessingPreset {
+    pub fn new(config: &SpectrometerConfig) -> Self {
+        let calibration = &config.spectrum_calibration;
+        Self {
+            version: PRESET_VERSION,
+            postprocessing_config: config.postprocessing_config.clone(),
+            linearize: calibration.linearize,
+            gain_r: calibration.gain_r,
+            gain_g: calibration.gain_g,
+            gain_b: calibration.gain_b,
+            flash_trigger_config: config.flash_trigger_config.clone(),
+        }
+    }
+
+    pub fn apply(self, config: &mut SpectrometerConfig) {
+        let calibration = &mut config.spectrum_calibration;
+        config.postprocessing_config = self.postprocessing_config;
+        calibration.linearize = self.linearize;
+        calibration.gain_r = self.gain_r;
+        calibration.gain_g = self.gain_g;
+        calibration.gain_b = self.gain_b;
+        config.flash_trigger_config = self.flash_trigger_config;
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        serde_json::to_writer_pretty(file, self).map_err(|e| e.to_string())
+    }
+
+    pub fn read(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let preset: Self = serde_json::from_reader(file).map_err(|e| e.to_string())?;
+        if preset.version > PRESET_VERSION {
+            return Err(format!(
+                "The preset has version {}, only up to {} is supported",
+                preset.version, PRESET_VERSION
+            ));
+        }
+        Ok(preset)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BaselineCorrection, ProcessingOrder};
+
+    #[test]
+    fn preset_round_trip() {
+        let mut config = SpectrometerConfig::default();
+        config.postprocessing_config.spectrum_buffer_size = 42;
+        config.postprocessing_config.processing_order = ProcessingOrder::AverageThenLinearize;
+        config.postprocessing_config.baseline_correction = BaselineCorrection::RollingBall;
+        config.spectrum_calibration.linearize = Linearize::SRgb;
+        config.spectrum_calibration.gain_g = 0.5;
+        config.spectrum_calibration.low.wavelength = 300;
+
+        let path = std::env::temp_dir().join("spectro_cam_rs_preset_test.json");
+        let path = path.to_str().unwrap();
+        PostprocessingPreset::new(&config).write(path).unwrap();
+        let preset = PostprocessingPreset::read(path).unwrap();
+
+        let mut other = SpectrometerConfig::default();
+        preset.clone().apply(&mut other);
+        assert_eq!(other.postprocessing_config, config.postprocessing_config);
+        assert_eq!(other.spectrum_calibration.linearize, Linearize::SRgb);
+        assert_eq!(other.spectrum_calibration.gain_g, 0.5);
+        // The calibration stays with the spectrometer
+        assert_ne!(other.spectrum_calibration.low.wavelength, 300);
+
+        PostprocessingPreset {
+            version: PRESET_VERSION + 1,
+            ..preset
+        }
+        .write(path)
+        .unwrap();
+        let result = PostprocessingPreset::read(path);
+        std::fs::remove_file(path).unwrap();
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,68 @@
+use crate::config::SpectrometerConfig;
+use serde::{Deserialize, Serialize};
+
+/// Identifies the software and settings a measurement was processed with
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct Provenance {
+    pub version: String,
+    pub git_hash: String,
+    /// Hash of the image and postprocessing settings
+    pub config_hash: String,
+    /// Hash of the wavelength calibration
+    pub calibration_hash: String,
+}
+
+impl Provenance {
+    pub fn new(config: &SpectrometerConfig) -> Self {
+        Self {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            git_hash: env!("GIT_HASH").to_string(),
+            config_hash: hash(&(
+                &config.image_config,
+                &config.postprocessing_config,
+                &config.transmission_config,
+            )),
+            calibration_hash: hash(&config.spectrum_calibration),
+        }
+    }
+
+    /// Comment lines to prepend to exported files
+    pub fn to_comment_lines(&self) -> Vec<String> {
+        vec![
+            format!("# Version: {}", self.version),
+            format!("# Git hash: {}", self.git_hash),
+            format!("# Config hash: {}", self.config_hash),
+            format!("# Calibration hash: {}", self.calibration_hash),
+        ]
+    }
+}
+
+/// FNV-1a of the JSON representation, stable across builds and platforms
+fn hash<T: Serialize>(value: &T) -> String {
+    let bytes = serde_json::to_vec(value).unwrap_or_default();
+    let hash = bytes.iter().fold(0xcbf29ce484222325u64, |hash, b| {
+        (hash ^ *b as u64).wrapping_mul(0x100000001b3)
+    });
+    format!("{hash:016x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn provenance_hashes() {
+        let mut config = SpectrometerConfig::default();
+        let provenance = Provenance::new(&config);
+        assert_eq!(provenance.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(provenance.config_hash.len(), 16);
+
+        config.view_config.show_library_window = !config.view_config.show_library_window;
+        assert_eq!(Provenance::new(&config), provenance);
+
+        config.spectrum_calibration.low.wavelength += 1;
+        let changed = Provenance::new(&config);
+        assert_eq!(changed.config_hash, provenance.config_hash);
+        assert_ne!(changed.calibration_hash, provenance.calibration_hash);
+    }
+}
@@ -0,0 +1,253 @@
+use crate::config::SampleMetadata;
+use crate::provenance::Provenance;
+use crate::spectrum::Spectrum;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct RecordedSpectrum {
+    pub timestamp: SystemTime,
+    /// Sequence number of the newest frame of the spectrum, see [`crate::Timestamped`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sequence: Option<u64>,
+    pub r: Vec<f32>,
+    pub g: Vec<f32>,
+    pub b: Vec<f32>,
+    pub sum: Vec<f32>,
+}
+
+impl RecordedSpectrum {
+    pub fn from_spectrum(spectrum: &Spectrum, timestamp: SystemTime) -> Self {
+        let row = |i| spectrum.row(i).iter().cloned().collect();
+        Self {
+            timestamp,
+            sequence: None,
+            r: row(0),
+            g: row(1),
+            b: row(2),
+            sum: row(3),
+        }
+    }
+
+    pub fn to_spectrum(&self) -> Spectrum {
+        let rows = [&self.r, &self.g, &self.b, &self.sum];
+        Spectrum::from_fn(self.sum.len(), |r, c| {
+            rows[r].get(c).cloned().unwrap_or_default()
+        })
+    }
+}
+
+/// One line of a recording file
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum RecordEntry {
+    Header {
+        metadata: SampleMetadata,
+        /// Missing in recordings made before it was added
+        #[serde(default)]
+        provenance: Option<Provenance>,
+    },
+    Spectrum(RecordedSpectrum),
+}
+
+/// Writes spectra as newline delimited JSON.
+///
+/// The file is synced to disk periodically, so after a power loss it can be loaded up to the
+/// last complete line.
+pub struct SpectrumRecorder {
+    writer: BufWriter<File>,
+    count: usize,
+    sync_interval: Duration,
+    last_sync: Instant,
+}
+
+impl SpectrumRecorder {
+    pub fn create(
+        path: &str,
+        metadata: &SampleMetadata,
+        provenance: &Provenance,
+        sync_interval: Duration,
+    ) -> Result<Self, String> {
+        let file = File::create(path).map_err(|e| e.to_string())?;
+        let mut recorder = Self {
+            writer: BufWriter::new(file),
+            count: 0,
+            sync_interval,
+            last_sync: Instant::now(),
+        };
+        recorder.write_entry(&RecordEntry::Header {
+            metadata: metadata.clone(),
+            provenance: Some(provenance.clone()),
+        })?;
+        recorder.sync()?;
+        Ok(recorder)
+    }
+
+    pub fn record(
+        &mut self,
+        spectrum: &Spectrum,
+        timestamp: SystemTime,
+        sequence: Option<u64>,
+    ) -> Result<(), String> {
+        self.write_entry(&RecordEntry::Spectrum(RecordedSpectrum {
+            sequence,
+            ..RecordedSpectrum::from_spectrum(spectrum, timestamp)
+        }))?;
+        self.count += 1;
+        if self.last_sync.elapsed() >= self.sync_interval {
+            self.sync()?;
+        }
+        Ok(())
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn finish(mut self) -> Result<(), String> {
+        self.sync()
+    }
+
+    /// Flush the buffer and wait until the data is on disk
+    fn sync(&mut self) -> Result<(), String> {
+        self.writer.flush().map_err(|e| e.to_string())?;
+        self.writer
+            .get_ref()
+            .sync_data()
+            .map_err(|e| e.to_string())?;
+        self.last_sync = Instant::now();
+        Ok(())
+    }
+
+    fn write_entry(&mut self, entry: &RecordEntry) -> Result<(), String> {
+        serde_json::to_writer(&mut self.writer, entry).map_err(|e| e.to_string())?;
+        writeln!(self.writer).map_err(|e| e.to_string())
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub metadata: SampleMetadata,
+    pub provenance: Option<Provenance>,
+    pub spectra: Vec<RecordedSpectrum>,
+    /// The last line was cut off, e.g. by a power loss while recording, and skipped
+    pub truncated: bool,
+}
+
+impl Recording {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let file = File::open(path).map_err(|e| e.to_string())?;
+        let mut recording = Self::default();
+        let mut lines = BufReader::new(file).lines().peekable();
+        while let Some(line) = lines.next() {
+            let line = line.map_err(|e| e.to_string())?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = match serde_json::from_str(&line) {
+                Ok(entry) => entry,
+                Err(e) if e.is_eof() && lines.peek().is_none() => {
+                    recording.truncated = true;
+                    break;
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            match entry {
+                RecordEntry::Header {
+                    metadata,
+                    provenance,
+                } => {
+                    recording.metadata = metadata;
+                    recording.provenance = provenance;
+                }
+                RecordEntry::Spectrum(spectrum) => recording.spectra.push(spectrum),
+            }
+        }
+        Ok(recording)
+    }
+
+    /// Seconds since the first recorded spectrum
+    pub fn relative_time(&self, index: usize) -> f32 {
+        match (self.spectra.first(), self.spectra.get(index)) {
+            (Some(first), Some(spectrum)) => spectrum
+                .timestamp
+                .duration_since(first.timestamp)
+                .unwrap_or_default()
+                .as_secs_f32(),
+            _ => 0.,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn load_header_without_provenance() {
+        let entry: RecordEntry = serde_json::from_str(
+            r#"{"type":"header","metadata":{"sample_name":"","operator":"","notes":""}}"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            entry,
+            RecordEntry::Header {
+                provenance: None,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn record_and_load() {
+        let path = std::env::temp_dir().join("spectro_cam_rs_recorder_test.ndjson");
+        let path = path.to_str().unwrap();
+        let metadata = SampleMetadata {
+            sample_name: "Sample".to_string(),
+            ..Default::default()
+        };
+        let start = SystemTime::UNIX_EPOCH + Duration::from_secs(1000);
+
+        let provenance = Provenance::new(&Default::default());
+
+        let mut recorder =
+            SpectrumRecorder::create(path, &metadata, &provenance, Duration::ZERO).unwrap();
+        recorder
+            .record(&Spectrum::from_element(10, 0.5), start, Some(3))
+            .unwrap();
+        recorder
+            .record(
+                &Spectrum::from_element(10, 0.25),
+                start + Duration::from_millis(1500),
+                None,
+            )
+            .unwrap();
+        assert_eq!(recorder.count(), 2);
+        recorder.finish().unwrap();
+
+        let recording = Recording::load(path).unwrap();
+
+        // Power loss in the middle of a line
+        let mut contents = std::fs::read_to_string(path).unwrap();
+        contents.truncate(contents.len() - 20);
+        std::fs::write(path, contents).unwrap();
+        let truncated = Recording::load(path).unwrap();
+        std::fs::remove_file(path).ok();
+        assert!(truncated.truncated);
+        assert_eq!(truncated.spectra, recording.spectra[..1]);
+
+        assert!(!recording.truncated);
+        assert_eq!(recording.metadata, metadata);
+        assert_eq!(recording.provenance, Some(provenance));
+        assert_eq!(recording.spectra.len(), 2);
+        assert_eq!(recording.spectra[0].sequence, Some(3));
+        assert_eq!(
+            recording.spectra[1].to_spectrum(),
+            Spectrum::from_element(10, 0.25)
+        );
+        assert_eq!(recording.relative_time(1), 1.5);
+    }
+}
@@ -0,0 +1,287 @@
+use crate::colorimetry::{color_rendering, colorimetry, spectral_locus};
+use crate::config::{SampleMetadata, SpectrumPoint};
+use crate::library::format_timestamp;
+use crate::provenance::Provenance;
+use crate::spectrum::{FittedPeak, PeakShape};
+use std::fmt::Write;
+use std::time::SystemTime;
+
+const PLOT_WIDTH: f32 = 600.;
+const PLOT_HEIGHT: f32 = 300.;
+const DIAGRAM_SIZE: f32 = 300.;
+
+/// Everything shown in a measurement report
+pub struct ReportData<'a> {
+    pub timestamp: SystemTime,
+    pub metadata: &'a SampleMetadata,
+    pub provenance: &'a Provenance,
+    /// Sum spectrum
+    pub spectrum: &'a [SpectrumPoint],
+    pub peaks: &'a [SpectrumPoint],
+    /// Replace the peaks if a line shape was fitted
+    pub fitted_peaks: &'a [FittedPeak],
+    pub dominant_peak: Option<PeakShape>,
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn spectrum_svg(spectrum: &[SpectrumPoint]) -> String {
+    let mut svg = format!(
+        r#"<svg width="{PLOT_WIDTH}" height="{PLOT_HEIGHT}" viewBox="-40 -10 {} {}">"#,
+        PLOT_WIDTH + 50.,
+        PLOT_HEIGHT + 40.
+    );
+    let (Some(first), Some(last)) = (spectrum.first(), spectrum.last()) else {
+        return svg + "</svg>";
+    };
+    let (low, high) = (first.wavelength, last.wavelength.max(first.wavelength + 1.));
+    let max = spectrum
+        .iter()
+        .map(|p| p.value)
+        .fold(f32::MIN_POSITIVE, f32::max);
+    let px = |w: f32| (w - low) / (high - low) * PLOT_WIDTH;
+    let py = |v: f32| PLOT_HEIGHT - v.max(0.) / max * PLOT_HEIGHT;
+
+    let _ = write!(
+        svg,
+        r##"<rect width="{PLOT_WIDTH}" height="{PLOT_HEIGHT}" fill="none" stroke="#888"/>"##
+    );
+    let mut tick = (low / 100.).ceil() * 100.;
+    while tick <= high {
+        let _ = write!(
+            svg,
+            r##"<line x1="{x}" y1="0" x2="{x}" y2="{PLOT_HEIGHT}" stroke="#ddd"/><text x="{x}" y="{}" font-size="12" text-anchor="middle">{tick} nm</text>"##,
+            PLOT_HEIGHT + 18.,
+            x = px(tick)
+        );
+        tick += 100.;
+    }
+    let _ = write!(
+        svg,
+        r##"<text x="-5" y="12" font-size="12" text-anchor="end">{max:.3}</text><text x="-5" y="{PLOT_HEIGHT}" font-size="12" text-anchor="end">0</text>"##
+    );
+    let points: Vec<String> = spectrum
+        .iter()
+        .map(|p| format!("{:.1},{:.1}", px(p.wavelength), py(p.value)))
+        .collect();
+    let _ = write!(
+        svg,
+        r##"<polyline points="{}" fill="none" stroke="#333" stroke-width="1.5"/></svg>"##,
+        points.join(" ")
+    );
+    svg
+}
+
+fn chromaticity_svg(xy: Option<(f32, f32)>) -> String {
+    // x from 0 to 0.8 and y from 0 to 0.9
+    let px = |x: f32| x / 0.8 * DIAGRAM_SIZE;
+    let py = |y: f32| DIAGRAM_SIZE - y / 0.9 * DIAGRAM_SIZE;
+
+    let mut svg = format!(
+        r#"<svg width="{DIAGRAM_SIZE}" height="{DIAGRAM_SIZE}" viewBox="-10 -10 {} {}">"#,
+        DIAGRAM_SIZE + 20.,
+        DIAGRAM_SIZE + 20.
+    );
+    let _ = write!(
+        svg,
+        r##"<rect width="{DIAGRAM_SIZE}" height="{DIAGRAM_SIZE}" fill="none" stroke="#888"/>"##
+    );
+    let locus: Vec<String> = spectral_locus()
+        .map(|(_, x, y)| format!("{:.1},{:.1}", px(x), py(y)))
+        .collect();
+    let _ = write!(
+        svg,
+        r##"<polygon points="{}" fill="#f4f4f4" stroke="#333"/>"##,
+        locus.join(" ")
+    );
+    for (wavelength, x, y) in spectral_locus()
+        .filter(|(w, _, _)| [460., 480., 500., 520., 540., 560., 580., 600., 620.].contains(w))
+    {
+        let _ = write!(
+            svg,
+            r##"<text x="{:.1}" y="{:.1}" font-size="10">{wavelength}</text>"##,
+            px(x) + 4.,
+            py(y)
+        );
+    }
+    if let Some((x, y)) = xy {
+        let _ = write!(
+            svg,
+            r##"<circle cx="{:.1}" cy="{:.1}" r="4" fill="red"/>"##,
+            px(x),
+            py(y)
+        );
+    }
+    svg + "</svg>"
+}
+
+/// Standalone HTML page with plots, colorimetry, peaks and metadata
+pub fn html_report(data: &ReportData) -> String {
+    let colorimetry = colorimetry(data.spectrum);
+    let title = if data.metadata.sample_name.is_empty() {
+        "Spectrum Report".to_string()
+    } else {
+        escape_html(&data.metadata.sample_name)
+    };
+
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        r#"<!DOCTYPE html>
+<html><head><meta charset="utf-8"><title>{title}</title>
+<style>body{{font-family:sans-serif;max-width:960px;margin:2em auto}}table{{border-collapse:collapse}}td,th{{border:1px solid #ccc;padding:2px 8px;text-align:right}}.row{{display:flex;gap:2em;align-items:flex-start}}</style>
+</head><body>
+<h1>{title}</h1>
+<p>{} UTC</p>
+{}
+<div class="row">
+"#,
+        format_timestamp(data.timestamp),
+        spectrum_svg(data.spectrum),
+    );
+
+    html += "<div><h2>Colorimetry (CIE 1931 2°)</h2><table>";
+    match colorimetry {
+        Some(c) => {
+            let _ = write!(
+                html,
+                "<tr><th>x</th><td>{:.4}</td></tr><tr><th>y</th><td>{:.4}</td></tr>\
+                 <tr><th>CCT</th><td>{:.0} K</td></tr><tr><th>Duv</th><td>{:.4}</td></tr>",
+                c.x, c.y, c.cct, c.duv
+            );
+        }
+        None => html += "<tr><td>No signal</td></tr>",
+    }
+    if let Some(cri) = color_rendering(data.spectrum) {
+        let note = if cri.is_valid() {
+            ""
+        } else {
+            " (too far from the Planckian locus)"
+        };
+        let special: Vec<String> = cri.special.iter().map(|r| format!("{r:.0}")).collect();
+        let _ = write!(
+            html,
+            "<tr><th>CRI Ra</th><td>{:.1}{note}</td></tr><tr><th>R1–R8</th><td>{}</td></tr>",
+            cri.ra,
+            special.join(" ")
+        );
+    }
+    if let Some(peak) = data.dominant_peak {
+        let _ = write!(
+            html,
+            "<tr><th>Dominant peak</th><td>{:.1} nm</td></tr><tr><th>FWHM</th><td>{:.1} nm</td></tr>",
+            peak.centroid, peak.fwhm
+        );
+    }
+    html += "</table>";
+
+    if data.fitted_peaks.is_empty() {
+        html += "<h2>Peaks</h2><table><tr><th>Wavelength</th><th>Value</th></tr>";
+        for peak in data.peaks {
+            let _ = write!(
+                html,
+                "<tr><td>{:.1} nm</td><td>{:.4}</td></tr>",
+                peak.wavelength, peak.value
+            );
+        }
+    } else {
+        html += "<h2>Peaks</h2><table><tr><th>Wavelength</th><th>Height</th><th>FWHM</th>\
+                 <th>Area</th></tr>";
+        for peak in data.fitted_peaks {
+            let _ = write!(
+                html,
+                "<tr><td>{:.2} nm</td><td>{:.4}</td><td>{:.2} nm</td><td>{:.4}</td></tr>",
+                peak.center, peak.height, peak.fwhm, peak.area
+            );
+        }
+    }
+    html += "</table></div>";
+
+    let _ = write!(
+        html,
+        "<div><h2>Chromaticity</h2>{}</div></div>",
+        chromaticity_svg(colorimetry.map(|c| (c.x, c.y)))
+    );
+
+    html += "<h2>Metadata</h2><table>";
+    for (label, value) in [
+        ("Sample", &data.metadata.sample_name),
+        ("Operator", &data.metadata.operator),
+        ("Notes", &data.metadata.notes),
+    ] {
+        if !value.is_empty() {
+            let _ = write!(
+                html,
+                "<tr><th>{label}</th><td style=\"text-align:left;white-space:pre-wrap\">{}</td></tr>",
+                escape_html(value)
+            );
+        }
+    }
+    for (label, value) in [
+        ("Version", &data.provenance.version),
+        ("Git hash", &data.provenance.git_hash),
+        ("Config hash", &data.provenance.config_hash),
+        ("Calibration hash", &data.provenance.calibration_hash),
+    ] {
+        let _ = write!(
+            html,
+            "<tr><th>{label}</th><td style=\"text-align:left\">{}</td></tr>",
+            escape_html(value)
+        );
+    }
+    html += "</table></body></html>\n";
+    html
+}
+
+pub fn write_html_report(path: &str, data: &ReportData) -> Result<(), String> {
+    std::fs::write(path, html_report(data)).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_contents() {
+        let metadata = SampleMetadata {
+            sample_name: "LED <1>".to_string(),
+            ..Default::default()
+        };
+        let spectrum: Vec<_> = (380..=780)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: 1.,
+            })
+            .collect();
+        let peaks = [SpectrumPoint {
+            wavelength: 450.,
+            value: 1.,
+        }];
+
+        let html = html_report(&ReportData {
+            timestamp: SystemTime::UNIX_EPOCH,
+            metadata: &metadata,
+            provenance: &Provenance::new(&Default::default()),
+            spectrum: &spectrum,
+            peaks: &peaks,
+            fitted_peaks: &[],
+            dominant_peak: None,
+        });
+
+        assert!(html.contains("<title>LED &lt;1&gt;</title>"));
+        assert!(html.contains("1970-01-01 00:00:00 UTC"));
+        assert!(html.contains("<td>0.3334</td>"));
+        assert!(html.contains("<td>450.0 nm</td>"));
+        assert!(html.contains("<th>CRI Ra</th><td>95.3 (too far from the Planckian locus)</td>"));
+        assert!(html.contains(&format!(
+            "<td style=\"text-align:left\">{}</td>",
+            env!("CARGO_PKG_VERSION")
+        )));
+        assert_eq!(html.matches("<svg").count(), 2);
+    }
+}
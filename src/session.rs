@@ -0,0 +1,145 @@
+use crate::recorder::RecordedSpectrum;
+use crate::spectrum::{Spectrum, SpectrumRgb};
+use serde::{Deserialize, Serialize};
+use std::time::SystemTime;
+
+/// Spectrum held for comparison with the live spectrum
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct Snapshot {
+    pub name: String,
+    pub spectrum: RecordedSpectrum,
+    /// Added to the values after scaling, used to stack snapshots in the plot
+    #[serde(default)]
+    pub offset: f32,
+    #[serde(default = "default_scale")]
+    pub scale: f32,
+}
+
+fn default_scale() -> f32 {
+    1.
+}
+
+impl Snapshot {
+    pub fn new(name: String, spectrum: RecordedSpectrum) -> Self {
+        Self {
+            name,
+            spectrum,
+            offset: 0.,
+            scale: 1.,
+        }
+    }
+
+    pub fn transform(&self, value: f32) -> f32 {
+        value * self.scale + self.offset
+    }
+
+    /// Offset the snapshots so that each one starts above the maximum of the previous one
+    ///
+    /// `gap` is added between neighbouring snapshots.
+    pub fn stack(snapshots: &mut [Snapshot], gap: f32) {
+        let mut offset = 0.;
+        for snapshot in snapshots {
+            snapshot.offset = offset;
+            let max = snapshot
+                .spectrum
+                .sum
+                .iter()
+                .map(|&v| v * snapshot.scale)
+                .fold(0f32, f32::max);
+            offset += max + gap;
+        }
+    }
+}
+
+/// Measurement state that is restored on the next start if enabled
+///
+/// Stored next to the config so long-running measurements survive a crash or reboot.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Default)]
+pub struct Session {
+    pub zero_reference: Option<RecordedSpectrum>,
+    /// Per channel zero reference, the sum is not used
+    pub raw_zero_reference: Option<RecordedSpectrum>,
+    pub snapshots: Vec<Snapshot>,
+}
+
+impl Session {
+    pub fn new(
+        zero_reference: Option<&Spectrum>,
+        raw_zero_reference: Option<&SpectrumRgb>,
+        snapshots: &[Snapshot],
+    ) -> Self {
+        let now = SystemTime::now();
+        Self {
+            zero_reference: zero_reference.map(|s| RecordedSpectrum::from_spectrum(s, now)),
+            raw_zero_reference: raw_zero_reference.map(|s| {
+                let spectrum =
+                    Spectrum::from_fn(s.ncols(), |r, c| if r < 3 { s[(r, c)] } else { 0. });
+                RecordedSpectrum::from_spectrum(&spectrum, now)
+            }),
+            snapshots: snapshots.to_vec(),
+        }
+    }
+
+    pub fn load() -> Result<Self, String> {
+        confy::load("spectro-cam-rs", "session").map_err(|e| e.to_string())
+    }
+
+    pub fn store(&self) -> Result<(), String> {
+        confy::store("spectro-cam-rs", "session", self).map_err(|e| e.to_string())
+    }
+
+    pub fn zero_reference(&self) -> Option<Spectrum> {
+        self.zero_reference
+            .as_ref()
+            .map(RecordedSpectrum::to_spectrum)
+    }
+
+    pub fn raw_zero_reference(&self) -> Option<SpectrumRgb> {
+        self.raw_zero_reference
+            .as_ref()
+            .map(|s| s.to_spectrum().fixed_rows::<3>(0).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_references() {
+        let zero_reference = Spectrum::from_fn(5, |r, c| (r * c) as f32);
+        let raw_zero_reference = SpectrumRgb::from_fn(5, |r, c| (r + c) as f32);
+
+        let session = Session::new(Some(&zero_reference), Some(&raw_zero_reference), &[]);
+
+        assert_eq!(session.zero_reference(), Some(zero_reference));
+        assert_eq!(session.raw_zero_reference(), Some(raw_zero_reference));
+        assert_eq!(Session::default().raw_zero_reference(), None);
+    }
+
+    #[test]
+    fn stack_snapshots() {
+        let snapshot = |values: &[f32]| {
+            let spectrum = Spectrum::from_fn(values.len(), |_, c| values[c]);
+            Snapshot::new(
+                String::new(),
+                RecordedSpectrum::from_spectrum(&spectrum, SystemTime::UNIX_EPOCH),
+            )
+        };
+        let mut snapshots = vec![snapshot(&[0.5, 1.]), snapshot(&[2., 0.]), snapshot(&[1.])];
+        snapshots[1].scale = 0.5;
+
+        Snapshot::stack(&mut snapshots, 0.1);
+
+        let offsets: Vec<_> = snapshots.iter().map(|s| s.offset).collect();
+        approx::assert_abs_diff_eq!(offsets.as_slice(), [0., 1.1, 2.2].as_slice());
+        approx::assert_abs_diff_eq!(snapshots[1].transform(2.), 2.1);
+
+        // Older sessions without offset and scale
+        let mut value = serde_json::to_value(&snapshots[0]).unwrap();
+        value.as_object_mut().unwrap().remove("offset");
+        value.as_object_mut().unwrap().remove("scale");
+        let snapshot: Snapshot = serde_json::from_value(value).unwrap();
+        assert_eq!((snapshot.offset, snapshot.scale), (0., 1.));
+    }
+}
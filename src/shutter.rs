@@ -0,0 +1,168 @@
+use serde::{Deserialize, Serialize};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ShutterConfig {
+    /// Shell command closing the shutter, e.g. writing to a serial port or GPIO
+    pub close_command: String,
+    /// Shell command opening the shutter
+    pub open_command: String,
+    /// Seconds between dark measurements
+    pub interval: f32,
+    /// Seconds to wait after moving the shutter before frames are used
+    pub settle_time: f32,
+}
+
+impl Default for ShutterConfig {
+    fn default() -> Self {
+        Self {
+            close_command: "echo 1 > /sys/class/gpio/gpio17/value".to_string(),
+            open_command: "echo 0 > /sys/class/gpio/gpio17/value".to_string(),
+            interval: 300.,
+            settle_time: 1.,
+        }
+    }
+}
+
+/// Run a shutter command without waiting for it to finish
+pub fn run_shutter_command(command: &str) -> Result<(), String> {
+    Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .spawn()
+        .map(|mut child| {
+            // Reap the process in the background
+            std::thread::spawn(move || child.wait());
+        })
+        .map_err(|e| format!("Could not run shutter command: {}", e))
+}
+
+/// What the caller has to do when the dark cycle advances
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum DarkCycleAction {
+    /// Run the close command
+    Close,
+    /// Drop the zero reference and start capturing a new one from the next spectra
+    CaptureDark,
+    /// Finish the zero reference and run the open command
+    Open,
+    /// Drop spectra seen while the shutter was moving and continue measuring
+    Resume,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum DarkCycleState {
+    Measuring { next: Instant },
+    Closing { until: Instant },
+    Capturing { remaining: usize },
+    Opening { until: Instant },
+}
+
+/// Periodically closes the shutter and re-measures the zero reference
+#[derive(Debug, PartialEq, Clone)]
+pub struct DarkCycle {
+    state: DarkCycleState,
+}
+
+impl DarkCycle {
+    /// Start with a dark measurement
+    pub fn new(now: Instant) -> Self {
+        Self {
+            state: DarkCycleState::Measuring { next: now },
+        }
+    }
+
+    /// True while the spectra do not show the sample
+    pub fn is_dark(&self) -> bool {
+        !matches!(self.state, DarkCycleState::Measuring { .. })
+    }
+
+    /// Advance the cycle, `dark_spectra` is the number of spectra averaged into the reference
+    pub fn update(
+        &mut self,
+        now: Instant,
+        new_spectrum: bool,
+        dark_spectra: usize,
+        config: &ShutterConfig,
+    ) -> Option<DarkCycleAction> {
+        let settle_time = Duration::from_secs_f32(config.settle_time.max(0.));
+        match self.state {
+            DarkCycleState::Measuring { next } if now >= next => {
+                self.state = DarkCycleState::Closing {
+                    until: now + settle_time,
+                };
+                Some(DarkCycleAction::Close)
+            }
+            DarkCycleState::Closing { until } if now >= until => {
+                self.state = DarkCycleState::Capturing {
+                    remaining: dark_spectra.max(1),
+                };
+                Some(DarkCycleAction::CaptureDark)
+            }
+            DarkCycleState::Capturing { remaining } if new_spectrum => {
+                if remaining > 1 {
+                    self.state = DarkCycleState::Capturing {
+                        remaining: remaining - 1,
+                    };
+                    None
+                } else {
+                    self.state = DarkCycleState::Opening {
+                        until: now + settle_time,
+                    };
+                    Some(DarkCycleAction::Open)
+                }
+            }
+            DarkCycleState::Opening { until } if now >= until => {
+                self.state = DarkCycleState::Measuring {
+                    next: now + Duration::from_secs_f32(config.interval.max(0.)),
+                };
+                Some(DarkCycleAction::Resume)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dark_cycle() {
+        let config = ShutterConfig {
+            interval: 10.,
+            settle_time: 1.,
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let at = |s: f32| start + Duration::from_secs_f32(s);
+        let mut cycle = DarkCycle::new(start);
+
+        assert_eq!(
+            cycle.update(at(0.), false, 2, &config),
+            Some(DarkCycleAction::Close)
+        );
+        assert!(cycle.is_dark());
+        assert_eq!(cycle.update(at(0.5), true, 2, &config), None);
+        assert_eq!(
+            cycle.update(at(1.), false, 2, &config),
+            Some(DarkCycleAction::CaptureDark)
+        );
+        assert_eq!(cycle.update(at(1.1), true, 2, &config), None);
+        assert_eq!(
+            cycle.update(at(1.2), true, 2, &config),
+            Some(DarkCycleAction::Open)
+        );
+        assert_eq!(
+            cycle.update(at(2.2), false, 2, &config),
+            Some(DarkCycleAction::Resume)
+        );
+        assert!(!cycle.is_dark());
+        assert_eq!(cycle.update(at(12.), true, 2, &config), None);
+        assert_eq!(
+            cycle.update(at(12.5), false, 2, &config),
+            Some(DarkCycleAction::Close)
+        );
+    }
+}
@@ -0,0 +1,646 @@
+use crate::feed::{FeedEvent, FeedSpectrum};
+use crate::{ThreadId, ThreadResult};
+use flume::{Receiver, RecvTimeoutError, Sender, TrySendError};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream, ToSocketAddrs};
+use std::time::{Duration, Instant};
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Spectra waiting for a sink, further spectra are skipped while it is full
+const SINK_QUEUE_LEN: usize = 16;
+const WRITE_TIMEOUT: Duration = Duration::from_millis(200);
+const MQTT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const MQTT_RECONNECT_DELAY: Duration = Duration::from_secs(5);
+/// Keep alive announced to the broker, pings are sent after half of it
+const MQTT_KEEP_ALIVE: u16 = 60;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub enum SinkKind {
+    /// Newline delimited JSON spectra to every client connected to the port
+    TcpJson { port: u16 },
+    /// One row per spectrum with a column per wavelength
+    Csv { path: String },
+    /// JSON spectra published to a topic of an MQTT 3.1.1 broker with QoS 0
+    Mqtt {
+        broker: String,
+        topic: String,
+        client_id: String,
+    },
+}
+
+impl SinkKind {
+    pub fn tcp_json() -> Self {
+        Self::TcpJson { port: 5556 }
+    }
+
+    pub fn csv() -> Self {
+        Self::Csv {
+            path: "spectra.csv".to_string(),
+        }
+    }
+
+    pub fn mqtt() -> Self {
+        Self::Mqtt {
+            broker: "localhost:1883".to_string(),
+            topic: "spectro-cam/spectrum".to_string(),
+            client_id: "spectro-cam-rs".to_string(),
+        }
+    }
+
+    /// Sinks configured in the network window, the others belong to the recording window
+    pub fn is_network(&self) -> bool {
+        !matches!(self, Self::Csv { .. })
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::TcpJson { .. } => "TCP JSON",
+            Self::Csv { .. } => "CSV",
+            Self::Mqtt { .. } => "MQTT",
+        }
+    }
+}
+
+impl Display for SinkKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::TcpJson { port } => write!(f, "TCP JSON on port {port}"),
+            Self::Csv { path } => write!(f, "CSV {path}"),
+            Self::Mqtt { broker, topic, .. } => write!(f, "MQTT {topic} at {broker}"),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SinkConfig {
+    pub active: bool,
+    /// Spectra per second, spectra in between are skipped. Zero passes every spectrum.
+    pub max_rate: f32,
+    pub kind: SinkKind,
+}
+
+impl SinkConfig {
+    pub fn new(kind: SinkKind) -> Self {
+        Self {
+            active: false,
+            max_rate: 0.,
+            kind,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum SinkEvent {
+    /// Open the active sinks, sinks whose kind did not change keep running
+    Configure(Vec<SinkConfig>),
+    /// Passed to the active sinks and, if `feed` is set, to the feed
+    Spectrum { spectrum: FeedSpectrum, feed: bool },
+}
+
+enum SinkOutput {
+    TcpJson(TcpJsonSink),
+    Csv(Box<CsvSink>),
+    Mqtt(MqttSink),
+}
+
+impl SinkOutput {
+    fn open(kind: &SinkKind) -> Result<Self, String> {
+        Ok(match kind {
+            SinkKind::TcpJson { port } => Self::TcpJson(TcpJsonSink::open(*port)?),
+            SinkKind::Csv { path } => Self::Csv(Box::new(CsvSink::create(path)?)),
+            SinkKind::Mqtt {
+                broker,
+                topic,
+                client_id,
+            } => Self::Mqtt(MqttSink::new(broker, topic, client_id)),
+        })
+    }
+
+    fn send(&mut self, spectrum: &FeedSpectrum) -> Result<(), String> {
+        match self {
+            Self::TcpJson(sink) => sink.send(spectrum),
+            Self::Csv(sink) => sink.send(spectrum),
+            Self::Mqtt(sink) => sink.send(spectrum),
+        }
+    }
+
+    fn poll(&mut self) -> Result<(), String> {
+        match self {
+            Self::Mqtt(sink) => sink.poll(),
+            _ => Ok(()),
+        }
+    }
+
+    /// A failed sink stays closed until it is reconfigured, the others keep retrying
+    fn is_fatal(&self) -> bool {
+        !matches!(self, Self::Mqtt(_))
+    }
+}
+
+/// Writes spectra to one output on its own thread, so a slow output does not delay the others
+struct SinkWorker {
+    kind: SinkKind,
+    output: SinkOutput,
+    spectrum_rx: Receiver<FeedSpectrum>,
+    result_tx: Sender<ThreadResult>,
+}
+
+impl SinkWorker {
+    /// The worker stops when the returned sender is dropped or the output fails fatally
+    fn spawn(
+        kind: SinkKind,
+        output: SinkOutput,
+        result_tx: Sender<ThreadResult>,
+    ) -> Sender<FeedSpectrum> {
+        let (spectrum_tx, spectrum_rx) = flume::bounded(SINK_QUEUE_LEN);
+        std::thread::spawn(move || {
+            Self {
+                kind,
+                output,
+                spectrum_rx,
+                result_tx,
+            }
+            .run()
+        });
+        spectrum_tx
+    }
+
+    fn run(mut self) {
+        loop {
+            let result = match self.spectrum_rx.recv_timeout(POLL_INTERVAL) {
+                Ok(spectrum) => self.output.send(&spectrum),
+                Err(RecvTimeoutError::Timeout) => self.output.poll(),
+                Err(RecvTimeoutError::Disconnected) => break,
+            };
+            let Err(e) = result else {
+                continue;
+            };
+            let error = Err(format!("{}: {}", self.kind, e));
+            if self.output.is_fatal() {
+                // Close the sink before reporting, so it is already closed when the error arrives
+                let result_tx = self.result_tx.clone();
+                drop(self);
+                send_result(&result_tx, error);
+                break;
+            }
+            send_result(&self.result_tx, error);
+        }
+    }
+}
+
+struct RunningSink {
+    kind: SinkKind,
+    max_rate: f32,
+    last_sent: Option<Instant>,
+    /// `None` if the output could not be opened
+    spectrum_tx: Option<Sender<FeedSpectrum>>,
+}
+
+impl RunningSink {
+    fn is_due(&self, now: Instant) -> bool {
+        self.max_rate <= 0.
+            || self.last_sent.is_none_or(|last| {
+                now.duration_since(last) >= Duration::from_secs_f32(1. / self.max_rate)
+            })
+    }
+}
+
+/// Passes spectra to the feed and to several outputs at once, each with its own rate
+pub struct SinkManager {
+    event_rx: Receiver<SinkEvent>,
+    result_tx: Sender<ThreadResult>,
+    feed_tx: Option<Sender<FeedEvent>>,
+    sinks: Vec<RunningSink>,
+}
+
+impl SinkManager {
+    pub fn new(event_rx: Receiver<SinkEvent>, result_tx: Sender<ThreadResult>) -> Self {
+        Self {
+            event_rx,
+            result_tx,
+            feed_tx: None,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Forward spectra to the feed thread
+    pub fn with_feed(mut self, feed_tx: Sender<FeedEvent>) -> Self {
+        self.feed_tx = Some(feed_tx);
+        self
+    }
+
+    pub fn run(&mut self) {
+        while let Ok(event) = self.event_rx.recv() {
+            match event {
+                SinkEvent::Configure(configs) => self.configure(configs),
+                SinkEvent::Spectrum { spectrum, feed } => {
+                    if let Some(feed_tx) = self.feed_tx.as_ref().filter(|_| feed) {
+                        feed_tx.send(FeedEvent::Spectrum(spectrum.clone())).ok();
+                    }
+                    self.send(&spectrum, Instant::now());
+                }
+            }
+        }
+    }
+
+    fn configure(&mut self, configs: Vec<SinkConfig>) {
+        let mut running = std::mem::take(&mut self.sinks);
+        for config in configs.into_iter().filter(|c| c.active) {
+            if let Some(i) = running.iter().position(|s| s.kind == config.kind) {
+                let mut sink = running.swap_remove(i);
+                sink.max_rate = config.max_rate;
+                self.sinks.push(sink);
+                continue;
+            }
+            let spectrum_tx = match SinkOutput::open(&config.kind) {
+                Ok(output) => Some(SinkWorker::spawn(
+                    config.kind.clone(),
+                    output,
+                    self.result_tx.clone(),
+                )),
+                Err(e) => {
+                    send_result(
+                        &self.result_tx,
+                        Err(format!("Could not open {}: {}", config.kind, e)),
+                    );
+                    None
+                }
+            };
+            self.sinks.push(RunningSink {
+                kind: config.kind,
+                max_rate: config.max_rate,
+                last_sent: None,
+                spectrum_tx,
+            });
+        }
+    }
+
+    fn send(&mut self, spectrum: &FeedSpectrum, now: Instant) {
+        for sink in self.sinks.iter_mut().filter(|s| s.is_due(now)) {
+            let Some(spectrum_tx) = sink.spectrum_tx.as_ref() else {
+                continue;
+            };
+            sink.last_sent = Some(now);
+            match spectrum_tx.try_send(spectrum.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    log::debug!("{} does not keep up, skipping a spectrum", sink.kind)
+                }
+                Err(TrySendError::Disconnected(_)) => sink.spectrum_tx = None,
+            }
+        }
+    }
+}
+
+fn send_result(result_tx: &Sender<ThreadResult>, result: Result<(), String>) {
+    if let Err(e) = &result {
+        log::error!("Sink error: {}", e);
+    }
+    result_tx
+        .send(ThreadResult {
+            id: ThreadId::Sinks,
+            result,
+        })
+        .ok();
+}
+
+struct TcpJsonSink {
+    listener: TcpListener,
+    clients: Vec<TcpStream>,
+}
+
+impl TcpJsonSink {
+    fn open(port: u16) -> Result<Self, String> {
+        let listener =
+            TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).map_err(|e| e.to_string())?;
+        listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+        Ok(Self {
+            listener,
+            clients: Vec::new(),
+        })
+    }
+
+    /// Clients that do not keep up are disconnected
+    fn send(&mut self, spectrum: &FeedSpectrum) -> Result<(), String> {
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+                    stream
+                        .set_write_timeout(Some(WRITE_TIMEOUT))
+                        .map_err(|e| e.to_string())?;
+                    self.clients.push(stream);
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                Err(e) => return Err(e.to_string()),
+            }
+        }
+        let mut line = serde_json::to_vec(spectrum).map_err(|e| e.to_string())?;
+        line.push(b'\n');
+        self.clients
+            .retain_mut(|client| match client.write_all(&line) {
+                Ok(()) => true,
+                Err(e) => {
+                    log::warn!("Dropping TCP sink client: {}", e);
+                    false
+                }
+            });
+        Ok(())
+    }
+}
+
+struct CsvSink {
+    writer: csv::Writer<File>,
+    /// Wavelength offset, delta and count of the header
+    grid: Option<(f32, f32, usize)>,
+}
+
+impl CsvSink {
+    fn create(path: &str) -> Result<Self, String> {
+        Ok(Self {
+            writer: csv::Writer::from_path(path).map_err(|e| e.to_string())?,
+            grid: None,
+        })
+    }
+
+    /// The first spectrum writes the header, spectra of another wavelength grid are rejected
+    fn send(&mut self, spectrum: &FeedSpectrum) -> Result<(), String> {
+        let grid = (
+            spectrum.wavelength_offset,
+            spectrum.wavelength_delta,
+            spectrum.sum.len(),
+        );
+        match self.grid {
+            None => {
+                let header = std::iter::once("timestamp".to_string())
+                    .chain((0..grid.2).map(|i| format!("{:.2}", grid.0 + i as f32 * grid.1)));
+                self.writer
+                    .write_record(header)
+                    .map_err(|e| e.to_string())?;
+                self.grid = Some(grid);
+            }
+            Some(header_grid) if header_grid != grid => {
+                return Err("The wavelength calibration changed".to_string());
+            }
+            Some(_) => {}
+        }
+        let row = std::iter::once(spectrum.timestamp.to_string())
+            .chain(spectrum.sum.iter().map(f32::to_string));
+        self.writer.write_record(row).map_err(|e| e.to_string())?;
+        self.writer.flush().map_err(|e| e.to_string())
+    }
+}
+
+/// Minimal MQTT 3.1.1 client that publishes with QoS 0
+struct MqttSink {
+    broker: String,
+    topic: String,
+    client_id: String,
+    stream: Option<TcpStream>,
+    last_packet: Instant,
+    retry_at: Option<Instant>,
+}
+
+impl MqttSink {
+    fn new(broker: &str, topic: &str, client_id: &str) -> Self {
+        Self {
+            broker: broker.to_string(),
+            topic: topic.to_string(),
+            client_id: client_id.to_string(),
+            stream: None,
+            last_packet: Instant::now(),
+            retry_at: None,
+        }
+    }
+
+    fn connect(&self) -> Result<TcpStream, String> {
+        let address = self
+            .broker
+            .to_socket_addrs()
+            .map_err(|e| e.to_string())?
+            .next()
+            .ok_or_else(|| format!("Could not resolve {}", self.broker))?;
+        let mut stream = TcpStream::connect_timeout(&address, MQTT_CONNECT_TIMEOUT)
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_read_timeout(Some(MQTT_CONNECT_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        stream
+            .set_write_timeout(Some(WRITE_TIMEOUT))
+            .map_err(|e| e.to_string())?;
+        stream
+            .write_all(&mqtt_connect(&self.client_id, MQTT_KEEP_ALIVE))
+            .map_err(|e| e.to_string())?;
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).map_err(|e| e.to_string())?;
+        match connack {
+            [0x20, 2, _, 0] => Ok(stream),
+            [0x20, 2, _, code] => Err(format!("Connection refused with code {code}")),
+            _ => Err("Invalid CONNACK".to_string()),
+        }
+    }
+
+    /// Connect unless a failed attempt was too recent, only the first failure is returned
+    fn ensure_connected(&mut self) -> Result<bool, String> {
+        if self.stream.is_some() {
+            return Ok(true);
+        }
+        if self.retry_at.is_some_and(|at| Instant::now() < at) {
+            return Ok(false);
+        }
+        match self.connect() {
+            Ok(stream) => {
+                self.stream = Some(stream);
+                self.last_packet = Instant::now();
+                self.retry_at = None;
+                Ok(true)
+            }
+            Err(e) => {
+                let first_failure = self.retry_at.is_none();
+                self.retry_at = Some(Instant::now() + MQTT_RECONNECT_DELAY);
+                if first_failure {
+                    Err(e)
+                } else {
+                    log::debug!("MQTT reconnect to {} failed: {}", self.broker, e);
+                    Ok(false)
+                }
+            }
+        }
+    }
+
+    fn write(&mut self, packet: &[u8]) -> Result<(), String> {
+        let Some(stream) = self.stream.as_mut() else {
+            return Ok(());
+        };
+        if let Err(e) = stream.write_all(packet) {
+            self.stream = None;
+            self.retry_at = Some(Instant::now() + MQTT_RECONNECT_DELAY);
+            return Err(format!("Connection lost: {}", e));
+        }
+        self.last_packet = Instant::now();
+        Ok(())
+    }
+
+    fn send(&mut self, spectrum: &FeedSpectrum) -> Result<(), String> {
+        if !self.ensure_connected()? {
+            return Ok(());
+        }
+        let payload = serde_json::to_vec(spectrum).map_err(|e| e.to_string())?;
+        let packet = mqtt_publish(&self.topic, &payload)?;
+        self.write(&packet)
+    }
+
+    /// Keep the connection alive and discard the ping responses
+    fn poll(&mut self) -> Result<(), String> {
+        if !self.ensure_connected()? {
+            return Ok(());
+        }
+        if let Some(stream) = self.stream.as_mut() {
+            let mut buffer = [0; 64];
+            stream.set_nonblocking(true).map_err(|e| e.to_string())?;
+            while let Ok(len @ 1..) = stream.read(&mut buffer) {
+                log::trace!("Discarding {} bytes from the MQTT broker", len);
+            }
+            stream.set_nonblocking(false).map_err(|e| e.to_string())?;
+        }
+        if self.last_packet.elapsed() >= Duration::from_secs(MQTT_KEEP_ALIVE as u64 / 2) {
+            self.write(&[0xc0, 0])?;
+        }
+        Ok(())
+    }
+}
+
+/// Variable length encoding of the remaining length of a packet
+fn mqtt_remaining_length(mut len: usize) -> Result<Vec<u8>, String> {
+    if len > 268_435_455 {
+        return Err("MQTT packet too large".to_string());
+    }
+    let mut bytes = Vec::new();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        bytes.push(byte);
+        if len == 0 {
+            return Ok(bytes);
+        }
+    }
+}
+
+fn mqtt_string(s: &str) -> Vec<u8> {
+    let mut bytes = (s.len() as u16).to_be_bytes().to_vec();
+    bytes.extend_from_slice(s.as_bytes());
+    bytes
+}
+
+fn mqtt_packet(header: u8, body: Vec<u8>) -> Result<Vec<u8>, String> {
+    let mut packet = vec![header];
+    packet.extend(mqtt_remaining_length(body.len())?);
+    packet.extend(body);
+    Ok(packet)
+}
+
+/// CONNECT with a clean session and without credentials
+fn mqtt_connect(client_id: &str, keep_alive: u16) -> Vec<u8> {
+    let mut body = mqtt_string("MQTT");
+    body.extend([4, 0x02]);
+    body.extend(keep_alive.to_be_bytes());
+    body.extend(mqtt_string(client_id));
+    mqtt_packet(0x10, body).expect("Client id is short")
+}
+
+fn mqtt_publish(topic: &str, payload: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = mqtt_string(topic);
+    body.extend_from_slice(payload);
+    mqtt_packet(0x30, body)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spectrum(timestamp: f64, sum: Vec<f32>) -> FeedSpectrum {
+        FeedSpectrum {
+            timestamp,
+            frame_sequence: None,
+            wavelength_offset: 400.,
+            wavelength_delta: 0.5,
+            sum,
+            r: None,
+            g: None,
+            b: None,
+            frame_metadata: None,
+            saturation: None,
+        }
+    }
+
+    #[test]
+    fn mqtt_packets() {
+        assert_eq!(mqtt_remaining_length(127).unwrap(), vec![0x7f]);
+        assert_eq!(mqtt_remaining_length(321).unwrap(), vec![0xc1, 0x02]);
+        assert_eq!(
+            mqtt_connect("ab", 60),
+            vec![0x10, 14, 0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 60, 0, 2, b'a', b'b']
+        );
+        assert_eq!(
+            mqtt_publish("t", b"{}").unwrap(),
+            vec![0x30, 5, 0, 1, b't', b'{', b'}']
+        );
+    }
+
+    #[test]
+    fn csv_sink_with_rate() {
+        let path = std::env::temp_dir().join("spectro_cam_rs_sink_test.csv");
+        let kind = SinkKind::Csv {
+            path: path.to_str().unwrap().to_string(),
+        };
+        let (_event_tx, event_rx) = flume::unbounded();
+        let (result_tx, result_rx) = flume::unbounded();
+        let mut manager = SinkManager::new(event_rx, result_tx);
+        manager.configure(vec![
+            SinkConfig {
+                active: true,
+                max_rate: 2.,
+                kind: kind.clone(),
+            },
+            SinkConfig::new(SinkKind::tcp_json()),
+        ]);
+        assert_eq!(manager.sinks.len(), 1);
+
+        let start = Instant::now();
+        manager.send(&spectrum(1., vec![1., 2.]), start);
+        // Skipped, the rate allows one spectrum every 500 ms
+        manager.send(
+            &spectrum(2., vec![3., 4.]),
+            start + Duration::from_millis(100),
+        );
+        manager.send(
+            &spectrum(3., vec![5., 6.]),
+            start + Duration::from_millis(600),
+        );
+        assert!(result_rx.is_empty());
+
+        // A new grid stops the sink, reconfiguring the same kind keeps it stopped
+        manager.send(&spectrum(4., vec![1.]), start + Duration::from_secs(2));
+        let result = result_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(result.result.is_err());
+        manager.configure(vec![SinkConfig {
+            active: true,
+            max_rate: 0.,
+            kind,
+        }]);
+        assert!(manager.sinks[0]
+            .spectrum_tx
+            .as_ref()
+            .is_none_or(Sender::is_disconnected));
+
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "timestamp,400.00,400.50\n1,1,2\n3,5,6\n");
+    }
+}
@@ -0,0 +1,180 @@
+use serde::{Deserialize, Serialize};
+use std::f32::consts::TAU;
+use std::io::Write;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+
+pub const SAMPLE_RATE: u32 = 22050;
+const CHUNK_SIZE: usize = SAMPLE_RATE as usize / 20;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SonificationConfig {
+    pub bands: usize,
+    pub min_frequency: f32,
+    pub max_frequency: f32,
+    pub volume: f32,
+    /// Command that plays signed 16 bit mono raw PCM from stdin
+    pub player_command: String,
+}
+
+impl Default for SonificationConfig {
+    fn default() -> Self {
+        Self {
+            bands: 16,
+            min_frequency: 220.,
+            max_frequency: 1760.,
+            volume: 0.5,
+            player_command: format!("aplay -q -t raw -f S16_LE -c 1 -r {}", SAMPLE_RATE),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Tone {
+    pub frequency: f32,
+    pub amplitude: f32,
+}
+
+/// Split the spectrum into bands and map each band to a tone. Short wavelengths get high
+/// frequencies, the amplitude is the mean band value relative to `max_value`.
+pub fn spectrum_to_tones(
+    spectrum: &[f32],
+    max_value: f32,
+    config: &SonificationConfig,
+) -> Vec<Tone> {
+    let bands = config.bands.clamp(1, spectrum.len().max(1));
+    let band_size = spectrum.len() / bands;
+    if band_size == 0 || max_value <= 0. {
+        return vec![];
+    }
+    let ratio = config.max_frequency / config.min_frequency;
+    (0..bands)
+        .map(|band| {
+            let values = &spectrum[band * band_size..(band + 1) * band_size];
+            let mean = values.iter().sum::<f32>() / values.len() as f32;
+            let position = if bands > 1 {
+                1. - band as f32 / (bands - 1) as f32
+            } else {
+                0.5
+            };
+            Tone {
+                frequency: config.min_frequency * ratio.powf(position),
+                amplitude: (mean / max_value).clamp(0., 1.),
+            }
+        })
+        .collect()
+}
+
+/// Fill `samples` with the sum of `tones`, continuing from and updating `phases`.
+pub fn synthesize(tones: &[Tone], phases: &mut Vec<f32>, volume: f32, samples: &mut [i16]) {
+    phases.resize(tones.len(), 0.);
+    let norm = volume / tones.len().max(1) as f32;
+    for sample in samples.iter_mut() {
+        let mut value = 0.;
+        for (tone, phase) in tones.iter().zip(phases.iter_mut()) {
+            value += tone.amplitude * phase.sin();
+            *phase = (*phase + TAU * tone.frequency / SAMPLE_RATE as f32) % TAU;
+        }
+        *sample = ((value * norm).clamp(-1., 1.) * i16::MAX as f32) as i16;
+    }
+}
+
+/// Plays the current tones continuously through an external player process.
+pub struct Sonifier {
+    tones: Arc<Mutex<Option<Vec<Tone>>>>,
+    child: Child,
+}
+
+impl Sonifier {
+    pub fn start(config: &SonificationConfig) -> Result<Self, String> {
+        let mut parts = config.player_command.split_whitespace();
+        let program = parts
+            .next()
+            .ok_or_else(|| "No audio player command".to_string())?;
+        let mut child = Command::new(program)
+            .args(parts)
+            .stdin(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Could not start audio player: {}", e))?;
+        let mut stdin = child.stdin.take().unwrap();
+
+        let tones: Arc<Mutex<Option<Vec<Tone>>>> = Arc::new(Mutex::new(Some(vec![])));
+        let thread_tones = Arc::clone(&tones);
+        let volume = config.volume;
+        std::thread::spawn(move || {
+            let mut phases = vec![];
+            let mut samples = vec![0; CHUNK_SIZE];
+            let mut bytes = Vec::with_capacity(CHUNK_SIZE * 2);
+            loop {
+                let tones = match thread_tones.lock().unwrap().as_ref() {
+                    Some(tones) => tones.clone(),
+                    None => return,
+                };
+                synthesize(&tones, &mut phases, volume, &mut samples);
+                bytes.clear();
+                bytes.extend(samples.iter().flat_map(|s| s.to_le_bytes()));
+                // Blocks until the player consumed the previous chunk
+                if stdin.write_all(&bytes).is_err() {
+                    return;
+                }
+            }
+        });
+
+        Ok(Self { tones, child })
+    }
+
+    pub fn set_tones(&self, tones: Vec<Tone>) {
+        let mut current = self.tones.lock().unwrap();
+        if current.is_some() {
+            *current = Some(tones);
+        }
+    }
+}
+
+impl Drop for Sonifier {
+    fn drop(&mut self) {
+        *self.tones.lock().unwrap() = None;
+        self.child.kill().ok();
+        self.child.wait().ok();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn tones() {
+        let config = SonificationConfig {
+            bands: 2,
+            ..Default::default()
+        };
+        let spectrum = [1., 1., 0., 0.];
+
+        let tones = spectrum_to_tones(&spectrum, 1., &config);
+
+        assert_eq!(tones.len(), 2);
+        assert_relative_eq!(tones[0].frequency, config.max_frequency);
+        assert_relative_eq!(tones[0].amplitude, 1.);
+        assert_relative_eq!(tones[1].frequency, config.min_frequency);
+        assert_relative_eq!(tones[1].amplitude, 0.);
+    }
+
+    #[test]
+    fn synthesis() {
+        let tones = [Tone {
+            frequency: 1000.,
+            amplitude: 1.,
+        }];
+        let mut phases = vec![];
+        let mut samples = [0; 100];
+
+        synthesize(&tones, &mut phases, 1., &mut samples);
+
+        assert_eq!(samples[0], 0);
+        assert!(samples.iter().any(|&s| s > i16::MAX / 2));
+        assert!(samples.iter().any(|&s| s < i16::MIN / 2));
+        assert!(phases[0] > 0.);
+    }
+}
@@ -1,18 +1,86 @@
+use crate::colorimetry::{ColorCoordinates, Illuminant};
 use crate::config::{
-    Linearize, ReferenceConfig, SpectrometerConfig, SpectrumCalibration, SpectrumPoint,
+    AccumulationMode, BaselineCorrection, BufferClearPolicy, BufferClearReason, ColumnAggregation,
+    ImportExportConfig, Linearize, PeakFit, PostprocessingConfig, ProcessingOrder, ReferenceConfig,
+    SampleMetadata, SpectrometerConfig, SpectrumCalibration, SpectrumOrientation, SpectrumPoint,
+    SpectrumWindow,
 };
+use crate::number_format::write_csv;
+use crate::provenance::Provenance;
+use crate::trigger::{FlashEvent, FlashTrigger};
+use crate::{Exposure, FrameMetadata, Timestamped};
 use biquad::{
     Biquad, Coefficients, DirectForm2Transposed, Hertz, ToHertz, Type, Q_BUTTERWORTH_F32,
+    Q_BUTTERWORTH_F64,
 };
+use egui::Vec2;
 use flume::{Receiver, Sender};
-use image::{ImageBuffer, Pixel, Rgb};
-use nalgebra::{Dyn, OMatrix, U3, U4};
+use image::{DynamicImage, ImageBuffer, Pixel, Primitive, Rgb};
+use nalgebra::{Dyn, Matrix3, OMatrix, RealField, Vector3, U3, U4};
 use rayon::prelude::*;
 use serde::{Deserialize, Serialize};
 use std::collections::VecDeque;
+use std::fs::File;
+use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
 
 pub type SpectrumRgb = OMatrix<f32, U3, Dyn>;
 pub type Spectrum = OMatrix<f32, U4, Dyn>;
+/// Spectrum of the double-precision pipeline, used for reference and absorbance math
+pub type SpectrumF64 = OMatrix<f64, U4, Dyn>;
+
+/// Spectrum while it is processed in the scalar type `T`
+type PipelineSpectrum<T> = OMatrix<T, U4, Dyn>;
+
+/// Scalar type the spectrum pipeline can run in after spectra are buffered
+trait PipelineScalar: RealField + Copy {
+    fn from_single(value: f32) -> Self;
+
+    fn from_double(value: f64) -> Self;
+
+    fn to_single(self) -> f32;
+
+    /// Zero-phase Butterworth low-pass applied to every channel
+    fn low_pass_filter(spectrum: &mut OMatrix<Self, U4, Dyn>, cutoff: f32);
+}
+
+macro_rules! impl_pipeline_scalar {
+    ($t:ty, $q:expr) => {
+        impl PipelineScalar for $t {
+            fn from_single(value: f32) -> Self {
+                value as $t
+            }
+
+            fn from_double(value: f64) -> Self {
+                value as $t
+            }
+
+            fn to_single(self) -> f32 {
+                self as f32
+            }
+
+            fn low_pass_filter(spectrum: &mut OMatrix<Self, U4, Dyn>, cutoff: f32) {
+                let fs: Hertz<$t> = (2.0 as $t).hz();
+                let f0: Hertz<$t> = (cutoff as $t).hz();
+
+                let coeffs = Coefficients::<$t>::from_params(Type::LowPass, fs, f0, $q).unwrap();
+                for mut channel in spectrum.row_iter_mut() {
+                    let mut biquad = DirectForm2Transposed::<$t>::new(coeffs);
+                    for sample in channel.iter_mut() {
+                        *sample = biquad.run(*sample);
+                    }
+                    // Apply filter in reverse to compensate phase error
+                    for sample in channel.iter_mut().rev() {
+                        *sample = biquad.run(*sample);
+                    }
+                }
+            }
+        }
+    };
+}
+
+impl_pipeline_scalar!(f32, Q_BUTTERWORTH_F32);
+impl_pipeline_scalar!(f64, Q_BUTTERWORTH_F64);
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy, Default)]
 pub struct SpectrumExportPoint {
@@ -23,180 +91,1822 @@ pub struct SpectrumExportPoint {
     pub sum: f32,
 }
 
+impl SpectrumExportPoint {
+    /// Linear interpolation between two points
+    pub fn interpolate(&self, other: &Self, wavelength: f32) -> Self {
+        let t = (wavelength - self.wavelength) / (other.wavelength - self.wavelength);
+        let lerp = |a: f32, b: f32| a + t * (b - a);
+        Self {
+            wavelength,
+            r: lerp(self.r, other.r),
+            g: lerp(self.g, other.g),
+            b: lerp(self.b, other.b),
+            sum: lerp(self.sum, other.sum),
+        }
+    }
+
+    fn round(&self, decimal_places: u32) -> Self {
+        let factor = 10f32.powi(decimal_places as i32);
+        let round = |v: f32| (v * factor).round() / factor;
+        Self {
+            wavelength: round(self.wavelength),
+            r: round(self.r),
+            g: round(self.g),
+            b: round(self.b),
+            sum: round(self.sum),
+        }
+    }
+}
+
+/// Color coordinates of the summed channels
+pub fn export_color_coordinates(
+    points: &[SpectrumExportPoint],
+    white_point: Illuminant,
+) -> Option<ColorCoordinates> {
+    let points: Vec<_> = points
+        .iter()
+        .map(|p| SpectrumPoint {
+            wavelength: p.wavelength,
+            value: p.sum,
+        })
+        .collect();
+    ColorCoordinates::new(&points, white_point)
+}
+
+/// Linearly interpolate points sorted by wavelength at multiples of `step`
+///
+/// Fewer than two points cannot be interpolated and are returned unchanged.
+fn resample_export_points(points: &[SpectrumExportPoint], step: f32) -> Vec<SpectrumExportPoint> {
+    let [first, .., last] = points else {
+        return points.to_vec();
+    };
+    let mut resampled = Vec::new();
+    let mut n = (first.wavelength / step).ceil() as i64;
+    while n as f32 * step <= last.wavelength {
+        let wavelength = n as f32 * step;
+        let i = points
+            .partition_point(|p| p.wavelength < wavelength)
+            .clamp(1, points.len() - 1);
+        resampled.push(points[i - 1].interpolate(&points[i], wavelength));
+        n += 1;
+    }
+    resampled
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
+pub struct Latency {
+    /// Polling and decoding the frame
+    pub capture: Duration,
+    /// Window extraction, spectrum calculation and queueing until the GUI picked it up
+    pub transfer: Duration,
+    /// Linearization, averaging and filtering
+    pub postprocessing: Duration,
+    /// Time between the starts of the last two frames
+    pub frame_interval: Duration,
+}
+
+impl Latency {
+    pub fn total(&self) -> Duration {
+        self.capture + self.transfer + self.postprocessing
+    }
+}
+
+/// Channel of every pixel of an undemosaiced window, indexed by `[y % 2][x % 2]`
+pub type BayerLayout = [[usize; 2]; 2];
+
+/// Subpixel types of the frames the spectrum can be calculated from
+pub trait WindowSubpixel: Primitive + Into<f32> + Ord + Send + Sync {
+    fn as_f32(self) -> f32 {
+        self.into()
+    }
+
+    /// Rounded and saturated at the limits of the type
+    fn from_f32(value: f32) -> Self;
+}
+
+impl WindowSubpixel for u8 {
+    fn from_f32(value: f32) -> Self {
+        value.round() as u8
+    }
+}
+
+impl WindowSubpixel for u16 {
+    fn from_f32(value: f32) -> Self {
+        value.round() as u16
+    }
+}
+
+/// Keep 16 bit images at full depth and convert everything else to 8 bit RGB
+pub fn to_window_depth(image: DynamicImage) -> DynamicImage {
+    let color = image.color();
+    match image {
+        DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgb16(_) => image,
+        image if color.bytes_per_pixel() > color.channel_count() => {
+            DynamicImage::ImageRgb16(image.into_rgb16())
+        }
+        image => DynamicImage::ImageRgb8(image.into_rgb8()),
+    }
+}
+
+/// Extract the window from the frame at window depth
+///
+/// Rotated windows are resampled bilinearly, so that every row of the result follows the
+/// rotated spectral line. Samples outside of the frame repeat its border.
+pub fn extract_window(frame: &DynamicImage, window: &SpectrumWindow) -> DynamicImage {
+    if !window.is_rotated() {
+        return to_window_depth(frame.crop_imm(
+            window.offset.x as u32,
+            window.offset.y as u32,
+            window.size.x as u32,
+            window.size.y as u32,
+        ));
+    }
+    // Only decode the bounding box of the rotated window
+    let corners = window.corners();
+    let min = corners.iter().fold(Vec2::splat(f32::MAX), |m, c| m.min(*c));
+    let max = corners.iter().fold(Vec2::splat(f32::MIN), |m, c| m.max(*c));
+    let (x0, y0) = (min.x.floor().max(0.) as u32, min.y.floor().max(0.) as u32);
+    let source = to_window_depth(frame.crop_imm(
+        x0,
+        y0,
+        (max.x.ceil() as u32).saturating_sub(x0) + 1,
+        (max.y.ceil() as u32).saturating_sub(y0) + 1,
+    ));
+    let origin = Vec2::new(x0 as f32, y0 as f32);
+    match source {
+        DynamicImage::ImageRgb16(source) => {
+            DynamicImage::ImageRgb16(sample_rotated(&source, window, origin))
+        }
+        DynamicImage::ImageRgb8(source) => {
+            DynamicImage::ImageRgb8(sample_rotated(&source, window, origin))
+        }
+        source => source,
+    }
+}
+
+fn sample_rotated<S: WindowSubpixel>(
+    source: &ImageBuffer<Rgb<S>, Vec<S>>,
+    window: &SpectrumWindow,
+    origin: Vec2,
+) -> ImageBuffer<Rgb<S>, Vec<S>>
+where
+    Rgb<S>: Pixel<Subpixel = S>,
+{
+    let (width, height) = source.dimensions();
+    let half = window.size / 2.;
+    ImageBuffer::from_fn(window.size.x as u32, window.size.y as u32, |x, y| {
+        let local = Vec2::new(x as f32 + 0.5, y as f32 + 0.5) - half;
+        // Pixel centers are at half coordinates
+        let position = window.to_frame(local) - origin - Vec2::splat(0.5);
+        let x0 = position.x.floor();
+        let y0 = position.y.floor();
+        let (fx, fy) = (position.x - x0, position.y - y0);
+        let pixel = |x: f32, y: f32| {
+            source.get_pixel(
+                (x.max(0.) as u32).min(width.saturating_sub(1)),
+                (y.max(0.) as u32).min(height.saturating_sub(1)),
+            )
+        };
+        let (p00, p10, p01, p11) = (
+            pixel(x0, y0),
+            pixel(x0 + 1., y0),
+            pixel(x0, y0 + 1.),
+            pixel(x0 + 1., y0 + 1.),
+        );
+        Rgb(std::array::from_fn(|c| {
+            let top = p00[c].as_f32() * (1. - fx) + p10[c].as_f32() * fx;
+            let bottom = p01[c].as_f32() * (1. - fx) + p11[c].as_f32() * fx;
+            S::from_f32(top * (1. - fy) + bottom * fy)
+        }))
+    })
+}
+
+/// Turn the extracted window so that the wavelength increases along its rows
+pub fn orient_window(
+    window: DynamicImage,
+    orientation: SpectrumOrientation,
+    rotate_180: bool,
+) -> DynamicImage {
+    match (orientation, rotate_180) {
+        (SpectrumOrientation::Horizontal, false) => window,
+        (SpectrumOrientation::Horizontal, true) => window.rotate180(),
+        // Counterclockwise, the top of the window ends up on the left
+        (SpectrumOrientation::Vertical, false) => window.rotate270(),
+        (SpectrumOrientation::Vertical, true) => window.rotate90(),
+    }
+}
+
+/// Layout of an undemosaiced window after [`orient_window`]
+///
+/// `width` and `height` are the dimensions of the window before it was turned.
+pub fn orient_bayer_layout(
+    layout: BayerLayout,
+    width: u32,
+    height: u32,
+    orientation: SpectrumOrientation,
+    rotate_180: bool,
+) -> BayerLayout {
+    let (w, h) = (width as usize, height as usize);
+    std::array::from_fn(|y| {
+        std::array::from_fn(|x| {
+            // Position in the unturned window of the pixel at (x, y), only the parity matters
+            let (sx, sy) = match (orientation, rotate_180) {
+                (SpectrumOrientation::Horizontal, false) => (x, y),
+                (SpectrumOrientation::Horizontal, true) => (w + 1 + x, h + 1 + y),
+                (SpectrumOrientation::Vertical, false) => (w + 1 + y, x),
+                (SpectrumOrientation::Vertical, true) => (y, h + 1 + x),
+            };
+            layout[sy % 2][sx % 2]
+        })
+    })
+}
+
+/// Frame intervals this many times longer than the median count as a coverage gap
+const COVERAGE_GAP_RATIO: f32 = 2.;
+
+/// Subpixel values from this fraction of the maximum on count as saturated
+pub const SATURATION_LEVEL: f32 = 0.98;
+
+/// Position of a frame in an exposure bracketing cycle
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Bracket {
+    pub index: usize,
+    pub count: usize,
+    /// Exposure time in driver units
+    pub exposure_time: i64,
+}
+
+/// Spectrum of one bracketed frame with the saturated channels of every column
+#[derive(Debug, Clone)]
+pub struct BracketFrame {
+    pub exposure_time: i64,
+    pub spectrum: SpectrumRgb,
+    pub saturated: Vec<[bool; 3]>,
+}
+
+/// Merge the spectra of one bracketing cycle into one scaled to the shortest exposure
+///
+/// Every value is the mean of the unsaturated frames weighted by their exposure time. Values
+/// that are saturated in all frames are taken from the shortest exposure.
+pub fn merge_hdr(frames: &[BracketFrame]) -> SpectrumRgb {
+    let exposure_time = |frame: &BracketFrame| frame.exposure_time.max(1) as f32;
+    let Some(shortest) = frames
+        .iter()
+        .min_by(|a, b| exposure_time(a).total_cmp(&exposure_time(b)))
+    else {
+        return SpectrumRgb::zeros(0);
+    };
+    let reference = exposure_time(shortest);
+    let ncols = shortest.spectrum.ncols();
+    SpectrumRgb::from_fn(ncols, |c, x| {
+        // Weighting with the exposure time cancels the scaling to the reference exposure
+        let (sum, total) = frames
+            .iter()
+            .filter(|f| f.spectrum.ncols() == ncols && !f.saturated[x][c])
+            .fold((0., 0.), |(sum, total), f| {
+                (sum + f.spectrum[(c, x)], total + exposure_time(f))
+            });
+        if total > 0. {
+            sum * reference / total
+        } else {
+            shortest.spectrum[(c, x)]
+        }
+    })
+}
+
+/// Spectrum window extracted from a frame
+pub struct WindowImage {
+    /// `ImageRgb8` or `ImageRgb16` depending on the bit depth of the source
+    pub image: DynamicImage,
+    pub column_aggregation: ColumnAggregation,
+    pub bayer_layout: Option<BayerLayout>,
+    /// Only set in HDR mode
+    pub bracket: Option<Bracket>,
+    /// Spectra are summed over this capture time before one is sent
+    pub long_exposure: Option<Duration>,
+}
+
+impl WindowImage {
+    /// Keep 16 bit sources at full depth and convert everything else to 8 bit RGB
+    pub fn new(
+        image: DynamicImage,
+        column_aggregation: ColumnAggregation,
+        bayer_layout: Option<BayerLayout>,
+    ) -> Self {
+        Self {
+            image: to_window_depth(image),
+            column_aggregation,
+            bayer_layout,
+            bracket: None,
+            long_exposure: None,
+        }
+    }
+
+    /// Fraction of the saturated subpixels, for Bayer windows only the pixels of the channel count
+    pub fn saturation(&self) -> f32 {
+        fn saturation<S: WindowSubpixel>(
+            image: &ImageBuffer<Rgb<S>, Vec<S>>,
+            layout: Option<BayerLayout>,
+        ) -> f32
+        where
+            Rgb<S>: Pixel<Subpixel = S>,
+        {
+            let level = S::DEFAULT_MAX_VALUE.as_f32() * SATURATION_LEVEL;
+            let (mut total, mut saturated) = (0usize, 0usize);
+            for (x, y, pixel) in image.enumerate_pixels() {
+                for (c, &value) in pixel.channels().iter().enumerate() {
+                    if layout.is_none_or(|l| l[y as usize % 2][x as usize % 2] == c) {
+                        total += 1;
+                        if value.as_f32() >= level {
+                            saturated += 1;
+                        }
+                    }
+                }
+            }
+            if total > 0 {
+                saturated as f32 / total as f32
+            } else {
+                0.
+            }
+        }
+        match &self.image {
+            DynamicImage::ImageRgb16(image) => saturation(image, self.bayer_layout),
+            DynamicImage::ImageRgb8(image) => saturation(image, self.bayer_layout),
+            image => saturation(&image.to_rgb8(), self.bayer_layout),
+        }
+    }
+
+    /// Highest subpixel value per channel relative to full scale, for Bayer windows only the
+    /// pixels of the channel count
+    pub fn channel_peaks(&self) -> [f32; 3] {
+        fn peaks<S: WindowSubpixel>(
+            image: &ImageBuffer<Rgb<S>, Vec<S>>,
+            layout: Option<BayerLayout>,
+        ) -> [f32; 3]
+        where
+            Rgb<S>: Pixel<Subpixel = S>,
+        {
+            let mut peaks = [0f32; 3];
+            for (x, y, pixel) in image.enumerate_pixels() {
+                for (c, &value) in pixel.channels().iter().enumerate() {
+                    if layout.is_none_or(|l| l[y as usize % 2][x as usize % 2] == c) {
+                        peaks[c] = peaks[c].max(value.as_f32());
+                    }
+                }
+            }
+            peaks.map(|peak| peak / S::DEFAULT_MAX_VALUE.as_f32())
+        }
+        match &self.image {
+            DynamicImage::ImageRgb16(image) => peaks(image, self.bayer_layout),
+            DynamicImage::ImageRgb8(image) => peaks(image, self.bayer_layout),
+            image => peaks(&image.to_rgb8(), self.bayer_layout),
+        }
+    }
+}
+
+/// Per channel maximum of two optional channel peaks
+fn max_channel_peaks(a: Option<[f32; 3]>, b: Option<[f32; 3]>) -> Option<[f32; 3]> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(std::array::from_fn(|c| a[c].max(b[c]))),
+        (a, b) => a.or(b),
+    }
+}
+
+/// Sums the windows of consecutive frames at full precision
+#[derive(Debug, Default)]
+pub struct WindowAccumulator {
+    sum: Vec<u32>,
+    dimensions: (u32, u32),
+    /// Full scale of the source subpixels
+    subpixel_max: u32,
+    count: usize,
+    start: Option<SystemTime>,
+    /// Highest saturation of the added windows
+    saturation: Option<f32>,
+    channel_peaks: Option<[f32; 3]>,
+}
+
+impl WindowAccumulator {
+    /// Add a window, returns the combined 16 bit window once `frames` windows were added
+    ///
+    /// Windows of a different size or bit depth restart the accumulation.
+    pub fn add(
+        &mut self,
+        window: Timestamped<WindowImage>,
+        frames: usize,
+        mode: AccumulationMode,
+    ) -> Option<Timestamped<WindowImage>> {
+        let (dimensions, subpixel_max, samples): (_, _, Box<dyn Iterator<Item = u32>>) =
+            match &window.value.image {
+                DynamicImage::ImageRgb16(image) => (
+                    image.dimensions(),
+                    u16::MAX as u32,
+                    Box::new(image.as_raw().iter().map(|&v| v as u32)),
+                ),
+                DynamicImage::ImageRgb8(image) => (
+                    image.dimensions(),
+                    u8::MAX as u32,
+                    Box::new(image.as_raw().iter().map(|&v| v as u32)),
+                ),
+                _ => return Some(window),
+            };
+        if self.count == 0 || self.dimensions != dimensions || self.subpixel_max != subpixel_max {
+            self.sum.clear();
+            self.sum
+                .resize(dimensions.0 as usize * dimensions.1 as usize * 3, 0);
+            self.dimensions = dimensions;
+            self.subpixel_max = subpixel_max;
+            self.count = 0;
+            self.start = Some(window.start);
+            self.saturation = None;
+            self.channel_peaks = None;
+        }
+        self.saturation = match (self.saturation, window.saturation) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            (a, b) => a.or(b),
+        };
+        self.channel_peaks = max_channel_peaks(self.channel_peaks, window.channel_peaks);
+        self.sum
+            .iter_mut()
+            .zip(samples)
+            .for_each(|(sum, v)| *sum += v);
+        self.count += 1;
+        if self.count < frames {
+            return None;
+        }
+
+        let divisor = match mode {
+            AccumulationMode::Mean => self.subpixel_max as u64 * self.count as u64,
+            AccumulationMode::Sum => self.subpixel_max as u64,
+        };
+        let values = self
+            .sum
+            .iter()
+            .map(|&sum| {
+                ((sum as u64 * u16::MAX as u64 + divisor / 2) / divisor).min(u16::MAX as u64) as u16
+            })
+            .collect();
+        self.count = 0;
+        let (width, height) = self.dimensions;
+        Some(Timestamped {
+            start: self.start.take().unwrap_or(window.start),
+            end: window.end,
+            sequence: window.sequence,
+            frame_metadata: window.frame_metadata,
+            saturation: self.saturation.take(),
+            channel_peaks: self.channel_peaks.take(),
+            value: WindowImage {
+                image: DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, values)?),
+                ..window.value
+            },
+        })
+    }
+}
+
 pub struct SpectrumCalculator {
-    window_rx: Receiver<ImageBuffer<Rgb<u8>, Vec<u8>>>,
-    spectrum_tx: Sender<SpectrumRgb>,
+    window_rx: Receiver<Timestamped<WindowImage>>,
+    spectrum_tx: Sender<Timestamped<SpectrumRgb>>,
+    /// Frames of the current bracketing cycle
+    bracket_frames: Vec<Timestamped<BracketFrame>>,
+    /// Sum of the spectra of the current long exposure
+    long_exposure_sum: Option<Timestamped<SpectrumRgb>>,
 }
 
 impl SpectrumCalculator {
     pub fn new(
-        window_rx: Receiver<ImageBuffer<Rgb<u8>, Vec<u8>>>,
-        spectrum_tx: Sender<SpectrumRgb>,
+        window_rx: Receiver<Timestamped<WindowImage>>,
+        spectrum_tx: Sender<Timestamped<SpectrumRgb>>,
     ) -> Self {
         SpectrumCalculator {
             window_rx,
             spectrum_tx,
+            bracket_frames: Vec::new(),
+            long_exposure_sum: None,
+        }
+    }
+
+    /// Process windows until either side is dropped
+    ///
+    /// Bracketed windows are collected and sent as one merged spectrum per cycle, long exposures
+    /// as one summed spectrum per exposure.
+    pub fn run(&mut self) {
+        while let Ok(window) = self.window_rx.recv() {
+            let spectrum = match (window.value.bracket, window.value.long_exposure) {
+                (Some(bracket), _) => match self.push_bracket_frame(window, bracket) {
+                    Some(spectrum) => spectrum,
+                    None => continue,
+                },
+                (None, Some(duration)) => {
+                    let spectrum = window.map(|w| Self::process_window_image(&w));
+                    match self.push_long_exposure(spectrum, duration) {
+                        Some(spectrum) => spectrum,
+                        None => continue,
+                    }
+                }
+                (None, None) => {
+                    self.long_exposure_sum = None;
+                    window.map(|w| Self::process_window_image(&w))
+                }
+            };
+
+            if self.spectrum_tx.send(spectrum).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Returns the merged spectrum once the cycle is complete
+    fn push_bracket_frame(
+        &mut self,
+        window: Timestamped<WindowImage>,
+        bracket: Bracket,
+    ) -> Option<Timestamped<SpectrumRgb>> {
+        // Start over on a new cycle or if frames were lost
+        if bracket.index != self.bracket_frames.len() {
+            self.bracket_frames.clear();
+            if bracket.index != 0 {
+                return None;
+            }
+        }
+        self.bracket_frames.push(window.map(|w| BracketFrame {
+            exposure_time: bracket.exposure_time,
+            spectrum: Self::process_window_image(&w),
+            saturated: Self::saturated_columns(&w),
+        }));
+        if self.bracket_frames.len() < bracket.count {
+            return None;
         }
+
+        let frames = std::mem::take(&mut self.bracket_frames);
+        let first = frames.first()?;
+        let last = frames.last()?;
+        let exposure_time = frames.iter().map(|f| f.value.exposure_time).min();
+        // Clipped values are replaced from shorter exposures where possible
+        let saturation = frames.iter().filter_map(|f| f.saturation).reduce(f32::min);
+        let channel_peaks = frames
+            .iter()
+            .filter_map(|f| f.channel_peaks)
+            .reduce(|a, b| std::array::from_fn(|c| a[c].min(b[c])));
+        Some(Timestamped {
+            start: first.start,
+            end: last.end,
+            sequence: last.sequence,
+            frame_metadata: last.frame_metadata.map(|metadata| FrameMetadata {
+                exposure: Exposure {
+                    exposure_time,
+                    ..metadata.exposure
+                },
+                ..metadata
+            }),
+            saturation,
+            channel_peaks,
+            value: merge_hdr(&frames.into_iter().map(|f| f.value).collect::<Vec<_>>()),
+        })
     }
 
-    pub fn run(&mut self) -> ! {
-        loop {
-            if let Ok(window) = self.window_rx.recv() {
-                let spectrum = Self::process_window(&window);
+    /// Returns the sum of the spectra once they span the duration
+    ///
+    /// Summing after the column aggregation equals summing the pixels for the mean aggregation.
+    /// A different spectrum size starts a new exposure.
+    fn push_long_exposure(
+        &mut self,
+        spectrum: Timestamped<SpectrumRgb>,
+        duration: Duration,
+    ) -> Option<Timestamped<SpectrumRgb>> {
+        let sum = match self.long_exposure_sum.take() {
+            Some(mut sum) if sum.value.ncols() == spectrum.value.ncols() => {
+                sum.value += &spectrum.value;
+                sum.end = spectrum.end;
+                sum.sequence = spectrum.sequence;
+                sum.frame_metadata = spectrum.frame_metadata;
+                sum.saturation = match (sum.saturation, spectrum.saturation) {
+                    (Some(a), Some(b)) => Some(a.max(b)),
+                    (a, b) => a.or(b),
+                };
+                sum.channel_peaks = max_channel_peaks(sum.channel_peaks, spectrum.channel_peaks);
+                sum
+            }
+            _ => spectrum,
+        };
+        if sum.end.duration_since(sum.start).unwrap_or_default() >= duration {
+            Some(sum)
+        } else {
+            self.long_exposure_sum = Some(sum);
+            None
+        }
+    }
 
-                self.spectrum_tx.send(spectrum).unwrap();
+    /// Saturated channels of every column, for Bayer windows only the pixels of the channel count
+    pub fn saturated_columns(window: &WindowImage) -> Vec<[bool; 3]> {
+        fn saturated<S: WindowSubpixel>(
+            image: &ImageBuffer<Rgb<S>, Vec<S>>,
+            layout: Option<BayerLayout>,
+        ) -> Vec<[bool; 3]>
+        where
+            Rgb<S>: Pixel<Subpixel = S>,
+        {
+            let level = S::DEFAULT_MAX_VALUE.as_f32() * SATURATION_LEVEL;
+            (0..image.width())
+                .map(|x| {
+                    std::array::from_fn(|c| {
+                        (0..image.height())
+                            .filter(|&y| {
+                                layout.is_none_or(|l| l[y as usize % 2][x as usize % 2] == c)
+                            })
+                            .any(|y| image.get_pixel(x, y)[c].as_f32() >= level)
+                    })
+                })
+                .collect()
+        }
+        match &window.image {
+            DynamicImage::ImageRgb16(image) => saturated(image, window.bayer_layout),
+            DynamicImage::ImageRgb8(image) => saturated(image, window.bayer_layout),
+            image => saturated(&image.to_rgb8(), window.bayer_layout),
+        }
+    }
+
+    pub fn process_window_image(window: &WindowImage) -> SpectrumRgb {
+        fn process<S: WindowSubpixel>(
+            image: &ImageBuffer<Rgb<S>, Vec<S>>,
+            window: &WindowImage,
+        ) -> SpectrumRgb
+        where
+            Rgb<S>: Pixel<Subpixel = S>,
+        {
+            match window.bayer_layout {
+                Some(layout) => SpectrumCalculator::process_bayer_window(
+                    image,
+                    layout,
+                    window.column_aggregation,
+                ),
+                None => SpectrumCalculator::process_window(image, window.column_aggregation),
             }
         }
+        match &window.image {
+            DynamicImage::ImageRgb16(image) => process(image, window),
+            DynamicImage::ImageRgb8(image) => process(image, window),
+            image => process(&image.to_rgb8(), window),
+        }
     }
 
-    pub fn process_window(window: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> SpectrumRgb {
+    /// Spectrum normalized to the maximum value of the subpixel type
+    pub fn process_window<S: WindowSubpixel>(
+        window: &ImageBuffer<Rgb<S>, Vec<S>>,
+        column_aggregation: ColumnAggregation,
+    ) -> SpectrumRgb
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+    {
         let columns = window.width();
         let rows = window.height();
-        let max_value = rows * u8::MAX as u32 * 3;
+        let subpixel_max = S::DEFAULT_MAX_VALUE.as_f32();
 
-        let spectrum: SpectrumRgb = window
-            .rows()
-            .par_bridge()
-            .map(|r| {
-                SpectrumRgb::from_vec(
-                    r.flat_map(|p| p.channels().iter().map(|&v| v as f32))
-                        .collect::<Vec<f32>>(),
-                )
+        match column_aggregation {
+            ColumnAggregation::Mean => {
+                let max_value = rows as f32 * subpixel_max * 3.;
+                window
+                    .rows()
+                    .par_bridge()
+                    .map(Self::row_to_spectrum)
+                    .reduce(
+                        || SpectrumRgb::from_element(columns as usize, 0.),
+                        |a, b| a + b,
+                    )
+                    / max_value
+            }
+            ColumnAggregation::Median => {
+                let max_value = subpixel_max * 3.;
+                let values = (0..columns)
+                    .into_par_iter()
+                    .flat_map_iter(|x| {
+                        (0..3).map(move |c| {
+                            let mut column: Vec<S> =
+                                (0..rows).map(|y| window.get_pixel(x, y)[c]).collect();
+                            column.sort_unstable();
+                            let mid = column.len() / 2;
+                            if column.len().is_multiple_of(2) {
+                                (column[mid - 1].as_f32() + column[mid].as_f32()) / 2.
+                            } else {
+                                column[mid].as_f32()
+                            }
+                        })
+                    })
+                    .collect::<Vec<f32>>();
+                SpectrumRgb::from_vec(values) / max_value
+            }
+            ColumnAggregation::Max => {
+                let max_value = subpixel_max * 3.;
+                window
+                    .rows()
+                    .par_bridge()
+                    .map(Self::row_to_spectrum)
+                    .reduce(
+                        || SpectrumRgb::from_element(columns as usize, 0.),
+                        |a, b| a.sup(&b),
+                    )
+                    / max_value
+            }
+        }
+    }
+
+    /// Spectrum of undemosaiced sensor data, every pixel only contributes to the channel of its
+    /// color filter
+    ///
+    /// Red and blue have no pixels in every other column, these are interpolated from the
+    /// neighboring columns of the same channel only. Windows need at least two rows for all
+    /// channels.
+    pub fn process_bayer_window<S: WindowSubpixel>(
+        window: &ImageBuffer<Rgb<S>, Vec<S>>,
+        layout: BayerLayout,
+        column_aggregation: ColumnAggregation,
+    ) -> SpectrumRgb
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+    {
+        let columns = window.width();
+        let rows = window.height();
+        let max_value = S::DEFAULT_MAX_VALUE.as_f32() * 3.;
+
+        let values = (0..columns)
+            .into_par_iter()
+            .flat_map_iter(|x| {
+                (0..3).map(move |c| {
+                    let mut column: Vec<S> = (0..rows)
+                        .filter(|&y| layout[y as usize % 2][x as usize % 2] == c)
+                        .map(|y| window.get_pixel(x, y)[c])
+                        .collect();
+                    if column.is_empty() {
+                        return f32::NAN;
+                    }
+                    match column_aggregation {
+                        ColumnAggregation::Mean => {
+                            column.iter().map(|&v| v.as_f32()).sum::<f32>() / column.len() as f32
+                        }
+                        ColumnAggregation::Median => {
+                            column.sort_unstable();
+                            let mid = column.len() / 2;
+                            if column.len().is_multiple_of(2) {
+                                (column[mid - 1].as_f32() + column[mid].as_f32()) / 2.
+                            } else {
+                                column[mid].as_f32()
+                            }
+                        }
+                        ColumnAggregation::Max => column.iter().copied().max().unwrap().as_f32(),
+                    }
+                })
             })
-            .reduce(
-                || SpectrumRgb::from_element(columns as usize, 0.),
-                |a, b| a + b,
-            )
-            / max_value as f32;
+            .collect::<Vec<f32>>();
+        let mut spectrum = SpectrumRgb::from_vec(values) / max_value;
+
+        for mut channel in spectrum.row_iter_mut() {
+            let known: Vec<(usize, f32)> = channel
+                .iter()
+                .enumerate()
+                .filter(|(_, v)| !v.is_nan())
+                .map(|(i, &v)| (i, v))
+                .collect();
+            for (i, value) in channel.iter_mut().enumerate() {
+                if !value.is_nan() {
+                    continue;
+                }
+                let next = known.partition_point(|&(k, _)| k < i);
+                *value = match (next.checked_sub(1).map(|p| known[p]), known.get(next)) {
+                    (Some((i0, v0)), Some(&(i1, v1))) => {
+                        v0 + (v1 - v0) * (i - i0) as f32 / (i1 - i0) as f32
+                    }
+                    (Some((_, v)), None) | (None, Some(&(_, v))) => v,
+                    (None, None) => 0.,
+                };
+            }
+        }
         spectrum
     }
+
+    fn row_to_spectrum<'a, S: WindowSubpixel + 'a>(
+        row: impl Iterator<Item = &'a Rgb<S>>,
+    ) -> SpectrumRgb
+    where
+        Rgb<S>: Pixel<Subpixel = S>,
+    {
+        SpectrumRgb::from_vec(
+            row.flat_map(|p| p.channels().iter().map(|&v| v.as_f32()))
+                .collect::<Vec<f32>>(),
+        )
+    }
+}
+
+/// Find the brightest horizontal band in a frame, i.e. the dispersed spectrum
+///
+/// Returns `None` if no band stands out from the background.
+pub fn find_spectrum_window(frame: &ImageBuffer<Rgb<u8>, Vec<u8>>) -> Option<SpectrumWindow> {
+    let (width, height) = frame.dimensions();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    let brightness = |x: u32, y: u32| {
+        frame
+            .get_pixel(x, y)
+            .channels()
+            .iter()
+            .map(|&v| v as f32)
+            .sum::<f32>()
+    };
+
+    let row_profile: Vec<f32> = (0..height)
+        .into_par_iter()
+        .map(|y| (0..width).map(|x| brightness(x, y)).sum::<f32>() / width as f32)
+        .collect();
+    let (peak_row, &peak) = row_profile
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))?;
+    let mut sorted = row_profile.clone();
+    sorted.sort_by(f32::total_cmp);
+    let background = sorted[sorted.len() / 2];
+    if peak - background < 3. {
+        return None;
+    }
+
+    // Rows above half of the peak height around the brightest row
+    let threshold = background + (peak - background) / 2.;
+    let top = (0..peak_row)
+        .rev()
+        .find(|&y| row_profile[y] < threshold)
+        .map_or(0, |y| y + 1);
+    let bottom = (peak_row..row_profile.len())
+        .find(|&y| row_profile[y] < threshold)
+        .unwrap_or(row_profile.len());
+
+    // Outermost columns of the band clearly above the column background
+    let column_profile: Vec<f32> = (0..width)
+        .map(|x| {
+            (top..bottom).map(|y| brightness(x, y as u32)).sum::<f32>() / (bottom - top) as f32
+        })
+        .collect();
+    let column_max = column_profile.iter().cloned().fold(0., f32::max);
+    let mut sorted = column_profile.clone();
+    sorted.sort_by(f32::total_cmp);
+    let column_background = sorted[sorted.len() / 10];
+    let column_threshold = column_background + (column_max - column_background) / 10.;
+    let left = column_profile.iter().position(|&v| v > column_threshold)?;
+    let right = column_profile.iter().rposition(|&v| v > column_threshold)?;
+
+    Some(SpectrumWindow {
+        offset: Vec2::new(left as f32, top as f32),
+        size: Vec2::new((right - left + 1) as f32, (bottom - top) as f32),
+        angle: 0.,
+    })
+}
+
+/// Position and width of a single peak
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct PeakShape {
+    /// Wavelength of the maximum
+    pub wavelength: f32,
+    pub value: f32,
+    /// Intensity weighted mean wavelength above half maximum
+    pub centroid: f32,
+    /// Full width at half maximum in nm
+    pub fwhm: f32,
+}
+
+/// Shape of the strongest peak
+///
+/// The half maximum crossings are interpolated linearly between neighbouring points. Returns
+/// `None` if the peak does not fall below half maximum on both sides.
+pub fn dominant_peak(points: &[SpectrumPoint]) -> Option<PeakShape> {
+    let (max_index, max) = points
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.value.total_cmp(&b.1.value))?;
+    if max.value <= 0. {
+        return None;
+    }
+    let half = max.value / 2.;
+    let left = points[..max_index].iter().rposition(|p| p.value < half)?;
+    let right = max_index + points[max_index..].iter().position(|p| p.value < half)?;
+
+    let crossing = |a: &SpectrumPoint, b: &SpectrumPoint| {
+        a.wavelength + (half - a.value) / (b.value - a.value) * (b.wavelength - a.wavelength)
+    };
+    let low = crossing(&points[left], &points[left + 1]);
+    let high = crossing(&points[right - 1], &points[right]);
+
+    let (weighted, total) = points[left + 1..right]
+        .iter()
+        .fold((0., 0.), |(weighted, total), p| {
+            (weighted + p.wavelength * p.value, total + p.value)
+        });
+
+    Some(PeakShape {
+        wavelength: max.wavelength,
+        value: max.value,
+        centroid: weighted / total,
+        fwhm: (high - low).abs(),
+    })
+}
+
+/// Sub-pixel wavelength of the maximum of a narrow line
+///
+/// Fits a Gaussian through the maximum and its neighbours, which is exact for Gaussian line
+/// shapes. Falls back to a parabola if a value is not positive.
+pub fn sub_pixel_peak(points: &[SpectrumPoint]) -> Option<f32> {
+    let (max_index, _) = points
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.value.total_cmp(&b.1.value))?;
+    if max_index == 0 || max_index + 1 >= points.len() {
+        return None;
+    }
+    let (p0, p1, p2) = (
+        points[max_index - 1],
+        points[max_index],
+        points[max_index + 1],
+    );
+    let (y0, y1, y2) = if p0.value > 0. && p1.value > 0. && p2.value > 0. {
+        (p0.value.ln(), p1.value.ln(), p2.value.ln())
+    } else {
+        (p0.value, p1.value, p2.value)
+    };
+    let denominator = y0 - 2. * y1 + y2;
+    let delta = if denominator == 0. {
+        0.
+    } else {
+        0.5 * (y0 - y2) / denominator
+    };
+    Some(p1.wavelength + delta * (p2.wavelength - p0.wavelength) / 2.)
+}
+
+/// Line shape fitted to a peak, see [`fit_peak`]
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct FittedPeak {
+    /// Center wavelength in nm
+    pub center: f32,
+    pub height: f32,
+    /// Full width at half maximum in nm
+    pub fwhm: f32,
+    /// Integral of the line shape over the wavelength
+    pub area: f32,
+}
+
+/// Fit a line shape by least squares to the points around the maximum above half of it
+///
+/// A Gaussian is a parabola in the logarithm of the values, a Lorentzian in their reciprocal.
+/// The points have to be sorted by wavelength. Returns `None` for fewer than three points above
+/// half maximum or if the fitted parabola does not describe a peak.
+pub fn fit_peak(points: &[SpectrumPoint], shape: PeakFit) -> Option<FittedPeak> {
+    let transform: fn(f64) -> f64 = match shape {
+        PeakFit::Off => return None,
+        PeakFit::Gaussian => f64::ln,
+        PeakFit::Lorentzian => f64::recip,
+    };
+    let (max_index, max) = points
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.value.total_cmp(&b.1.value))?;
+    let half = max.value / 2.;
+    if half <= 0. {
+        return None;
+    }
+    let left = points[..max_index]
+        .iter()
+        .rposition(|p| p.value <= half)
+        .map_or(0, |i| i + 1);
+    let right = points[max_index..]
+        .iter()
+        .position(|p| p.value <= half)
+        .map_or(points.len(), |i| max_index + i);
+    if right - left < 3 {
+        return None;
+    }
+
+    // Relative to the maximum for a well conditioned system
+    let mut normal = Matrix3::<f64>::zeros();
+    let mut rhs = Vector3::<f64>::zeros();
+    for p in &points[left..right] {
+        let x = (p.wavelength - max.wavelength) as f64;
+        let powers = Vector3::new(1., x, x * x);
+        normal += powers * powers.transpose();
+        rhs += powers * transform(p.value as f64);
+    }
+    let [a, b, c] = normal.lu().solve(&rhs)?.into();
+    let vertex = a - b * b / (4. * c);
+    let center = max.wavelength as f64 - b / (2. * c);
+    let (height, fwhm, area) = match shape {
+        PeakFit::Gaussian if c < 0. => {
+            let sigma = (-1. / (2. * c)).sqrt();
+            let height = vertex.exp();
+            (
+                height,
+                2. * (2. * std::f64::consts::LN_2).sqrt() * sigma,
+                height * sigma * (2. * std::f64::consts::PI).sqrt(),
+            )
+        }
+        PeakFit::Lorentzian if c > 0. && vertex > 0. => {
+            let gamma = (vertex / c).sqrt();
+            let height = vertex.recip();
+            (height, 2. * gamma, std::f64::consts::PI * height * gamma)
+        }
+        _ => return None,
+    };
+    Some(FittedPeak {
+        center: center as f32,
+        height: height as f32,
+        fwhm: fwhm as f32,
+        area: area as f32,
+    })
+}
+
+/// Iterations of the ALS reweighting, it usually converges in less
+const ALS_ITERATIONS: usize = 10;
+
+/// Baseline of the values with the configured correction, `None` if it is off
+pub fn estimate_baseline(values: &[f32], config: &PostprocessingConfig) -> Option<Vec<f32>> {
+    match config.baseline_correction {
+        BaselineCorrection::Off => None,
+        BaselineCorrection::Als => Some(als_baseline(
+            values,
+            10f64.powf(config.baseline_smoothness as f64),
+            config.baseline_asymmetry.clamp(1e-4, 0.5) as f64,
+        )),
+        BaselineCorrection::RollingBall => {
+            Some(rolling_ball_baseline(values, config.baseline_radius))
+        }
+    }
+}
+
+/// Asymmetric least squares baseline after Eilers and Boelens
+///
+/// Solves `(W + lambda D'D) z = W y` with the second difference matrix `D`, points above the
+/// baseline are weighted with `p` and the others with `1 - p`.
+fn als_baseline(values: &[f32], lambda: f64, p: f64) -> Vec<f32> {
+    let n = values.len();
+    if n < 3 {
+        return values.to_vec();
+    }
+    // Diagonal and upper bands of lambda D'D
+    let mut bands = [vec![0.; n], vec![0.; n], vec![0.; n]];
+    let difference = [1., -2., 1.];
+    for k in 0..n - 2 {
+        for (a, ca) in difference.iter().enumerate() {
+            for (b, cb) in difference.iter().enumerate().skip(a) {
+                bands[b - a][k + a] += lambda * ca * cb;
+            }
+        }
+    }
+    let y: Vec<f64> = values.iter().map(|&v| v as f64).collect();
+    let mut weights = vec![1.; n];
+    let mut baseline = y.clone();
+    for _ in 0..ALS_ITERATIONS {
+        let diagonal: Vec<f64> = bands[0].iter().zip(&weights).map(|(a, w)| a + w).collect();
+        let rhs = y.iter().zip(&weights).map(|(y, w)| y * w).collect();
+        baseline = solve_pentadiagonal([&diagonal, &bands[1], &bands[2]], rhs);
+        let new_weights: Vec<f64> = y
+            .iter()
+            .zip(&baseline)
+            .map(|(y, z)| if y > z { p } else { 1. - p })
+            .collect();
+        if new_weights == weights {
+            break;
+        }
+        weights = new_weights;
+    }
+    baseline.into_iter().map(|v| v as f32).collect()
+}
+
+/// Solve `A x = b` by Cholesky decomposition for a symmetric positive definite matrix given by
+/// its diagonal and two upper bands
+fn solve_pentadiagonal(bands: [&[f64]; 3], mut b: Vec<f64>) -> Vec<f64> {
+    let n = b.len();
+    // Diagonal and lower bands of the factor, l[d][i] is the element in row i and column i - d
+    let mut l = [vec![0.; n], vec![0.; n], vec![0.; n]];
+    for i in 0..n {
+        if i >= 2 {
+            l[2][i] = bands[2][i - 2] / l[0][i - 2];
+        }
+        if i >= 1 {
+            l[1][i] = (bands[1][i - 1] - l[2][i] * l[1][i - 1]) / l[0][i - 1];
+        }
+        l[0][i] = (bands[0][i] - l[2][i].powi(2) - l[1][i].powi(2)).sqrt();
+    }
+    for i in 0..n {
+        let previous = if i >= 2 { l[2][i] * b[i - 2] } else { 0. }
+            + if i >= 1 { l[1][i] * b[i - 1] } else { 0. };
+        b[i] = (b[i] - previous) / l[0][i];
+    }
+    for i in (0..n).rev() {
+        let next = if i + 1 < n {
+            l[1][i + 1] * b[i + 1]
+        } else {
+            0.
+        } + if i + 2 < n {
+            l[2][i + 2] * b[i + 2]
+        } else {
+            0.
+        };
+        b[i] = (b[i] - next) / l[0][i];
+    }
+    b
+}
+
+/// Opening by a flat window of the radius, smoothed by a moving average of the same radius
+fn rolling_ball_baseline(values: &[f32], radius: usize) -> Vec<f32> {
+    let n = values.len();
+    let window = |i: usize| i.saturating_sub(radius)..(i + radius + 1).min(n);
+    let minimum: Vec<f32> = (0..n)
+        .map(|i| {
+            values[window(i)]
+                .iter()
+                .copied()
+                .fold(f32::INFINITY, f32::min)
+        })
+        .collect();
+    let opened: Vec<f32> = (0..n)
+        .map(|i| {
+            minimum[window(i)]
+                .iter()
+                .copied()
+                .fold(f32::NEG_INFINITY, f32::max)
+        })
+        .collect();
+    (0..n)
+        .map(|i| opened[window(i)].iter().sum::<f32>() / window(i).len() as f32)
+        .collect()
+}
+
+/// Subtract the configured baseline from every channel within the valid range
+fn subtract_baseline(spectrum: &mut Spectrum, config: &SpectrometerConfig) {
+    let valid_indices = config.spectrum_calibration.valid_indices(spectrum.ncols());
+    for mut row in spectrum.row_iter_mut() {
+        let values: Vec<f32> = row
+            .iter()
+            .skip(valid_indices.start)
+            .take(valid_indices.len())
+            .copied()
+            .collect();
+        if let Some(baseline) = estimate_baseline(&values, &config.postprocessing_config) {
+            row.iter_mut()
+                .skip(valid_indices.start)
+                .zip(baseline)
+                .for_each(|(v, b)| *v -= b);
+        }
+    }
+}
+
+/// Round a spectrum of the pipeline to single precision and subtract the baseline
+fn finish_spectrum<T: PipelineScalar>(
+    spectrum: &PipelineSpectrum<T>,
+    config: &SpectrometerConfig,
+) -> Spectrum {
+    let mut spectrum = spectrum.map(T::to_single);
+    subtract_baseline(&mut spectrum, config);
+    spectrum
+}
+
+/// Vertical brightness centroid of the window extended by `margin` rows above and below
+pub fn vertical_centroid(
+    frame: &ImageBuffer<Rgb<u8>, Vec<u8>>,
+    window: &SpectrumWindow,
+    margin: u32,
+) -> Option<f32> {
+    let (width, height) = frame.dimensions();
+    let left = (window.offset.x as u32).min(width);
+    let right = ((window.offset.x + window.size.x) as u32).min(width);
+    let top = (window.offset.y as u32).saturating_sub(margin);
+    let bottom = ((window.offset.y + window.size.y) as u32 + margin).min(height);
+    if left >= right || top >= bottom {
+        return None;
+    }
+
+    let row_profile: Vec<f32> = (top..bottom)
+        .map(|y| {
+            (left..right)
+                .map(|x| {
+                    frame
+                        .get_pixel(x, y)
+                        .channels()
+                        .iter()
+                        .map(|&v| v as f32)
+                        .sum::<f32>()
+                })
+                .sum()
+        })
+        .collect();
+    // Remove the background so it does not pull the centroid to the search center
+    let background = row_profile.iter().cloned().fold(f32::INFINITY, f32::min);
+    let (weighted, total) =
+        row_profile
+            .iter()
+            .enumerate()
+            .fold((0., 0.), |(weighted, total), (i, &v)| {
+                let w = v - background;
+                (weighted + (top as usize + i) as f32 * w, total + w)
+            });
+    (total > 0.).then(|| weighted / total)
 }
 
 pub struct SpectrumContainer {
     spectrum: Spectrum,
-    spectrum_buffer: VecDeque<SpectrumRgb>,
+    /// The spectrum before rounding to single precision, only with double precision
+    precise_spectrum: Option<SpectrumF64>,
+    unfiltered_spectrum: Option<Spectrum>,
+    spectrum_buffer: VecDeque<Timestamped<SpectrumRgb>>,
     zero_reference: Option<Spectrum>,
-    spectrum_rx: Receiver<SpectrumRgb>,
+    /// The zero reference before rounding, if it was set from a double-precision spectrum
+    precise_zero_reference: Option<SpectrumF64>,
+    raw_zero_reference: Option<SpectrumRgb>,
+    raw_zero_reference_accumulator: Option<(SpectrumRgb, usize)>,
+    spectrum_rx: Receiver<Timestamped<SpectrumRgb>>,
+    last_update: Instant,
+    latency: Option<Latency>,
+    last_start: Option<SystemTime>,
+    flash_trigger: Option<FlashTrigger>,
+    flash_events: Vec<FlashEvent>,
+    last_buffer_reset: Option<(Instant, BufferClearReason)>,
+    /// Running sum of the buffer for the incremental average
+    buffer_sum: Option<SpectrumRgb>,
+    /// Buffer length the running sum belongs to
+    buffer_sum_len: usize,
+    /// Updates since the running sum was recomputed from the buffer
+    buffer_sum_updates: usize,
 }
 
+/// The running sum is recomputed after this many updates to get rid of rounding errors
+const BUFFER_SUM_RESYNC: usize = 1000;
+
 impl SpectrumContainer {
-    pub fn new(spectrum_rx: Receiver<SpectrumRgb>) -> Self {
+    pub fn new(spectrum_rx: Receiver<Timestamped<SpectrumRgb>>) -> Self {
         SpectrumContainer {
             spectrum: Spectrum::zeros(0),
+            precise_spectrum: None,
+            unfiltered_spectrum: None,
             spectrum_buffer: VecDeque::with_capacity(100),
             zero_reference: None,
+            precise_zero_reference: None,
+            raw_zero_reference: None,
+            raw_zero_reference_accumulator: None,
             spectrum_rx,
+            last_update: Instant::now(),
+            latency: None,
+            last_start: None,
+            flash_trigger: None,
+            flash_events: Vec::new(),
+            last_buffer_reset: None,
+            buffer_sum: None,
+            buffer_sum_len: 0,
+            buffer_sum_updates: 0,
         }
     }
 
     pub fn clear_buffer(&mut self) {
         self.spectrum_buffer.clear();
+        self.buffer_sum = None;
+    }
+
+    /// Clear the buffer if the policy asks for it on this change
+    pub fn invalidate_buffer(&mut self, policy: BufferClearPolicy, reason: BufferClearReason) {
+        if !reason.clears(policy) {
+            return;
+        }
+        self.clear_buffer();
+        // Nothing to point out when a new acquisition starts
+        if reason != BufferClearReason::Restart {
+            self.last_buffer_reset = Some((Instant::now(), reason));
+        }
     }
 
-    pub fn update(&mut self, config: &SpectrometerConfig) {
-        if let Ok(spectrum) = self.spectrum_rx.try_recv() {
-            self.update_spectrum(spectrum, config);
+    /// Reason of the last buffer reset if it happened within the given time
+    pub fn recent_buffer_reset(&self, within: Duration) -> Option<BufferClearReason> {
+        self.last_buffer_reset
+            .filter(|(at, _)| at.elapsed() < within)
+            .map(|(_, reason)| reason)
+    }
+
+    /// A spectrum is waiting to be received by [`Self::update`]
+    pub fn has_pending(&self) -> bool {
+        !self.spectrum_rx.is_empty()
+    }
+
+    /// Returns true if a new spectrum was received
+    ///
+    /// In low power mode all pending spectra are processed, otherwise one per call.
+    pub fn update(&mut self, config: &SpectrometerConfig) -> bool {
+        let mut updated = false;
+        while let Some(received) = self.receive_next(config) {
+            updated |= received;
+            if !config.low_power_mode {
+                break;
+            }
         }
+        updated
     }
 
-    pub fn update_spectrum(&mut self, mut spectrum: SpectrumRgb, config: &SpectrometerConfig) {
+    /// Receive the next pending spectrum
+    ///
+    /// Returns None if no spectrum is pending, otherwise whether it updated the spectrum.
+    pub fn receive_next(&mut self, config: &SpectrometerConfig) -> Option<bool> {
+        let spectrum = self.spectrum_rx.try_recv().ok()?;
+        Some(self.receive(spectrum, config))
+    }
+
+    fn receive(&mut self, spectrum: Timestamped<SpectrumRgb>, config: &SpectrometerConfig) -> bool {
+        let received = SystemTime::now();
+        self.last_update = Instant::now();
+        let (start, end) = (spectrum.start, spectrum.end);
+        let updated = match self.flash_trigger.as_mut() {
+            Some(trigger) => {
+                match trigger.push(spectrum.value, start, &config.flash_trigger_config) {
+                    Some((event, event_spectrum)) => {
+                        // Show only the event, not an average with earlier events
+                        self.spectrum_buffer.clear();
+                        self.update_timestamped_spectrum(
+                            Timestamped {
+                                start,
+                                end,
+                                sequence: spectrum.sequence,
+                                frame_metadata: spectrum.frame_metadata,
+                                saturation: spectrum.saturation,
+                                channel_peaks: spectrum.channel_peaks,
+                                value: event_spectrum,
+                            },
+                            config,
+                        );
+                        self.flash_events.push(event);
+                        true
+                    }
+                    None => false,
+                }
+            }
+            None => {
+                self.update_timestamped_spectrum(spectrum, config);
+                true
+            }
+        };
+        let frame_interval = self
+            .last_start
+            .and_then(|last_start| start.duration_since(last_start).ok())
+            .unwrap_or_default();
+        self.last_start = Some(start);
+        self.latency = Some(Latency {
+            capture: end.duration_since(start).unwrap_or_default(),
+            transfer: received.duration_since(end).unwrap_or_default(),
+            postprocessing: self.last_update.elapsed(),
+            frame_interval,
+        });
+        updated
+    }
+
+    /// In flash trigger mode only events update the spectrum, which is frozen in between
+    pub fn set_flash_trigger(&mut self, active: bool) {
+        self.flash_trigger = active.then(FlashTrigger::default);
+    }
+
+    pub fn is_flash_trigger_active(&self) -> bool {
+        self.flash_trigger.is_some()
+    }
+
+    pub fn flash_events(&self) -> &[FlashEvent] {
+        &self.flash_events
+    }
+
+    pub fn clear_flash_events(&mut self) {
+        self.flash_events.clear();
+    }
+
+    /// Latency of the most recently received spectrum
+    pub fn latency(&self) -> Option<Latency> {
+        self.latency
+    }
+
+    pub fn spectrum(&self) -> &Spectrum {
+        &self.spectrum
+    }
+
+    /// The spectrum without rounding to single precision if the double-precision pipeline
+    /// produced it
+    pub fn precise_spectrum(&self) -> SpectrumF64 {
+        self.precise_spectrum
+            .clone()
+            .unwrap_or_else(|| self.spectrum.clone().cast())
+    }
+
+    /// Replace the current spectrum, e.g. for playback of recorded spectra
+    pub fn set_spectrum(&mut self, spectrum: Spectrum) {
+        self.spectrum = spectrum;
+        self.precise_spectrum = None;
+        self.unfiltered_spectrum = None;
+    }
+
+    pub fn reset_last_update(&mut self) {
+        self.last_update = Instant::now();
+    }
+
+    pub fn time_since_last_update(&self) -> Duration {
+        self.last_update.elapsed()
+    }
+
+    /// Capture span of the averaged spectrum from the start of the oldest to the end of the
+    /// newest buffered frame
+    pub fn time_range(&self) -> Option<(SystemTime, SystemTime)> {
+        Some((
+            self.spectrum_buffer.back()?.start,
+            self.spectrum_buffer.front()?.end,
+        ))
+    }
+
+    /// Time in seconds each buffered spectrum stands for, from its start to the start of the
+    /// next newer one
+    ///
+    /// The newest spectrum gets the mean interval of the others.
+    fn buffer_intervals(&self) -> Vec<f32> {
+        let mut intervals: Vec<f32> = self
+            .spectrum_buffer
+            .iter()
+            .zip(self.spectrum_buffer.iter().skip(1))
+            .map(|(newer, older)| {
+                newer
+                    .start
+                    .duration_since(older.start)
+                    .unwrap_or_default()
+                    .as_secs_f32()
+            })
+            .collect();
+        let newest = if intervals.is_empty() {
+            1.
+        } else {
+            intervals.iter().sum::<f32>() / intervals.len() as f32
+        };
+        intervals.insert(0, newest);
+        intervals
+    }
+
+    /// Weights of the buffered spectra for averaging, summing up to one
+    fn buffer_weights(&self, time_weighted: bool) -> Vec<f32> {
+        let intervals = self.buffer_intervals();
+        let total: f32 = intervals.iter().sum();
+        if time_weighted && total > 0. {
+            intervals.iter().map(|i| i / total).collect()
+        } else {
+            vec![1. / intervals.len() as f32; intervals.len()]
+        }
+    }
+
+    /// Longest frame interval in the averaging buffer if it exceeds twice the median, i.e.
+    /// frames were dropped
+    pub fn coverage_gap(&self) -> Option<Duration> {
+        let mut intervals = self.buffer_intervals();
+        // The newest interval is only estimated
+        intervals.remove(0);
+        if intervals.len() < 2 {
+            return None;
+        }
+        intervals.sort_by(f32::total_cmp);
+        let median = intervals[intervals.len() / 2];
+        let longest = *intervals.last()?;
+        (longest > median * COVERAGE_GAP_RATIO).then(|| Duration::from_secs_f32(longest))
+    }
+
+    /// Highest channel value of the newest buffered spectrum as fraction of full scale
+    pub fn newest_peak(&self) -> Option<f32> {
+        // Every channel is normalized to a third of full scale
+        self.spectrum_buffer.front().map(|s| s.value.max() * 3.)
+    }
+
+    /// Sequence number of the newest buffered frame
+    pub fn sequence(&self) -> Option<u64> {
+        self.spectrum_buffer.front()?.sequence
+    }
+
+    /// Camera metadata of the newest buffered frame
+    pub fn frame_metadata(&self) -> Option<FrameMetadata> {
+        self.spectrum_buffer.front()?.frame_metadata
+    }
+
+    /// Capture time of the newest buffered frame, now if the driver did not report it
+    pub fn capture_time(&self) -> SystemTime {
+        self.frame_metadata()
+            .and_then(|metadata| metadata.capture_time)
+            .unwrap_or_else(SystemTime::now)
+    }
+
+    /// Exposure settings of the newest buffered frame
+    pub fn exposure(&self) -> Option<Exposure> {
+        self.frame_metadata().map(|metadata| metadata.exposure)
+    }
+
+    /// Highest saturation of the buffered frames, the average is clipped if it is above zero
+    pub fn saturation(&self) -> Option<f32> {
+        self.spectrum_buffer
+            .iter()
+            .filter_map(|s| s.saturation)
+            .reduce(f32::max)
+    }
+
+    /// Highest channel peaks of the buffered frames, 1 is full scale
+    pub fn channel_peaks(&self) -> Option<[f32; 3]> {
+        self.spectrum_buffer
+            .iter()
+            .map(|s| s.channel_peaks)
+            .reduce(max_channel_peaks)
+            .flatten()
+    }
+
+    /// Add a spectrum captured now to the buffer
+    pub fn update_spectrum(&mut self, spectrum: SpectrumRgb, config: &SpectrometerConfig) {
+        let now = SystemTime::now();
+        self.update_timestamped_spectrum(
+            Timestamped {
+                start: now,
+                end: now,
+                sequence: None,
+                frame_metadata: None,
+                saturation: None,
+                channel_peaks: None,
+                value: spectrum,
+            },
+            config,
+        );
+    }
+
+    pub fn update_timestamped_spectrum(
+        &mut self,
+        spectrum: Timestamped<SpectrumRgb>,
+        config: &SpectrometerConfig,
+    ) {
+        let Timestamped {
+            start,
+            end,
+            sequence,
+            frame_metadata,
+            saturation,
+            channel_peaks,
+            value: mut spectrum,
+        } = spectrum;
         let ncols = spectrum.ncols();
 
         // Clear buffer and zero reference on dimension change
         if let Some(s) = self.spectrum_buffer.front() {
-            if s.ncols() != ncols {
+            if s.value.ncols() != ncols {
+                self.spectrum_buffer.clear();
+                self.clear_zero_reference();
+            }
+        }
+
+        // Spectra used for the zero reference are not part of the measurement
+        if let Some((sum, count)) = self.raw_zero_reference_accumulator.as_mut() {
+            if sum.ncols() != ncols {
+                *sum = SpectrumRgb::zeros(ncols);
+                *count = 0;
+            }
+            *sum += &spectrum;
+            *count += 1;
+            if *count >= config.postprocessing_config.spectrum_buffer_size {
+                self.raw_zero_reference = Some(&*sum / *count as f32);
+                self.raw_zero_reference_accumulator = None;
                 self.spectrum_buffer.clear();
-                self.zero_reference = None;
             }
+            return;
         }
+        if let Some(raw_zero_reference) = self.raw_zero_reference.as_ref() {
+            if raw_zero_reference.ncols() == ncols {
+                spectrum -= raw_zero_reference;
+            }
+        }
+
+        let linearize = config.spectrum_calibration.linearize;
+        let processing_order = config.postprocessing_config.processing_order;
 
-        if config.spectrum_calibration.linearize != Linearize::Off {
+        if linearize != Linearize::Off && processing_order == ProcessingOrder::LinearizeThenAverage
+        {
             spectrum
                 .iter_mut()
-                .for_each(|v| *v = config.spectrum_calibration.linearize.linearize(*v));
+                .for_each(|v| *v = linearize.linearize(*v));
         }
 
-        self.spectrum_buffer.push_front(spectrum);
-        self.spectrum_buffer
-            .truncate(config.postprocessing_config.spectrum_buffer_size);
+        let postprocessing_config = &config.postprocessing_config;
+        let incremental = (postprocessing_config.incremental_average || config.low_power_mode)
+            && !postprocessing_config.time_weighted_average
+            && !postprocessing_config.double_precision;
+        if incremental {
+            let sum_valid = self.buffer_sum.as_ref().is_some_and(|sum| {
+                sum.ncols() == ncols
+                    && self.buffer_sum_len == self.spectrum_buffer.len()
+                    && self.buffer_sum_updates < BUFFER_SUM_RESYNC
+            });
+            if !sum_valid {
+                self.buffer_sum = Some(
+                    self.spectrum_buffer
+                        .iter()
+                        .fold(SpectrumRgb::zeros(ncols), |sum, s| sum + &s.value),
+                );
+                self.buffer_sum_updates = 0;
+            }
+            if let Some(sum) = self.buffer_sum.as_mut() {
+                *sum += &spectrum;
+            }
+        } else {
+            self.buffer_sum = None;
+        }
 
-        let mut combined_buffer = self
-            .spectrum_buffer
-            .par_iter()
-            .cloned()
-            .reduce(|| SpectrumRgb::from_element(ncols, 0.), |a, b| a + b)
-            / self.spectrum_buffer.len() as f32;
+        self.spectrum_buffer.push_front(Timestamped {
+            start,
+            end,
+            sequence,
+            frame_metadata,
+            saturation,
+            channel_peaks,
+            value: spectrum,
+        });
+        while self.spectrum_buffer.len() > postprocessing_config.spectrum_buffer_size {
+            if let (Some(removed), Some(sum)) =
+                (self.spectrum_buffer.pop_back(), self.buffer_sum.as_mut())
+            {
+                *sum -= &removed.value;
+            }
+        }
+        self.buffer_sum_len = self.spectrum_buffer.len();
+        self.buffer_sum_updates += 1;
+
+        if config.postprocessing_config.double_precision {
+            let (spectrum, unfiltered_spectrum) = self.combine_buffer::<f64>(ncols, config);
+            self.spectrum = finish_spectrum(&spectrum, config);
+            // The baseline is estimated in single precision and subtracted from both
+            let baseline = spectrum.map(|v| v as f32) - &self.spectrum;
+            self.precise_spectrum = Some(spectrum - baseline.cast::<f64>());
+            self.unfiltered_spectrum = unfiltered_spectrum.map(|s| finish_spectrum(&s, config));
+        } else {
+            let (spectrum, unfiltered_spectrum) = self.combine_buffer::<f32>(ncols, config);
+            self.spectrum = finish_spectrum(&spectrum, config);
+            self.precise_spectrum = None;
+            self.unfiltered_spectrum = unfiltered_spectrum.map(|s| finish_spectrum(&s, config));
+        }
+    }
+
+    /// Average the buffer and apply gains, scaling, filter and zero reference in `T`
+    ///
+    /// The running sum is used for the average if available. If the filter is active the
+    /// spectrum without filter is returned as well. The baseline is not subtracted yet.
+    fn combine_buffer<T: PipelineScalar>(
+        &self,
+        ncols: usize,
+        config: &SpectrometerConfig,
+    ) -> (PipelineSpectrum<T>, Option<PipelineSpectrum<T>>) {
+        let linearize = config.spectrum_calibration.linearize;
+        let mut combined_buffer = match self.buffer_sum.as_ref() {
+            Some(sum) => {
+                sum.map(T::from_single)
+                    * T::from_single(1. / self.spectrum_buffer.len().max(1) as f32)
+            }
+            None => {
+                let weights =
+                    self.buffer_weights(config.postprocessing_config.time_weighted_average);
+                self.spectrum_buffer
+                    .par_iter()
+                    .zip(weights.par_iter())
+                    .map(|(s, &w)| s.value.map(T::from_single) * T::from_single(w))
+                    .reduce(|| OMatrix::<T, U3, Dyn>::zeros(ncols), |a, b| a + b)
+            }
+        };
 
+        if linearize != Linearize::Off
+            && config.postprocessing_config.processing_order
+                == ProcessingOrder::AverageThenLinearize
+        {
+            combined_buffer
+                .iter_mut()
+                .for_each(|v| *v = linearize.linearize(*v));
+        }
+
+        let calibration = &config.spectrum_calibration;
         combined_buffer.set_row(
             0,
-            &(combined_buffer.row(0) * config.spectrum_calibration.gain_r),
+            &(combined_buffer.row(0) * T::from_single(calibration.gain_r)),
         );
         combined_buffer.set_row(
             1,
-            &(combined_buffer.row(1) * config.spectrum_calibration.gain_g),
+            &(combined_buffer.row(1) * T::from_single(calibration.gain_g)),
         );
         combined_buffer.set_row(
             2,
-            &(combined_buffer.row(2) * config.spectrum_calibration.gain_b),
+            &(combined_buffer.row(2) * T::from_single(calibration.gain_b)),
         );
 
-        let mut current_spectrum = Spectrum::from_rows(&[
+        let mut sum = combined_buffer.row_sum();
+        if calibration.scaling.is_some() {
+            sum.iter_mut().enumerate().for_each(|(i, v)| {
+                *v *= T::from_single(calibration.get_scaling_factor_from_index(i));
+            });
+        }
+        let mut current_spectrum = OMatrix::<T, U4, Dyn>::from_rows(&[
             combined_buffer.row(0).clone_owned(),
             combined_buffer.row(1).clone_owned(),
             combined_buffer.row(2).clone_owned(),
-            if config.spectrum_calibration.scaling.is_some() {
-                let mut sum = combined_buffer.row_sum();
-                sum.iter_mut().enumerate().for_each(|(i, v)| {
-                    *v *= config.spectrum_calibration.get_scaling_factor_from_index(i);
-                });
-                sum / 3.
-            } else {
-                combined_buffer.row_sum() / 3.
-            },
+            sum / T::from_single(3.),
         ]);
 
+        let mut unfiltered_spectrum = None;
         if config.postprocessing_config.spectrum_filter_active {
+            unfiltered_spectrum = Some(current_spectrum.clone());
             let cutoff = config
                 .postprocessing_config
                 .spectrum_filter_cutoff
                 .clamp(0.001, 1.);
-            let fs: Hertz<f32> = 2.0.hz();
-            let f0: Hertz<f32> = cutoff.hz();
-
-            let coeffs =
-                Coefficients::<f32>::from_params(Type::LowPass, fs, f0, Q_BUTTERWORTH_F32).unwrap();
-            for mut channel in current_spectrum.row_iter_mut() {
-                let mut biquad = DirectForm2Transposed::<f32>::new(coeffs);
-                for sample in channel.iter_mut() {
-                    *sample = biquad.run(*sample);
-                }
-                // Apply filter in reverse to compensate phase error
-                for sample in channel.iter_mut().rev() {
-                    *sample = biquad.run(*sample);
-                }
-            }
+            T::low_pass_filter(&mut current_spectrum, cutoff);
         }
 
-        if let Some(zero_reference) = self.zero_reference.as_ref() {
-            current_spectrum -= zero_reference;
+        let zero_reference = match (&self.precise_zero_reference, &self.zero_reference) {
+            (Some(precise), _) => Some(precise.map(T::from_double)),
+            (None, Some(zero_reference)) => Some(zero_reference.map(T::from_single)),
+            (None, None) => None,
         }
-
-        self.spectrum = current_spectrum;
+        // A restored zero reference may not match the current window
+        .filter(|zero_reference| zero_reference.ncols() == ncols);
+        let subtract_zero_reference = |mut spectrum: OMatrix<T, U4, Dyn>| {
+            if let Some(zero_reference) = zero_reference.as_ref() {
+                spectrum -= zero_reference;
+            }
+            spectrum
+        };
+        (
+            subtract_zero_reference(current_spectrum),
+            unfiltered_spectrum.map(subtract_zero_reference),
+        )
     }
 
+    /// Local maxima or minima that are the extreme within the filter window
+    ///
+    /// With a peak fit the peaks are moved to the center and height of the fitted line shape,
+    /// peaks the fit fails for are kept at the maximum.
     pub fn spectrum_to_peaks_and_dips(
         &self,
         peaks: bool,
         config: &SpectrometerConfig,
     ) -> Vec<SpectrumPoint> {
+        let found = self.find_peaks_and_dips(peaks, config);
+        if !peaks || config.view_config.peak_fit == PeakFit::Off {
+            return found;
+        }
+        let points = self.sorted_sum(config);
+        found
+            .into_iter()
+            .map(|peak| match Self::fit_around(&peak, &points, config) {
+                Some(fit) => SpectrumPoint {
+                    wavelength: fit.center,
+                    value: fit.height,
+                },
+                None => peak,
+            })
+            .collect()
+    }
+
+    /// Peaks with the configured line shape fitted, empty without a peak fit
+    pub fn spectrum_to_fitted_peaks(&self, config: &SpectrometerConfig) -> Vec<FittedPeak> {
+        if config.view_config.peak_fit == PeakFit::Off {
+            return Vec::new();
+        }
+        let points = self.sorted_sum(config);
+        self.find_peaks_and_dips(true, config)
+            .iter()
+            .filter_map(|peak| Self::fit_around(peak, &points, config))
+            .collect()
+    }
+
+    fn sorted_sum(&self, config: &SpectrometerConfig) -> Vec<SpectrumPoint> {
+        let mut points = self.get_spectrum_channel(3, config);
+        points.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        points
+    }
+
+    /// Fit the points within the find window of the peak
+    fn fit_around(
+        peak: &SpectrumPoint,
+        points: &[SpectrumPoint],
+        config: &SpectrometerConfig,
+    ) -> Option<FittedPeak> {
+        let width = (config.view_config.peaks_dips_find_window as f32 + 0.5)
+            * config.spectrum_calibration.get_wavelength_delta().abs();
+        let start = points.partition_point(|p| p.wavelength < peak.wavelength - width);
+        let end = points.partition_point(|p| p.wavelength <= peak.wavelength + width);
+        fit_peak(&points[start..end], config.view_config.peak_fit)
+    }
+
+    fn find_peaks_and_dips(&self, peaks: bool, config: &SpectrometerConfig) -> Vec<SpectrumPoint> {
         let mut peaks_dips = Vec::new();
 
+        let valid_indices = config
+            .spectrum_calibration
+            .valid_indices(self.spectrum.ncols());
         let spectrum: Vec<_> = self.spectrum.row(3).iter().cloned().collect();
+        let spectrum = &spectrum[valid_indices.clone()];
 
         let windows_size = config.view_config.peaks_dips_find_window * 2 + 1;
         let mid_index = (windows_size - 1) / 2;
 
-        for (i, win) in spectrum.as_slice().windows(windows_size).enumerate() {
+        for (i, win) in spectrum
+            .windows(windows_size)
+            .enumerate()
+            .map(|(i, win)| (i + valid_indices.start, win))
+        {
             let (lower, upper) = win.split_at(mid_index);
 
             if lower.iter().chain(upper[1..].iter()).all(|&v| {
@@ -241,12 +1951,35 @@ impl SpectrumContainer {
         &self,
         channel_index: usize,
         config: &SpectrometerConfig,
+    ) -> Vec<SpectrumPoint> {
+        Self::spectrum_channel_points(&self.spectrum, channel_index, config)
+    }
+
+    /// Channel of the last spectrum before the low-pass filter, if the filter is active
+    pub fn get_unfiltered_spectrum_channel(
+        &self,
+        channel_index: usize,
+        config: &SpectrometerConfig,
+    ) -> Option<Vec<SpectrumPoint>> {
+        self.unfiltered_spectrum
+            .as_ref()
+            .map(|s| Self::spectrum_channel_points(s, channel_index, config))
+    }
+
+    /// Channel of any spectrum, e.g. a snapshot, limited to the valid range
+    pub fn spectrum_channel_points(
+        spectrum: &Spectrum,
+        channel_index: usize,
+        config: &SpectrometerConfig,
     ) -> Vec<SpectrumPoint> {
         let calibration = &config.spectrum_calibration;
-        self.spectrum
+        let valid_indices = calibration.valid_indices(spectrum.ncols());
+        spectrum
             .row(channel_index)
             .iter()
             .enumerate()
+            .skip(valid_indices.start)
+            .take(valid_indices.len())
             .map(|(i, p)| {
                 let wavelength = calibration.get_wavelength_from_index(i);
                 let value = *p;
@@ -276,40 +2009,148 @@ impl SpectrumContainer {
         );
     }
 
+    /// Adjust the channel gains so that r, g and b coincide around `wavelength`
+    ///
+    /// The channels are averaged over `width` nm, but at least over the nearest column. Their
+    /// mean level is kept.
+    pub fn balance_channels(
+        &self,
+        calibration: &mut SpectrumCalibration,
+        wavelength: f32,
+        width: f32,
+    ) -> Result<(), String> {
+        let half_width = (width / 2.).max(calibration.get_wavelength_delta().abs() / 2.);
+        let columns: Vec<_> = (0..self.spectrum.ncols())
+            .filter(|&i| {
+                (calibration.get_wavelength_from_index(i) - wavelength).abs() <= half_width
+            })
+            .collect();
+        if columns.is_empty() {
+            return Err(format!("No spectrum at {} nm", wavelength));
+        }
+        let means: Vec<f32> = (0..3)
+            .map(|r| {
+                columns.iter().map(|&c| self.spectrum[(r, c)]).sum::<f32>() / columns.len() as f32
+            })
+            .collect();
+        if means.iter().any(|&m| m <= f32::EPSILON) {
+            return Err(format!(
+                "Not all channels have signal at {} nm, cannot balance",
+                wavelength
+            ));
+        }
+        let target = means.iter().sum::<f32>() / 3.;
+        calibration.gain_r *= target / means[0];
+        calibration.gain_g *= target / means[1];
+        calibration.gain_b *= target / means[2];
+        Ok(())
+    }
+
     pub fn has_zero_reference(&self) -> bool {
         self.zero_reference.is_some()
+            || self.raw_zero_reference.is_some()
+            || self.raw_zero_reference_accumulator.is_some()
+    }
+
+    pub fn is_capturing_zero_reference(&self) -> bool {
+        self.raw_zero_reference_accumulator.is_some()
     }
 
-    pub fn set_zero_reference(&mut self) {
-        self.zero_reference = Some(self.spectrum.clone());
+    pub fn zero_references(&self) -> (Option<&Spectrum>, Option<&SpectrumRgb>) {
+        (
+            self.zero_reference.as_ref(),
+            self.raw_zero_reference.as_ref(),
+        )
+    }
+
+    pub fn restore_zero_references(
+        &mut self,
+        zero_reference: Option<Spectrum>,
+        raw_zero_reference: Option<SpectrumRgb>,
+    ) {
+        self.zero_reference = zero_reference;
+        self.precise_zero_reference = None;
+        self.raw_zero_reference = raw_zero_reference;
+        self.raw_zero_reference_accumulator = None;
+    }
+
+    /// Use the current spectrum as zero reference.
+    ///
+    /// With `raw` the next incoming spectra are averaged per channel instead and subtracted
+    /// before linearization and gains.
+    pub fn set_zero_reference(&mut self, raw: bool) {
+        if raw {
+            self.raw_zero_reference_accumulator =
+                Some((SpectrumRgb::zeros(self.spectrum.ncols()), 0));
+        } else {
+            self.zero_reference = Some(self.spectrum.clone());
+            self.precise_zero_reference = self.precise_spectrum.clone();
+        }
     }
 
     pub fn clear_zero_reference(&mut self) {
         self.zero_reference = None;
+        self.precise_zero_reference = None;
+        self.raw_zero_reference = None;
+        self.raw_zero_reference_accumulator = None;
     }
 
     pub fn write_to_csv(
         &self,
         path: &String,
         calibration: &SpectrumCalibration,
+        export_config: &ImportExportConfig,
+        metadata: &SampleMetadata,
+        value_comment: Option<&str>,
+        provenance: &Provenance,
     ) -> Result<(), String> {
-        let writer = csv::Writer::from_path(path);
-        match writer {
-            Ok(mut writer) => {
-                for p in self.spectrum_to_point_vec(calibration) {
-                    writer.serialize(p).unwrap();
+        let file = File::create(path).and_then(|mut file| {
+            for line in metadata.to_comment_lines() {
+                writeln!(file, "{}", line)?;
+            }
+            for line in provenance.to_comment_lines() {
+                writeln!(file, "{}", line)?;
+            }
+            if let Some(value_comment) = value_comment {
+                writeln!(file, "# Values: {}", value_comment)?;
+            }
+            if let Some(frame_metadata) = self.frame_metadata() {
+                for line in frame_metadata.to_comment_lines() {
+                    writeln!(file, "{}", line)?;
                 }
-                writer.flush().unwrap();
-                Ok(())
             }
-            Err(e) => Err(e.to_string()),
-        }
+            if let Some(color) = export_config.color_white_point.and_then(|white_point| {
+                export_color_coordinates(
+                    &self.spectrum_to_point_vec(calibration, &Default::default()),
+                    white_point,
+                )
+            }) {
+                for line in color.to_comment_lines() {
+                    writeln!(file, "{}", line)?;
+                }
+            }
+            Ok(file)
+        });
+        write_csv(
+            file.map_err(|e| e.to_string())?,
+            self.spectrum_to_point_vec(calibration, export_config),
+            export_config.decimal_comma,
+        )
     }
 
-    fn spectrum_to_point_vec(&self, calibration: &SpectrumCalibration) -> Vec<SpectrumExportPoint> {
-        self.spectrum
+    /// Current spectrum with wavelengths, processed according to the export options
+    pub fn spectrum_to_point_vec(
+        &self,
+        calibration: &SpectrumCalibration,
+        export_config: &ImportExportConfig,
+    ) -> Vec<SpectrumExportPoint> {
+        let valid_indices = calibration.valid_indices(self.spectrum.ncols());
+        let mut points: Vec<_> = self
+            .spectrum
             .column_iter()
             .enumerate()
+            .skip(valid_indices.start)
+            .take(valid_indices.len())
             .map(|(i, p)| {
                 let x = calibration.get_wavelength_from_index(i);
                 SpectrumExportPoint {
@@ -320,7 +2161,24 @@ impl SpectrumContainer {
                     sum: p[3],
                 }
             })
-            .collect()
+            .collect();
+        points.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+
+        if let Some(step) = export_config.wavelength_step.filter(|&s| s > 0.) {
+            points = resample_export_points(&points, step);
+        }
+        if let Some(range) = export_config.wavelength_range {
+            points.retain(|p| p.wavelength >= range.low && p.wavelength <= range.high);
+        }
+        if let Some(decimal_places) = export_config.decimal_places {
+            points = points.iter().map(|p| p.round(decimal_places)).collect();
+        }
+        points
+    }
+
+    /// Shape of the strongest peak of the sum spectrum
+    pub fn get_dominant_peak(&self, config: &SpectrometerConfig) -> Option<PeakShape> {
+        dominant_peak(&self.get_spectrum_channel(3, config))
     }
 
     pub fn get_spectrum_max_value(&self) -> Option<f32> {
@@ -331,6 +2189,8 @@ impl SpectrumContainer {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::{GainPresets, SpectrumCalibrationPoint, WavelengthRange};
+    use image::{Luma, Rgba};
     use rstest::*;
 
     #[fixture]
@@ -365,6 +2225,676 @@ mod tests {
         );
     }
 
+    #[rstest]
+    fn time_weighted_average(
+        mut spectrum_container: SpectrumContainer,
+        mut config: SpectrometerConfig,
+    ) {
+        config.postprocessing_config.time_weighted_average = true;
+        let at = |ms| SystemTime::UNIX_EPOCH + Duration::from_millis(ms);
+        // The second frame stands for the two dropped after it
+        for (start, value) in [(0, 0.3), (100, 0.6), (400, 0.3), (500, 0.3)] {
+            spectrum_container.update_timestamped_spectrum(
+                Timestamped {
+                    start: at(start),
+                    end: at(start + 10),
+                    sequence: None,
+                    frame_metadata: None,
+                    saturation: None,
+                    channel_peaks: None,
+                    value: SpectrumRgb::from_element(10, value),
+                },
+                &config,
+            );
+        }
+
+        // Intervals 100, 300, 100 and the mean of 500 / 3 for the newest
+        approx::assert_relative_eq!(
+            spectrum_container.spectrum()[(0, 0)],
+            (0.3 * 100. + 0.6 * 300. + 0.3 * 100. + 0.3 * 500. / 3.) / (500. + 500. / 3.),
+            epsilon = 1e-6
+        );
+        assert_eq!(
+            spectrum_container.coverage_gap().map(|gap| gap.as_millis()),
+            Some(300)
+        );
+    }
+
+    #[rstest]
+    fn incremental_average(
+        mut spectrum_container: SpectrumContainer,
+        mut config: SpectrometerConfig,
+    ) {
+        let mut full = self::spectrum_container();
+        let mut incremental_config = config.clone();
+        incremental_config.postprocessing_config.incremental_average = true;
+
+        for i in 0..25 {
+            if i == 15 {
+                // Shrinking the buffer drops several spectra at once
+                config.postprocessing_config.spectrum_buffer_size = 4;
+                incremental_config
+                    .postprocessing_config
+                    .spectrum_buffer_size = 4;
+            }
+            if i == 20 {
+                spectrum_container.clear_buffer();
+                full.clear_buffer();
+            }
+            let value = SpectrumRgb::from_fn(10, |r, c| ((i * 7 + r * 3 + c) % 11) as f32 / 10.);
+            spectrum_container.update_spectrum(value.clone(), &incremental_config);
+            full.update_spectrum(value, &config);
+            approx::assert_relative_eq!(spectrum_container.spectrum, full.spectrum, epsilon = 1e-5);
+        }
+        assert!(spectrum_container.buffer_sum.is_some());
+        assert!(full.buffer_sum.is_none());
+    }
+
+    #[rstest]
+    fn time_range(mut spectrum_container: SpectrumContainer, mut config: SpectrometerConfig) {
+        config.postprocessing_config.spectrum_buffer_size = 2;
+        let at = |s| SystemTime::UNIX_EPOCH + Duration::from_secs(s);
+        assert_eq!(spectrum_container.time_range(), None);
+
+        for s in 0..3 {
+            spectrum_container.update_timestamped_spectrum(
+                Timestamped {
+                    start: at(s * 10),
+                    end: at(s * 10 + 1),
+                    sequence: None,
+                    frame_metadata: None,
+                    saturation: None,
+                    channel_peaks: None,
+                    value: SpectrumRgb::from_element(10, 0.5),
+                },
+                &config,
+            );
+        }
+
+        assert_eq!(spectrum_container.time_range(), Some((at(10), at(21))));
+    }
+
+    #[rstest]
+    fn raw_zero_reference(
+        mut spectrum_container: SpectrumContainer,
+        mut config: SpectrometerConfig,
+    ) {
+        config.postprocessing_config.raw_zero_reference = true;
+        config.spectrum_calibration.gain_r = 2.;
+
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(100, 0.1), &config);
+        spectrum_container.set_zero_reference(true);
+        for _ in 0..config.postprocessing_config.spectrum_buffer_size {
+            assert!(spectrum_container.is_capturing_zero_reference());
+            spectrum_container.update_spectrum(SpectrumRgb::from_element(100, 0.1), &config);
+        }
+        assert!(!spectrum_container.is_capturing_zero_reference());
+
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(100, 0.5), &config);
+
+        approx::assert_relative_eq!(spectrum_container.spectrum[(0, 0)], 0.8);
+        approx::assert_relative_eq!(spectrum_container.spectrum[(1, 0)], 0.4);
+        approx::assert_relative_eq!(spectrum_container.spectrum[(3, 0)], 1.6 / 3.);
+    }
+
+    #[rstest]
+    fn processing_order(mut spectrum_container: SpectrumContainer, mut config: SpectrometerConfig) {
+        config.spectrum_calibration.linearize = Linearize::SRgb;
+
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 0.2), &config);
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 0.8), &config);
+        approx::assert_relative_eq!(
+            spectrum_container.spectrum[(0, 0)],
+            (Linearize::SRgb.linearize(0.2) + Linearize::SRgb.linearize(0.8)) / 2.
+        );
+
+        spectrum_container.clear_buffer();
+        config.postprocessing_config.processing_order = ProcessingOrder::AverageThenLinearize;
+
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 0.2), &config);
+        spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 0.8), &config);
+        approx::assert_relative_eq!(
+            spectrum_container.spectrum[(0, 0)],
+            Linearize::SRgb.linearize(0.5)
+        );
+    }
+
+    #[rstest]
+    fn double_precision(mut spectrum_container: SpectrumContainer, mut config: SpectrometerConfig) {
+        config.postprocessing_config.spectrum_filter_active = true;
+        config.spectrum_calibration.gain_r = 0.3;
+
+        let spectrum = SpectrumRgb::from_fn(100, |r, c| ((r + 1) * c) as f32 / 300.);
+        spectrum_container.update_spectrum(spectrum.clone(), &config);
+        let single = spectrum_container.spectrum.clone();
+
+        spectrum_container.clear_buffer();
+        config.postprocessing_config.double_precision = true;
+        spectrum_container.update_spectrum(spectrum, &config);
+
+        approx::assert_relative_eq!(spectrum_container.spectrum, single, epsilon = 1e-5);
+    }
+
+    #[rstest]
+    fn double_precision_zero_reference(
+        mut spectrum_container: SpectrumContainer,
+        mut config: SpectrometerConfig,
+    ) {
+        config.spectrum_calibration.linearize = Linearize::Off;
+        config.spectrum_calibration.gain_r = 1.;
+
+        // The average 0.5 + 5e-9 rounds to 0.5 in single precision, cancelling the zero reference
+        for double_precision in [false, true] {
+            config.postprocessing_config.double_precision = double_precision;
+            spectrum_container.clear_buffer();
+            spectrum_container.clear_zero_reference();
+            spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 0.5), &config);
+            spectrum_container.set_zero_reference(false);
+
+            spectrum_container.clear_buffer();
+            spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 1.), &config);
+            spectrum_container.update_spectrum(SpectrumRgb::from_element(10, 1e-8), &config);
+
+            let expected = if double_precision { 5e-9 } else { 0. };
+            approx::assert_relative_eq!(
+                spectrum_container.precise_spectrum()[(0, 0)],
+                expected,
+                epsilon = 1e-12
+            );
+        }
+    }
+
+    #[test]
+    fn process_window_column_aggregation() {
+        let mut window = ImageBuffer::<Rgb<u8>, _>::new(2, 3);
+        for (y, v) in [10, 20, 90].into_iter().enumerate() {
+            window.put_pixel(0, y as u32, Rgb([v, 0, 255]));
+            window.put_pixel(1, y as u32, Rgb([0, v, 0]));
+        }
+
+        let mean = SpectrumCalculator::process_window(&window, ColumnAggregation::Mean);
+        approx::assert_relative_eq!(mean[(0, 0)], 40. / 765.);
+        approx::assert_relative_eq!(mean[(2, 0)], 1. / 3.);
+
+        let median = SpectrumCalculator::process_window(&window, ColumnAggregation::Median);
+        approx::assert_relative_eq!(median[(0, 0)], 20. / 765.);
+        approx::assert_relative_eq!(median[(1, 1)], 20. / 765.);
+
+        let max = SpectrumCalculator::process_window(&window, ColumnAggregation::Max);
+        approx::assert_relative_eq!(max[(0, 0)], 90. / 765.);
+        approx::assert_relative_eq!(max[(1, 1)], 90. / 765.);
+        approx::assert_relative_eq!(max[(2, 1)], 0.);
+    }
+
+    #[test]
+    fn window_orientation() {
+        // 2x3 window with the wavelength index increasing from top to bottom
+        let window = DynamicImage::ImageRgb8(ImageBuffer::from_fn(2, 3, |_, y| Rgb([y as u8; 3])));
+
+        let spectrum = |image: DynamicImage| {
+            SpectrumCalculator::process_window(&image.into_rgb8(), ColumnAggregation::Mean)
+                .row(0)
+                .iter()
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+        let vertical = orient_window(window.clone(), SpectrumOrientation::Vertical, false);
+        assert_eq!((vertical.width(), vertical.height()), (3, 2));
+        let values = spectrum(vertical);
+        assert!(values[0] < values[1] && values[1] < values[2]);
+        let mut reversed = spectrum(orient_window(window, SpectrumOrientation::Vertical, true));
+        reversed.reverse();
+        assert_eq!(reversed, values);
+
+        // RGGB, the top left pixel of the window is red
+        let layout = [[0, 1], [1, 2]];
+        let orient =
+            |orientation, rotate_180| orient_bayer_layout(layout, 2, 3, orientation, rotate_180);
+        assert_eq!(orient(SpectrumOrientation::Horizontal, false), layout);
+        assert_eq!(
+            orient(SpectrumOrientation::Horizontal, true),
+            [[1, 0], [2, 1]]
+        );
+        // The top right pixel ends up at the top left, the bottom left one with rotation
+        assert_eq!(
+            orient(SpectrumOrientation::Vertical, false),
+            [[1, 2], [0, 1]]
+        );
+        assert_eq!(
+            orient(SpectrumOrientation::Vertical, true),
+            [[0, 1], [1, 2]]
+        );
+    }
+
+    #[test]
+    fn process_window_16_bit() {
+        let window = WindowImage::new(
+            DynamicImage::ImageLuma16(ImageBuffer::from_fn(3, 2, |x, _| Luma([x as u16 * 1000]))),
+            ColumnAggregation::Mean,
+            None,
+        );
+        assert!(matches!(window.image, DynamicImage::ImageRgb16(_)));
+
+        let spectrum = SpectrumCalculator::process_window_image(&window);
+        approx::assert_relative_eq!(spectrum[(0, 1)], 1000. / (65535. * 3.));
+        approx::assert_relative_eq!(spectrum[(2, 2)], 2000. / (65535. * 3.));
+
+        let window = WindowImage::new(
+            DynamicImage::ImageRgba8(ImageBuffer::from_pixel(1, 1, Rgba([255, 0, 0, 255]))),
+            ColumnAggregation::Mean,
+            None,
+        );
+        assert!(matches!(window.image, DynamicImage::ImageRgb8(_)));
+    }
+
+    #[test]
+    fn process_bayer_window_planes() {
+        let layout = [[0, 1], [1, 2]];
+        let mut window = ImageBuffer::new(5, 4);
+        for (x, y, pixel) in window.enumerate_pixels_mut() {
+            let value = [30, 60, 90][layout[y as usize % 2][x as usize % 2]] + x as u8;
+            *pixel = Rgb([value; 3]);
+        }
+
+        let spectrum =
+            SpectrumCalculator::process_bayer_window(&window, layout, ColumnAggregation::Mean);
+        for x in 0..5 {
+            // Odd columns have no red, even columns no blue
+            approx::assert_relative_eq!(spectrum[(0, x)], (30. + x as f32) / 765.);
+            approx::assert_relative_eq!(spectrum[(1, x)], (60. + x as f32) / 765.);
+        }
+        approx::assert_relative_eq!(spectrum[(2, 0)], 91. / 765.);
+        approx::assert_relative_eq!(spectrum[(2, 2)], 92. / 765.);
+        approx::assert_relative_eq!(spectrum[(2, 4)], 93. / 765.);
+    }
+
+    #[test]
+    fn merge_bracketed_frames() {
+        let frame = |exposure_time, values: [f32; 2], saturated: [bool; 2]| BracketFrame {
+            exposure_time,
+            spectrum: SpectrumRgb::from_fn(2, |_, x| values[x]),
+            saturated: saturated.iter().map(|&s| [s; 3]).collect(),
+        };
+        let frames = [
+            frame(100, [0.3, 0.01], [true, false]),
+            frame(10, [0.1, 0.001], [false, false]),
+        ];
+
+        let spectrum = merge_hdr(&frames);
+        approx::assert_relative_eq!(spectrum[(0, 0)], 0.1);
+        approx::assert_relative_eq!(spectrum[(1, 1)], 0.001);
+    }
+
+    #[test]
+    fn bracketing_cycle() {
+        let (window_tx, window_rx) = flume::unbounded();
+        let (spectrum_tx, spectrum_rx) = flume::unbounded();
+        let window = |index, exposure_time, value| {
+            let image = ImageBuffer::<Rgb<u8>, _>::from_pixel(4, 2, Rgb([value; 3]));
+            let mut window = WindowImage::new(
+                DynamicImage::ImageRgb8(image),
+                ColumnAggregation::Mean,
+                None,
+            );
+            window.bracket = Some(Bracket {
+                index,
+                count: 2,
+                exposure_time,
+            });
+            Timestamped {
+                start: SystemTime::UNIX_EPOCH,
+                end: SystemTime::UNIX_EPOCH,
+                sequence: None,
+                frame_metadata: Some(FrameMetadata::default()),
+                saturation: None,
+                channel_peaks: None,
+                value: window,
+            }
+        };
+        // The incomplete cycle is discarded
+        window_tx.send(window(1, 20, 255)).unwrap();
+        window_tx.send(window(0, 10, 30)).unwrap();
+        window_tx.send(window(1, 20, 255)).unwrap();
+        drop(window_tx);
+
+        SpectrumCalculator::new(window_rx, spectrum_tx).run();
+
+        let spectra: Vec<_> = spectrum_rx.drain().collect();
+        assert_eq!(spectra.len(), 1);
+        assert_eq!(
+            spectra[0].frame_metadata.unwrap().exposure.exposure_time,
+            Some(10)
+        );
+        approx::assert_relative_eq!(spectra[0].value[(0, 0)], 30. / 765.);
+    }
+
+    #[test]
+    fn long_exposure() {
+        let (window_tx, window_rx) = flume::unbounded();
+        let (spectrum_tx, spectrum_rx) = flume::unbounded();
+        let window = |seconds, width| {
+            let image = ImageBuffer::<Rgb<u8>, _>::from_pixel(width, 2, Rgb([51; 3]));
+            let mut window = WindowImage::new(
+                DynamicImage::ImageRgb8(image),
+                ColumnAggregation::Mean,
+                None,
+            );
+            window.long_exposure = Some(Duration::from_secs(2));
+            let time = SystemTime::UNIX_EPOCH + Duration::from_secs(seconds);
+            Timestamped {
+                start: time,
+                end: time,
+                sequence: None,
+                frame_metadata: None,
+                saturation: Some(seconds as f32 / 10.),
+                channel_peaks: None,
+                value: window,
+            }
+        };
+        // The different size starts a new exposure
+        window_tx.send(window(0, 2)).unwrap();
+        for seconds in 1..=4 {
+            window_tx.send(window(seconds, 4)).unwrap();
+        }
+        drop(window_tx);
+
+        SpectrumCalculator::new(window_rx, spectrum_tx).run();
+
+        let spectra: Vec<_> = spectrum_rx.drain().collect();
+        assert_eq!(spectra.len(), 1);
+        assert_eq!(spectra[0].value.ncols(), 4);
+        assert_eq!(spectra[0].saturation, Some(0.3));
+        // Three frames of a fifth of full scale
+        approx::assert_relative_eq!(spectra[0].value[(0, 0)], 3. * 51. / 765.);
+    }
+
+    #[test]
+    fn window_saturation() {
+        let image = ImageBuffer::from_fn(4, 2, |x, _| Rgb([if x == 0 { 255 } else { 10 }, 10, 10]));
+        let window = WindowImage::new(
+            DynamicImage::ImageRgb8(image.clone()),
+            ColumnAggregation::Mean,
+            None,
+        );
+        approx::assert_relative_eq!(window.saturation(), 2. / 24.);
+        assert_eq!(window.channel_peaks(), [1., 10. / 255., 10. / 255.]);
+        // Only the red pixels of an RGGB layout count for the red channel
+        let window = WindowImage::new(
+            DynamicImage::ImageRgb8(image),
+            ColumnAggregation::Mean,
+            Some([[0, 1], [1, 2]]),
+        );
+        approx::assert_relative_eq!(window.saturation(), 1. / 8.);
+        // The green and blue pixels of the first column do not count for red
+        let image = ImageBuffer::from_fn(2, 2, |x, y| Rgb([(x + 2 * y) as u8 * 50; 3]));
+        let window = WindowImage::new(
+            DynamicImage::ImageRgb8(image),
+            ColumnAggregation::Mean,
+            Some([[0, 1], [1, 2]]),
+        );
+        assert_eq!(window.channel_peaks(), [0., 100. / 255., 150. / 255.]);
+    }
+
+    #[rstest]
+    #[case(AccumulationMode::Mean, 3855)]
+    #[case(AccumulationMode::Sum, 7710)]
+    fn accumulate_windows(#[case] mode: AccumulationMode, #[case] expected: u16) {
+        let window = |second, value| Timestamped {
+            start: SystemTime::UNIX_EPOCH + Duration::from_secs(second),
+            end: SystemTime::UNIX_EPOCH + Duration::from_secs(second + 1),
+            sequence: None,
+            frame_metadata: None,
+            saturation: None,
+            channel_peaks: None,
+            value: WindowImage::new(
+                DynamicImage::ImageRgb8(ImageBuffer::from_pixel(3, 1, Rgb([value; 3]))),
+                ColumnAggregation::Mean,
+                None,
+            ),
+        };
+        let mut accumulator = WindowAccumulator::default();
+
+        assert!(accumulator.add(window(0, 10), 2, mode).is_none());
+        let accumulated = accumulator.add(window(1, 20), 2, mode).unwrap();
+        assert_eq!(accumulated.start, SystemTime::UNIX_EPOCH);
+        assert_eq!(
+            accumulated.end,
+            SystemTime::UNIX_EPOCH + Duration::from_secs(2)
+        );
+        // 15 of 255 on average
+        assert_eq!(
+            accumulated.value.image.as_rgb16().unwrap().get_pixel(2, 0),
+            &Rgb([expected; 3])
+        );
+        // The next cycle starts over
+        assert!(accumulator.add(window(2, 10), 2, mode).is_none());
+    }
+
+    #[test]
+    fn extract_rotated_window() {
+        let frame = ImageBuffer::from_fn(40, 40, |x, y| Rgb([if x == y { 200u8 } else { 0 }; 3]));
+        // Centered on the diagonal
+        let window = SpectrumWindow {
+            offset: Vec2::new(12.5, 19.),
+            size: Vec2::new(16., 3.),
+            angle: 45.,
+        };
+
+        let image = extract_window(&DynamicImage::ImageRgb8(frame), &window);
+        let image = image.as_rgb8().unwrap();
+        assert_eq!(image.dimensions(), (16, 3));
+        // The diagonal runs along the middle row of the rotated window
+        for x in 0..16 {
+            let row = |y| image.get_pixel(x, y)[0];
+            assert!(row(1) >= 100, "{x}: {}", row(1));
+            assert!(row(1) > row(0) && row(1) > row(2));
+        }
+    }
+
+    #[test]
+    fn find_spectrum_window_band() {
+        let mut frame = ImageBuffer::from_pixel(100, 80, Rgb([5, 5, 5]));
+        for y in 40..50 {
+            for x in 20..80 {
+                frame.put_pixel(x, y, Rgb([200, 100, 50]));
+            }
+        }
+
+        let window = find_spectrum_window(&frame).unwrap();
+        assert_eq!(window.offset, Vec2::new(20., 40.));
+        assert_eq!(window.size, Vec2::new(60., 10.));
+
+        assert!(find_spectrum_window(&ImageBuffer::from_pixel(10, 10, Rgb([5, 5, 5]))).is_none());
+    }
+
+    #[test]
+    fn vertical_centroid_follows_trace() {
+        let mut frame = ImageBuffer::from_pixel(50, 50, Rgb([5, 5, 5]));
+        for x in 0..50 {
+            frame.put_pixel(x, 27, Rgb([100, 100, 100]));
+            frame.put_pixel(x, 28, Rgb([100, 100, 100]));
+        }
+        let window = SpectrumWindow {
+            offset: Vec2::new(10., 20.),
+            size: Vec2::new(30., 5.),
+            angle: 0.,
+        };
+
+        approx::assert_relative_eq!(vertical_centroid(&frame, &window, 5).unwrap(), 27.5);
+        assert!(vertical_centroid(&frame, &window, 0).is_none());
+    }
+
+    #[rstest]
+    fn export_points(mut spectrum_container: SpectrumContainer) {
+        let calibration = SpectrumCalibration::default();
+        spectrum_container.set_spectrum(Spectrum::from_fn(500, |_, c| c as f32 * 0.001));
+        let export_config = ImportExportConfig {
+            decimal_places: Some(2),
+            wavelength_step: Some(5.),
+            wavelength_range: Some(WavelengthRange {
+                low: 400.,
+                high: 500.,
+            }),
+            ..Default::default()
+        };
+
+        let points = spectrum_container.spectrum_to_point_vec(&calibration, &export_config);
+
+        assert_eq!(points.len(), 21);
+        for (i, p) in points.iter().enumerate() {
+            approx::assert_relative_eq!(p.wavelength, 400. + 5. * i as f32);
+            let index = calibration.low.index as f32
+                + (p.wavelength - calibration.low.wavelength as f32)
+                    / calibration.get_wavelength_delta();
+            approx::assert_relative_eq!(p.sum, index * 0.001, epsilon = 0.005 + 1e-6);
+            approx::assert_relative_eq!(p.sum * 100., (p.sum * 100.).round(), epsilon = 1e-3);
+        }
+
+        let single = [SpectrumExportPoint {
+            wavelength: 401.,
+            ..Default::default()
+        }];
+        assert_eq!(resample_export_points(&single, 5.), single);
+        assert!(resample_export_points(&[], 5.).is_empty());
+    }
+
+    #[test]
+    fn dominant_peak_shape() {
+        let sigma = 5.;
+        let points: Vec<_> = (500..600)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: (-((w as f32 - 550.).powi(2)) / (2. * sigma * sigma)).exp(),
+            })
+            .collect();
+
+        let peak = dominant_peak(&points).unwrap();
+
+        approx::assert_relative_eq!(peak.wavelength, 550.);
+        approx::assert_relative_eq!(peak.centroid, 550., epsilon = 1e-3);
+        approx::assert_relative_eq!(peak.fwhm, 2.3548 * sigma, epsilon = 0.1);
+        assert!(dominant_peak(&points[..52]).is_none());
+        assert!(dominant_peak(&[]).is_none());
+    }
+
+    #[test]
+    fn sub_pixel_peak_gaussian() {
+        let points: Vec<_> = (540..560)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: (-((w as f32 - 550.3).powi(2)) / (2. * 1.5 * 1.5)).exp(),
+            })
+            .collect();
+
+        approx::assert_relative_eq!(sub_pixel_peak(&points).unwrap(), 550.3, epsilon = 1e-3);
+        assert!(sub_pixel_peak(&points[..11]).is_none());
+    }
+
+    #[rstest]
+    #[case(PeakFit::Gaussian)]
+    #[case(PeakFit::Lorentzian)]
+    fn peak_fit(#[case] shape: PeakFit) {
+        let (center, fwhm, height) = (550.3, 4., 2.);
+        let line = |w: f32| match shape {
+            PeakFit::Gaussian => {
+                height * (-4. * std::f32::consts::LN_2 * ((w - center) / fwhm).powi(2)).exp()
+            }
+            _ => height / (1. + (2. * (w - center) / fwhm).powi(2)),
+        };
+        let points: Vec<_> = (530..570)
+            .map(|w| SpectrumPoint {
+                wavelength: w as f32,
+                value: line(w as f32),
+            })
+            .collect();
+
+        let fit = fit_peak(&points, shape).unwrap();
+        approx::assert_relative_eq!(fit.center, center, epsilon = 1e-3);
+        approx::assert_relative_eq!(fit.fwhm, fwhm, epsilon = 1e-3);
+        approx::assert_relative_eq!(fit.height, height, epsilon = 1e-3);
+        let sum: f32 = (3500..7500).map(|w| line(w as f32 / 10.) / 10.).sum();
+        approx::assert_relative_eq!(fit.area, sum, max_relative = 1e-2);
+
+        assert!(fit_peak(&points, PeakFit::Off).is_none());
+        // Only two points above half maximum
+        assert!(fit_peak(&points[..21], shape).is_none());
+
+        let (_tx, rx) = flume::unbounded();
+        let mut spectrum_container = SpectrumContainer::new(rx);
+        let mut config = SpectrometerConfig::default();
+        config.view_config.peak_fit = shape;
+        config.spectrum_calibration.low = SpectrumCalibrationPoint {
+            wavelength: 530,
+            index: 0,
+        };
+        config.spectrum_calibration.high = SpectrumCalibrationPoint {
+            wavelength: 570,
+            index: 40,
+        };
+        spectrum_container.update_spectrum(
+            SpectrumRgb::from_fn(40, |_, c| line(530. + c as f32)),
+            &config,
+        );
+        let peaks = spectrum_container.spectrum_to_peaks_and_dips(true, &config);
+        assert_eq!(peaks.len(), 1);
+        approx::assert_relative_eq!(peaks[0].wavelength, center, epsilon = 1e-2);
+        assert_eq!(
+            spectrum_container.spectrum_to_fitted_peaks(&config).len(),
+            1
+        );
+    }
+
+    #[rstest]
+    #[case(BaselineCorrection::Als)]
+    #[case(BaselineCorrection::RollingBall)]
+    fn baseline_correction(#[case] method: BaselineCorrection) {
+        let slope = |i: usize| 1. + i as f32 / 100.;
+        let peak = |i: usize| 5. * (-((i as f32 - 150.) / 3.).powi(2)).exp();
+        let values: Vec<f32> = (0..300).map(|i| slope(i) + peak(i)).collect();
+        let mut config = PostprocessingConfig {
+            baseline_correction: method,
+            baseline_radius: 20,
+            ..Default::default()
+        };
+
+        let baseline = estimate_baseline(&values, &config).unwrap();
+        // The peak is kept while the sloping background is removed
+        approx::assert_abs_diff_eq!(values[150] - baseline[150], 5., epsilon = 0.1);
+        for i in (30..270_usize).step_by(10).filter(|i| i.abs_diff(150) > 20) {
+            approx::assert_abs_diff_eq!(baseline[i], slope(i), epsilon = 0.05);
+        }
+
+        config.baseline_correction = BaselineCorrection::Off;
+        assert!(estimate_baseline(&values, &config).is_none());
+    }
+
+    #[rstest]
+    fn unfiltered_spectrum(
+        mut spectrum_container: SpectrumContainer,
+        mut config: SpectrometerConfig,
+    ) {
+        let spectrum = SpectrumRgb::from_fn(100, |_, c| (c % 2) as f32);
+
+        spectrum_container.update_spectrum(spectrum.clone(), &config);
+        let unfiltered = spectrum_container.get_spectrum_channel(3, &config);
+        assert!(spectrum_container
+            .get_unfiltered_spectrum_channel(3, &config)
+            .is_none());
+
+        spectrum_container.clear_buffer();
+        config.postprocessing_config.spectrum_filter_active = true;
+        spectrum_container.update_spectrum(spectrum, &config);
+
+        assert_eq!(
+            spectrum_container.get_unfiltered_spectrum_channel(3, &config),
+            Some(unfiltered.clone())
+        );
+        assert_ne!(
+            spectrum_container.get_spectrum_channel(3, &config),
+            unfiltered
+        );
+    }
+
     #[rstest]
     fn get_spectrum_max_value(
         mut spectrum_container: SpectrumContainer,
@@ -374,4 +2904,31 @@ mod tests {
 
         assert_eq!(spectrum_container.get_spectrum_max_value(), Some(0.5));
     }
+
+    #[rstest]
+    fn balance_channels(mut spectrum_container: SpectrumContainer, mut config: SpectrometerConfig) {
+        config
+            .spectrum_calibration
+            .set_gain_preset(GainPresets::Unity);
+        spectrum_container.update_spectrum(
+            SpectrumRgb::from_fn(
+                1000,
+                |r, c| if c < 500 { 0.5 } else { 0.2 * (r + 1) as f32 },
+            ),
+            &config,
+        );
+        let calibration = &mut config.spectrum_calibration;
+        let wavelength = calibration.get_wavelength_from_index(700);
+
+        spectrum_container
+            .balance_channels(calibration, wavelength, 10.)
+            .unwrap();
+
+        approx::assert_relative_eq!(calibration.gain_r, 2., epsilon = 1e-5);
+        approx::assert_relative_eq!(calibration.gain_g, 1., epsilon = 1e-5);
+        approx::assert_relative_eq!(calibration.gain_b, 2. / 3., epsilon = 1e-5);
+        assert!(spectrum_container
+            .balance_channels(calibration, -1000., 0.)
+            .is_err());
+    }
 }
@@ -0,0 +1,110 @@
+use crate::config::SpectrumPoint;
+use egui_plot::{Line, PlotPoints};
+use serde::{Deserialize, Serialize};
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ToleranceConfig {
+    pub active: bool,
+    /// Target spectrum sorted by wavelength
+    pub target: Option<Vec<SpectrumPoint>>,
+    /// Allowed absolute deviation from the target in both directions
+    pub tolerance: f32,
+    /// Notified on every change between pass and fail
+    pub webhook_url: String,
+}
+
+impl Default for ToleranceConfig {
+    fn default() -> Self {
+        Self {
+            active: false,
+            target: None,
+            tolerance: 0.05,
+            webhook_url: String::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct ToleranceResult {
+    pub passed: bool,
+    /// Largest absolute deviation from the target
+    pub max_deviation: f32,
+    /// Wavelength of the largest deviation in nm
+    pub wavelength: f32,
+}
+
+impl ToleranceConfig {
+    pub fn set_target(&mut self, mut target: Vec<SpectrumPoint>) {
+        target.sort_by(|a, b| a.wavelength.total_cmp(&b.wavelength));
+        self.target = Some(target);
+    }
+
+    /// Compare a spectrum with the target, points outside of the target are ignored
+    pub fn check(&self, spectrum: &[SpectrumPoint]) -> Option<ToleranceResult> {
+        let target = self.target.as_ref()?;
+        spectrum
+            .iter()
+            .filter_map(|p| {
+                SpectrumPoint::interpolate(target, p.wavelength)
+                    .map(|t| (p.wavelength, (p.value - t).abs()))
+            })
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(wavelength, max_deviation)| ToleranceResult {
+                passed: max_deviation <= self.tolerance,
+                max_deviation,
+                wavelength,
+            })
+    }
+
+    /// Lower and upper limit of the tolerance band
+    /// Lower and upper limit with the wavelengths multiplied by `wavelength_sign`
+    pub fn to_band_lines(&self, wavelength_sign: f64) -> Option<(Line, Line)> {
+        self.target.as_ref().map(|target| {
+            let limit = |offset: f32| {
+                Line::new(PlotPoints::from_iter(target.iter().map(|p| {
+                    [
+                        wavelength_sign * p.wavelength as f64,
+                        (p.value + offset) as f64,
+                    ]
+                })))
+            };
+            (limit(-self.tolerance), limit(self.tolerance))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    fn points(values: &[(f32, f32)]) -> Vec<SpectrumPoint> {
+        values
+            .iter()
+            .map(|&(wavelength, value)| SpectrumPoint { wavelength, value })
+            .collect()
+    }
+
+    #[test]
+    fn tolerance_check() {
+        let mut config = ToleranceConfig {
+            tolerance: 0.1,
+            ..Default::default()
+        };
+        assert_eq!(config.check(&points(&[(500., 0.5)])), None);
+
+        config.set_target(points(&[(600., 1.), (400., 0.), (500., 0.5)]));
+
+        let result = config
+            .check(&points(&[(450., 0.3), (550., 0.72), (700., 5.)]))
+            .unwrap();
+        assert!(result.passed);
+        assert_relative_eq!(result.max_deviation, 0.05, epsilon = 1e-6);
+        assert_relative_eq!(result.wavelength, 450.);
+
+        let result = config.check(&points(&[(400., 0.), (550., 0.9)])).unwrap();
+        assert!(!result.passed);
+        assert_relative_eq!(result.max_deviation, 0.15, epsilon = 1e-6);
+        assert_relative_eq!(result.wavelength, 550.);
+    }
+}
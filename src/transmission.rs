@@ -0,0 +1,175 @@
+use crate::spectrum::SpectrumF64;
+use serde::{Deserialize, Serialize};
+
+/// Differences between reference and dark below this are treated as no signal
+const MIN_SIGNAL: f64 = 1e-4;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct TransmissionConfig {
+    /// Show `log10(reference / sample)` instead of the transmittance
+    pub optical_density: bool,
+    /// Transmittance below this is treated as the noise floor, limiting the optical density
+    pub transmittance_floor: f32,
+}
+
+impl Default for TransmissionConfig {
+    fn default() -> Self {
+        Self {
+            optical_density: false,
+            transmittance_floor: 1e-5,
+        }
+    }
+}
+
+impl TransmissionConfig {
+    pub fn max_optical_density(&self) -> f32 {
+        -self.transmittance_floor.log10()
+    }
+}
+
+/// Optical density of every channel, at most `-log10(floor)`
+pub fn optical_density(transmittance: &SpectrumF64, floor: f32) -> SpectrumF64 {
+    transmittance.map(|t| -t.max(floor as f64).log10())
+}
+
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum TransmissionStep {
+    CaptureReference,
+    CaptureDark,
+    Measuring,
+}
+
+/// Guided capture of reference and dark followed by live transmittance
+///
+/// Spectra are kept in double precision, so the ratios of the double-precision pipeline are not
+/// rounded.
+#[derive(Debug, Clone)]
+pub struct TransmissionSequence {
+    step: TransmissionStep,
+    reference: Option<SpectrumF64>,
+    dark: Option<SpectrumF64>,
+}
+
+impl Default for TransmissionSequence {
+    fn default() -> Self {
+        Self {
+            step: TransmissionStep::CaptureReference,
+            reference: None,
+            dark: None,
+        }
+    }
+}
+
+impl TransmissionSequence {
+    pub fn step(&self) -> TransmissionStep {
+        self.step
+    }
+
+    /// Instruction for the current step
+    pub fn prompt(&self) -> &'static str {
+        match self.step {
+            TransmissionStep::CaptureReference => "Insert the blank and capture the reference",
+            TransmissionStep::CaptureDark => "Block the light path and capture the dark spectrum",
+            TransmissionStep::Measuring => "Insert the sample, showing transmittance",
+        }
+    }
+
+    /// Store the spectrum for the current step and advance to the next one
+    pub fn capture(&mut self, spectrum: &SpectrumF64) {
+        match self.step {
+            TransmissionStep::CaptureReference => {
+                self.reference = Some(spectrum.clone());
+                self.step = TransmissionStep::CaptureDark;
+            }
+            TransmissionStep::CaptureDark => {
+                self.dark = Some(spectrum.clone());
+                self.step = TransmissionStep::Measuring;
+            }
+            TransmissionStep::Measuring => {}
+        }
+    }
+
+    /// `(sample - dark) / (reference - dark)` per channel, zero where the reference has no signal
+    pub fn transmittance(&self, spectrum: &SpectrumF64) -> Option<SpectrumF64> {
+        let (reference, dark) = match (self.step, &self.reference, &self.dark) {
+            (TransmissionStep::Measuring, Some(reference), Some(dark)) => (reference, dark),
+            _ => return None,
+        };
+        if reference.ncols() != spectrum.ncols() || dark.ncols() != spectrum.ncols() {
+            return None;
+        }
+        Some(SpectrumF64::from_fn(spectrum.ncols(), |r, c| {
+            let signal = reference[(r, c)] - dark[(r, c)];
+            if signal > MIN_SIGNAL {
+                (spectrum[(r, c)] - dark[(r, c)]) / signal
+            } else {
+                0.
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn transmission_sequence() {
+        let mut sequence = TransmissionSequence::default();
+        let sample = SpectrumF64::from_element(5, 0.3);
+        assert_eq!(sequence.transmittance(&sample), None);
+
+        sequence.capture(&SpectrumF64::from_fn(
+            5,
+            |_, c| if c == 0 { 0.1 } else { 0.5 },
+        ));
+        assert_eq!(sequence.step(), TransmissionStep::CaptureDark);
+        sequence.capture(&SpectrumF64::from_element(5, 0.1));
+        assert_eq!(sequence.step(), TransmissionStep::Measuring);
+
+        let transmittance = sequence.transmittance(&sample).unwrap();
+        assert_relative_eq!(transmittance[(3, 0)], 0.);
+        assert_relative_eq!(transmittance[(3, 1)], 0.5, epsilon = 1e-6);
+        assert_eq!(sequence.transmittance(&SpectrumF64::zeros(4)), None);
+    }
+
+    #[test]
+    fn optical_density_large_dynamic_range() {
+        let config = TransmissionConfig::default();
+        let mut sequence = TransmissionSequence::default();
+        sequence.capture(&SpectrumF64::from_element(1, 10_001.));
+        sequence.capture(&SpectrumF64::from_element(1, 10_000.));
+        let sample = SpectrumF64::from_element(1, 10_000.000_1);
+
+        let transmittance = sequence.transmittance(&sample).unwrap();
+        let od = optical_density(&transmittance, config.transmittance_floor);
+        assert_relative_eq!(od[(3, 0)], 4., epsilon = 1e-6);
+
+        // In single precision the sample rounds to the dark level, leaving only the floor
+        let rounded = sample.map(|v| v as f32 as f64);
+        let transmittance = sequence.transmittance(&rounded).unwrap();
+        let od = optical_density(&transmittance, config.transmittance_floor);
+        assert_relative_eq!(
+            od[(3, 0)],
+            config.max_optical_density() as f64,
+            epsilon = 1e-6
+        );
+    }
+
+    #[test]
+    fn optical_density_floor() {
+        let config = TransmissionConfig::default();
+        let transmittance = SpectrumF64::from_fn(3, |_, c| [1., 0.01, 0.][c]);
+        let od = optical_density(&transmittance, config.transmittance_floor);
+
+        assert_relative_eq!(od[(3, 0)], 0.);
+        assert_relative_eq!(od[(3, 1)], 2., epsilon = 1e-6);
+        assert_relative_eq!(
+            od[(3, 2)],
+            config.max_optical_density() as f64,
+            epsilon = 1e-6
+        );
+        assert_relative_eq!(config.max_optical_density(), 5., epsilon = 1e-6);
+    }
+}
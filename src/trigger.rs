@@ -0,0 +1,154 @@
+use crate::spectrum::SpectrumRgb;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::time::SystemTime;
+
+/// Lowest background intensity the relative threshold is applied to
+const MIN_BACKGROUND: f32 = 1. / 255.;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct FlashTriggerConfig {
+    /// Relative intensity rise over the background that starts an event
+    pub threshold: f32,
+    /// Number of recent frames used as background
+    pub history: usize,
+    /// Maximum number of frames averaged into one event
+    pub max_frames: usize,
+}
+
+impl Default for FlashTriggerConfig {
+    fn default() -> Self {
+        Self {
+            threshold: 0.5,
+            history: 10,
+            max_frames: 30,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct FlashEvent {
+    /// Capture start of the first frame of the event
+    pub start: SystemTime,
+    pub frames: usize,
+    /// Highest mean intensity of a frame within the event
+    pub peak_intensity: f32,
+}
+
+struct PendingEvent {
+    start: SystemTime,
+    sum: SpectrumRgb,
+    frames: usize,
+    peak_intensity: f32,
+}
+
+/// Detects rapid intensity jumps and averages the frames of each jump
+///
+/// Frames before an event serve as background which is subtracted from the event spectrum.
+#[derive(Default)]
+pub struct FlashTrigger {
+    history: VecDeque<(SpectrumRgb, f32)>,
+    event: Option<PendingEvent>,
+}
+
+impl FlashTrigger {
+    /// Feed a raw spectrum, returns the averaged spectrum once an event is complete
+    pub fn push(
+        &mut self,
+        spectrum: SpectrumRgb,
+        start: SystemTime,
+        config: &FlashTriggerConfig,
+    ) -> Option<(FlashEvent, SpectrumRgb)> {
+        let intensity = spectrum.mean();
+
+        if self
+            .history
+            .front()
+            .is_some_and(|(s, _)| s.ncols() != spectrum.ncols())
+        {
+            self.history.clear();
+            self.event = None;
+        }
+        if self.history.is_empty() {
+            self.history.push_front((spectrum, intensity));
+            return None;
+        }
+
+        let background =
+            self.history.iter().map(|(_, i)| i).sum::<f32>() / self.history.len() as f32;
+        let level = background + config.threshold * background.max(MIN_BACKGROUND);
+
+        match self.event.as_mut() {
+            Some(event) if intensity > level && event.frames < config.max_frames.max(1) => {
+                event.sum += &spectrum;
+                event.frames += 1;
+                event.peak_intensity = event.peak_intensity.max(intensity);
+                None
+            }
+            Some(_) => {
+                let event = self.event.take().unwrap();
+                let background_spectrum = self
+                    .history
+                    .iter()
+                    .fold(SpectrumRgb::zeros(spectrum.ncols()), |acc, (s, _)| acc + s)
+                    / self.history.len() as f32;
+                Some((
+                    FlashEvent {
+                        start: event.start,
+                        frames: event.frames,
+                        peak_intensity: event.peak_intensity,
+                    },
+                    event.sum / event.frames as f32 - background_spectrum,
+                ))
+            }
+            None if intensity > level => {
+                self.event = Some(PendingEvent {
+                    start,
+                    sum: spectrum,
+                    frames: 1,
+                    peak_intensity: intensity,
+                });
+                None
+            }
+            None => {
+                self.history.push_front((spectrum, intensity));
+                self.history.truncate(config.history.max(1));
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use approx::assert_relative_eq;
+
+    #[test]
+    fn flash_event() {
+        let config = FlashTriggerConfig::default();
+        let mut trigger = FlashTrigger::default();
+        let now = SystemTime::now();
+
+        for _ in 0..5 {
+            assert!(trigger
+                .push(SpectrumRgb::from_element(10, 0.1), now, &config)
+                .is_none());
+        }
+        for v in [0.5, 0.7, 0.6] {
+            assert!(trigger
+                .push(SpectrumRgb::from_element(10, v), now, &config)
+                .is_none());
+        }
+        let (event, spectrum) = trigger
+            .push(SpectrumRgb::from_element(10, 0.1), now, &config)
+            .unwrap();
+
+        assert_eq!(event.frames, 3);
+        assert_relative_eq!(event.peak_intensity, 0.7);
+        assert_relative_eq!(spectrum[(1, 5)], 0.5, epsilon = 1e-6);
+        assert!(trigger
+            .push(SpectrumRgb::from_element(10, 0.1), now, &config)
+            .is_none());
+    }
+}
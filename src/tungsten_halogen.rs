@@ -1,24 +1,160 @@
-use crate::config::SpectrumPoint;
+use crate::config::{SpectrumPoint, WavelengthRange};
+use serde::{Deserialize, Serialize};
+use std::fmt::{Display, Formatter};
 
 const T0: f64 = 2.200;
 const C: f64 = physical_constants::SPEED_OF_LIGHT_IN_VACUUM;
 const H: f64 = physical_constants::PLANCK_CONSTANT;
 const K: f64 = physical_constants::BOLTZMANN_CONSTANT;
 
-pub fn reference_from_filament_temp(filament_temp: u16) -> Vec<SpectrumPoint> {
-    let mut ref_points = (340..2000)
-        .map(|wavelength| SpectrumPoint {
-            wavelength: wavelength as f32,
-            value: spectral_irradiance(wavelength as f64, filament_temp as f64).unwrap() as f32,
+/// Wavelengths the emissivity model is valid for
+pub const MODEL_RANGE: WavelengthRange = WavelengthRange {
+    low: 340.,
+    high: 2600.,
+};
+
+/// Room temperature in K the cold resistance is measured at
+const ROOM_TEMP: f32 = 293.;
+/// The resistivity of tungsten rises roughly with T^1.2 between room temperature and 3000 K
+const RESISTIVITY_EXPONENT: f32 = 1.2;
+/// The color temperature of incandescent lamps rises roughly with V^0.42
+const VOLTAGE_EXPONENT: f32 = 0.42;
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum EstimationMethod {
+    /// Operating voltage of a lamp with known rated voltage and color temperature
+    #[default]
+    RatedVoltage,
+    /// Hot resistance from voltage and current, relative to the measured cold resistance
+    VoltageCurrent,
+    ResistanceRatio,
+}
+
+impl Display for EstimationMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            EstimationMethod::RatedVoltage => write!(f, "Rated Voltage"),
+            EstimationMethod::VoltageCurrent => write!(f, "Voltage and Current"),
+            EstimationMethod::ResistanceRatio => write!(f, "Resistance Ratio"),
+        }
+    }
+}
+
+/// Estimate of the filament temperature from electrical measurements
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct FilamentEstimate {
+    pub method: EstimationMethod,
+    /// Voltage across the lamp in V
+    pub voltage: f32,
+    /// Current through the lamp in A
+    pub current: f32,
+    /// Resistance at room temperature in Ω, without the leads
+    pub cold_resistance: f32,
+    /// Hot to cold resistance
+    pub resistance_ratio: f32,
+    pub rated_voltage: f32,
+    /// Color temperature at the rated voltage from the datasheet
+    pub rated_temp: u16,
+}
+
+impl Default for FilamentEstimate {
+    fn default() -> Self {
+        Self {
+            method: EstimationMethod::default(),
+            voltage: 12.,
+            current: 1.67,
+            cold_resistance: 0.5,
+            resistance_ratio: 15.,
+            rated_voltage: 12.,
+            rated_temp: 3000,
+        }
+    }
+}
+
+impl FilamentEstimate {
+    /// Temperature in K, `None` if the inputs are not positive
+    pub fn filament_temp(&self) -> Option<f32> {
+        match self.method {
+            EstimationMethod::RatedVoltage => {
+                (self.voltage > 0. && self.rated_voltage > 0.).then(|| {
+                    self.rated_temp as f32
+                        * (self.voltage / self.rated_voltage).powf(VOLTAGE_EXPONENT)
+                })
+            }
+            EstimationMethod::VoltageCurrent => (self.current > 0. && self.cold_resistance > 0.)
+                .then(|| self.voltage / self.current / self.cold_resistance)
+                .and_then(filament_temp_from_resistance_ratio),
+            EstimationMethod::ResistanceRatio => {
+                filament_temp_from_resistance_ratio(self.resistance_ratio)
+            }
+        }
+    }
+}
+
+/// Temperature in K at which the filament has this multiple of its room temperature resistance
+pub fn filament_temp_from_resistance_ratio(ratio: f32) -> Option<f32> {
+    (ratio > 0.).then(|| ROOM_TEMP * ratio.powf(1. / RESISTIVITY_EXPONENT))
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub struct TungstenConfig {
+    pub filament_temp: u16,
+    pub wavelength_range: WavelengthRange,
+    /// Wavelength step of the generated reference in nm
+    pub wavelength_step: f32,
+    pub estimate: FilamentEstimate,
+    /// Regenerate a preview of the reference whenever the settings change
+    pub live_preview: bool,
+}
+
+impl Default for TungstenConfig {
+    fn default() -> Self {
+        Self {
+            filament_temp: 2800,
+            wavelength_range: WavelengthRange {
+                low: 340.,
+                high: 2000.,
+            },
+            wavelength_step: 1.,
+            estimate: FilamentEstimate::default(),
+            live_preview: false,
+        }
+    }
+}
+
+/// Reference normalized to a maximum of 1, the upper end of the range is included
+///
+/// Fails if the range exceeds [`MODEL_RANGE`] instead of leaving out the wavelengths the model
+/// is not valid for.
+pub fn reference_from_filament_temp(config: &TungstenConfig) -> Result<Vec<SpectrumPoint>, String> {
+    let WavelengthRange { low, high } = config.wavelength_range;
+    if !(config.wavelength_step > 0. && low < high) {
+        return Err("Invalid wavelength range or step".to_string());
+    }
+    let steps = ((high - low) / config.wavelength_step).floor() as usize;
+    let mut ref_points = (0..=steps)
+        .map(|i| {
+            let wavelength = low + i as f32 * config.wavelength_step;
+            spectral_irradiance(wavelength as f64, config.filament_temp as f64)
+                .map(|value| SpectrumPoint {
+                    wavelength,
+                    value: value as f32,
+                })
+                .ok_or_else(|| {
+                    format!(
+                        "The tungsten model is only valid from {} to {} nm",
+                        MODEL_RANGE.low, MODEL_RANGE.high
+                    )
+                })
         })
-        .collect::<Vec<_>>();
+        .collect::<Result<Vec<_>, _>>()?;
     let max = ref_points
         .iter()
         .map(|rp| rp.value)
         .reduce(f32::max)
-        .unwrap();
+        .unwrap_or(1.);
     ref_points.iter_mut().for_each(|rp| rp.value /= max);
-    ref_points
+    Ok(ref_points)
 }
 
 /// From: <https://doi.org/10.1364/AO.49.000880>
@@ -37,7 +173,7 @@ fn emissivity(wavelength: f64, filament_temp: f64) -> Option<f64> {
     let filament_temp = filament_temp / 1000.;
 
     let (l0, a0, a1, b0, b1, b2, c0, c1) = match wavelength {
-        w if w < 340. => return None,
+        w if w < MODEL_RANGE.low as f64 => return None,
         w if w < 420. => (0.380, 0.47245, -0.0155, -0.0086, -0.0229, 0., -2.86, 0.),
         w if w < 480. => (0.450, 0.46361, -0.0172, -0.1304, 0., 0., 0.52, 0.),
         w if w < 580. => (0.530, 0.45549, -0.0173, -0.1150, 0., 0., -0.5, 0.),
@@ -49,7 +185,9 @@ fn emissivity(wavelength: f64, filament_temp: f64) -> Option<f64> {
             0.850, 0.40610, -0.0259, -0.1889, 0.0087, 0.0290, -0.126, 0.246,
         ),
         w if w < 1600. => (1.270, 0.32835, 0., -0.1686, 0.0737, 0., 0.046, 0.016),
-        w if w <= 2600. => (2.100, 0.22631, 0.0431, -0.0829, 0.0241, 0., 0.04, -0.026),
+        w if w <= MODEL_RANGE.high as f64 => {
+            (2.100, 0.22631, 0.0431, -0.0829, 0.0241, 0., 0.04, -0.026)
+        }
         _ => return None,
     };
     Some(
@@ -66,11 +204,60 @@ mod tests {
 
     #[test]
     fn tungsten() {
-        let r = reference_from_filament_temp(2500);
+        let r = reference_from_filament_temp(&TungstenConfig {
+            filament_temp: 2500,
+            ..Default::default()
+        })
+        .unwrap();
 
         assert_eq!(r.iter().map(|rp| rp.value).reduce(f32::max), Some(1.));
-        assert_eq!(r.len(), 2000 - 340);
+        assert_eq!(r.len(), 2000 - 340 + 1);
         assert_eq!(r.first().unwrap().wavelength, 340.);
-        assert_eq!(r.last().unwrap().wavelength, 2000. - 1.);
+        assert_eq!(r.last().unwrap().wavelength, 2000.);
+    }
+
+    #[test]
+    fn filament_estimate() {
+        let mut estimate = FilamentEstimate {
+            voltage: 10.,
+            ..Default::default()
+        };
+        // Lower color temperature when run below the rated voltage
+        approx::assert_abs_diff_eq!(estimate.filament_temp().unwrap(), 2779., epsilon = 1.);
+
+        estimate.method = EstimationMethod::VoltageCurrent;
+        estimate.current = 1.33;
+        estimate.cold_resistance = 0.5;
+        approx::assert_abs_diff_eq!(estimate.filament_temp().unwrap(), 2804., epsilon = 1.);
+        estimate.current = 0.;
+        assert_eq!(estimate.filament_temp(), None);
+
+        estimate.method = EstimationMethod::ResistanceRatio;
+        estimate.resistance_ratio = 1.;
+        approx::assert_relative_eq!(estimate.filament_temp().unwrap(), ROOM_TEMP);
+    }
+
+    #[test]
+    fn tungsten_range() {
+        let config = TungstenConfig {
+            wavelength_range: WavelengthRange {
+                low: 800.,
+                high: 2600.,
+            },
+            wavelength_step: 5.,
+            ..Default::default()
+        };
+        let r = reference_from_filament_temp(&config).unwrap();
+        assert_eq!(r.len(), 361);
+        assert_eq!(r.last().unwrap().wavelength, 2600.);
+
+        let config = TungstenConfig {
+            wavelength_range: WavelengthRange {
+                low: 300.,
+                high: 1000.,
+            },
+            ..config
+        };
+        assert!(reference_from_filament_temp(&config).is_err());
     }
 }
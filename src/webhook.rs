@@ -0,0 +1,94 @@
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::time::Duration;
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Post a JSON body to a plain `http://` URL in a background thread.
+///
+/// Errors are only logged since alerts must never block or stop acquisition.
+pub fn post_json(url: &str, body: serde_json::Value) {
+    let url = url.to_string();
+    std::thread::spawn(move || {
+        if let Err(e) = post_json_blocking(&url, &body) {
+            log::error!("Could not post to webhook {url}: {e}");
+        }
+    });
+}
+
+fn post_json_blocking(url: &str, body: &serde_json::Value) -> Result<(), String> {
+    let (host, path) = split_url(url)?;
+    let body = body.to_string();
+
+    let mut stream = TcpStream::connect(socket_address(host)).map_err(|e| e.to_string())?;
+    stream
+        .set_read_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+    stream
+        .set_write_timeout(Some(TIMEOUT))
+        .map_err(|e| e.to_string())?;
+
+    write!(
+        stream,
+        "POST {path} HTTP/1.1\r\nHost: {host}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )
+    .map_err(|e| e.to_string())?;
+
+    let mut response = String::new();
+    stream
+        .read_to_string(&mut response)
+        .map_err(|e| e.to_string())?;
+    match response.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        Some(status) => Err(format!("HTTP status {status}")),
+        None => Err("Invalid HTTP response".to_string()),
+    }
+}
+
+fn split_url(url: &str) -> Result<(&str, &str), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .ok_or_else(|| "Only http:// webhook URLs are supported".to_string())?;
+    Ok(match rest.find('/') {
+        Some(i) => (&rest[..i], &rest[i..]),
+        None => (rest, "/"),
+    })
+}
+
+/// Host with the default port 80 if the URL has none, IPv6 hosts are written as `[addr]`
+fn socket_address(host: &str) -> String {
+    let has_port = match host.rfind(']') {
+        Some(i) => host[i..].contains(':'),
+        None => host.contains(':'),
+    };
+    if has_port {
+        host.to_string()
+    } else {
+        format!("{host}:80")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn url() {
+        assert_eq!(
+            split_url("http://localhost:8080/hook"),
+            Ok(("localhost:8080", "/hook"))
+        );
+        assert_eq!(split_url("http://example.com"), Ok(("example.com", "/")));
+        assert!(split_url("https://example.com").is_err());
+        assert_eq!(
+            split_url("http://[::1]:8080/hook"),
+            Ok(("[::1]:8080", "/hook"))
+        );
+
+        assert_eq!(socket_address("localhost:8080"), "localhost:8080");
+        assert_eq!(socket_address("example.com"), "example.com:80");
+        assert_eq!(socket_address("[::1]:8080"), "[::1]:8080");
+        assert_eq!(socket_address("[fe80::1]"), "[fe80::1]:80");
+    }
+}